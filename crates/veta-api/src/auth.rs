@@ -0,0 +1,364 @@
+//! Bearer-token authentication for the API.
+//!
+//! Requests carry an API key in `Authorization: Bearer <key>`. Keys are
+//! looked up by hash in the database, and the request is then handled by a
+//! service limited to the key's scope, tags and namespaces. An admin token configured
+//! on the server (`VETA_ADMIN_TOKEN`) works as an admin key that isn't
+//! stored anywhere.
+//!
+//! Every route except the health check needs a key, even before any keys
+//! exist, so nobody can claim a new deployment by creating the first admin
+//! key. The first key is created with the admin token or with `veta keys`
+//! on the machine that holds the database.
+
+use serde::{Deserialize, Serialize};
+use veta_core::auth::hash_key;
+use veta_core::{ApiKey, Database, Error, Scope, VetaService};
+
+use crate::{handle, Request, Response};
+
+/// Prefix of generated keys, so they're easy to recognize in config files.
+pub const KEY_PREFIX: &str = "veta_";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateKeyRequest {
+    pub name: String,
+    pub scope: Scope,
+    /// Only allow notes with one of these tags. Empty allows all notes.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Only allow these namespaces. Empty allows all namespaces.
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+    /// Hash of a key generated by the client. If not given, the server
+    /// generates a key and returns it once.
+    pub key_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateKeyResponse {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    /// The new key, if the server generated it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+/// Generate a new random API key.
+pub fn generate_key() -> Result<String, Error> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| Error::Internal(format!("failed to generate key: {}", e)))?;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("{}{}", KEY_PREFIX, hex))
+}
+
+/// Handle an API request after checking its API key.
+///
+/// Requests without a valid key get a 401. Requests outside the key's scope
+/// or namespaces get a 403 from the scoped service.
+pub async fn handle_with_auth<D: Database>(
+    service: &VetaService<D>,
+    req: &Request,
+    admin_token: Option<&str>,
+) -> Response {
+    // Switch namespace before scoping, since a scoped service is a view
+    // that can't be moved to another namespace
+    match req.namespace {
+        Some(ref ns) if ns != service.namespace() => match service.in_namespace(ns) {
+            Ok(service) => authorize(&service, req, admin_token).await,
+            Err(e) => e.into(),
+        },
+        _ => authorize(service, req, admin_token).await,
+    }
+}
+
+async fn authorize<D: Database>(
+    service: &VetaService<D>,
+    req: &Request,
+    admin_token: Option<&str>,
+) -> Response {
+    // The health check stays open
+    if req.path.trim_matches('/').is_empty() {
+        return handle(service, req).await;
+    }
+
+    let Some(ref token) = req.token else {
+        return unauthorized("Missing API key");
+    };
+
+    // Compare hashes rather than the tokens themselves, so the comparison
+    // doesn't leak how much of the admin token was right
+    let token_hash = hash_key(token);
+    if admin_token.is_some_and(|admin| hash_key(admin) == token_hash) {
+        return match service.scoped(Scope::Admin, Vec::new(), Vec::new()) {
+            Ok(service) => handle(&service, req).await,
+            Err(e) => e.into(),
+        };
+    }
+
+    let key = match service.authenticate(token).await {
+        Ok(Some(key)) => key,
+        Ok(None) => return unauthorized("Invalid API key"),
+        Err(e) => return e.into(),
+    };
+    match service.scoped(key.scope, key.tags, key.namespaces) {
+        Ok(service) => handle(&service, req).await,
+        Err(e) => e.into(),
+    }
+}
+
+fn unauthorized(message: &str) -> Response {
+    Response::error(message, 401)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::Method;
+    use veta_sqlite::SqliteDatabase;
+
+    async fn call(
+        service: &VetaService<SqliteDatabase>,
+        token: Option<&str>,
+        method: Method,
+        path: &str,
+        body: &str,
+    ) -> (u16, serde_json::Value) {
+        let auth = token.map(|t| format!("Bearer {}", t));
+        let req = Request::new(method, path, body.into()).with_authorization(auth.as_deref());
+        let response = handle_with_auth(service, &req, Some("admin-secret")).await;
+        (
+            response.status,
+            serde_json::from_str(&response.body).unwrap(),
+        )
+    }
+
+    async fn create_key(
+        service: &VetaService<SqliteDatabase>,
+        scope: &str,
+        tags: &str,
+    ) -> (i64, String) {
+        let body = format!(
+            r#"{{"name": "test", "scope": "{}", "tags": {}}}"#,
+            scope, tags
+        );
+        let (status, body) =
+            call(service, Some("admin-secret"), Method::POST, "/keys", &body).await;
+        assert_eq!(status, 201);
+        (
+            body["id"].as_i64().unwrap(),
+            body["key"].as_str().unwrap().to_string(),
+        )
+    }
+
+    #[test]
+    fn test_generate_key() {
+        let key = generate_key().unwrap();
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_eq!(key.len(), KEY_PREFIX.len() + 64);
+        assert_ne!(key, generate_key().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_missing_and_invalid_keys() {
+        let service = VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+
+        let (status, body) = call(&service, None, Method::GET, "/notes", "").await;
+        assert_eq!(
+            (status, body["error"].clone()),
+            (401, "Missing API key".into())
+        );
+
+        let (status, body) = call(&service, Some("nope"), Method::GET, "/notes", "").await;
+        assert_eq!(
+            (status, body["error"].clone()),
+            (401, "Invalid API key".into())
+        );
+
+        let response = handle_with_auth(
+            &service,
+            &Request::new(Method::GET, "/", vec![]),
+            Some("admin-secret"),
+        )
+        .await;
+        assert_eq!(response.status, 200);
+
+        // Revoked keys stop working
+        let (id, key) = create_key(&service, "read", "[]").await;
+        let (status, _) = call(&service, Some(&key), Method::GET, "/notes", "").await;
+        assert_eq!(status, 200);
+        let path = format!("/keys/{}", id);
+        let (status, _) = call(&service, Some("admin-secret"), Method::DELETE, &path, "").await;
+        assert_eq!(status, 200);
+        let (status, _) = call(&service, Some(&key), Method::GET, "/notes", "").await;
+        assert_eq!(status, 401);
+    }
+
+    #[tokio::test]
+    async fn test_closed_without_keys() {
+        // A new deployment can't be claimed by creating the first key
+        let service = VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+        let body = r#"{"name": "first", "scope": "admin"}"#;
+        let req = Request::new(Method::POST, "/keys", body.into());
+        assert_eq!(handle_with_auth(&service, &req, None).await.status, 401);
+        let req = Request::new(Method::GET, "/notes", vec![]);
+        assert_eq!(handle_with_auth(&service, &req, None).await.status, 401);
+        assert!(service.list_api_keys().await.unwrap().is_empty());
+
+        let req = Request::new(Method::GET, "/", vec![]);
+        assert_eq!(handle_with_auth(&service, &req, None).await.status, 200);
+    }
+
+    #[tokio::test]
+    async fn test_scopes() {
+        let service = VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+        let (_, reader) = create_key(&service, "read", "[]").await;
+        let (_, writer) = create_key(&service, "write", "[]").await;
+
+        let note = r#"{"title": "Deploy", "body": "", "tags": ["ops"]}"#;
+        let (status, body) = call(&service, Some(&reader), Method::POST, "/notes", note).await;
+        assert_eq!(status, 403);
+        assert_eq!(body["error"], "forbidden: this API key is read-only");
+
+        let (status, _) = call(&service, Some(&writer), Method::POST, "/notes", note).await;
+        assert_eq!(status, 201);
+        let (status, body) = call(&service, Some(&reader), Method::GET, "/notes/1", "").await;
+        assert_eq!((status, body["title"].clone()), (200, "Deploy".into()));
+
+        let (status, body) = call(&service, Some(&writer), Method::GET, "/keys", "").await;
+        assert_eq!(status, 403);
+        assert_eq!(
+            body["error"],
+            "forbidden: this API key can't manage API keys"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_namespace_keys() {
+        let service = VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+        let body = r#"{"name": "alpha", "scope": "admin", "namespaces": ["alpha"]}"#;
+        let (status, body) =
+            call(&service, Some("admin-secret"), Method::POST, "/keys", body).await;
+        assert_eq!(
+            (status, body["namespaces"].clone()),
+            (201, serde_json::json!(["alpha"]))
+        );
+        let key = body["key"].as_str().unwrap();
+
+        let note = r#"{"title": "Deploy", "body": "", "tags": []}"#;
+        call(&service, Some("admin-secret"), Method::POST, "/notes", note).await;
+        let (status, _) = call(
+            &service,
+            Some(key),
+            Method::POST,
+            "/spaces/alpha/notes",
+            note,
+        )
+        .await;
+        assert_eq!(status, 201);
+        let (status, body) = call(&service, Some(key), Method::GET, "/spaces/beta/notes", "").await;
+        assert_eq!(status, 403);
+        assert_eq!(
+            body["error"],
+            "forbidden: this API key can't use namespace 'beta'"
+        );
+        let (status, _) = call(&service, Some(key), Method::GET, "/notes", "").await;
+        assert_eq!(status, 403);
+
+        // Keys work in every namespace, so one limited to some can't manage them
+        let (status, _) = call(&service, Some(key), Method::GET, "/spaces/alpha/keys", "").await;
+        assert_eq!(status, 403);
+
+        // A scoped service can't be moved out of the key's namespaces either
+        let alpha = service.in_namespace("alpha").unwrap();
+        let scoped = alpha
+            .scoped(Scope::Admin, vec![], vec!["alpha".into()])
+            .unwrap();
+        assert!(matches!(
+            scoped.in_namespace("beta"),
+            Err(Error::Forbidden(_))
+        ));
+        let names: Vec<_> = scoped
+            .list_namespaces()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|ns| ns.name)
+            .collect();
+        assert_eq!(names, ["alpha"]);
+    }
+
+    #[tokio::test]
+    async fn test_tag_allow_list() {
+        let service = VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+        let (_, key) = create_key(&service, "write", r#"["Public"]"#).await;
+
+        let secret = r#"{"title": "Secret", "body": "", "tags": ["private"]}"#;
+        call(
+            &service,
+            Some("admin-secret"),
+            Method::POST,
+            "/notes",
+            secret,
+        )
+        .await;
+
+        let (status, body) = call(&service, Some(&key), Method::POST, "/notes", secret).await;
+        assert_eq!(status, 403);
+        assert_eq!(
+            body["error"],
+            "forbidden: this API key can only write notes tagged public"
+        );
+
+        let public = r#"{"title": "Open", "body": "", "tags": ["public", "docs"]}"#;
+        let (status, _) = call(&service, Some(&key), Method::POST, "/notes", public).await;
+        assert_eq!(status, 201);
+
+        let (_, body) = call(&service, Some(&key), Method::GET, "/notes", "").await;
+        let titles: Vec<_> = body["notes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|n| n["title"].clone())
+            .collect();
+        assert_eq!(titles, vec!["Open"]);
+
+        // Notes outside the allow-list look like they don't exist
+        let (status, _) = call(&service, Some(&key), Method::GET, "/notes/1", "").await;
+        assert_eq!(status, 404);
+        let (status, _) = call(&service, Some(&key), Method::DELETE, "/notes/1", "").await;
+        assert_eq!(status, 404);
+        let (_, body) = call(&service, Some(&key), Method::GET, "/tags", "").await;
+        assert_eq!(body, serde_json::json!([{"name": "public", "count": 1}]));
+
+        // So do aliases that stand for or are named like other tags
+        for (alias, tag) in [
+            ("open", "public"),
+            ("secret", "private"),
+            ("public%2Fold", "private"),
+        ] {
+            let body = format!(r#"{{"tag": "{}"}}"#, tag);
+            let path = format!("/tags/aliases/{}", alias);
+            let (status, _) = call(&service, Some("admin-secret"), Method::PUT, &path, &body).await;
+            assert_eq!(status, 200);
+        }
+        let (_, body) = call(&service, Some(&key), Method::GET, "/tags/aliases", "").await;
+        assert_eq!(body, serde_json::json!([]));
+        let body = r#"{"tag": "public"}"#;
+        let path = "/tags/aliases/public%2Fnew";
+        let (status, _) = call(&service, Some("admin-secret"), Method::PUT, path, body).await;
+        assert_eq!(status, 200);
+        let (_, body) = call(&service, Some(&key), Method::GET, "/tags/aliases", "").await;
+        assert_eq!(
+            body,
+            serde_json::json!([{"alias": "public/new", "tag": "public"}])
+        );
+
+        let untag = r#"{"tags": ["docs"]}"#;
+        let (status, _) = call(&service, Some(&key), Method::PATCH, "/notes/2", untag).await;
+        assert_eq!(status, 403);
+        let (status, _) = call(&service, Some(&key), Method::POST, "/undo", "").await;
+        assert_eq!(status, 403);
+    }
+}
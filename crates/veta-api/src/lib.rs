@@ -0,0 +1,1055 @@
+//! HTTP API for the Veta knowledge base.
+//!
+//! This crate maps HTTP requests to [`VetaService`] calls and their results
+//! back to JSON responses. It does no I/O itself: the Cloudflare worker and
+//! `veta serve` convert their requests to a [`Request`], pass it to
+//! [`handle`], and send the [`Response`] back.
+//!
+//! [`mcp`] serves the same operations over the Model Context Protocol, and
+//! [`auth`] checks API keys before handing requests on.
+//!
+//! With the `server` feature, [`server::Server`] serves the API over HTTP on
+//! native targets.
+
+pub mod auth;
+pub mod mcp;
+#[cfg(feature = "server")]
+pub mod server;
+
+use http::Method;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use veta_core::archive::{read_archive, write_archive};
+use veta_core::{
+    parse_query, Cursor, Database, Error, ImportMode, LinkKind, NoteQuery, Operation, ParsedQuery,
+    TagExpr, UpdateNote, VetaService,
+};
+
+/// Content type of note archives, which are JSON Lines.
+pub const ARCHIVE_CONTENT_TYPE: &str = "application/x-ndjson";
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CreateNoteRequest {
+    pub title: String,
+    /// Note body (markdown)
+    pub body: String,
+    pub tags: Vec<String>,
+    /// References to external resources (source code paths, URLs, documentation links, etc.)
+    #[serde(default)]
+    pub references: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct UpdateNoteRequest {
+    pub title: Option<String>,
+    pub body: Option<String>,
+    pub tags: Option<Vec<String>>,
+    /// References to external resources (source code paths, URLs, documentation links, etc.)
+    pub references: Option<Vec<String>>,
+}
+
+/// Body of `PATCH /tags/:name`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateTagRequest {
+    /// New name for the tag. Fails if that tag exists; merge into it instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// What the tag is for. An empty description clears it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Body of `POST /tags/:name/merge`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeTagsRequest {
+    /// Tags to merge into the one in the path.
+    pub tags: Vec<String>,
+}
+
+/// Body of `PUT /tags/aliases/:alias`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagAliasRequest {
+    /// The tag the alias stands for.
+    pub tag: String,
+}
+
+/// The notes a tag change retagged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagChangeResponse {
+    pub ids: Vec<i64>,
+}
+
+/// Body of `POST /notes/:id/links`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkRequest {
+    /// The note to link to.
+    pub to: i64,
+    pub kind: LinkKind,
+}
+
+/// Whether a link was added, or already existed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkResponse {
+    pub added: bool,
+}
+
+/// The kinds of the links an unlink removed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnlinkResponse {
+    pub kinds: Vec<LinkKind>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdResponse {
+    pub id: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CountResponse {
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffResponse {
+    pub diff: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoResponse {
+    pub undone: Option<Operation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OkResponse {
+    pub ok: bool,
+}
+
+/// Filters, sorting and paging shared by note listings and grep.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct NoteFilters {
+    /// Tag expression: `a,b` matches notes tagged a or b, `a+b` requires both, `-c` excludes c
+    pub tags: Option<String>,
+    /// Substrings that must all appear in the title (case-insensitive)
+    #[serde(default)]
+    pub title: Vec<String>,
+    /// Substrings that must each appear in the title or body (case-insensitive)
+    #[serde(default)]
+    pub text: Vec<String>,
+    /// Substrings that must each appear in one of the note's references
+    #[serde(default)]
+    pub references: Vec<String>,
+    /// Only notes updated (or created, with `sort` = `created`) on or after this time, e.g. "2 days ago"
+    pub from: Option<String>,
+    /// Only notes updated (or created, with `sort` = `created`) on or before this time
+    pub to: Option<String>,
+    /// Timestamp to sort and filter by: `updated` (default) or `created`
+    pub sort: Option<String>,
+    /// `desc` (default) or `asc`
+    pub order: Option<String>,
+    /// Maximum number of notes (default 100, 0 for all)
+    pub limit: Option<i64>,
+    /// `next_cursor` from the previous page
+    pub after: Option<String>,
+    /// Number of notes to skip
+    pub offset: Option<i64>,
+}
+
+impl NoteFilters {
+    /// Add the filters to `query`. Tags are ANDed with the query's tags.
+    pub fn apply(self, mut query: NoteQuery) -> Result<NoteQuery, Error> {
+        if let Some(tags) = self
+            .tags
+            .as_deref()
+            .map(TagExpr::parse)
+            .transpose()?
+            .flatten()
+        {
+            query.tags = Some(match query.tags.take() {
+                Some(query_tags) => query_tags.and(tags),
+                None => tags,
+            });
+        }
+        query.title.extend(self.title);
+        query.text.extend(self.text);
+        query.references.extend(self.references);
+
+        let mut query = match self.sort {
+            Some(field) => query.with_time_field(field.parse()?)?,
+            None => query,
+        };
+        if let Some(order) = self.order {
+            query.order = order.parse()?;
+        }
+        Ok(NoteQuery {
+            from: self.from.or(query.from),
+            to: self.to.or(query.to),
+            limit: self.limit,
+            after: self.after.as_deref().map(Cursor::parse).transpose()?,
+            offset: self.offset,
+            ..query
+        })
+    }
+}
+
+/// Percent-encode a path segment, such as a tag name in `/tags/:name`.
+pub fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Decode a percent-encoded path segment.
+fn decode_path_segment(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// An incoming HTTP request.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: Method,
+    /// URL path without the query string, e.g. `/notes/1`.
+    pub path: String,
+    /// Decoded query string parameters.
+    pub query: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Bearer token from the `Authorization` header.
+    pub token: Option<String>,
+    /// Namespace from a `/spaces/:ns` path prefix or the `X-Veta-Space`
+    /// header. `None` uses the service's own namespace.
+    pub namespace: Option<String>,
+}
+
+impl Request {
+    /// Build a request from a URL path with an optional query string, e.g.
+    /// `/notes?tags=api&limit=20`.
+    pub fn new(method: Method, path_and_query: &str, body: Vec<u8>) -> Self {
+        let (path, query) = match path_and_query.split_once('?') {
+            Some((path, query)) => (path, query),
+            None => (path_and_query, ""),
+        };
+        // `/spaces/:ns/notes` is `/notes` in namespace `ns`
+        let (namespace, path) = match path.strip_prefix("/spaces/") {
+            Some(rest) => match rest.split_once('/') {
+                Some((ns, rest)) => (Some(ns.to_string()), format!("/{}", rest)),
+                None => (Some(rest.to_string()), "/".to_string()),
+            },
+            None => (None, path.to_string()),
+        };
+        Request {
+            method,
+            path,
+            query: form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect(),
+            body,
+            token: None,
+            namespace,
+        }
+    }
+
+    /// Take the namespace from an `X-Veta-Space` header value, unless the
+    /// path already chose one.
+    pub fn with_space_header(self, header: Option<&str>) -> Self {
+        let namespace = self.namespace.or_else(|| {
+            header
+                .map(|h| h.trim().to_string())
+                .filter(|h| !h.is_empty())
+        });
+        Request { namespace, ..self }
+    }
+
+    /// Take the bearer token from an `Authorization` header value.
+    pub fn with_authorization(self, header: Option<&str>) -> Self {
+        let token = header
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty());
+        Request { token, ..self }
+    }
+
+    /// First value of a query string parameter.
+    fn param(&self, key: &str) -> Option<&str> {
+        self.params(key).next()
+    }
+
+    /// All values of a repeatable query string parameter.
+    fn params<'a>(&'a self, key: &str) -> impl Iterator<Item = &'a str> {
+        let key = key.to_string();
+        self.query
+            .iter()
+            .filter(move |(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn param_i64(&self, key: &str) -> Option<i64> {
+        self.param(key).and_then(|v| v.parse().ok())
+    }
+
+    fn param_bool(&self, key: &str) -> bool {
+        matches!(self.param(key), Some("true" | "1"))
+    }
+
+    /// Parse the `tags` parameter as a tag expression (e.g. `a+b,-c`).
+    fn param_tags(&self) -> Result<Option<TagExpr>, Error> {
+        match self.param("tags") {
+            Some(tags) => TagExpr::parse(tags),
+            None => Ok(None),
+        }
+    }
+
+    /// Apply the filters shared by `/notes` and `/grep` to a query.
+    fn param_filters(&self, query: NoteQuery) -> Result<NoteQuery, Error> {
+        let filters = NoteFilters {
+            tags: self.param("tags").map(String::from),
+            title: self.params("title").map(String::from).collect(),
+            text: self.params("text").map(String::from).collect(),
+            references: self.params("ref").map(String::from).collect(),
+            from: self.param("from").map(String::from),
+            to: self.param("to").map(String::from),
+            sort: self.param("sort").map(String::from),
+            order: self.param("order").map(String::from),
+            limit: self.param_i64("limit"),
+            after: self.param("after").map(String::from),
+            offset: self.param_i64("offset"),
+        };
+        filters.apply(query)
+    }
+
+    fn json<T: DeserializeOwned>(&self) -> Result<T, Response> {
+        serde_json::from_slice(&self.body)
+            .map_err(|e| Response::error(&format!("Invalid JSON: {}", e), 400))
+    }
+}
+
+/// An outgoing HTTP response.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json<T: Serialize>(data: &T, status: u16) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_string(data).unwrap(),
+        }
+    }
+
+    pub fn error(msg: &str, status: u16) -> Self {
+        Response::json(
+            &ErrorResponse {
+                error: msg.to_string(),
+            },
+            status,
+        )
+    }
+
+    fn ok() -> Self {
+        Response::json(&OkResponse { ok: true }, 200)
+    }
+
+    fn not_found() -> Self {
+        Response::error("Not found", 404)
+    }
+
+    /// Empty 202 response, for MCP notifications.
+    fn accepted() -> Self {
+        Response {
+            status: 202,
+            content_type: "text/plain; charset=utf-8",
+            body: String::new(),
+        }
+    }
+
+    fn text(body: &str) -> Self {
+        Response {
+            status: 200,
+            content_type: "text/plain; charset=utf-8",
+            body: body.to_string(),
+        }
+    }
+}
+
+/// Invalid input is the client's fault, missing notes are 404s, calls the
+/// API key doesn't allow are 403s, and everything else is a server error.
+impl From<Error> for Response {
+    fn from(e: Error) -> Self {
+        let status = match e {
+            Error::Validation(_) => 400,
+            Error::NotFound(_) => 404,
+            Error::Forbidden(_) => 403,
+            Error::Database(_) | Error::Internal(_) => 500,
+        };
+        Response::error(&e.to_string(), status)
+    }
+}
+
+/// Handle an API request, in the namespace it asks for.
+pub async fn handle<D: Database>(service: &VetaService<D>, req: &Request) -> Response {
+    let result = match req.namespace {
+        Some(ref ns) if ns != service.namespace() => match service.in_namespace(ns) {
+            Ok(service) => route(&service, req).await,
+            Err(e) => Err(e.into()),
+        },
+        _ => route(service, req).await,
+    };
+    match result {
+        Ok(response) | Err(response) => response,
+    }
+}
+
+async fn route<D: Database>(service: &VetaService<D>, req: &Request) -> Result<Response, Response> {
+    let segments: Vec<&str> = req
+        .path
+        .trim_matches('/')
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+    // Unparseable IDs are treated like IDs that don't exist
+    let id = |s: &str| s.parse::<i64>().unwrap_or(0);
+
+    match (&req.method, segments.as_slice()) {
+        // Health check
+        (&Method::GET, []) => Ok(Response::text("Veta API")),
+        (&Method::POST, ["notes"]) => create_note(service, req).await,
+        (&Method::GET, ["notes"]) => list_notes(service, req).await,
+        (&Method::GET, ["notes", "count"]) => count_notes(service, req).await,
+        (&Method::GET, ["notes", note_id]) => match service.get_note(id(note_id)).await? {
+            Some(note) => Ok(Response::json(&note, 200)),
+            None => Ok(Response::not_found()),
+        },
+        (&Method::PATCH, ["notes", note_id]) => update_note(service, req, id(note_id)).await,
+        // Move note to the trash
+        (&Method::DELETE, ["notes", note_id]) => {
+            found_or_404(service.delete_note(id(note_id)).await?)
+        }
+        (&Method::POST, ["notes", note_id, "restore"]) => {
+            found_or_404(service.restore_note(id(note_id)).await?)
+        }
+        (&Method::GET, ["trash"]) => Ok(Response::json(&service.list_trash().await?, 200)),
+        // Permanently delete a note in the trash
+        (&Method::DELETE, ["trash", note_id]) => {
+            found_or_404(service.purge_note(id(note_id)).await?)
+        }
+        (&Method::POST, ["undo"]) => {
+            let undone = service.undo().await?;
+            Ok(Response::json(&UndoResponse { undone }, 200))
+        }
+        (&Method::GET, ["notes", note_id, "revisions"]) => {
+            let note_id = id(note_id);
+            if service.get_note(note_id).await?.is_none() {
+                return Ok(Response::not_found());
+            }
+            Ok(Response::json(&service.list_revisions(note_id).await?, 200))
+        }
+        (&Method::GET, ["notes", note_id, "revisions", rev]) => {
+            match service.get_revision(id(note_id), id(rev)).await? {
+                Some(revision) => Ok(Response::json(&revision, 200)),
+                None => Ok(Response::not_found()),
+            }
+        }
+        (&Method::POST, ["notes", note_id, "revisions", rev, "restore"]) => {
+            service.restore_revision(id(note_id), id(rev)).await?;
+            Ok(Response::ok())
+        }
+        // Diff a revision (latest by default, or ?rev=N) against the note
+        (&Method::GET, ["notes", note_id, "diff"]) => {
+            let diff = service
+                .diff_revision(id(note_id), req.param_i64("rev"))
+                .await?;
+            Ok(Response::json(&DiffResponse { diff }, 200))
+        }
+        (&Method::GET, ["notes", note_id, "links"]) => {
+            match service.list_links(id(note_id)).await? {
+                Some(links) => Ok(Response::json(&links, 200)),
+                None => Ok(Response::not_found()),
+            }
+        }
+        (&Method::POST, ["notes", note_id, "links"]) => {
+            let body: LinkRequest = req.json()?;
+            let added = service.link_notes(id(note_id), body.to, body.kind).await?;
+            let status = if added { 201 } else { 200 };
+            Ok(Response::json(&LinkResponse { added }, status))
+        }
+        // Remove the links to another note, or only those of ?kind=K
+        (&Method::DELETE, ["notes", note_id, "links", to]) => {
+            let kind = req.param("kind").map(str::parse).transpose()?;
+            let kinds = service.unlink_notes(id(note_id), id(to), kind).await?;
+            Ok(Response::json(&UnlinkResponse { kinds }, 200))
+        }
+        (&Method::GET, ["tags"]) => Ok(Response::json(&service.list_tags().await?, 200)),
+        (&Method::GET, ["tags", "aliases"]) => {
+            Ok(Response::json(&service.list_tag_aliases().await?, 200))
+        }
+        (&Method::PUT, ["tags", "aliases", alias]) => {
+            let body: TagAliasRequest = req.json()?;
+            let ids = service
+                .add_tag_alias(&decode_path_segment(alias), &body.tag)
+                .await?;
+            Ok(Response::json(&TagChangeResponse { ids }, 200))
+        }
+        (&Method::DELETE, ["tags", "aliases", alias]) => {
+            service
+                .remove_tag_alias(&decode_path_segment(alias))
+                .await?;
+            Ok(Response::ok())
+        }
+        (&Method::PATCH, ["tags", name]) => {
+            update_tag(service, req, &decode_path_segment(name)).await
+        }
+        (&Method::POST, ["tags", name, "merge"]) => {
+            let body: MergeTagsRequest = req.json()?;
+            let ids = service
+                .merge_tags(&body.tags, &decode_path_segment(name))
+                .await?;
+            Ok(Response::json(&TagChangeResponse { ids }, 200))
+        }
+        // Untag notes without deleting them
+        (&Method::DELETE, ["tags", name]) => {
+            let ids = service.remove_tag(&decode_path_segment(name)).await?;
+            Ok(Response::json(&TagChangeResponse { ids }, 200))
+        }
+        (&Method::GET, ["export"]) => {
+            let archive = write_archive(&service.export().await?);
+            Ok(Response {
+                status: 200,
+                content_type: ARCHIVE_CONTENT_TYPE,
+                body: archive,
+            })
+        }
+        (&Method::POST, ["import"]) => import(service, req).await,
+        (&Method::GET, ["spaces"]) => Ok(Response::json(&service.list_namespaces().await?, 200)),
+        (&Method::GET, ["grep"]) => grep(service, req).await,
+        // MCP over streamable HTTP. Each POST holds one JSON-RPC message and
+        // gets a JSON response; there's no session or server-sent stream.
+        (&Method::POST, ["mcp"]) => {
+            let message = String::from_utf8_lossy(&req.body);
+            match mcp::handle_message(service, &message).await {
+                Some(response) => Ok(Response {
+                    status: 200,
+                    content_type: "application/json",
+                    body: response,
+                }),
+                None => Ok(Response::accepted()),
+            }
+        }
+        (&Method::GET | &Method::DELETE, ["mcp"]) => Ok(Response::error("Method not allowed", 405)),
+        (&Method::GET, ["keys"]) => Ok(Response::json(&service.list_api_keys().await?, 200)),
+        (&Method::POST, ["keys"]) => create_key(service, req).await,
+        (&Method::DELETE, ["keys", key_id]) => {
+            found_or_404(service.revoke_api_key(id(key_id)).await?)
+        }
+        (&Method::GET, ["search"]) => {
+            let query = req.param("q").unwrap_or_default();
+            let results = service
+                .search(query, req.param_tags()?, req.param_i64("limit"))
+                .await?;
+            Ok(Response::json(&results, 200))
+        }
+        _ => Ok(Response::not_found()),
+    }
+}
+
+fn found_or_404(found: bool) -> Result<Response, Response> {
+    if found {
+        Ok(Response::ok())
+    } else {
+        Ok(Response::not_found())
+    }
+}
+
+async fn create_note<D: Database>(
+    service: &VetaService<D>,
+    req: &Request,
+) -> Result<Response, Response> {
+    let body: CreateNoteRequest = req.json()?;
+    let id = service
+        .add_note(body.title, body.body, body.tags, body.references)
+        .await?;
+    Ok(Response::json(&IdResponse { id }, 201))
+}
+
+/// Import an archive, with `?mode=keep` (default), `renumber` or `merge`.
+async fn import<D: Database>(
+    service: &VetaService<D>,
+    req: &Request,
+) -> Result<Response, Response> {
+    let mode = match req.param("mode") {
+        Some(mode) => mode.parse::<ImportMode>()?,
+        None => ImportMode::default(),
+    };
+    let notes = read_archive(&String::from_utf8_lossy(&req.body))?;
+    let summary = service.import(notes, mode).await?;
+    Ok(Response::json(&summary, 200))
+}
+
+/// Store a new API key. Unless the client sent the hash of a key it
+/// generated, generate one and return it; it can't be retrieved later.
+async fn create_key<D: Database>(
+    service: &VetaService<D>,
+    req: &Request,
+) -> Result<Response, Response> {
+    let body: auth::CreateKeyRequest = req.json()?;
+    let (key, key_hash) = match body.key_hash {
+        Some(key_hash) => (None, key_hash),
+        None => {
+            let key = auth::generate_key()?;
+            let key_hash = veta_core::auth::hash_key(&key);
+            (Some(key), key_hash)
+        }
+    };
+    let api_key = service
+        .add_api_key(body.name, body.scope, body.tags, body.namespaces, key_hash)
+        .await?;
+    Ok(Response::json(
+        &auth::CreateKeyResponse { api_key, key },
+        201,
+    ))
+}
+
+async fn list_notes<D: Database>(
+    service: &VetaService<D>,
+    req: &Request,
+) -> Result<Response, Response> {
+    // `q` is a structured query, e.g. `title:jwt tag:auth updated:>2026-01-01`
+    let ParsedQuery { query, pattern } = match req.param("q") {
+        Some(q) => parse_query(q)?,
+        None => ParsedQuery::default(),
+    };
+    let query = req.param_filters(query)?;
+
+    let page = match pattern {
+        Some(pattern) => service.grep(&pattern, query, false).await?,
+        None => service.list_notes(query).await?,
+    };
+    Ok(Response::json(&page, 200))
+}
+
+/// Count the notes `GET /notes` would list, without sending them.
+async fn count_notes<D: Database>(
+    service: &VetaService<D>,
+    req: &Request,
+) -> Result<Response, Response> {
+    let ParsedQuery { query, pattern } = match req.param("q") {
+        Some(q) => parse_query(q)?,
+        None => ParsedQuery::default(),
+    };
+    if pattern.is_some() {
+        return Err(Error::Validation("can't count notes matching a pattern".into()).into());
+    }
+    let count = service.count_notes(req.param_filters(query)?).await?;
+    Ok(Response::json(&CountResponse { count }, 200))
+}
+
+async fn update_note<D: Database>(
+    service: &VetaService<D>,
+    req: &Request,
+    id: i64,
+) -> Result<Response, Response> {
+    let body: UpdateNoteRequest = req.json()?;
+    let update = UpdateNote {
+        title: body.title,
+        body: body.body,
+        tags: body.tags,
+        references: body.references,
+    };
+    found_or_404(service.update_note(id, update).await?)
+}
+
+/// Rename a tag and/or set its description, which then belongs to the new
+/// name.
+async fn update_tag<D: Database>(
+    service: &VetaService<D>,
+    req: &Request,
+    name: &str,
+) -> Result<Response, Response> {
+    let body: UpdateTagRequest = req.json()?;
+    if body.name.is_none() && body.description.is_none() {
+        return Err(
+            Error::Validation("nothing to update: send a name or description".into()).into(),
+        );
+    }
+    let mut ids = Vec::new();
+    let mut name = name.to_string();
+    if let Some(new_name) = body.name {
+        ids = service.rename_tag(&name, &new_name).await?;
+        name = new_name;
+    }
+    if let Some(description) = body.description {
+        service.describe_tag(&name, &description).await?;
+    }
+    Ok(Response::json(&TagChangeResponse { ids }, 200))
+}
+
+async fn grep<D: Database>(service: &VetaService<D>, req: &Request) -> Result<Response, Response> {
+    let pattern = req.param("q").unwrap_or_default();
+    let query = req.param_filters(NoteQuery::default())?;
+    let case_sensitive = req.param_bool("case_sensitive");
+
+    let page = service.grep(pattern, query, case_sensitive).await?;
+    Ok(Response::json(&page, 200))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use veta_sqlite::SqliteDatabase;
+
+    fn service() -> VetaService<SqliteDatabase> {
+        VetaService::new(SqliteDatabase::open_in_memory().unwrap())
+    }
+
+    async fn call(
+        service: &VetaService<SqliteDatabase>,
+        method: Method,
+        path: &str,
+        body: &str,
+    ) -> (u16, serde_json::Value) {
+        let response = handle(service, &Request::new(method, path, body.into())).await;
+        assert_eq!(response.content_type, "application/json");
+        (
+            response.status,
+            serde_json::from_str(&response.body).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_request_query_decoding() {
+        let req = Request::new(Method::GET, "/notes?tags=a%2Bb,-c&q=title:jwt+auth", vec![]);
+        assert_eq!(req.path, "/notes");
+        assert_eq!(req.param("tags"), Some("a+b,-c"));
+        assert_eq!(req.param("q"), Some("title:jwt auth"));
+        assert_eq!(req.param("limit"), None);
+    }
+
+    #[test]
+    fn test_request_namespace() {
+        let req = Request::new(Method::GET, "/spaces/team/notes/1?limit=5", vec![]);
+        assert_eq!(req.namespace.as_deref(), Some("team"));
+        assert_eq!(
+            (req.path.as_str(), req.param("limit")),
+            ("/notes/1", Some("5"))
+        );
+
+        let req = Request::new(Method::GET, "/notes", vec![]).with_space_header(Some("team"));
+        assert_eq!(req.namespace.as_deref(), Some("team"));
+        let req = Request::new(Method::GET, "/spaces/a/notes", vec![]).with_space_header(Some("b"));
+        assert_eq!(req.namespace.as_deref(), Some("a"));
+
+        let req = Request::new(Method::GET, "/spaces", vec![]);
+        assert_eq!((req.path.as_str(), req.namespace), ("/spaces", None));
+    }
+
+    #[tokio::test]
+    async fn test_namespaces() {
+        let service = service();
+        let note = r#"{"title": "Deploy", "body": "", "tags": ["ops"]}"#;
+        let (_, body) = call(&service, Method::POST, "/spaces/team/notes", note).await;
+        let path = format!("/spaces/team/notes/{}", body["id"]);
+
+        let (status, _) = call(&service, Method::GET, &path, "").await;
+        assert_eq!(status, 200);
+        let (status, _) = call(&service, Method::GET, &path["/spaces/team".len()..], "").await;
+        assert_eq!(status, 404);
+        let (_, body) = call(&service, Method::GET, "/tags", "").await;
+        assert_eq!(body, serde_json::json!([]));
+
+        let (_, body) = call(&service, Method::GET, "/spaces", "").await;
+        assert_eq!(body, serde_json::json!([{"name": "team", "count": 1}]));
+
+        let (status, body) = call(&service, Method::GET, "/spaces/Team/notes", "").await;
+        assert_eq!(status, 400);
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .contains("invalid namespace"));
+    }
+
+    #[tokio::test]
+    async fn test_export_and_import() {
+        let source = service();
+        let note = r#"{"title": "Deploy", "body": "Uses wrangler", "tags": ["ops"]}"#;
+        call(&source, Method::POST, "/notes", note).await;
+        let response = handle(&source, &Request::new(Method::GET, "/export", vec![])).await;
+        assert_eq!(response.content_type, ARCHIVE_CONTENT_TYPE);
+        assert_eq!(response.body.lines().count(), 2);
+
+        let target = service();
+        let (status, body) = call(&target, Method::POST, "/import", &response.body).await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            serde_json::json!({"added": 1, "updated": 0, "skipped": 0})
+        );
+        let (_, body) = call(&target, Method::GET, "/notes/1", "").await;
+        assert_eq!(body["title"], "Deploy");
+
+        let (status, _) = call(&target, Method::POST, "/import", &response.body).await;
+        assert_eq!(status, 400);
+        let path = "/import?mode=renumber";
+        let (_, body) = call(&target, Method::POST, path, &response.body).await;
+        assert_eq!(body["added"], 1);
+        let (status, body) = call(&target, Method::POST, "/import?mode=replace", "").await;
+        assert_eq!(status, 400);
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .contains("invalid import mode"));
+    }
+
+    #[tokio::test]
+    async fn test_notes_round_trip() {
+        let service = service();
+
+        let (status, body) = call(
+            &service,
+            Method::POST,
+            "/notes",
+            r#"{"title": "Deploy", "body": "Uses wrangler", "tags": ["ops"]}"#,
+        )
+        .await;
+        assert_eq!(status, 201);
+        assert_eq!(body["id"], 1);
+
+        let (status, body) = call(&service, Method::GET, "/notes?tags=ops", "").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["notes"][0]["title"], "Deploy");
+        assert_eq!(body["next_cursor"], serde_json::Value::Null);
+
+        // Counting takes the same filters, without sending the notes
+        let (_, body) = call(&service, Method::GET, "/notes/count?tags=ops", "").await;
+        assert_eq!(body, serde_json::json!({"count": 1}));
+        let (_, body) = call(&service, Method::GET, "/notes/count?q=tag:api", "").await;
+        assert_eq!(body, serde_json::json!({"count": 0}));
+        let (status, _) = call(&service, Method::GET, "/notes/count?q=re:wrangler", "").await;
+        assert_eq!(status, 400);
+
+        let (status, body) = call(
+            &service,
+            Method::PATCH,
+            "/notes/1",
+            r#"{"body": "Uses terraform"}"#,
+        )
+        .await;
+        assert_eq!((status, body["ok"].clone()), (200, true.into()));
+
+        let (status, body) = call(&service, Method::GET, "/notes/1", "").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["body"], "Uses terraform");
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let service = service();
+
+        let (status, body) = call(&service, Method::GET, "/notes/1", "").await;
+        assert_eq!((status, body["error"].clone()), (404, "Not found".into()));
+
+        let (status, _) = call(&service, Method::GET, "/notes?tags=api,-", "").await;
+        assert_eq!(status, 400);
+
+        let (status, body) = call(&service, Method::POST, "/notes", "{").await;
+        assert_eq!(status, 400);
+        assert!(body["error"].as_str().unwrap().starts_with("Invalid JSON"));
+
+        let (status, _) = call(&service, Method::GET, "/notes/1/revisions/1/diff", "").await;
+        assert_eq!(status, 404);
+
+        let (status, body) = call(&service, Method::POST, "/notes/1/revisions/2/restore", "").await;
+        assert_eq!(status, 404);
+        assert_eq!(body["error"], "not found: revision 2 of note 1");
+    }
+
+    #[tokio::test]
+    async fn test_tag_routes() {
+        let service = service();
+        for tags in [r#"["debugging"]"#, r#"["debug", "rust"]"#] {
+            let note = format!(r#"{{"title": "Bug", "body": "", "tags": {}}}"#, tags);
+            call(&service, Method::POST, "/notes", &note).await;
+        }
+
+        let (status, body) = call(
+            &service,
+            Method::PATCH,
+            "/tags/debugging",
+            r#"{"name": "debug"}"#,
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert!(body["error"].as_str().unwrap().contains("merge"));
+
+        let body = r#"{"tags": ["debugging"]}"#;
+        let (status, body) = call(&service, Method::POST, "/tags/debug/merge", body).await;
+        assert_eq!((status, body), (200, serde_json::json!({"ids": [1]})));
+
+        let body = r#"{"name": "Rust lang", "description": "The language"}"#;
+        let (_, body) = call(&service, Method::PATCH, "/tags/rust", body).await;
+        assert_eq!(body, serde_json::json!({"ids": [2]}));
+        let (_, body) = call(&service, Method::GET, "/tags", "").await;
+        assert_eq!(body[1]["name"], "rust lang");
+        assert_eq!(body[1]["description"], "The language");
+        assert!(body[0].get("description").is_none());
+
+        let (_, body) = call(&service, Method::DELETE, "/tags/rust%20lang", "").await;
+        assert_eq!(body, serde_json::json!({"ids": [2]}));
+        let (status, body) = call(&service, Method::DELETE, "/tags/rust%20lang", "").await;
+        assert_eq!(
+            (status, body["error"].clone()),
+            (404, "not found: tag 'rust lang'".into())
+        );
+        let (status, _) = call(&service, Method::PATCH, "/tags/debug", "{}").await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_link_routes() {
+        let service = service();
+        for title in ["New", "Old"] {
+            let note = format!(r#"{{"title": "{}", "body": "", "tags": []}}"#, title);
+            call(&service, Method::POST, "/notes", &note).await;
+        }
+
+        let body = r#"{"to": 2, "kind": "supersedes"}"#;
+        let (status, body) = call(&service, Method::POST, "/notes/1/links", body).await;
+        assert_eq!((status, body), (201, serde_json::json!({"added": true})));
+        let body = r#"{"to": 2, "kind": "supersedes"}"#;
+        let (status, _) = call(&service, Method::POST, "/notes/1/links", body).await;
+        assert_eq!(status, 200);
+        let body = r#"{"to": 3, "kind": "supersedes"}"#;
+        let (status, _) = call(&service, Method::POST, "/notes/1/links", body).await;
+        assert_eq!(status, 404);
+        let body = r#"{"to": 2, "kind": "replaces"}"#;
+        let (status, _) = call(&service, Method::POST, "/notes/1/links", body).await;
+        assert_eq!(status, 400);
+
+        let (_, body) = call(&service, Method::GET, "/notes/2/links", "").await;
+        assert_eq!(
+            body,
+            serde_json::json!([{"from": 1, "to": 2, "kind": "supersedes"}])
+        );
+        let (status, _) = call(&service, Method::GET, "/notes/3/links", "").await;
+        assert_eq!(status, 404);
+
+        let path = "/notes/1/links/2?kind=relates-to";
+        let (status, _) = call(&service, Method::DELETE, path, "").await;
+        assert_eq!(status, 404);
+        let (status, body) = call(&service, Method::DELETE, "/notes/1/links/2", "").await;
+        assert_eq!(
+            (status, body),
+            (200, serde_json::json!({"kinds": ["supersedes"]}))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tag_alias_routes() {
+        let service = service();
+        let note = r#"{"title": "Bug", "body": "", "tags": ["debug", "rust/async"]}"#;
+        call(&service, Method::POST, "/notes", note).await;
+
+        let body = r#"{"tag": "debugging"}"#;
+        let (status, body) = call(&service, Method::PUT, "/tags/aliases/debug", body).await;
+        assert_eq!((status, body), (200, serde_json::json!({"ids": [1]})));
+        let body = r#"{"tag": "lang/rust"}"#;
+        call(&service, Method::PUT, "/tags/aliases/rust", body).await;
+        let (_, body) = call(&service, Method::GET, "/tags/aliases", "").await;
+        assert_eq!(
+            body,
+            serde_json::json!([
+                {"alias": "debug", "tag": "debugging"},
+                {"alias": "rust", "tag": "lang/rust"}
+            ])
+        );
+
+        let (_, body) = call(&service, Method::GET, "/notes/1", "").await;
+        assert_eq!(
+            body["tags"],
+            serde_json::json!(["debugging", "lang/rust/async"])
+        );
+        let (_, body) = call(&service, Method::GET, "/notes?tags=lang", "").await;
+        assert_eq!(body["notes"].as_array().unwrap().len(), 1);
+        let (_, body) = call(&service, Method::GET, "/notes?tags=debug", "").await;
+        assert_eq!(body["notes"].as_array().unwrap().len(), 1);
+
+        let (status, _) = call(&service, Method::DELETE, "/tags/aliases/debug", "").await;
+        assert_eq!(status, 200);
+        let (status, _) = call(&service, Method::DELETE, "/tags/aliases/debug", "").await;
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn test_path_segments() {
+        let encoded = encode_path_segment("c++ & rust/é");
+        assert_eq!(encoded, "c%2B%2B%20%26%20rust%2F%C3%A9");
+        assert_eq!(decode_path_segment(&encoded), "c++ & rust/é");
+        assert_eq!(decode_path_segment("100%"), "100%");
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let response = handle(&service(), &Request::new(Method::GET, "/", vec![])).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, "Veta API");
+    }
+
+    #[tokio::test]
+    async fn test_mcp_endpoint() {
+        let service = service();
+        let (status, body) = call(
+            &service,
+            Method::POST,
+            "/mcp",
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "tools/call",
+                "params": {"name": "add_note", "arguments": {"title": "Deploy", "body": "", "tags": [" OPS "]}}}"#,
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body["result"]["isError"], false);
+
+        // Tools go through the same service as the REST routes
+        let (_, body) = call(&service, Method::GET, "/notes/1", "").await;
+        assert_eq!(body["tags"], serde_json::json!(["ops"]));
+
+        let notification = r#"{"jsonrpc": "2.0", "method": "notifications/initialized"}"#;
+        let request = Request::new(Method::POST, "/mcp", notification.into());
+        let response = handle(&service, &request).await;
+        assert_eq!((response.status, response.body.as_str()), (202, ""));
+
+        let (status, _) = call(&service, Method::GET, "/mcp", "").await;
+        assert_eq!(status, 405);
+    }
+}
@@ -0,0 +1,683 @@
+//! Model Context Protocol (MCP) server for the Veta knowledge base.
+//!
+//! Exposes [`VetaService`] methods as MCP tools and notes as resources
+//! (`veta://note/<id>`). [`handle_message`] answers one JSON-RPC message and
+//! does no I/O itself: `veta mcp` reads messages from stdin and writes the
+//! responses to stdout.
+
+use schemars::gen::SchemaSettings;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use veta_core::{
+    parse_query, Cursor, Database, Error, Note, NotePage, NoteQuery, SearchResult, TagExpr,
+    UpdateNote, VetaService,
+};
+
+use crate::{CreateNoteRequest, IdResponse, NoteFilters, UpdateNoteRequest};
+
+/// The latest protocol version this server implements.
+pub const PROTOCOL_VERSION: &str = "2025-06-18";
+
+/// Protocol versions a client can ask for in `initialize`.
+const SUPPORTED_VERSIONS: &[&str] = &["2024-11-05", "2025-03-26", PROTOCOL_VERSION];
+
+const NOTE_URI_PREFIX: &str = "veta://note/";
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+const RESOURCE_NOT_FOUND: i64 = -32002;
+
+/// A JSON-RPC error.
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(e: Error) -> Self {
+        let code = match e {
+            Error::Validation(_) => INVALID_PARAMS,
+            Error::NotFound(_) => RESOURCE_NOT_FOUND,
+            Error::Forbidden(_) => INVALID_REQUEST,
+            Error::Database(_) | Error::Internal(_) => INTERNAL_ERROR,
+        };
+        RpcError::new(code, e.to_string())
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct NoteIdArgs {
+    /// Note ID
+    id: i64,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ListNotesArgs {
+    /// Structured query, e.g. `title:jwt tag:auth updated:>2026-01-01 "token expiry"`
+    q: Option<String>,
+    #[serde(flatten)]
+    filters: NoteFilters,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct GrepArgs {
+    /// Regular expression matched against titles, bodies and tags
+    pattern: String,
+    /// Match case (default false)
+    #[serde(default)]
+    case_sensitive: bool,
+    #[serde(flatten)]
+    filters: NoteFilters,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct SearchArgs {
+    /// Search terms
+    query: String,
+    /// Tag expression: `a,b` matches notes tagged a or b, `a+b` requires both, `-c` excludes c
+    tags: Option<String>,
+    /// Maximum number of results (default 20, 0 for all)
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct UpdateNoteArgs {
+    /// Note ID
+    id: i64,
+    #[serde(flatten)]
+    update: UpdateNoteRequest,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct NoArgs {}
+
+#[derive(Serialize)]
+struct Tool {
+    name: &'static str,
+    description: &'static str,
+    #[serde(rename = "inputSchema")]
+    input_schema: Value,
+}
+
+/// JSON schema of a tool's arguments.
+fn schema<T: JsonSchema>() -> Value {
+    let settings = SchemaSettings::draft07().with(|s| {
+        s.inline_subschemas = true;
+        s.option_add_null_type = false;
+    });
+    let mut schema = serde_json::to_value(settings.into_generator().into_root_schema_for::<T>())
+        .expect("schemas serialize to JSON");
+    if let Some(object) = schema.as_object_mut() {
+        object.remove("$schema");
+        object.remove("title");
+    }
+    schema
+}
+
+fn tools() -> Vec<Tool> {
+    vec![
+        Tool {
+            name: "add_note",
+            description: "Add a note to the knowledge base. Returns the new note's ID.",
+            input_schema: schema::<CreateNoteRequest>(),
+        },
+        Tool {
+            name: "get_note",
+            description: "Get a note with its full body, tags and references.",
+            input_schema: schema::<NoteIdArgs>(),
+        },
+        Tool {
+            name: "list_notes",
+            description: "List notes, most recently updated first, with body previews. \
+                          Pass `next_cursor` as `after` to get the next page.",
+            input_schema: schema::<ListNotesArgs>(),
+        },
+        Tool {
+            name: "grep",
+            description: "Find notes whose title, body or tags match a regular expression.",
+            input_schema: schema::<GrepArgs>(),
+        },
+        Tool {
+            name: "search",
+            description: "Full-text search, with the most relevant notes first.",
+            input_schema: schema::<SearchArgs>(),
+        },
+        Tool {
+            name: "list_tags",
+            description: "List all tags with the number of notes in each.",
+            input_schema: schema::<NoArgs>(),
+        },
+        Tool {
+            name: "update_note",
+            description: "Update a note. Only the given fields are changed.",
+            input_schema: schema::<UpdateNoteArgs>(),
+        },
+        Tool {
+            name: "delete_note",
+            description: "Move a note to the trash.",
+            input_schema: schema::<NoteIdArgs>(),
+        },
+        Tool {
+            name: "restore_note",
+            description: "Restore a note from the trash.",
+            input_schema: schema::<NoteIdArgs>(),
+        },
+    ]
+}
+
+fn parse_args<T: DeserializeOwned>(name: &str, arguments: Value) -> Result<T, RpcError> {
+    serde_json::from_value(arguments).map_err(|e| {
+        RpcError::new(
+            INVALID_PARAMS,
+            format!("Invalid arguments for {}: {}", name, e),
+        )
+    })
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string_pretty(value).expect("results serialize to JSON")
+}
+
+/// Run a tool. Unknown tools and invalid arguments are protocol errors;
+/// errors from the service are returned to the model as the tool's result.
+async fn call_tool<D: Database>(
+    service: &VetaService<D>,
+    name: &str,
+    arguments: Value,
+) -> Result<Result<String, Error>, RpcError> {
+    Ok(match name {
+        "add_note" => {
+            let args: CreateNoteRequest = parse_args(name, arguments)?;
+            service
+                .add_note(args.title, args.body, args.tags, args.references)
+                .await
+                .map(|id| to_json(&IdResponse { id }))
+        }
+        "get_note" => {
+            let args: NoteIdArgs = parse_args(name, arguments)?;
+            service.get_note(args.id).await.and_then(|note| {
+                note.map(|note| to_json(&note))
+                    .ok_or_else(|| not_found(args.id))
+            })
+        }
+        "list_notes" => {
+            let args: ListNotesArgs = parse_args(name, arguments)?;
+            list_notes(service, args).await.map(|page| to_json(&page))
+        }
+        "grep" => {
+            let args: GrepArgs = parse_args(name, arguments)?;
+            grep(service, args).await.map(|page| to_json(&page))
+        }
+        "search" => {
+            let args: SearchArgs = parse_args(name, arguments)?;
+            search(service, args).await.map(|results| to_json(&results))
+        }
+        "list_tags" => {
+            let _: NoArgs = parse_args(name, arguments)?;
+            service.list_tags().await.map(|tags| to_json(&tags))
+        }
+        "update_note" => {
+            let args: UpdateNoteArgs = parse_args(name, arguments)?;
+            let update = UpdateNote {
+                title: args.update.title,
+                body: args.update.body,
+                tags: args.update.tags,
+                references: args.update.references,
+            };
+            match service.update_note(args.id, update).await {
+                Ok(true) => Ok(format!("Updated note {}", args.id)),
+                Ok(false) => Err(not_found(args.id)),
+                Err(e) => Err(e),
+            }
+        }
+        "delete_note" => {
+            let args: NoteIdArgs = parse_args(name, arguments)?;
+            match service.delete_note(args.id).await {
+                Ok(true) => Ok(format!("Moved note {} to the trash", args.id)),
+                Ok(false) => Err(not_found(args.id)),
+                Err(e) => Err(e),
+            }
+        }
+        "restore_note" => {
+            let args: NoteIdArgs = parse_args(name, arguments)?;
+            match service.restore_note(args.id).await {
+                Ok(true) => Ok(format!("Restored note {}", args.id)),
+                Ok(false) => Err(Error::NotFound(format!("note {} in the trash", args.id))),
+                Err(e) => Err(e),
+            }
+        }
+        _ => {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("Unknown tool: {}", name),
+            ))
+        }
+    })
+}
+
+fn not_found(id: i64) -> Error {
+    Error::NotFound(format!("note {}", id))
+}
+
+async fn list_notes<D: Database>(
+    service: &VetaService<D>,
+    args: ListNotesArgs,
+) -> Result<NotePage, Error> {
+    let parsed = match args.q {
+        Some(q) => parse_query(&q)?,
+        None => Default::default(),
+    };
+    let query = args.filters.apply(parsed.query)?;
+    match parsed.pattern {
+        Some(pattern) => service.grep(&pattern, query, false).await,
+        None => service.list_notes(query).await,
+    }
+}
+
+async fn grep<D: Database>(service: &VetaService<D>, args: GrepArgs) -> Result<NotePage, Error> {
+    let query = args.filters.apply(NoteQuery::default())?;
+    service
+        .grep(&args.pattern, query, args.case_sensitive)
+        .await
+}
+
+async fn search<D: Database>(
+    service: &VetaService<D>,
+    args: SearchArgs,
+) -> Result<Vec<SearchResult>, Error> {
+    let tags = args
+        .tags
+        .as_deref()
+        .map(TagExpr::parse)
+        .transpose()?
+        .flatten();
+    service.search(&args.query, tags, args.limit).await
+}
+
+fn note_uri(id: i64) -> String {
+    format!("{}{}", NOTE_URI_PREFIX, id)
+}
+
+/// A note as markdown, laid out like `veta show`.
+fn note_markdown(note: &Note) -> String {
+    let mut text = format!(
+        "# {}\n\n{}\n\n---\n\nCreated: {}\nLast modified: {}\nTags: {}\n",
+        note.title,
+        note.body,
+        note.created_at,
+        note.updated_at,
+        note.tags.join(",")
+    );
+    if !note.references.is_empty() {
+        text.push_str("References:\n");
+        for reference in &note.references {
+            text.push_str(&format!("  - {}\n", reference));
+        }
+    }
+    text
+}
+
+async fn list_resources<D: Database>(
+    service: &VetaService<D>,
+    params: &Value,
+) -> Result<Value, RpcError> {
+    let after = match params.get("cursor").and_then(Value::as_str) {
+        Some(cursor) => Some(Cursor::parse(cursor)?),
+        None => None,
+    };
+    let page = service
+        .list_notes(NoteQuery {
+            after,
+            ..Default::default()
+        })
+        .await?;
+    let resources: Vec<Value> = page
+        .notes
+        .iter()
+        .map(|note| {
+            json!({
+                "uri": note_uri(note.id),
+                "name": note.title,
+                "description": note.body_preview,
+                "mimeType": "text/markdown",
+            })
+        })
+        .collect();
+    let mut result = json!({ "resources": resources });
+    if let Some(cursor) = page.next_cursor {
+        result["nextCursor"] = json!(cursor);
+    }
+    Ok(result)
+}
+
+async fn read_resource<D: Database>(
+    service: &VetaService<D>,
+    params: &Value,
+) -> Result<Value, RpcError> {
+    let uri = params
+        .get("uri")
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing uri"))?;
+    let not_found = || RpcError::new(RESOURCE_NOT_FOUND, format!("Resource not found: {}", uri));
+    let id = uri
+        .strip_prefix(NOTE_URI_PREFIX)
+        .and_then(|id| id.parse::<i64>().ok())
+        .ok_or_else(not_found)?;
+    let note = service.get_note(id).await?.ok_or_else(not_found)?;
+    Ok(json!({
+        "contents": [{
+            "uri": uri,
+            "mimeType": "text/markdown",
+            "text": note_markdown(&note),
+        }]
+    }))
+}
+
+async fn dispatch<D: Database>(
+    service: &VetaService<D>,
+    method: &str,
+    params: Value,
+) -> Result<Value, RpcError> {
+    match method {
+        "initialize" => {
+            let requested = params.get("protocolVersion").and_then(Value::as_str);
+            let version = match requested {
+                Some(v) if SUPPORTED_VERSIONS.contains(&v) => v,
+                _ => PROTOCOL_VERSION,
+            };
+            Ok(json!({
+                "protocolVersion": version,
+                "capabilities": { "tools": {}, "resources": {} },
+                "serverInfo": { "name": "veta", "version": env!("CARGO_PKG_VERSION") },
+                "instructions": "Veta is a knowledge base of notes. Search it before starting \
+                                 work, and add notes about what you learn.",
+            }))
+        }
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tools() })),
+        "tools/call" => {
+            let name = params
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing tool name"))?;
+            let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+            let (text, is_error) = match call_tool(service, name, arguments).await? {
+                Ok(text) => (text, false),
+                Err(e) => (e.to_string(), true),
+            };
+            Ok(json!({
+                "content": [{ "type": "text", "text": text }],
+                "isError": is_error,
+            }))
+        }
+        "resources/list" => list_resources(service, &params).await,
+        "resources/templates/list" => Ok(json!({
+            "resourceTemplates": [{
+                "uriTemplate": format!("{}{{id}}", NOTE_URI_PREFIX),
+                "name": "note",
+                "description": "A note by ID",
+                "mimeType": "text/markdown",
+            }]
+        })),
+        "resources/read" => read_resource(service, &params).await,
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Method not found: {}", method),
+        )),
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+    .to_string()
+}
+
+/// Handle one JSON-RPC message and return the response to send back, if
+/// any. Notifications and responses from the client get no response.
+pub async fn handle_message<D: Database>(
+    service: &VetaService<D>,
+    message: &str,
+) -> Option<String> {
+    let message: Value = match serde_json::from_str(message) {
+        Ok(message) => message,
+        Err(e) => {
+            let error = RpcError::new(PARSE_ERROR, format!("Parse error: {}", e));
+            return Some(error_response(Value::Null, error));
+        }
+    };
+    let id = message.get("id").cloned();
+    let method = message.get("method").and_then(Value::as_str);
+    let (id, method) = match (id, method) {
+        (Some(id), Some(method)) => (id, method),
+        (None, Some(_)) => return None,
+        (_, None) if message.get("result").is_some() || message.get("error").is_some() => {
+            return None
+        }
+        (id, None) => {
+            let error = RpcError::new(INVALID_REQUEST, "Invalid request");
+            return Some(error_response(id.unwrap_or(Value::Null), error));
+        }
+    };
+
+    let params = message.get("params").cloned().unwrap_or(json!({}));
+    Some(match dispatch(service, method, params).await {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }).to_string(),
+        Err(error) => error_response(id, error),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use veta_sqlite::SqliteDatabase;
+
+    fn service() -> VetaService<SqliteDatabase> {
+        VetaService::new(SqliteDatabase::open_in_memory().unwrap())
+    }
+
+    async fn call(service: &VetaService<SqliteDatabase>, method: &str, params: Value) -> Value {
+        let message = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response = handle_message(service, &message.to_string()).await.unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    async fn call_tool(service: &VetaService<SqliteDatabase>, name: &str, args: Value) -> Value {
+        let response = call(
+            service,
+            "tools/call",
+            json!({ "name": name, "arguments": args }),
+        )
+        .await;
+        response["result"].clone()
+    }
+
+    #[tokio::test]
+    async fn test_initialize_and_list_tools() {
+        let service = service();
+        let response = call(
+            &service,
+            "initialize",
+            json!({ "protocolVersion": "2025-03-26", "capabilities": {} }),
+        )
+        .await;
+        assert_eq!(response["result"]["protocolVersion"], "2025-03-26");
+        assert_eq!(response["result"]["serverInfo"]["name"], "veta");
+
+        let notification = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+        assert!(handle_message(&service, &notification.to_string())
+            .await
+            .is_none());
+
+        let response = call(&service, "tools/list", json!({})).await;
+        let tools = response["result"]["tools"].as_array().unwrap();
+        let names: Vec<&str> = tools.iter().map(|t| t["name"].as_str().unwrap()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "add_note",
+                "get_note",
+                "list_notes",
+                "grep",
+                "search",
+                "list_tags",
+                "update_note",
+                "delete_note",
+                "restore_note"
+            ]
+        );
+        let add = &tools[0]["inputSchema"];
+        assert_eq!(add["type"], "object");
+        assert_eq!(add["required"], json!(["body", "tags", "title"]));
+        let update = &tools[6]["inputSchema"];
+        assert_eq!(update["required"], json!(["id"]));
+        assert_eq!(update["properties"]["tags"]["type"], "array");
+    }
+
+    #[tokio::test]
+    async fn test_tools() {
+        let service = service();
+        let result = call_tool(
+            &service,
+            "add_note",
+            json!({ "title": "Deploy", "body": "Uses wrangler", "tags": ["ops"] }),
+        )
+        .await;
+        assert_eq!(result["isError"], false);
+        let id: IdResponse =
+            serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap();
+
+        let result = call_tool(
+            &service,
+            "update_note",
+            json!({ "id": id.id, "body": "Uses terraform" }),
+        )
+        .await;
+        assert_eq!(result["content"][0]["text"], "Updated note 1");
+
+        let result = call_tool(
+            &service,
+            "list_notes",
+            json!({ "tags": "ops", "limit": 10 }),
+        )
+        .await;
+        let page: Value =
+            serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(page["notes"][0]["body_preview"], "Uses terraform");
+
+        let result = call_tool(
+            &service,
+            "grep",
+            json!({ "pattern": "TERRA", "case_sensitive": true }),
+        )
+        .await;
+        let page: Value =
+            serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(page["notes"], json!([]));
+
+        let result = call_tool(&service, "search", json!({ "query": "terraform" })).await;
+        let results: Value =
+            serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(results[0]["title"], "Deploy");
+
+        let result = call_tool(&service, "delete_note", json!({ "id": 1 })).await;
+        assert_eq!(result["isError"], false);
+        let result = call_tool(&service, "restore_note", json!({ "id": 1 })).await;
+        assert_eq!(result["content"][0]["text"], "Restored note 1");
+        call_tool(&service, "delete_note", json!({ "id": 1 })).await;
+
+        // Errors from the service are tool results, so the model can see them
+        let result = call_tool(&service, "get_note", json!({ "id": 1 })).await;
+        assert_eq!(result["isError"], true);
+        assert_eq!(result["content"][0]["text"], "not found: note 1");
+        let result = call_tool(
+            &service,
+            "add_note",
+            json!({ "title": " ", "body": "", "tags": [] }),
+        )
+        .await;
+        assert_eq!(
+            result["content"][0]["text"],
+            "validation error: title cannot be empty"
+        );
+
+        // Bad arguments and unknown tools are protocol errors
+        let response = call(
+            &service,
+            "tools/call",
+            json!({ "name": "get_note", "arguments": {} }),
+        )
+        .await;
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+        let response = call(&service, "tools/call", json!({ "name": "nope" })).await;
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[tokio::test]
+    async fn test_resources() {
+        let service = service();
+        service
+            .add_note(
+                "Deploy".into(),
+                "Uses wrangler".into(),
+                vec!["ops".into()],
+                vec!["wrangler.toml".into()],
+            )
+            .await
+            .unwrap();
+
+        let response = call(&service, "resources/list", json!({})).await;
+        let resources = &response["result"]["resources"];
+        assert_eq!(resources[0]["uri"], "veta://note/1");
+        assert_eq!(resources[0]["name"], "Deploy");
+        assert!(response["result"].get("nextCursor").is_none());
+
+        let response = call(
+            &service,
+            "resources/read",
+            json!({ "uri": "veta://note/1" }),
+        )
+        .await;
+        let text = response["result"]["contents"][0]["text"].as_str().unwrap();
+        assert!(text.starts_with("# Deploy\n\nUses wrangler\n"));
+        assert!(text.ends_with("Tags: ops\nReferences:\n  - wrangler.toml\n"));
+
+        let response = call(
+            &service,
+            "resources/read",
+            json!({ "uri": "veta://note/2" }),
+        )
+        .await;
+        assert_eq!(response["error"]["code"], RESOURCE_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_messages() {
+        let service = service();
+        let response: Value =
+            serde_json::from_str(&handle_message(&service, "{not json").await.unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+
+        let response = call(&service, "prompts/list", json!({})).await;
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(response["id"], 1);
+    }
+}
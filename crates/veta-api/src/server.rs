@@ -0,0 +1,76 @@
+//! Native HTTP server for the API, used by `veta serve`.
+
+use std::io;
+use std::net::SocketAddr;
+
+use veta_core::{Database, VetaService};
+
+use crate::auth::handle_with_auth;
+use crate::{Request, Response};
+
+/// An HTTP server that hands every request to [`handle_with_auth`].
+pub struct Server {
+    inner: tiny_http::Server,
+}
+
+impl Server {
+    /// Listen on `addr`, e.g. `127.0.0.1:8787`. Port 0 picks a free port.
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        let inner = tiny_http::Server::http(addr).map_err(io::Error::other)?;
+        Ok(Server { inner })
+    }
+
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.inner.server_addr().to_ip()
+    }
+
+    /// Serve requests until the process exits. `admin_token`, if set, is
+    /// accepted as an admin API key.
+    ///
+    /// Requests are handled one at a time, which keeps the single-threaded
+    /// runtime and the database's file locking simple.
+    pub async fn run<D: Database>(&self, service: &VetaService<D>, admin_token: Option<&str>) {
+        for mut request in self.inner.incoming_requests() {
+            let mut body = Vec::new();
+            if let Err(e) = request.as_reader().read_to_end(&mut body) {
+                respond(
+                    request,
+                    Response::error(&format!("Failed to read request: {}", e), 400),
+                );
+                continue;
+            }
+            let method = match http::Method::from_bytes(request.method().as_str().as_bytes()) {
+                Ok(method) => method,
+                Err(_) => {
+                    respond(request, Response::error("Invalid method", 405));
+                    continue;
+                }
+            };
+
+            let header = |name: &'static str| {
+                request
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv(name))
+                    .map(|h| h.value.as_str().to_string())
+            };
+            let api_request = Request::new(method, request.url(), body)
+                .with_authorization(header("Authorization").as_deref())
+                .with_space_header(header("X-Veta-Space").as_deref());
+            let response = handle_with_auth(service, &api_request, admin_token).await;
+            respond(request, response);
+        }
+    }
+}
+
+fn respond(request: tiny_http::Request, response: Response) {
+    let content_type = tiny_http::Header::from_bytes("Content-Type", response.content_type)
+        .expect("content type is a valid header");
+    let reply = tiny_http::Response::from_string(response.body)
+        .with_status_code(response.status)
+        .with_header(content_type);
+    if let Err(e) = request.respond(reply) {
+        eprintln!("Failed to send response: {}", e);
+    }
+}
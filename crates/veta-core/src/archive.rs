@@ -0,0 +1,181 @@
+//! Portable archives of notes, for moving a knowledge base between stores.
+//!
+//! An archive is JSON Lines: a header line, `{"format":"veta","version":1}`,
+//! then one note per line with its ID, title, body, tags, references and
+//! timestamps. Revisions, the trash and the undo journal aren't included.
+//!
+//! [`crate::VetaService::export`] and [`crate::VetaService::import`] read and
+//! write the notes; an [`ImportMode`] decides what happens to their IDs.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Note};
+
+/// Value of the header's `format` field.
+pub const ARCHIVE_FORMAT: &str = "veta";
+
+/// Version of the archive format written by [`write_archive`]. Bump it when
+/// a change would make older versions of veta misread an archive.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+}
+
+/// What to do with the IDs of imported notes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Keep each note's ID. Fails without importing anything if an ID is
+    /// already used.
+    #[default]
+    Keep,
+    /// Give every note a new ID.
+    Renumber,
+    /// Keep each note's ID. A note whose ID is already used replaces the
+    /// existing one if it was updated later, and is skipped otherwise.
+    Merge,
+}
+
+impl fmt::Display for ImportMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportMode::Keep => write!(f, "keep"),
+            ImportMode::Renumber => write!(f, "renumber"),
+            ImportMode::Merge => write!(f, "merge"),
+        }
+    }
+}
+
+impl FromStr for ImportMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "keep" => Ok(ImportMode::Keep),
+            "renumber" => Ok(ImportMode::Renumber),
+            "merge" => Ok(ImportMode::Merge),
+            _ => Err(Error::Validation(format!(
+                "invalid import mode '{}': expected keep, renumber or merge",
+                s
+            ))),
+        }
+    }
+}
+
+/// How many notes an import added, updated and skipped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub skipped: usize,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} updated, {} skipped",
+            self.added, self.updated, self.skipped
+        )
+    }
+}
+
+/// Write notes as an archive.
+pub fn write_archive(notes: &[Note]) -> String {
+    let header = Header {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+    };
+    let mut archive = serde_json::to_string(&header).expect("header serializes");
+    archive.push('\n');
+    for note in notes {
+        archive.push_str(&serde_json::to_string(note).expect("note serializes"));
+        archive.push('\n');
+    }
+    archive
+}
+
+/// Read the notes of an archive.
+pub fn read_archive(archive: &str) -> Result<Vec<Note>, Error> {
+    let mut lines = archive
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    let header = match lines.next() {
+        Some((_, line)) => serde_json::from_str::<Header>(line).ok(),
+        None => None,
+    }
+    .filter(|header| header.format == ARCHIVE_FORMAT)
+    .ok_or_else(|| Error::Validation("not a veta archive".into()))?;
+    if header.version > ARCHIVE_VERSION {
+        return Err(Error::Validation(format!(
+            "archive version {} is newer than this veta supports ({})",
+            header.version, ARCHIVE_VERSION
+        )));
+    }
+
+    lines
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                Error::Validation(format!("invalid note on line {} of archive: {}", i + 1, e))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: i64, title: &str) -> Note {
+        Note {
+            id,
+            title: title.to_string(),
+            body: "Line one\nline two".to_string(),
+            tags: vec!["ops".to_string()],
+            references: vec!["src/lib.rs".to_string()],
+            created_at: "2026-01-01 10:00:00".to_string(),
+            updated_at: "2026-01-02 10:00:00".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_archive_round_trip() {
+        let notes = vec![note(3, "Deploy"), note(7, "Rollback")];
+        let archive = write_archive(&notes);
+        assert_eq!(archive.lines().count(), 3);
+        assert!(archive.starts_with(r#"{"format":"veta","version":1}"#));
+
+        let read = read_archive(&archive).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!((read[1].id, read[1].title.as_str()), (7, "Rollback"));
+        assert_eq!(read[0].body, "Line one\nline two");
+        assert_eq!(read[0].created_at, "2026-01-01 10:00:00");
+    }
+
+    #[test]
+    fn test_read_invalid_archive() {
+        let err = read_archive("").unwrap_err();
+        assert_eq!(err.to_string(), "validation error: not a veta archive");
+        assert!(read_archive(r#"{"id": 1}"#).is_err());
+
+        let err = read_archive(r#"{"format":"veta","version":2}"#).unwrap_err();
+        assert!(err.to_string().contains("newer than this veta supports"));
+
+        let archive = "{\"format\":\"veta\",\"version\":1}\n{\"title\": \"No ID\"}\n";
+        let err = read_archive(archive).unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn test_import_mode() {
+        assert_eq!("merge".parse::<ImportMode>().unwrap(), ImportMode::Merge);
+        assert!("replace".parse::<ImportMode>().is_err());
+    }
+}
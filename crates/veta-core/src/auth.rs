@@ -0,0 +1,539 @@
+//! API keys and what they're allowed to do.
+//!
+//! Keys are stored as SHA-256 hashes, so the database never holds a working
+//! key. [`ScopedDatabase`] wraps a database and enforces a key's [`Scope`],
+//! tag allow-list and namespaces on every call, so everything built on
+//! [`crate::VetaService`] (the HTTP routes and MCP tools alike) is checked
+//! the same way.
+
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::tagexpr::tag_matches;
+use crate::{
+    CreateNote, Database, Error, ImportMode, ImportSummary, LinkKind, NamespaceCount, Note,
+    NoteLink, NoteQuery, Operation, Revision, SearchHit, TagAlias, TagCount, TagExpr, TrashedNote,
+    UpdateNote,
+};
+
+/// What an API key may do. Each scope includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Read notes, tags, revisions and the trash.
+    Read,
+    /// Also add, edit, delete and restore notes.
+    Write,
+    /// Also manage API keys.
+    Admin,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Read => write!(f, "read"),
+            Scope::Write => write!(f, "write"),
+            Scope::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "read" => Ok(Scope::Read),
+            "write" => Ok(Scope::Write),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(Error::Validation(format!(
+                "invalid scope '{}': expected read, write or admin",
+                s
+            ))),
+        }
+    }
+}
+
+/// A stored API key. The key itself is only known to whoever created it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub scope: Scope,
+    /// If not empty, the key can only see and change notes with one of
+    /// these tags.
+    pub tags: Vec<String>,
+    /// If not empty, the key can only be used in these namespaces.
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+    pub created_at: String,
+}
+
+/// Parameters for storing a new API key.
+#[derive(Debug, Clone)]
+pub struct CreateApiKey {
+    pub name: String,
+    /// SHA-256 of the key, from [`hash_key`].
+    pub key_hash: String,
+    pub scope: Scope,
+    pub tags: Vec<String>,
+    pub namespaces: Vec<String>,
+}
+
+/// Hash a key for storage and lookup, as lowercase hex.
+pub fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Whether `s` looks like a hash returned by [`hash_key`].
+pub fn is_key_hash(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// The database behind a [`ScopedDatabase`]: the one of the service it was
+/// made from, or its own handle to another namespace.
+enum Handle<'a, D> {
+    Borrowed(&'a D),
+    Owned(D),
+}
+
+impl<D> Deref for Handle<'_, D> {
+    type Target = D;
+
+    fn deref(&self) -> &D {
+        match self {
+            Handle::Borrowed(db) => db,
+            Handle::Owned(db) => db,
+        }
+    }
+}
+
+/// A database as seen through an API key.
+///
+/// Calls outside the key's scope fail with [`Error::Forbidden`]. With a tag
+/// allow-list, notes without an allowed tag are treated as if they didn't
+/// exist, and new or edited notes must keep an allowed tag. A key limited
+/// to some namespaces can't be used in, or moved to, any other.
+pub struct ScopedDatabase<'a, D> {
+    db: Handle<'a, D>,
+    scope: Scope,
+    tags: Vec<String>,
+    namespaces: Vec<String>,
+}
+
+impl<'a, D: Database> ScopedDatabase<'a, D> {
+    /// Wrap `db` for a key, failing with [`Error::Forbidden`] if the key
+    /// can't use its namespace.
+    pub fn new(
+        db: &'a D,
+        scope: Scope,
+        tags: Vec<String>,
+        namespaces: Vec<String>,
+    ) -> Result<Self, Error> {
+        let scoped = ScopedDatabase {
+            db: Handle::Borrowed(db),
+            scope,
+            tags,
+            namespaces,
+        };
+        scoped.require_namespace(db.namespace())?;
+        Ok(scoped)
+    }
+
+    /// Error unless the key may use `namespace`.
+    fn require_namespace(&self, namespace: &str) -> Result<(), Error> {
+        if self.namespaces.is_empty() || self.namespaces.iter().any(|n| n == namespace) {
+            Ok(())
+        } else {
+            Err(Error::Forbidden(format!(
+                "this API key can't use namespace '{}'",
+                namespace
+            )))
+        }
+    }
+
+    /// API keys work across namespaces, so managing them needs a key that
+    /// isn't limited to some.
+    fn require_key_management(&self) -> Result<(), Error> {
+        self.require(Scope::Admin)?;
+        if self.namespaces.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Forbidden(
+                "managing API keys needs an API key for every namespace".into(),
+            ))
+        }
+    }
+
+    fn require(&self, scope: Scope) -> Result<(), Error> {
+        if self.scope >= scope {
+            return Ok(());
+        }
+        Err(Error::Forbidden(match scope {
+            Scope::Admin => "this API key can't manage API keys".into(),
+            _ => "this API key is read-only".into(),
+        }))
+    }
+
+    /// True if one of `tags` is an allowed tag or one of its children.
+    fn allows(&self, tags: &[String]) -> bool {
+        self.tags.is_empty()
+            || tags
+                .iter()
+                .any(|t| self.tags.iter().any(|allowed| tag_matches(allowed, t)))
+    }
+
+    /// Error unless `tags` includes an allowed tag.
+    fn require_tags(&self, tags: &[String]) -> Result<(), Error> {
+        if self.allows(tags) {
+            Ok(())
+        } else {
+            Err(Error::Forbidden(format!(
+                "this API key can only write notes tagged {}",
+                self.tags.join(", ")
+            )))
+        }
+    }
+
+    /// Imports can replace any note, so they need a key that can see them
+    /// all.
+    fn require_import(&self) -> Result<(), Error> {
+        self.require(Scope::Write)?;
+        if self.tags.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Forbidden(
+                "importing needs an API key without a tag allow-list".into(),
+            ))
+        }
+    }
+
+    /// Renaming, merging or removing a tag changes every note that has it,
+    /// so it needs a key that can see them all.
+    fn require_tag_changes(&self) -> Result<(), Error> {
+        self.require(Scope::Write)?;
+        if self.tags.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Forbidden(
+                "changing tags needs an API key without a tag allow-list".into(),
+            ))
+        }
+    }
+
+    /// Limit a tag filter to the allowed tags.
+    fn restrict(&self, tags: Option<TagExpr>) -> Option<TagExpr> {
+        if self.tags.is_empty() {
+            return tags;
+        }
+        let allowed = TagExpr::any_of(self.tags.iter().cloned());
+        Some(match tags {
+            Some(tags) => tags.and(allowed),
+            None => allowed,
+        })
+    }
+
+    fn restrict_query(&self, query: NoteQuery) -> NoteQuery {
+        NoteQuery {
+            tags: self.restrict(query.tags.clone()),
+            ..query
+        }
+    }
+
+    /// Whether the key may see the note, in the trash or not.
+    async fn can_see(&self, id: i64) -> Result<bool, Error> {
+        if self.tags.is_empty() {
+            return Ok(true);
+        }
+        if let Some(note) = self.db.get_note(id).await? {
+            return Ok(self.allows(&note.tags));
+        }
+        let trash = self.db.list_trash().await?;
+        Ok(trash
+            .iter()
+            .any(|t| t.note.id == id && self.allows(&t.note.tags)))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<D: Database> Database for ScopedDatabase<'_, D> {
+    fn namespace(&self) -> &str {
+        self.db.namespace()
+    }
+
+    fn with_namespace(&self, namespace: &str) -> Result<Self, Error> {
+        self.require_namespace(namespace)?;
+        Ok(ScopedDatabase {
+            db: Handle::Owned(self.db.with_namespace(namespace)?),
+            scope: self.scope,
+            tags: self.tags.clone(),
+            namespaces: self.namespaces.clone(),
+        })
+    }
+
+    async fn list_namespaces(&self) -> Result<Vec<NamespaceCount>, Error> {
+        // Counting notes in other namespaces would bypass the tag allow-list
+        if !self.tags.is_empty() {
+            let count = self.count_notes(NoteQuery::default()).await?;
+            return Ok(vec![NamespaceCount {
+                name: self.namespace().to_string(),
+                count,
+            }]);
+        }
+        let mut namespaces = self.db.list_namespaces().await?;
+        namespaces.retain(|ns| self.require_namespace(&ns.name).is_ok());
+        Ok(namespaces)
+    }
+
+    async fn add_note(&self, note: CreateNote) -> Result<i64, Error> {
+        self.require(Scope::Write)?;
+        self.require_tags(&note.tags)?;
+        self.db.add_note(note).await
+    }
+
+    async fn put_note(&self, note: &Note) -> Result<(), Error> {
+        self.require_import()?;
+        self.db.put_note(note).await
+    }
+
+    async fn get_note(&self, id: i64) -> Result<Option<Note>, Error> {
+        let note = self.db.get_note(id).await?;
+        Ok(note.filter(|note| self.allows(&note.tags)))
+    }
+
+    async fn list_notes(&self, query: NoteQuery) -> Result<Vec<Note>, Error> {
+        self.db.list_notes(self.restrict_query(query)).await
+    }
+
+    async fn count_notes(&self, query: NoteQuery) -> Result<i64, Error> {
+        self.db.count_notes(self.restrict_query(query)).await
+    }
+
+    async fn update_note(&self, id: i64, update: UpdateNote) -> Result<bool, Error> {
+        self.require(Scope::Write)?;
+        if !self.can_see(id).await? {
+            return Ok(false);
+        }
+        if let Some(ref tags) = update.tags {
+            self.require_tags(tags)?;
+        }
+        self.db.update_note(id, update).await
+    }
+
+    async fn delete_note(&self, id: i64) -> Result<bool, Error> {
+        self.require(Scope::Write)?;
+        if !self.can_see(id).await? {
+            return Ok(false);
+        }
+        self.db.delete_note(id).await
+    }
+
+    async fn list_trash(&self) -> Result<Vec<TrashedNote>, Error> {
+        let mut trash = self.db.list_trash().await?;
+        trash.retain(|t| self.allows(&t.note.tags));
+        Ok(trash)
+    }
+
+    async fn restore_note(&self, id: i64) -> Result<bool, Error> {
+        self.require(Scope::Write)?;
+        if !self.can_see(id).await? {
+            return Ok(false);
+        }
+        self.db.restore_note(id).await
+    }
+
+    async fn purge_note(&self, id: i64) -> Result<bool, Error> {
+        self.require(Scope::Write)?;
+        if !self.can_see(id).await? {
+            return Ok(false);
+        }
+        self.db.purge_note(id).await
+    }
+
+    async fn push_operation(&self, operation: &Operation) -> Result<(), Error> {
+        self.require(Scope::Write)?;
+        self.db.push_operation(operation).await
+    }
+
+    async fn pop_operation(&self) -> Result<Option<Operation>, Error> {
+        self.require(Scope::Write)?;
+        self.db.pop_operation().await
+    }
+
+    /// The journal is shared by everyone, so undoing with a tag allow-list
+    /// could reverse changes to notes the key can't see.
+    async fn undo(&self) -> Option<Result<Option<Operation>, Error>> {
+        if let Err(e) = self.require(Scope::Write) {
+            return Some(Err(e));
+        }
+        if !self.tags.is_empty() {
+            return Some(Err(Error::Forbidden(
+                "undo needs an API key without a tag allow-list".into(),
+            )));
+        }
+        self.db.undo().await
+    }
+
+    async fn import(
+        &self,
+        notes: &[Note],
+        mode: ImportMode,
+    ) -> Option<Result<ImportSummary, Error>> {
+        if let Err(e) = self.require_import() {
+            return Some(Err(e));
+        }
+        self.db.import(notes, mode).await
+    }
+
+    async fn list_revisions(&self, note_id: i64) -> Result<Vec<Revision>, Error> {
+        if !self.can_see(note_id).await? {
+            return Ok(Vec::new());
+        }
+        self.db.list_revisions(note_id).await
+    }
+
+    async fn get_revision(&self, note_id: i64, revision: i64) -> Result<Option<Revision>, Error> {
+        if !self.can_see(note_id).await? {
+            return Ok(None);
+        }
+        self.db.get_revision(note_id, revision).await
+    }
+
+    async fn list_tags(&self) -> Result<Vec<TagCount>, Error> {
+        let mut tags = self.db.list_tags().await?;
+        tags.retain(|t| self.allows(std::slice::from_ref(&t.name)));
+        Ok(tags)
+    }
+
+    async fn merge_tags(&self, sources: &[String], target: &str) -> Result<Vec<i64>, Error> {
+        self.require_tag_changes()?;
+        self.db.merge_tags(sources, target).await
+    }
+
+    async fn remove_tag(&self, tag: &str) -> Result<Vec<i64>, Error> {
+        self.require_tag_changes()?;
+        self.db.remove_tag(tag).await
+    }
+
+    async fn set_tag_description(
+        &self,
+        tag: &str,
+        description: Option<&str>,
+    ) -> Result<bool, Error> {
+        self.require_tag_changes()?;
+        self.db.set_tag_description(tag, description).await
+    }
+
+    async fn list_tag_aliases(&self) -> Result<Vec<TagAlias>, Error> {
+        let mut aliases = self.db.list_tag_aliases().await?;
+        aliases.retain(|a| {
+            self.allows(std::slice::from_ref(&a.alias)) && self.allows(std::slice::from_ref(&a.tag))
+        });
+        Ok(aliases)
+    }
+
+    async fn set_tag_alias(&self, alias: &str, tag: Option<&str>) -> Result<bool, Error> {
+        self.require_tag_changes()?;
+        self.db.set_tag_alias(alias, tag).await
+    }
+
+    async fn list_links(&self, id: i64) -> Result<Vec<NoteLink>, Error> {
+        if !self.can_see(id).await? {
+            return Ok(Vec::new());
+        }
+        let mut visible = Vec::new();
+        for link in self.db.list_links(id).await? {
+            let other = if link.from == id { link.to } else { link.from };
+            if self.can_see(other).await? {
+                visible.push(link);
+            }
+        }
+        Ok(visible)
+    }
+
+    async fn add_link(&self, link: &NoteLink) -> Result<bool, Error> {
+        self.require(Scope::Write)?;
+        for id in [link.from, link.to] {
+            if !self.can_see(id).await? {
+                return Err(Error::NotFound(format!("note {}", id)));
+            }
+        }
+        self.db.add_link(link).await
+    }
+
+    async fn remove_link(&self, from: i64, to: i64, kind: LinkKind) -> Result<bool, Error> {
+        self.require(Scope::Write)?;
+        if !self.can_see(from).await? || !self.can_see(to).await? {
+            return Ok(false);
+        }
+        self.db.remove_link(from, to, kind).await
+    }
+
+    async fn grep(
+        &self,
+        pattern: &str,
+        query: &NoteQuery,
+        case_sensitive: bool,
+    ) -> Result<Vec<Note>, Error> {
+        let query = self.restrict_query(query.clone());
+        self.db.grep(pattern, &query, case_sensitive).await
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        tags: Option<&TagExpr>,
+        limit: Option<i64>,
+    ) -> Result<Vec<SearchHit>, Error> {
+        let tags = self.restrict(tags.cloned());
+        self.db.search(query, tags.as_ref(), limit).await
+    }
+
+    async fn add_api_key(&self, key: CreateApiKey) -> Result<ApiKey, Error> {
+        self.require_key_management()?;
+        self.db.add_api_key(key).await
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, Error> {
+        self.require_key_management()?;
+        self.db.find_api_key(key_hash).await
+    }
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>, Error> {
+        self.require_key_management()?;
+        self.db.list_api_keys().await
+    }
+
+    async fn revoke_api_key(&self, id: i64) -> Result<bool, Error> {
+        self.require_key_management()?;
+        self.db.revoke_api_key(id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_order() {
+        assert!(Scope::Admin > Scope::Write && Scope::Write > Scope::Read);
+        assert_eq!("write".parse::<Scope>().unwrap(), Scope::Write);
+        assert!("owner".parse::<Scope>().is_err());
+    }
+
+    #[test]
+    fn test_hash_key() {
+        let hash = hash_key("veta_secret");
+        assert!(is_key_hash(&hash));
+        assert_eq!(hash, hash_key("veta_secret"));
+        assert_ne!(hash, hash_key("veta_other"));
+        assert!(!is_key_hash("veta_secret"));
+    }
+}
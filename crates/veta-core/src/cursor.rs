@@ -0,0 +1,177 @@
+//! Opaque cursors for paging through note listings.
+//!
+//! A cursor records the sort key (timestamp and ID) of the last note on a
+//! page. The next page holds the notes that sort strictly after it, so pages
+//! stay stable when notes are added or edited in between requests.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::{Error, Note, SortOrder, TimeField};
+
+/// Position after the last note of a page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    /// Sort the cursor was created for.
+    pub time_field: TimeField,
+    pub order: SortOrder,
+    /// Sort key of the last note on the page.
+    pub time: String,
+    pub id: i64,
+}
+
+impl Cursor {
+    /// Cursor pointing after `note` in the given sort.
+    pub fn after(note: &Note, time_field: TimeField, order: SortOrder) -> Self {
+        Cursor {
+            time_field,
+            order,
+            time: time_field.of(note).to_string(),
+            id: note.id,
+        }
+    }
+
+    /// Parse a cursor returned by [`Cursor::to_string`].
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let invalid = || Error::Validation(format!("invalid cursor '{}'", s));
+
+        let bytes = (0..s.len())
+            .step_by(2)
+            .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        let decoded = String::from_utf8(bytes).map_err(|_| invalid())?;
+
+        let mut parts = decoded.splitn(4, '|');
+        let time_field = match parts.next() {
+            Some("c") => TimeField::Created,
+            Some("u") => TimeField::Updated,
+            _ => return Err(invalid()),
+        };
+        let order = match parts.next() {
+            Some("a") => SortOrder::Asc,
+            Some("d") => SortOrder::Desc,
+            _ => return Err(invalid()),
+        };
+        let id = parts
+            .next()
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(invalid)?;
+        let time = parts.next().ok_or_else(invalid)?.to_string();
+
+        Ok(Cursor {
+            time_field,
+            order,
+            time,
+            id,
+        })
+    }
+
+    /// Whether `note` sorts after the cursor.
+    pub fn is_before(&self, note: &Note) -> bool {
+        let ordering = self
+            .time_field
+            .of(note)
+            .cmp(self.time.as_str())
+            .then_with(|| note.id.cmp(&self.id));
+        match self.order {
+            SortOrder::Asc => ordering == Ordering::Greater,
+            SortOrder::Desc => ordering == Ordering::Less,
+        }
+    }
+
+    /// SQL condition selecting notes (aliased as `n`) that sort after the
+    /// cursor. The timestamp is bound as a parameter appended to `params`.
+    pub fn to_sql(&self, params: &mut Vec<String>) -> String {
+        params.push(self.time.clone());
+        let op = match self.order {
+            SortOrder::Asc => '>',
+            SortOrder::Desc => '<',
+        };
+        format!(
+            "({col} {op} ?{p} OR ({col} = ?{p} AND n.id {op} {id}))",
+            col = self.time_field.column(),
+            op = op,
+            p = params.len(),
+            id = self.id
+        )
+    }
+}
+
+/// Cursors are hex-encoded so they can be passed around in URLs and shell
+/// arguments without quoting.
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let field = match self.time_field {
+            TimeField::Created => 'c',
+            TimeField::Updated => 'u',
+        };
+        let order = match self.order {
+            SortOrder::Asc => 'a',
+            SortOrder::Desc => 'd',
+        };
+        let raw = format!("{}|{}|{}|{}", field, order, self.id, self.time);
+        for byte in raw.bytes() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: i64, updated_at: &str) -> Note {
+        Note {
+            id,
+            title: String::new(),
+            body: String::new(),
+            tags: vec![],
+            references: vec![],
+            created_at: "2026-01-01 00:00:00".to_string(),
+            updated_at: updated_at.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let cursor = Cursor::after(
+            &note(12, "2026-01-07 11:41:00"),
+            TimeField::Updated,
+            SortOrder::Desc,
+        );
+        let encoded = cursor.to_string();
+        assert!(encoded.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(Cursor::parse(&encoded).unwrap(), cursor);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Cursor::parse("zz").is_err());
+        assert!(Cursor::parse("abc").is_err());
+        assert!(Cursor::parse("787c647c317c78").is_err()); // "x|d|1|x"
+    }
+
+    #[test]
+    fn test_is_before() {
+        let cursor = Cursor::after(
+            &note(5, "2026-01-07 00:00:00"),
+            TimeField::Updated,
+            SortOrder::Desc,
+        );
+        assert!(cursor.is_before(&note(9, "2026-01-06 00:00:00")));
+        assert!(cursor.is_before(&note(4, "2026-01-07 00:00:00")));
+        assert!(!cursor.is_before(&note(5, "2026-01-07 00:00:00")));
+        assert!(!cursor.is_before(&note(6, "2026-01-07 00:00:00")));
+        assert!(!cursor.is_before(&note(1, "2026-01-08 00:00:00")));
+
+        let asc = Cursor {
+            order: SortOrder::Asc,
+            ..cursor
+        };
+        assert!(asc.is_before(&note(6, "2026-01-07 00:00:00")));
+        assert!(asc.is_before(&note(1, "2026-01-08 00:00:00")));
+        assert!(!asc.is_before(&note(4, "2026-01-07 00:00:00")));
+    }
+}
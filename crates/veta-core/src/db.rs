@@ -1,4 +1,8 @@
-use crate::{CreateNote, Error, Note, NoteQuery, TagCount, UpdateNote};
+use crate::{
+    ApiKey, CreateApiKey, CreateNote, Error, ImportMode, ImportSummary, LinkKind, NamespaceCount,
+    Note, NoteLink, NoteQuery, Operation, Revision, SearchHit, TagAlias, TagCount, TagExpr,
+    TrashedNote, UpdateNote,
+};
 
 /// Database abstraction that works for both SQLite and D1.
 ///
@@ -6,10 +10,48 @@ use crate::{CreateNote, Error, Note, NoteQuery, TagCount, UpdateNote};
 /// The `?Send` is critical - WASM is single-threaded and JS values aren't Send.
 #[async_trait::async_trait(?Send)]
 pub trait Database {
+    /// The namespace this handle reads and writes.
+    fn namespace(&self) -> &str {
+        crate::DEFAULT_NAMESPACE
+    }
+
+    /// Another handle to the same database that reads and writes
+    /// `namespace` instead.
+    ///
+    /// Backends that hold a single namespace (like a `.veta` directory)
+    /// keep the default, which returns an error.
+    fn with_namespace(&self, namespace: &str) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        Err(Error::Validation(format!(
+            "can't use namespace '{}': this database only holds '{}'",
+            namespace,
+            self.namespace()
+        )))
+    }
+
+    /// List the namespaces that have notes, with their note counts.
+    ///
+    /// Backends that hold a single namespace list just that one.
+    async fn list_namespaces(&self) -> Result<Vec<NamespaceCount>, Error> {
+        let count = self.count_notes(NoteQuery::default()).await?;
+        Ok(vec![NamespaceCount {
+            name: self.namespace().to_string(),
+            count,
+        }])
+    }
+
     /// Add a new note and return its ID.
     async fn add_note(&self, note: CreateNote) -> Result<i64, Error>;
 
-    /// Get a note by ID.
+    /// Store a note under its ID with its timestamps, as when importing it.
+    ///
+    /// A note with the same ID, in the trash or not, is replaced; its
+    /// revisions are kept. Nothing is added to the undo journal.
+    async fn put_note(&self, note: &Note) -> Result<(), Error>;
+
+    /// Get a note by ID. Notes in the trash are not returned.
     async fn get_note(&self, id: i64) -> Result<Option<Note>, Error>;
 
     /// List notes matching the query.
@@ -18,20 +60,140 @@ pub trait Database {
     /// Count notes matching the query (ignores limit).
     async fn count_notes(&self, query: NoteQuery) -> Result<i64, Error>;
 
-    /// Update an existing note.
+    /// Update an existing note, saving its previous version as a revision.
     async fn update_note(&self, id: i64, update: UpdateNote) -> Result<bool, Error>;
 
-    /// Delete a note by ID. Returns true if deleted, false if not found.
+    /// Move a note to the trash. Returns true if deleted, false if not found
+    /// (or already in the trash).
     async fn delete_note(&self, id: i64) -> Result<bool, Error>;
 
+    /// List notes in the trash, most recently deleted first.
+    async fn list_trash(&self) -> Result<Vec<TrashedNote>, Error>;
+
+    /// Restore a note from the trash. Returns false if it isn't in the trash.
+    async fn restore_note(&self, id: i64) -> Result<bool, Error>;
+
+    /// Permanently delete a note in the trash, along with its revisions.
+    /// Returns false if it isn't in the trash.
+    async fn purge_note(&self, id: i64) -> Result<bool, Error>;
+
+    /// Append an operation to the undo journal, keeping at most
+    /// [`crate::journal::JOURNAL_LIMIT`] entries.
+    async fn push_operation(&self, operation: &Operation) -> Result<(), Error>;
+
+    /// Remove and return the most recent operation from the undo journal.
+    async fn pop_operation(&self) -> Result<Option<Operation>, Error>;
+
+    /// Undo the most recent operation, for backends that keep the undo
+    /// journal elsewhere (like a remote server) and so must undo there.
+    ///
+    /// Returns `None` by default, in which case [`crate::VetaService::undo`]
+    /// pops the journal and reverses the operation itself.
+    async fn undo(&self) -> Option<Result<Option<Operation>, Error>> {
+        None
+    }
+
+    /// Export every note with its full body, for backends whose listings
+    /// only carry previews (like a remote server).
+    ///
+    /// Returns `None` by default, in which case
+    /// [`crate::VetaService::export`] lists the notes itself.
+    async fn export(&self) -> Option<Result<Vec<Note>, Error>> {
+        None
+    }
+
+    /// Import notes, for backends that must import elsewhere (like a remote
+    /// server).
+    ///
+    /// Returns `None` by default, in which case
+    /// [`crate::VetaService::import`] stores the notes with
+    /// [`Database::put_note`].
+    async fn import(
+        &self,
+        _notes: &[Note],
+        _mode: ImportMode,
+    ) -> Option<Result<ImportSummary, Error>> {
+        None
+    }
+
+    /// List the saved revisions of a note, newest first.
+    async fn list_revisions(&self, note_id: i64) -> Result<Vec<Revision>, Error>;
+
+    /// Get a saved revision of a note.
+    async fn get_revision(&self, note_id: i64, revision: i64) -> Result<Option<Revision>, Error>;
+
     /// List all tags with their note counts.
     async fn list_tags(&self) -> Result<Vec<TagCount>, Error>;
 
-    /// Search notes by pattern (regex) in title and body.
+    /// Replace the tags `sources` with `target` on every note that has one of
+    /// them, saving each note's previous version as a revision. Notes in the
+    /// trash are retagged too, so restoring one doesn't bring a source back.
+    /// `target` keeps its description, or else takes one of the sources'.
+    ///
+    /// SQL backends change all notes in one transaction. Returns the IDs of
+    /// the changed notes that aren't in the trash.
+    async fn merge_tags(&self, sources: &[String], target: &str) -> Result<Vec<i64>, Error>;
+
+    /// Remove `tag` and its description from every note that has it, like
+    /// [`Database::merge_tags`] without a target. The notes themselves stay.
+    async fn remove_tag(&self, tag: &str) -> Result<Vec<i64>, Error>;
+
+    /// Set or clear (with `None`) a tag's description. Returns false if no
+    /// note has the tag.
+    async fn set_tag_description(
+        &self,
+        tag: &str,
+        description: Option<&str>,
+    ) -> Result<bool, Error>;
+
+    /// List tag aliases, by alias.
+    async fn list_tag_aliases(&self) -> Result<Vec<TagAlias>, Error>;
+
+    /// Make `alias` another name for `tag`, or remove it (with `None`).
+    /// Returns false if there was no such alias to remove.
+    async fn set_tag_alias(&self, alias: &str, tag: Option<&str>) -> Result<bool, Error>;
+
+    /// Links from and to a note, leaving out those to or from notes in the
+    /// trash.
+    async fn list_links(&self, id: i64) -> Result<Vec<NoteLink>, Error>;
+
+    /// Link two notes, which the caller has checked exist. Returns false if
+    /// the link already exists.
+    async fn add_link(&self, link: &NoteLink) -> Result<bool, Error>;
+
+    /// Remove a link. Returns false if there was no such link.
+    async fn remove_link(&self, from: i64, to: i64, kind: LinkKind) -> Result<bool, Error>;
+
+    /// Search notes matching the query by pattern (regex) in title and body.
+    ///
+    /// The query's limit applies to the notes that match the pattern.
     async fn grep(
         &self,
         pattern: &str,
-        tags: Option<&[String]>,
+        query: &NoteQuery,
         case_sensitive: bool,
     ) -> Result<Vec<Note>, Error>;
+
+    /// Full-text search over title and body, ranked by BM25 relevance.
+    ///
+    /// All tokens in `query` must appear in a matching note. Results are
+    /// ordered by descending score.
+    async fn search(
+        &self,
+        query: &str,
+        tags: Option<&TagExpr>,
+        limit: Option<i64>,
+    ) -> Result<Vec<SearchHit>, Error>;
+
+    /// Store a new API key.
+    async fn add_api_key(&self, key: CreateApiKey) -> Result<ApiKey, Error>;
+
+    /// Find the API key with the given hash.
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, Error>;
+
+    /// List API keys, oldest first.
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>, Error>;
+
+    /// Delete an API key. Returns false if it doesn't exist.
+    async fn revoke_api_key(&self, id: i64) -> Result<bool, Error>;
 }
@@ -0,0 +1,77 @@
+//! Line-based diffs between versions of a note.
+
+use similar::TextDiff;
+
+use crate::Note;
+
+/// Render the parts of a note that are compared in a diff.
+///
+/// The modification time is left out since it differs between every version.
+fn render(note: &Note) -> String {
+    let mut text = format!("# {}\n\n{}", note.title, note.body);
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str("\n---\n\n");
+    text.push_str(&format!("Tags: {}\n", note.tags.join(",")));
+    if !note.references.is_empty() {
+        text.push_str("References:\n");
+        for reference in &note.references {
+            text.push_str(&format!("  - {}\n", reference));
+        }
+    }
+    text
+}
+
+/// Unified diff from `old` to `new`, with the given labels in the header.
+///
+/// Returns an empty string if the notes have the same content.
+pub fn unified_diff(old: &Note, old_label: &str, new: &Note, new_label: &str) -> String {
+    let old_text = render(old);
+    let new_text = render(new);
+    if old_text == new_text {
+        return String::new();
+    }
+    TextDiff::from_lines(&old_text, &new_text)
+        .unified_diff()
+        .header(old_label, new_label)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(title: &str, body: &str, tags: &[&str]) -> Note {
+        Note {
+            id: 1,
+            title: title.to_string(),
+            body: body.to_string(),
+            tags: tags.iter().map(|s| s.to_string()).collect(),
+            references: vec![],
+            created_at: "2026-01-01 00:00:00".to_string(),
+            updated_at: "2026-01-01 00:00:00".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = note("JWT", "Tokens expire after 15 minutes", &["auth"]);
+        let new = note("JWT", "Tokens expire after 30 minutes", &["auth", "tokens"]);
+        let diff = unified_diff(&old, "a", &new, "b");
+
+        assert!(diff.starts_with("--- a\n+++ b\n"));
+        assert!(diff.contains("-Tokens expire after 15 minutes\n"));
+        assert!(diff.contains("+Tokens expire after 30 minutes\n"));
+        assert!(diff.contains("-Tags: auth\n+Tags: auth,tokens\n"));
+        assert!(diff.contains(" # JWT\n"));
+    }
+
+    #[test]
+    fn test_unified_diff_ignores_timestamp() {
+        let old = note("JWT", "Same", &[]);
+        let mut new = old.clone();
+        new.updated_at = "2026-02-01 00:00:00".to_string();
+        assert_eq!(unified_diff(&old, "a", &new, "b"), "");
+    }
+}
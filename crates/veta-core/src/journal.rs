@@ -0,0 +1,105 @@
+//! Operation journal used to undo mutating commands.
+//!
+//! [`crate::VetaService`] records one [`Operation`] per mutating call, and
+//! [`crate::VetaService::undo`] reverses the most recent one.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of operations kept in the journal.
+pub const JOURNAL_LIMIT: usize = 100;
+
+/// A mutating operation, with enough information to reverse it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    /// A note was added.
+    Add { id: i64 },
+    /// A note was updated; `revision` holds the version it replaced.
+    Update { id: i64, revision: i64 },
+    /// Notes were moved to the trash.
+    Delete { ids: Vec<i64> },
+    /// Notes were restored from the trash.
+    Restore { ids: Vec<i64> },
+    /// Tags were renamed, merged or removed.
+    Retag { notes: Vec<PreviousTags> },
+    /// A tag alias was added, retagging `notes`. `previous` is the tag the
+    /// alias stood for before, if any.
+    Alias {
+        alias: String,
+        previous: Option<String>,
+        notes: Vec<PreviousTags>,
+    },
+}
+
+/// A note's tags before a tag change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreviousTags {
+    pub id: i64,
+    pub tags: Vec<String>,
+}
+
+fn format_ids(ids: &[i64]) -> String {
+    let noun = if ids.len() == 1 { "note" } else { "notes" };
+    let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+    format!("{} {}", noun, ids.join(", "))
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Add { id } => write!(f, "add of note {}", id),
+            Operation::Update { id, .. } => write!(f, "edit of note {}", id),
+            Operation::Delete { ids } => write!(f, "delete of {}", format_ids(ids)),
+            Operation::Restore { ids } => write!(f, "restore of {}", format_ids(ids)),
+            Operation::Retag { notes } => {
+                let ids: Vec<i64> = notes.iter().map(|note| note.id).collect();
+                write!(f, "retag of {}", format_ids(&ids))
+            }
+            Operation::Alias { alias, .. } => write!(f, "tag alias '{}'", alias),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialization() {
+        let op = Operation::Update { id: 3, revision: 2 };
+        let json = serde_json::to_string(&op).unwrap();
+        assert_eq!(json, r#"{"op":"update","id":3,"revision":2}"#);
+        assert_eq!(serde_json::from_str::<Operation>(&json).unwrap(), op);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            Operation::Delete { ids: vec![1, 2] }.to_string(),
+            "delete of notes 1, 2"
+        );
+        assert_eq!(
+            Operation::Restore { ids: vec![4] }.to_string(),
+            "restore of note 4"
+        );
+        let notes = vec![PreviousTags {
+            id: 2,
+            tags: vec!["ops".into()],
+        }];
+        assert_eq!(
+            Operation::Retag {
+                notes: notes.clone()
+            }
+            .to_string(),
+            "retag of note 2"
+        );
+        let op = Operation::Alias {
+            alias: "bug".into(),
+            previous: None,
+            notes,
+        };
+        assert_eq!(op.to_string(), "tag alias 'bug'");
+    }
+}
@@ -2,16 +2,42 @@
 //!
 //! This crate contains no I/O and can be compiled for any target.
 
+pub mod archive;
+pub mod auth;
+mod cursor;
 mod dateparse;
 mod db;
+pub mod diff;
 mod error;
+pub mod journal;
+pub mod link;
 pub mod migrations;
+pub mod namespace;
 mod note;
+mod query;
+pub mod reference;
+pub mod search;
 mod service;
+pub mod sync;
+mod tagexpr;
 
+pub use archive::{ImportMode, ImportSummary};
+pub use auth::{ApiKey, CreateApiKey, Scope};
+pub use cursor::Cursor;
 pub use dateparse::parse_human_date;
 pub use db::Database;
 pub use error::Error;
-pub use migrations::{get_pending_migrations, Migration, MIGRATIONS, SCHEMA_VERSION};
-pub use note::{CreateNote, Note, NoteQuery, NoteSummary, TagCount, UpdateNote};
+pub use journal::{Operation, PreviousTags};
+pub use link::{LinkKind, NoteLink};
+pub use migrations::{
+    get_pending_migrations, is_add_column, Migration, MIGRATIONS, SCHEMA_VERSION,
+};
+pub use namespace::{NamespaceCount, DEFAULT_NAMESPACE};
+pub use note::{
+    CreateNote, Note, NotePage, NoteQuery, NoteSummary, Revision, SearchHit, SearchResult,
+    SortOrder, TagAlias, TagCount, TimeField, TrashedNote, UpdateNote,
+};
+pub use query::{parse_query, ParsedQuery};
+pub use reference::{LineRange, Reference};
 pub use service::VetaService;
+pub use tagexpr::{tag_matches, TagClause, TagExpr};
@@ -0,0 +1,96 @@
+//! Typed links between notes, such as one note superseding another.
+//!
+//! Links are directed, from the note that says something about another to
+//! that note: `42 supersedes 17` is a link from 42 to 17. Links to or from a
+//! note in the trash are left out of listings until it's restored, and are
+//! deleted with the note when it's purged.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// How a note relates to the note it links to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkKind {
+    /// See also.
+    RelatesTo,
+    /// Replaces the other note, which is out of date.
+    Supersedes,
+    /// Says the opposite of the other note.
+    Contradicts,
+    /// Is part of the other note's topic.
+    ChildOf,
+}
+
+impl LinkKind {
+    pub const ALL: [LinkKind; 4] = [
+        LinkKind::RelatesTo,
+        LinkKind::Supersedes,
+        LinkKind::Contradicts,
+        LinkKind::ChildOf,
+    ];
+
+    /// How the link reads from the note it points to, e.g. `superseded by`.
+    pub fn incoming(self) -> &'static str {
+        match self {
+            LinkKind::RelatesTo => "related to",
+            LinkKind::Supersedes => "superseded by",
+            LinkKind::Contradicts => "contradicted by",
+            LinkKind::ChildOf => "parent of",
+        }
+    }
+}
+
+impl fmt::Display for LinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkKind::RelatesTo => write!(f, "relates-to"),
+            LinkKind::Supersedes => write!(f, "supersedes"),
+            LinkKind::Contradicts => write!(f, "contradicts"),
+            LinkKind::ChildOf => write!(f, "child-of"),
+        }
+    }
+}
+
+impl FromStr for LinkKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        LinkKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s)
+            .ok_or_else(|| {
+                Error::Validation(format!(
+                    "invalid link kind '{}': expected relates-to, supersedes, contradicts or child-of",
+                    s
+                ))
+            })
+    }
+}
+
+/// A link from one note to another.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoteLink {
+    pub from: i64,
+    pub to: i64,
+    pub kind: LinkKind,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_round_trips() {
+        for kind in LinkKind::ALL {
+            assert_eq!(kind.to_string().parse::<LinkKind>().unwrap(), kind);
+            let json = serde_json::to_string(&kind).unwrap();
+            assert_eq!(json, format!("\"{}\"", kind));
+        }
+        assert!("replaces".parse::<LinkKind>().is_err());
+    }
+}
@@ -4,7 +4,7 @@
 //! The schema version is tracked in the `_veta_meta` table.
 
 /// Current schema version. Increment when adding new migrations.
-pub const SCHEMA_VERSION: i64 = 2;
+pub const SCHEMA_VERSION: i64 = 13;
 
 /// A database migration with version number and SQL statements.
 pub struct Migration {
@@ -52,6 +52,266 @@ pub const MIGRATIONS: &[Migration] = &[
             "ALTER TABLE notes ADD COLUMN \"references\" TEXT NOT NULL DEFAULT '[]'",
         ],
     },
+    Migration {
+        version: 3,
+        name: "add_fulltext_search",
+        statements: &[
+            // External-content FTS5 index over notes, kept in sync by triggers
+            "CREATE VIRTUAL TABLE IF NOT EXISTS notes_fts USING fts5(
+                title,
+                body,
+                content='notes',
+                content_rowid='id'
+            )",
+            "CREATE TRIGGER IF NOT EXISTS notes_fts_insert AFTER INSERT ON notes BEGIN
+                INSERT INTO notes_fts (rowid, title, body) VALUES (new.id, new.title, new.body);
+            END",
+            "CREATE TRIGGER IF NOT EXISTS notes_fts_delete AFTER DELETE ON notes BEGIN
+                INSERT INTO notes_fts (notes_fts, rowid, title, body)
+                VALUES ('delete', old.id, old.title, old.body);
+            END",
+            "CREATE TRIGGER IF NOT EXISTS notes_fts_update AFTER UPDATE OF title, body ON notes BEGIN
+                INSERT INTO notes_fts (notes_fts, rowid, title, body)
+                VALUES ('delete', old.id, old.title, old.body);
+                INSERT INTO notes_fts (rowid, title, body) VALUES (new.id, new.title, new.body);
+            END",
+            // Index notes that existed before this migration
+            "INSERT INTO notes_fts (notes_fts) VALUES ('rebuild')",
+        ],
+    },
+    Migration {
+        version: 4,
+        name: "add_note_revisions",
+        statements: &[
+            // Previous versions of notes, saved before each update
+            "CREATE TABLE IF NOT EXISTS note_revisions (
+                note_id INTEGER NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+                revision INTEGER NOT NULL,
+                title TEXT NOT NULL,
+                body TEXT NOT NULL,
+                tags TEXT NOT NULL DEFAULT '[]',
+                \"references\" TEXT NOT NULL DEFAULT '[]',
+                updated_at TEXT NOT NULL,
+                PRIMARY KEY (note_id, revision)
+            )",
+        ],
+    },
+    Migration {
+        version: 5,
+        name: "add_trash_and_journal",
+        statements: &[
+            // Deleted notes stay in the table until the trash is emptied
+            "ALTER TABLE notes ADD COLUMN deleted_at TEXT",
+            "CREATE INDEX IF NOT EXISTS idx_notes_deleted_at ON notes(deleted_at)",
+            // Recent mutating operations, used by undo
+            "CREATE TABLE IF NOT EXISTS journal (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                operation TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            )",
+        ],
+    },
+    Migration {
+        version: 6,
+        name: "backfill_created_at",
+        statements: &[
+            // Notes inserted with an explicit timestamp could end up created after
+            // their last update; clamp those so sorting by creation time is sane
+            "UPDATE notes SET created_at = updated_at
+             WHERE created_at IS NULL OR created_at = '' OR created_at > updated_at",
+            "CREATE INDEX IF NOT EXISTS idx_notes_created_at ON notes(created_at)",
+        ],
+    },
+    Migration {
+        version: 7,
+        name: "add_api_keys",
+        statements: &[
+            // Keys for the HTTP API; only a hash of each key is stored
+            "CREATE TABLE IF NOT EXISTS api_keys (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                key_hash TEXT NOT NULL UNIQUE,
+                scope TEXT NOT NULL,
+                tags TEXT NOT NULL DEFAULT '[]',
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            )",
+        ],
+    },
+    Migration {
+        version: 8,
+        name: "add_namespaces",
+        statements: &[
+            // Existing notes and undo history end up in the default namespace.
+            // Tag names are shared, but tag counts only include the notes of
+            // one namespace
+            "ALTER TABLE notes ADD COLUMN namespace TEXT NOT NULL DEFAULT 'default'",
+            "CREATE INDEX IF NOT EXISTS idx_notes_namespace ON notes(namespace, deleted_at)",
+            "ALTER TABLE journal ADD COLUMN namespace TEXT NOT NULL DEFAULT 'default'",
+        ],
+    },
+    Migration {
+        version: 9,
+        name: "add_tag_descriptions",
+        statements: &[
+            // Tag names are shared by namespaces, so descriptions are per
+            // namespace like tag counts
+            "CREATE TABLE IF NOT EXISTS tag_descriptions (
+                namespace TEXT NOT NULL,
+                name TEXT NOT NULL,
+                description TEXT NOT NULL,
+                PRIMARY KEY (namespace, name)
+            )",
+        ],
+    },
+    Migration {
+        version: 10,
+        name: "add_tag_aliases",
+        statements: &["CREATE TABLE IF NOT EXISTS tag_aliases (
+                namespace TEXT NOT NULL,
+                alias TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (namespace, alias)
+            )"],
+    },
+    Migration {
+        version: 11,
+        name: "add_note_links",
+        statements: &[
+            // Links are deleted with either note when it's purged
+            "CREATE TABLE IF NOT EXISTS note_links (
+                from_id INTEGER NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+                to_id INTEGER NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+                kind TEXT NOT NULL,
+                PRIMARY KEY (from_id, to_id, kind)
+            )",
+            "CREATE INDEX IF NOT EXISTS idx_note_links_to_id ON note_links(to_id)",
+        ],
+    },
+    Migration {
+        version: 12,
+        name: "per_namespace_ids",
+        statements: &[
+            // Each namespace numbers its own notes: `id` is the note's ID in
+            // its namespace, and `row_id` the key other tables refer to.
+            // Existing notes keep their IDs. Tags belong to a namespace. The
+            // tables are rebuilt, and the old ones dropped children first so
+            // that dropping them doesn't cascade.
+            "CREATE TABLE IF NOT EXISTS notes_new (
+                row_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                id INTEGER NOT NULL,
+                namespace TEXT NOT NULL DEFAULT 'default',
+                title TEXT NOT NULL,
+                body TEXT NOT NULL,
+                \"references\" TEXT NOT NULL DEFAULT '[]',
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+                deleted_at TEXT,
+                UNIQUE (namespace, id)
+            )",
+            "INSERT INTO notes_new
+                 (row_id, id, namespace, title, body, \"references\", created_at, updated_at, deleted_at)
+             SELECT id, id, namespace, title, body, \"references\", created_at, updated_at, deleted_at
+             FROM notes",
+            "CREATE TABLE IF NOT EXISTS tags_new (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                namespace TEXT NOT NULL,
+                name TEXT NOT NULL,
+                UNIQUE (namespace, name)
+            )",
+            "INSERT INTO tags_new (namespace, name)
+             SELECT DISTINCT n.namespace, t.name FROM note_tags nt
+             JOIN tags t ON t.id = nt.tag_id
+             JOIN notes n ON n.id = nt.note_id",
+            "CREATE TABLE IF NOT EXISTS note_tags_new (
+                note_id INTEGER NOT NULL REFERENCES notes_new(row_id) ON DELETE CASCADE,
+                tag_id INTEGER NOT NULL REFERENCES tags_new(id) ON DELETE CASCADE,
+                PRIMARY KEY (note_id, tag_id)
+            )",
+            "INSERT INTO note_tags_new (note_id, tag_id)
+             SELECT nt.note_id, tn.id FROM note_tags nt
+             JOIN tags t ON t.id = nt.tag_id
+             JOIN notes n ON n.id = nt.note_id
+             JOIN tags_new tn ON tn.namespace = n.namespace AND tn.name = t.name",
+            "CREATE TABLE IF NOT EXISTS note_revisions_new (
+                note_id INTEGER NOT NULL REFERENCES notes_new(row_id) ON DELETE CASCADE,
+                revision INTEGER NOT NULL,
+                title TEXT NOT NULL,
+                body TEXT NOT NULL,
+                tags TEXT NOT NULL DEFAULT '[]',
+                \"references\" TEXT NOT NULL DEFAULT '[]',
+                updated_at TEXT NOT NULL,
+                PRIMARY KEY (note_id, revision)
+            )",
+            "INSERT INTO note_revisions_new
+                 (note_id, revision, title, body, tags, \"references\", updated_at)
+             SELECT note_id, revision, title, body, tags, \"references\", updated_at
+             FROM note_revisions",
+            "CREATE TABLE IF NOT EXISTS note_links_new (
+                from_id INTEGER NOT NULL REFERENCES notes_new(row_id) ON DELETE CASCADE,
+                to_id INTEGER NOT NULL REFERENCES notes_new(row_id) ON DELETE CASCADE,
+                kind TEXT NOT NULL,
+                PRIMARY KEY (from_id, to_id, kind)
+            )",
+            "INSERT INTO note_links_new (from_id, to_id, kind)
+             SELECT from_id, to_id, kind FROM note_links",
+            "DROP TRIGGER IF EXISTS notes_fts_insert",
+            "DROP TRIGGER IF EXISTS notes_fts_delete",
+            "DROP TRIGGER IF EXISTS notes_fts_update",
+            "DROP TABLE IF EXISTS notes_fts",
+            "DROP TABLE note_links",
+            "DROP TABLE note_revisions",
+            "DROP TABLE note_tags",
+            "DROP TABLE tags",
+            "DROP TABLE notes",
+            "ALTER TABLE notes_new RENAME TO notes",
+            "ALTER TABLE tags_new RENAME TO tags",
+            "ALTER TABLE note_tags_new RENAME TO note_tags",
+            "ALTER TABLE note_revisions_new RENAME TO note_revisions",
+            "ALTER TABLE note_links_new RENAME TO note_links",
+            "CREATE INDEX IF NOT EXISTS idx_notes_updated_at ON notes(updated_at)",
+            "CREATE INDEX IF NOT EXISTS idx_notes_created_at ON notes(created_at)",
+            "CREATE INDEX IF NOT EXISTS idx_notes_deleted_at ON notes(deleted_at)",
+            "CREATE INDEX IF NOT EXISTS idx_notes_namespace ON notes(namespace, deleted_at)",
+            "CREATE INDEX IF NOT EXISTS idx_note_tags_tag_id ON note_tags(tag_id)",
+            "CREATE INDEX IF NOT EXISTS idx_note_links_to_id ON note_links(to_id)",
+            "CREATE VIRTUAL TABLE IF NOT EXISTS notes_fts USING fts5(
+                title,
+                body,
+                content='notes',
+                content_rowid='row_id'
+            )",
+            "CREATE TRIGGER IF NOT EXISTS notes_fts_insert AFTER INSERT ON notes BEGIN
+                INSERT INTO notes_fts (rowid, title, body) VALUES (new.row_id, new.title, new.body);
+            END",
+            "CREATE TRIGGER IF NOT EXISTS notes_fts_delete AFTER DELETE ON notes BEGIN
+                INSERT INTO notes_fts (notes_fts, rowid, title, body)
+                VALUES ('delete', old.row_id, old.title, old.body);
+            END",
+            "CREATE TRIGGER IF NOT EXISTS notes_fts_update AFTER UPDATE OF title, body ON notes BEGIN
+                INSERT INTO notes_fts (notes_fts, rowid, title, body)
+                VALUES ('delete', old.row_id, old.title, old.body);
+                INSERT INTO notes_fts (rowid, title, body) VALUES (new.row_id, new.title, new.body);
+            END",
+            "INSERT INTO notes_fts (notes_fts) VALUES ('rebuild')",
+            // The last ID given out in each namespace, so IDs of purged
+            // notes aren't reused
+            "CREATE TABLE IF NOT EXISTS note_ids (
+                namespace TEXT PRIMARY KEY,
+                last_id INTEGER NOT NULL
+            )",
+            "INSERT OR IGNORE INTO note_ids (namespace, last_id)
+             SELECT namespace, MAX(id) FROM notes GROUP BY namespace",
+        ],
+    },
+    Migration {
+        version: 13,
+        name: "add_api_key_namespaces",
+        statements: &[
+            // The namespaces a key may use, as a JSON array. Existing keys
+            // keep working in every namespace
+            "ALTER TABLE api_keys ADD COLUMN namespaces TEXT NOT NULL DEFAULT '[]'",
+        ],
+    },
 ];
 
 /// Get migrations that need to be applied given the current version.
@@ -61,3 +321,10 @@ pub fn get_pending_migrations(current_version: i64) -> Vec<&'static Migration> {
         .filter(|m| m.version > current_version)
         .collect()
 }
+
+/// Whether a migration statement adds a column. SQLite has no
+/// `ADD COLUMN IF NOT EXISTS`, so backends treat a duplicate column error
+/// from these statements as already applied.
+pub fn is_add_column(statement: &str) -> bool {
+    statement.starts_with("ALTER TABLE") && statement.contains(" ADD COLUMN ")
+}
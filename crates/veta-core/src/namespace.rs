@@ -0,0 +1,55 @@
+//! Namespaces ("spaces") partition one database into isolated knowledge
+//! bases, e.g. one per project sharing a worker.
+//!
+//! Each note belongs to one namespace, and listings, tags, search, the trash
+//! and the undo journal only see the notes of the current one. Each
+//! namespace numbers its notes from 1, so a note is known by its namespace
+//! and ID.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// The namespace used when none is chosen, which holds all notes created
+/// before namespaces existed.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// A namespace and the number of notes in it (not counting the trash).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceCount {
+    pub name: String,
+    pub count: i64,
+}
+
+/// Check that `name` can be used as a namespace: 1 to 64 lowercase letters,
+/// digits, `-` or `_`, so it's safe in URLs and paths.
+pub fn validate_namespace(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .bytes()
+            .all(|b| matches!(b, b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_'));
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::Validation(format!(
+            "invalid namespace '{}': use up to 64 lowercase letters, digits, '-' and '_'",
+            name
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_namespace() {
+        assert!(validate_namespace(DEFAULT_NAMESPACE).is_ok());
+        assert!(validate_namespace("team-a_2").is_ok());
+        assert!(validate_namespace("").is_err());
+        assert!(validate_namespace("Team").is_err());
+        assert!(validate_namespace("a/b").is_err());
+        assert!(validate_namespace(&"a".repeat(65)).is_err());
+    }
+}
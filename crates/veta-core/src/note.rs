@@ -1,7 +1,12 @@
+use std::fmt;
+use std::str::FromStr;
+
 use serde::{Deserialize, Serialize};
 
+use crate::{Cursor, Error, TagExpr};
+
 /// A full note with all fields.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Note {
     pub id: i64,
     pub title: String,
@@ -10,6 +15,7 @@ pub struct Note {
     /// References to external resources (source code paths, URLs, documentation links, etc.)
     #[serde(default)]
     pub references: Vec<String>,
+    pub created_at: String,
     pub updated_at: String,
 }
 
@@ -20,6 +26,39 @@ pub struct NoteSummary {
     pub title: String,
     pub body_preview: String,
     pub tags: Vec<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// One page of a note listing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotePage {
+    pub notes: Vec<NoteSummary>,
+    /// Cursor for the next page, or `None` if this is the last one.
+    pub next_cursor: Option<String>,
+}
+
+/// A deleted note in the trash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedNote {
+    #[serde(flatten)]
+    pub note: Note,
+    /// When the note was moved to the trash.
+    pub deleted_at: String,
+}
+
+/// A previous version of a note, saved before the note was updated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    pub note_id: i64,
+    /// Revision number, starting at 1 for the first saved version of a note.
+    pub revision: i64,
+    pub title: String,
+    pub body: String,
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub references: Vec<String>,
+    /// When this version of the note was written.
     pub updated_at: String,
 }
 
@@ -28,14 +67,148 @@ pub struct NoteSummary {
 pub struct TagCount {
     pub name: String,
     pub count: i64,
+    /// What the tag is for, if someone described it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Another name for a tag, which is replaced by the tag when notes are
+/// written or filtered.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagAlias {
+    pub alias: String,
+    pub tag: String,
+}
+
+/// A note matched by full-text search, with its relevance score.
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub note: Note,
+    /// BM25 relevance score (higher is more relevant).
+    pub score: f64,
+}
+
+/// A search result for display (truncated body plus relevance score).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub note: NoteSummary,
+    pub score: f64,
+}
+
+/// A note timestamp that queries can filter and sort by.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TimeField {
+    Created,
+    #[default]
+    Updated,
+}
+
+impl TimeField {
+    /// The timestamp's value on a note.
+    pub fn of(self, note: &Note) -> &str {
+        match self {
+            TimeField::Created => &note.created_at,
+            TimeField::Updated => &note.updated_at,
+        }
+    }
+
+    /// The SQL column, on notes aliased as `n`.
+    pub fn column(self) -> &'static str {
+        match self {
+            TimeField::Created => "n.created_at",
+            TimeField::Updated => "n.updated_at",
+        }
+    }
+}
+
+impl fmt::Display for TimeField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeField::Created => write!(f, "created"),
+            TimeField::Updated => write!(f, "updated"),
+        }
+    }
+}
+
+impl FromStr for TimeField {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "created" => Ok(TimeField::Created),
+            "updated" => Ok(TimeField::Updated),
+            _ => Err(Error::Validation(format!(
+                "invalid sort field '{}': expected created or updated",
+                s
+            ))),
+        }
+    }
+}
+
+/// Sort order for listed notes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    /// The SQL keyword for this order.
+    pub fn sql(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortOrder::Asc => write!(f, "asc"),
+            SortOrder::Desc => write!(f, "desc"),
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "asc" => Ok(SortOrder::Asc),
+            "desc" => Ok(SortOrder::Desc),
+            _ => Err(Error::Validation(format!(
+                "invalid sort order '{}': expected asc or desc",
+                s
+            ))),
+        }
+    }
 }
 
 /// Query parameters for listing notes.
 #[derive(Debug, Default, Clone)]
 pub struct NoteQuery {
-    pub tags: Option<Vec<String>>,
+    pub tags: Option<TagExpr>,
+    /// Only notes whose `time_field` is on or after this time.
     pub from: Option<String>,
+    /// Only notes whose `time_field` is on or before this time.
     pub to: Option<String>,
+    /// Timestamp used by `from`/`to` and for sorting.
+    pub time_field: TimeField,
+    pub order: SortOrder,
+    /// Only notes that sort after this cursor.
+    pub after: Option<Cursor>,
+    /// Number of matching notes to skip.
+    pub offset: Option<i64>,
+    /// Substrings that must all appear in the title (case-insensitive).
+    pub title: Vec<String>,
+    /// Substrings that must each appear in the title or body (case-insensitive).
+    pub text: Vec<String>,
+    /// Substrings that must each appear in one of the note's references.
+    pub references: Vec<String>,
     pub limit: Option<i64>,
 }
 
@@ -59,6 +232,22 @@ pub struct UpdateNote {
     pub references: Option<Vec<String>>,
 }
 
+impl Revision {
+    /// The note as it was at this revision. Revisions don't record when the
+    /// note was created, so `created_at` is left empty.
+    pub fn to_note(&self) -> Note {
+        Note {
+            id: self.note_id,
+            title: self.title.clone(),
+            body: self.body.clone(),
+            tags: self.tags.clone(),
+            references: self.references.clone(),
+            created_at: String::new(),
+            updated_at: self.updated_at.clone(),
+        }
+    }
+}
+
 impl Note {
     /// Convert to summary with truncated body preview.
     pub fn to_summary(&self, max_len: usize) -> NoteSummary {
@@ -84,6 +273,7 @@ impl Note {
             title: self.title.clone(),
             body_preview,
             tags: self.tags.clone(),
+            created_at: self.created_at.clone(),
             updated_at: self.updated_at.clone(),
         }
     }
@@ -0,0 +1,573 @@
+//! Structured note queries.
+//!
+//! A query is a whitespace-separated list of terms, all of which must match:
+//!
+//! ```text
+//! title:jwt                  title contains "jwt" (case-insensitive)
+//! tag:auth                   tagged auth (accepts tag expressions, e.g. tag:a+b,c)
+//! -tag:old                   not tagged old
+//! updated:>2026-01-01        updated on or after the date
+//! updated:<"2 days ago"      updated on or before the date
+//! created:>2026-01-01        created on or after the date (can't be combined with updated:)
+//! ref:src/auth.rs            a reference contains "src/auth.rs"
+//! re:expir(y|es)             title or body matches the regex
+//! "token expiry"             title or body contains the phrase (case-insensitive)
+//! ```
+//!
+//! Values containing spaces can be quoted, e.g. `title:"token expiry"`.
+
+use std::cmp::Ordering;
+
+use crate::{parse_human_date, Error, Note, NoteQuery, SortOrder, TagExpr, TimeField};
+
+/// A parsed query: note filters plus an optional regex to match against
+/// title and body (see [`crate::Database::grep`]).
+#[derive(Debug, Default, Clone)]
+pub struct ParsedQuery {
+    pub query: NoteQuery,
+    pub pattern: Option<String>,
+}
+
+/// Parse a structured query string.
+pub fn parse_query(input: &str) -> Result<ParsedQuery, Error> {
+    let mut parsed = ParsedQuery::default();
+
+    for term in lex(input)? {
+        let key = match term.key {
+            Some(key) => key,
+            None => {
+                if term.negated {
+                    return Err(Error::Validation(format!(
+                        "invalid query term '-{}': only tag: terms can be negated",
+                        term.value
+                    )));
+                }
+                parsed.query.text.push(term.value);
+                continue;
+            }
+        };
+
+        if term.value.is_empty() {
+            return Err(Error::Validation(format!(
+                "invalid query term '{}:': missing value",
+                key
+            )));
+        }
+        if term.negated && key != "tag" {
+            return Err(Error::Validation(format!(
+                "invalid query term '-{}:{}': only tag: terms can be negated",
+                key, term.value
+            )));
+        }
+
+        let query = &mut parsed.query;
+        match key.as_str() {
+            "title" => query.title.push(term.value),
+            "ref" => query.references.push(term.value),
+            "tag" => {
+                let expr = if term.negated {
+                    negated_tags(&term.value)?
+                } else {
+                    TagExpr::parse(&term.value)?
+                };
+                if let Some(expr) = expr {
+                    query.tags = Some(match query.tags.take() {
+                        Some(tags) => tags.and(expr),
+                        None => expr,
+                    });
+                }
+            }
+            "updated" | "created" => {
+                let field = if key == "created" {
+                    TimeField::Created
+                } else {
+                    TimeField::Updated
+                };
+                if field != query.time_field && (query.from.is_some() || query.to.is_some()) {
+                    return Err(Error::Validation(
+                        "invalid query: created: and updated: terms can't be combined".into(),
+                    ));
+                }
+                query.time_field = field;
+
+                let (op, date) = split_comparison(&key, &term.value)?;
+                let date = parse_human_date(date)?;
+                // Keep the narrowest range if a bound is given twice
+                if op == '>' {
+                    if query.from.as_ref().is_none_or(|from| date > *from) {
+                        query.from = Some(date);
+                    }
+                } else if query.to.as_ref().is_none_or(|to| date < *to) {
+                    query.to = Some(date);
+                }
+            }
+            "re" => {
+                if parsed.pattern.is_some() {
+                    return Err(Error::Validation(
+                        "invalid query: only one re: term is allowed".into(),
+                    ));
+                }
+                parsed.pattern = Some(term.value);
+            }
+            _ => {
+                return Err(Error::Validation(format!(
+                    "invalid query term '{}:{}': unknown field '{}' (expected title, tag, updated, created, ref or re; quote the term to search for it as text)",
+                    key, term.value, key
+                )));
+            }
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// `-tag:a,b` excludes each of the listed tags.
+fn negated_tags(value: &str) -> Result<Option<TagExpr>, Error> {
+    if value.contains(['+', '-']) || value.chars().any(char::is_whitespace) {
+        return Err(Error::Validation(format!(
+            "invalid query term '-tag:{}': negated tags must be a comma-separated list",
+            value
+        )));
+    }
+    let exclude: Vec<String> = value
+        .split(',')
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect();
+    if exclude.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(TagExpr {
+        any: Vec::new(),
+        exclude,
+    }))
+}
+
+/// Split `>date`, `>=date`, `<date` or `<=date` into `'>'`/`'<'` and the date.
+///
+/// Bounds are inclusive either way, matching `--from` and `--to`.
+fn split_comparison<'a>(key: &str, value: &'a str) -> Result<(char, &'a str), Error> {
+    let op = match value.chars().next() {
+        Some(c @ ('>' | '<')) => c,
+        _ => {
+            return Err(Error::Validation(format!(
+                "invalid query term '{0}:{1}': expected a comparison such as {0}:>2026-01-01",
+                key, value
+            )))
+        }
+    };
+    let date = value[1..].strip_prefix('=').unwrap_or(&value[1..]);
+    Ok((op, date))
+}
+
+#[derive(Debug)]
+struct Term {
+    negated: bool,
+    key: Option<String>,
+    value: String,
+}
+
+/// Split a query into terms, honouring double quotes.
+fn lex(input: &str) -> Result<Vec<Term>, Error> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut terms = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        // A leading `-` negates the term, unless it stands alone
+        let negated = chars[i] == '-' && chars.get(i + 1).is_some_and(|c| !c.is_whitespace());
+        if negated {
+            i += 1;
+        }
+
+        let mut key = None;
+        let mut value = String::new();
+        let mut quoted = false;
+
+        while i < chars.len() && !chars[i].is_whitespace() {
+            match chars[i] {
+                '"' => {
+                    quoted = true;
+                    i += 1;
+                    while i < chars.len() && chars[i] != '"' {
+                        value.push(chars[i]);
+                        i += 1;
+                    }
+                    if i == chars.len() {
+                        return Err(Error::Validation(format!(
+                            "invalid query '{}': unterminated quote",
+                            input
+                        )));
+                    }
+                }
+                // Only an unquoted prefix can be a field name
+                ':' if key.is_none() && !quoted && !value.is_empty() => {
+                    key = Some(std::mem::take(&mut value).to_lowercase());
+                }
+                c => value.push(c),
+            }
+            i += 1;
+        }
+
+        if key.is_none() && value.is_empty() {
+            // `""`
+            continue;
+        }
+        terms.push(Term {
+            negated,
+            key,
+            value,
+        });
+    }
+
+    Ok(terms)
+}
+
+/// Case-insensitive substring match, using ASCII case folding like SQLite's
+/// `lower()` so that every backend agrees.
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack
+        .to_ascii_lowercase()
+        .contains(&needle.to_ascii_lowercase())
+}
+
+impl NoteQuery {
+    /// Use `field` for date filters and sorting.
+    ///
+    /// Fails if the query already filters on the other timestamp, e.g. from a
+    /// `created:` term in a query that should be sorted by update time.
+    pub fn with_time_field(self, field: TimeField) -> Result<Self, Error> {
+        if field != self.time_field && (self.from.is_some() || self.to.is_some()) {
+            return Err(Error::Validation(format!(
+                "can't sort by {} time when filtering by {} time",
+                field, self.time_field
+            )));
+        }
+        Ok(NoteQuery {
+            time_field: field,
+            ..self
+        })
+    }
+
+    /// Evaluate the query's filters (everything except `limit` and `offset`)
+    /// against a note.
+    pub fn matches(&self, note: &Note) -> bool {
+        if let Some(ref tags) = self.tags {
+            if !tags.matches(&note.tags) {
+                return false;
+            }
+        }
+        if let Some(ref after) = self.after {
+            if !after.is_before(note) {
+                return false;
+            }
+        }
+        let time = self.time_field.of(note);
+        if let Some(ref from) = self.from {
+            if time < from.as_str() {
+                return false;
+            }
+        }
+        if let Some(ref to) = self.to {
+            if time > to.as_str() {
+                return false;
+            }
+        }
+
+        self.title
+            .iter()
+            .all(|t| contains_ignore_case(&note.title, t))
+            && self.text.iter().all(|t| {
+                contains_ignore_case(&note.title, t) || contains_ignore_case(&note.body, t)
+            })
+            && self
+                .references
+                .iter()
+                .all(|r| note.references.iter().any(|nr| nr.contains(r.as_str())))
+    }
+
+    /// Render the query's filters as an SQL condition on notes aliased as `n`.
+    ///
+    /// Values are bound as numbered parameters (`?N`), appended to `params`.
+    pub fn to_sql(&self, params: &mut Vec<String>) -> String {
+        let mut conditions = Vec::new();
+
+        if let Some(ref tags) = self.tags {
+            conditions.push(tags.to_sql(params));
+        }
+
+        if let Some(ref after) = self.after {
+            conditions.push(after.to_sql(params));
+        }
+
+        let column = self.time_field.column();
+        if let Some(ref from) = self.from {
+            params.push(from.clone());
+            conditions.push(format!("{} >= ?{}", column, params.len()));
+        }
+
+        if let Some(ref to) = self.to {
+            params.push(to.clone());
+            conditions.push(format!("{} <= ?{}", column, params.len()));
+        }
+
+        for title in &self.title {
+            params.push(title.to_ascii_lowercase());
+            conditions.push(format!("instr(lower(n.title), ?{}) > 0", params.len()));
+        }
+
+        for text in &self.text {
+            params.push(text.to_ascii_lowercase());
+            conditions.push(format!(
+                "(instr(lower(n.title), ?{0}) > 0 OR instr(lower(n.body), ?{0}) > 0)",
+                params.len()
+            ));
+        }
+
+        for reference in &self.references {
+            params.push(reference.clone());
+            conditions.push(format!(
+                "EXISTS (SELECT 1 FROM json_each(n.\"references\") WHERE instr(json_each.value, ?{}) > 0)",
+                params.len()
+            ));
+        }
+
+        if conditions.is_empty() {
+            "1 = 1".to_string()
+        } else {
+            conditions.join(" AND ")
+        }
+    }
+
+    /// SQL `ORDER BY` terms for the query's sort, with note ID as tie-breaker.
+    pub fn order_by_sql(&self) -> String {
+        let order = self.order.sql();
+        format!("{} {}, n.id {}", self.time_field.column(), order, order)
+    }
+
+    /// SQL `LIMIT`/`OFFSET` clause (with a leading space), or an empty string.
+    pub fn limit_sql(&self) -> String {
+        match (self.limit, self.offset.filter(|&n| n > 0)) {
+            (Some(limit), Some(offset)) => format!(" LIMIT {} OFFSET {}", limit, offset),
+            (Some(limit), None) => format!(" LIMIT {}", limit),
+            // SQLite requires a LIMIT before OFFSET; -1 means no limit
+            (None, Some(offset)) => format!(" LIMIT -1 OFFSET {}", offset),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// Apply `offset` and `limit` to notes that are already filtered and sorted.
+    pub fn paginate(&self, notes: &mut Vec<Note>) {
+        let offset = self.offset.unwrap_or(0).max(0) as usize;
+        notes.drain(..offset.min(notes.len()));
+        if let Some(limit) = self.limit {
+            notes.truncate(limit.max(0) as usize);
+        }
+    }
+
+    /// Sort notes in the same order as [`NoteQuery::order_by_sql`].
+    pub fn sort(&self, notes: &mut [Note]) {
+        notes.sort_by(|a, b| {
+            let ordering: Ordering = self
+                .time_field
+                .of(a)
+                .cmp(self.time_field.of(b))
+                .then_with(|| a.id.cmp(&b.id));
+            match self.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(title: &str, body: &str, tags: &[&str], references: &[&str]) -> Note {
+        Note {
+            id: 1,
+            title: title.to_string(),
+            body: body.to_string(),
+            tags: tags.iter().map(|s| s.to_string()).collect(),
+            references: references.iter().map(|s| s.to_string()).collect(),
+            created_at: "2026-01-10 09:00:00".to_string(),
+            updated_at: "2026-01-15 12:00:00".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_full_example() {
+        let parsed = parse_query(
+            r#"title:jwt tag:auth -tag:old updated:>2026-01-01 ref:src/auth.rs "token expiry""#,
+        )
+        .unwrap();
+        let query = parsed.query;
+
+        assert_eq!(query.title, vec!["jwt"]);
+        assert_eq!(query.tags.unwrap().to_string(), "auth,-old");
+        assert_eq!(query.from.as_deref(), Some("2026-01-01 00:00:00"));
+        assert_eq!(query.to, None);
+        assert_eq!(query.references, vec!["src/auth.rs"]);
+        assert_eq!(query.text, vec!["token expiry"]);
+        assert_eq!(parsed.pattern, None);
+    }
+
+    #[test]
+    fn test_parse_quoted_values() {
+        let parsed = parse_query(r#"title:"token expiry" updated:<"2026-02-01 10:00:00""#).unwrap();
+        assert_eq!(parsed.query.title, vec!["token expiry"]);
+        assert_eq!(parsed.query.to.as_deref(), Some("2026-02-01 10:00:00"));
+    }
+
+    #[test]
+    fn test_parse_quoted_field_is_text() {
+        let parsed = parse_query(r#""title:jwt" https"#).unwrap();
+        assert_eq!(parsed.query.text, vec!["title:jwt", "https"]);
+        assert!(parsed.query.title.is_empty());
+    }
+
+    #[test]
+    fn test_parse_multiple_tag_terms_are_anded() {
+        let query = parse_query("tag:a,b tag:c").unwrap().query;
+        let tags = query.tags.unwrap();
+        assert!(tags.matches(&["a".to_string(), "c".to_string()]));
+        assert!(!tags.matches(&["a".to_string()]));
+    }
+
+    #[test]
+    fn test_parse_updated_keeps_narrowest_range() {
+        let query = parse_query("updated:>=2026-01-01 updated:>2026-01-10 updated:<2026-03-01")
+            .unwrap()
+            .query;
+        assert_eq!(query.from.as_deref(), Some("2026-01-10 00:00:00"));
+        assert_eq!(query.to.as_deref(), Some("2026-03-01 00:00:00"));
+    }
+
+    #[test]
+    fn test_parse_regex() {
+        let parsed = parse_query("tag:auth re:expir(y|es)").unwrap();
+        assert_eq!(parsed.pattern.as_deref(), Some("expir(y|es)"));
+        assert!(parse_query("re:a re:b").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_query("tags:auth").is_err());
+        assert!(parse_query("title:").is_err());
+        assert!(parse_query("-title:jwt").is_err());
+        assert!(parse_query("-jwt").is_err());
+        assert!(parse_query("-tag:a+b").is_err());
+        assert!(parse_query("updated:2026-01-01").is_err());
+        assert!(parse_query("updated:>whenever").is_err());
+        assert!(parse_query(r#""unterminated"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        let parsed = parse_query("   ").unwrap();
+        assert!(parsed.query.tags.is_none());
+        assert!(parsed.query.text.is_empty());
+        assert!(parsed.pattern.is_none());
+    }
+
+    #[test]
+    fn test_matches() {
+        let n = note(
+            "JWT handling",
+            "Token expiry is 15 minutes",
+            &["auth"],
+            &["src/auth.rs:10"],
+        );
+
+        let matches = |input: &str| parse_query(input).unwrap().query.matches(&n);
+        assert!(matches(
+            r#"title:jwt tag:auth ref:src/auth.rs "token expiry""#
+        ));
+        assert!(matches("updated:>2026-01-01 updated:<2026-02-01"));
+        assert!(!matches("title:expiry"));
+        assert!(!matches("-tag:auth"));
+        assert!(!matches("ref:SRC/auth.rs"));
+        assert!(!matches("updated:>2026-01-16"));
+        assert!(matches("created:<2026-01-11"));
+        assert!(!matches("created:>2026-01-11"));
+        assert!(!matches(r#""token refresh""#));
+    }
+
+    #[test]
+    fn test_created_terms() {
+        let query = parse_query("created:>2026-01-01").unwrap().query;
+        assert_eq!(query.time_field, TimeField::Created);
+        assert_eq!(query.from.as_deref(), Some("2026-01-01 00:00:00"));
+
+        assert!(parse_query("created:>2026-01-01 updated:<2026-02-01").is_err());
+        assert!(query.clone().with_time_field(TimeField::Updated).is_err());
+        assert!(query.with_time_field(TimeField::Created).is_ok());
+        assert!(NoteQuery::default()
+            .with_time_field(TimeField::Created)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut a = note("a", "", &[], &[]);
+        a.id = 1;
+        a.created_at = "2026-01-01 00:00:00".to_string();
+        a.updated_at = "2026-01-20 00:00:00".to_string();
+        let mut b = note("b", "", &[], &[]);
+        b.id = 2;
+        b.created_at = "2026-01-05 00:00:00".to_string();
+        b.updated_at = "2026-01-10 00:00:00".to_string();
+        let mut c = b.clone();
+        c.id = 3;
+
+        let ids = |query: NoteQuery| {
+            let mut notes = vec![a.clone(), b.clone(), c.clone()];
+            query.sort(&mut notes);
+            notes.iter().map(|n| n.id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(NoteQuery::default()), vec![1, 3, 2]);
+        assert_eq!(
+            ids(NoteQuery {
+                time_field: TimeField::Created,
+                ..Default::default()
+            }),
+            vec![3, 2, 1]
+        );
+        assert_eq!(
+            ids(NoteQuery {
+                time_field: TimeField::Created,
+                order: SortOrder::Asc,
+                ..Default::default()
+            }),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            NoteQuery::default().order_by_sql(),
+            "n.updated_at DESC, n.id DESC"
+        );
+    }
+
+    #[test]
+    fn test_to_sql_numbers_params() {
+        let query = parse_query(r#"tag:a title:JWT "x" ref:y updated:>2026-01-01"#)
+            .unwrap()
+            .query;
+        let mut params = vec!["existing".to_string()];
+        let sql = query.to_sql(&mut params);
+        assert_eq!(
+            params,
+            vec!["existing", "a", "2026-01-01 00:00:00", "jwt", "x", "y"]
+        );
+        for n in 2..=6 {
+            assert!(sql.contains(&format!("?{}", n)));
+        }
+        assert_eq!(NoteQuery::default().to_sql(&mut params), "1 = 1");
+    }
+}
@@ -0,0 +1,258 @@
+//! Parsing note references into what they point at.
+//!
+//! References are stored as written. [`Reference::parse`] reads one as:
+//!
+//! - a file, with an optional line or range: `src/auth.rs`, `src/auth.rs:42`
+//!   or `src/auth.rs:42-58`. A quoted snippet of the code there may follow,
+//!   `src/auth.rs:42 "fn verify_token"`, so the code can be found again
+//!   after it moves
+//! - a URL: `https://jwt.io/introduction`
+//! - a note: `note:17`
+//! - a commit: `commit:1a2b3c4`, or a bare SHA of 7 to 40 hex digits
+//!
+//! Anything else, such as `RFC 7519`, is kept as text.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Lines of a file, numbered from 1. A single line has `start == end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LineRange {
+    /// The same number of lines, starting at `start`.
+    pub fn moved_to(self, start: usize) -> Self {
+        LineRange {
+            start,
+            end: start + (self.end - self.start),
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        let number = |s: &str| s.parse::<usize>().ok().filter(|&n| n > 0);
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (number(start)?, number(end)?),
+            None => (number(s)?, number(s)?),
+        };
+        (start <= end).then_some(LineRange { start, end })
+    }
+}
+
+impl fmt::Display for LineRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// What a reference points at.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Reference {
+    File {
+        /// Relative to the project, the directory that holds `.veta`.
+        path: String,
+        lines: Option<LineRange>,
+        snippet: Option<String>,
+    },
+    Url {
+        url: String,
+    },
+    Note {
+        id: i64,
+    },
+    Commit {
+        sha: String,
+    },
+    Text {
+        text: String,
+    },
+}
+
+impl Reference {
+    pub fn parse(reference: &str) -> Self {
+        let s = reference.trim();
+        let text = || Reference::Text {
+            text: s.to_string(),
+        };
+
+        if let Some((scheme, rest)) = s.split_once("://") {
+            let scheme_chars = |c: char| c.is_ascii_alphanumeric() || "+-.".contains(c);
+            if !scheme.is_empty() && scheme.chars().all(scheme_chars) && !rest.is_empty() {
+                return Reference::Url { url: s.to_string() };
+            }
+        }
+        if let Some(id) = s.strip_prefix("note:") {
+            return match id.parse() {
+                Ok(id) if id > 0 => Reference::Note { id },
+                _ => text(),
+            };
+        }
+        if let Some(sha) = s.strip_prefix("commit:") {
+            if !is_sha(sha) {
+                return text();
+            }
+            return Reference::Commit {
+                sha: sha.to_lowercase(),
+            };
+        }
+        // A bare SHA has digits and letters, so numbers and words aren't one
+        if is_sha(s)
+            && s.chars().any(|c| c.is_ascii_digit())
+            && s.chars().any(|c| c.is_ascii_alphabetic())
+        {
+            return Reference::Commit {
+                sha: s.to_lowercase(),
+            };
+        }
+
+        let (location, snippet) = match s.split_once(" \"") {
+            Some((location, quoted)) if quoted.len() > 1 && quoted.ends_with('"') => {
+                (location.trim_end(), Some(&quoted[..quoted.len() - 1]))
+            }
+            _ => (s, None),
+        };
+        if location.is_empty() || location.contains(char::is_whitespace) {
+            return text();
+        }
+        let (path, lines) = match location.rsplit_once(':') {
+            Some((path, lines)) => match LineRange::parse(lines) {
+                Some(lines) if !path.is_empty() => (path, Some(lines)),
+                _ => (location, None),
+            },
+            None => (location, None),
+        };
+        // Without a line, only something that looks like a path is a file
+        if lines.is_none() && snippet.is_none() && !path.contains(['/', '.']) {
+            return text();
+        }
+        Reference::File {
+            path: path.to_string(),
+            lines,
+            snippet: snippet.map(str::to_string),
+        }
+    }
+}
+
+fn is_sha(s: &str) -> bool {
+    (7..=40).contains(&s.len()) && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reference::File {
+                path,
+                lines,
+                snippet,
+            } => {
+                write!(f, "{}", path)?;
+                if let Some(lines) = lines {
+                    write!(f, ":{}", lines)?;
+                }
+                if let Some(snippet) = snippet {
+                    write!(f, " \"{}\"", snippet)?;
+                }
+                Ok(())
+            }
+            Reference::Url { url } => write!(f, "{}", url),
+            Reference::Note { id } => write!(f, "note:{}", id),
+            Reference::Commit { sha } => write!(f, "commit:{}", sha),
+            Reference::Text { text } => write!(f, "{}", text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, lines: Option<(usize, usize)>, snippet: Option<&str>) -> Reference {
+        Reference::File {
+            path: path.to_string(),
+            lines: lines.map(|(start, end)| LineRange { start, end }),
+            snippet: snippet.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_parse_files() {
+        assert_eq!(
+            Reference::parse("src/auth/token.rs:142"),
+            file("src/auth/token.rs", Some((142, 142)), None)
+        );
+        assert_eq!(
+            Reference::parse(" src/lib.rs:10-20 "),
+            file("src/lib.rs", Some((10, 20)), None)
+        );
+        assert_eq!(
+            Reference::parse("wrangler.toml"),
+            file("wrangler.toml", None, None)
+        );
+        assert_eq!(
+            Reference::parse("Makefile:3 \"build: deps\""),
+            file("Makefile", Some((3, 3)), Some("build: deps"))
+        );
+        // Not line numbers, so part of the path
+        assert_eq!(
+            Reference::parse("src/lib.rs:20-10"),
+            file("src/lib.rs:20-10", None, None)
+        );
+        assert_eq!(
+            Reference::parse("C:\\veta\\a.rs:0"),
+            file("C:\\veta\\a.rs:0", None, None)
+        );
+    }
+
+    #[test]
+    fn test_parse_other_kinds() {
+        assert_eq!(
+            Reference::parse("https://jwt.io/introduction"),
+            Reference::Url {
+                url: "https://jwt.io/introduction".into()
+            }
+        );
+        assert_eq!(Reference::parse("note:17"), Reference::Note { id: 17 });
+        assert_eq!(
+            Reference::parse("1A2B3C4"),
+            Reference::Commit {
+                sha: "1a2b3c4".into()
+            }
+        );
+        assert_eq!(
+            Reference::parse("commit:deadbeef"),
+            Reference::Commit {
+                sha: "deadbeef".into()
+            }
+        );
+        for text in ["RFC 7519", "README", "20260101", "note:x", "commit:xyz", ""] {
+            assert_eq!(
+                Reference::parse(text),
+                Reference::Text { text: text.into() }
+            );
+        }
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for reference in [
+            "src/lib.rs",
+            "src/lib.rs:10-20 \"fn main() {\"",
+            "https://example.com/a?b=c",
+            "note:17",
+            "commit:1a2b3c4",
+            "RFC 7519",
+        ] {
+            assert_eq!(Reference::parse(reference).to_string(), reference);
+        }
+        let moved = LineRange { start: 10, end: 20 }.moved_to(31);
+        assert_eq!(moved.to_string(), "31-41");
+    }
+}
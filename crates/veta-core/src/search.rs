@@ -0,0 +1,118 @@
+//! Full-text search helpers shared by all backends.
+//!
+//! SQLite and D1 rank with FTS5's built-in `bm25()`, while file-based storage
+//! scores with [`bm25_score`]. Both use the same tokenization, column weights
+//! and parameters so results are comparable across backends.
+
+/// BM25 term frequency saturation parameter (same as FTS5).
+pub const BM25_K1: f64 = 1.2;
+
+/// BM25 document length normalization parameter (same as FTS5).
+pub const BM25_B: f64 = 0.75;
+
+/// Weight of a match in the title relative to the body.
+pub const TITLE_WEIGHT: f64 = 2.0;
+
+/// Weight of a match in the body.
+pub const BODY_WEIGHT: f64 = 1.0;
+
+/// Split text into lowercase alphanumeric tokens.
+///
+/// This mirrors the FTS5 `unicode61` tokenizer closely enough that the
+/// file backend and the SQL backends agree on what a "term" is.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+/// Build an FTS5 MATCH expression from a free-text query.
+///
+/// Every token is quoted so FTS5 operators in user input are treated as
+/// plain text, and all tokens must be present in a matching note.
+/// Returns `None` if the query contains no searchable tokens.
+pub fn fts5_query(query: &str) -> Option<String> {
+    let tokens = tokenize(query);
+    if tokens.is_empty() {
+        return None;
+    }
+    Some(
+        tokens
+            .iter()
+            .map(|t| format!("\"{}\"", t))
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Inverse document frequency of a term, as computed by FTS5.
+pub fn bm25_idf(total_docs: usize, docs_with_term: usize) -> f64 {
+    let n = total_docs as f64;
+    let nq = docs_with_term as f64;
+    let idf = ((n - nq + 0.5) / (nq + 0.5)).ln();
+    if idf <= 0.0 {
+        1e-6
+    } else {
+        idf
+    }
+}
+
+/// BM25 score for a single document.
+///
+/// `terms` holds one `(idf, weighted_term_frequency)` pair per query term,
+/// where the term frequency is `TITLE_WEIGHT * title_hits + BODY_WEIGHT * body_hits`.
+/// Higher scores are better (unlike FTS5's raw `bm25()`, which is negated).
+pub fn bm25_score(terms: &[(f64, f64)], doc_len: usize, avg_doc_len: f64) -> f64 {
+    let len_norm = if avg_doc_len > 0.0 {
+        1.0 - BM25_B + BM25_B * (doc_len as f64 / avg_doc_len)
+    } else {
+        1.0
+    };
+    terms
+        .iter()
+        .map(|(idf, tf)| idf * (tf * (BM25_K1 + 1.0)) / (tf + BM25_K1 * len_norm))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("Auth uses JWT, tokens expire (15min)"),
+            vec!["auth", "uses", "jwt", "tokens", "expire", "15min"]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_empty() {
+        assert!(tokenize("  -- !! ").is_empty());
+    }
+
+    #[test]
+    fn test_fts5_query_quotes_tokens() {
+        assert_eq!(
+            fts5_query("token OR \"expiry\"").unwrap(),
+            "\"token\" \"or\" \"expiry\""
+        );
+        assert!(fts5_query("***").is_none());
+    }
+
+    #[test]
+    fn test_rare_terms_score_higher() {
+        let common = bm25_score(&[(bm25_idf(100, 90), 1.0)], 10, 10.0);
+        let rare = bm25_score(&[(bm25_idf(100, 2), 1.0)], 10, 10.0);
+        assert!(rare > common);
+    }
+
+    #[test]
+    fn test_shorter_documents_score_higher() {
+        let idf = bm25_idf(100, 5);
+        let short = bm25_score(&[(idf, 1.0)], 5, 10.0);
+        let long = bm25_score(&[(idf, 1.0)], 50, 10.0);
+        assert!(short > long);
+    }
+}
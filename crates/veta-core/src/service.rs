@@ -1,4 +1,132 @@
-use crate::{CreateNote, Database, Error, Note, NoteQuery, NoteSummary, TagCount, UpdateNote};
+use std::collections::BTreeMap;
+
+use crate::auth::{self, ScopedDatabase};
+use crate::{
+    diff, namespace, search, tagexpr, ApiKey, CreateApiKey, CreateNote, Cursor, Database, Error,
+    ImportMode, ImportSummary, LinkKind, NamespaceCount, Note, NoteLink, NotePage, NoteQuery,
+    Operation, PreviousTags, Revision, Scope, SearchResult, TagAlias, TagCount, TagExpr,
+    TrashedNote, UpdateNote,
+};
+
+/// Default number of notes per page for listings and grep.
+const DEFAULT_PAGE_SIZE: i64 = 100;
+
+/// Apply the default page size (0 means no limit) and check that the cursor
+/// was created for the query's sort.
+fn page_query(query: NoteQuery) -> Result<NoteQuery, Error> {
+    if let Some(ref after) = query.after {
+        if after.time_field != query.time_field || after.order != query.order {
+            return Err(Error::Validation(
+                "cursor was created for a different sort order".into(),
+            ));
+        }
+    }
+    let limit = match query.limit {
+        Some(0) => None,
+        Some(n) => Some(n),
+        None => Some(DEFAULT_PAGE_SIZE),
+    };
+    Ok(NoteQuery { limit, ..query })
+}
+
+/// Fetch one note more than the page size, to tell whether there's a next page.
+fn lookahead(query: &NoteQuery) -> NoteQuery {
+    NoteQuery {
+        limit: query.limit.map(|n| n + 1),
+        ..query.clone()
+    }
+}
+
+/// Turn the notes fetched with [`lookahead`] into a page.
+fn into_page(mut notes: Vec<Note>, query: &NoteQuery) -> NotePage {
+    let mut next_cursor = None;
+    if let Some(limit) = query.limit {
+        if notes.len() as i64 > limit {
+            notes.truncate(limit.max(0) as usize);
+            next_cursor = notes
+                .last()
+                .map(|last| Cursor::after(last, query.time_field, query.order).to_string());
+        }
+    }
+    NotePage {
+        notes: notes.into_iter().map(|n| n.to_summary(140)).collect(),
+        next_cursor,
+    }
+}
+
+/// Lowercase and trim a tag, and each part of a hierarchical tag like
+/// `lang/rust`, dropping empty parts. Returns `None` if nothing is left.
+fn clean_tag(tag: &str) -> Result<Option<String>, Error> {
+    let parts: Vec<String> = tag
+        .split('/')
+        .map(|part| part.trim().to_lowercase())
+        .filter(|part| !part.is_empty())
+        .collect();
+    // The files backend keeps `lang/rust` in tags/lang/rust
+    if parts.iter().any(|part| part == "." || part == "..") {
+        return Err(Error::Validation(format!(
+            "invalid tag '{}': '.' and '..' can't be parts of a tag",
+            tag.trim()
+        )));
+    }
+    Ok((!parts.is_empty()).then(|| parts.join("/")))
+}
+
+/// Normalize tags: lowercase, trim, deduplicate, remove empty.
+fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>, Error> {
+    let mut tags = tags
+        .iter()
+        .filter_map(|t| clean_tag(t).transpose())
+        .collect::<Result<Vec<_>, _>>()?;
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
+}
+
+/// Normalize a single tag name like [`normalize_tags`] does.
+fn normalize_tag(tag: &str) -> Result<String, Error> {
+    clean_tag(tag)?.ok_or_else(|| Error::Validation("tag cannot be empty".into()))
+}
+
+/// Replace `tag`, or the parent of it that's an alias, by the aliased tag:
+/// with `rust` an alias of `lang/rust`, `rust/async` is `lang/rust/async`.
+fn resolve_alias(aliases: &BTreeMap<String, String>, tag: &str) -> String {
+    let mut prefix = tag;
+    loop {
+        if let Some(target) = aliases.get(prefix) {
+            return format!("{}{}", target, &tag[prefix.len()..]);
+        }
+        match prefix.rfind('/') {
+            Some(i) => prefix = &prefix[..i],
+            None => return tag.to_string(),
+        }
+    }
+}
+
+/// Normalize tags like [`normalize_tags`], with aliases replaced.
+fn canonical_tags(
+    aliases: &BTreeMap<String, String>,
+    tags: Vec<String>,
+) -> Result<Vec<String>, Error> {
+    let mut tags: Vec<String> = normalize_tags(tags)?
+        .iter()
+        .map(|t| resolve_alias(aliases, t))
+        .collect();
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
+}
+
+/// Normalize references: trim, deduplicate, remove empty.
+fn normalize_references(references: Vec<String>) -> Vec<String> {
+    let mut references: Vec<String> = references
+        .into_iter()
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty())
+        .collect();
+    references.dedup();
+    references
+}
 
 /// The main service that contains all business logic.
 /// Generic over the database implementation.
@@ -11,6 +139,68 @@ impl<D: Database> VetaService<D> {
         Self { db }
     }
 
+    /// The database behind this service, for writes that bypass validation
+    /// and the undo journal.
+    pub(crate) fn db(&self) -> &D {
+        &self.db
+    }
+
+    /// The namespace this service reads and writes.
+    pub fn namespace(&self) -> &str {
+        self.db.namespace()
+    }
+
+    /// A service for `namespace` in the same database.
+    pub fn in_namespace(&self, namespace: &str) -> Result<Self, Error> {
+        namespace::validate_namespace(namespace)?;
+        Ok(Self::new(self.db.with_namespace(namespace)?))
+    }
+
+    /// List the namespaces that have notes, with their note counts.
+    pub async fn list_namespaces(&self) -> Result<Vec<NamespaceCount>, Error> {
+        self.db.list_namespaces().await
+    }
+
+    /// A view of this service limited to what an API key with `scope`, the
+    /// tag allow-list `tags` and the namespaces `namespaces` may do. Fails
+    /// with [`Error::Forbidden`] if the key can't use this namespace.
+    pub fn scoped(
+        &self,
+        scope: Scope,
+        tags: Vec<String>,
+        namespaces: Vec<String>,
+    ) -> Result<VetaService<ScopedDatabase<'_, D>>, Error> {
+        let db = ScopedDatabase::new(&self.db, scope, tags, namespaces)?;
+        Ok(VetaService::new(db))
+    }
+
+    /// Tag aliases, mapped to the tags they stand for.
+    async fn aliases(&self) -> Result<BTreeMap<String, String>, Error> {
+        let aliases = self.db.list_tag_aliases().await?;
+        Ok(aliases.into_iter().map(|a| (a.alias, a.tag)).collect())
+    }
+
+    /// Normalize tags and replace aliases by the tags they stand for, as
+    /// when a note is written.
+    pub(crate) async fn canonicalize_tags(&self, tags: Vec<String>) -> Result<Vec<String>, Error> {
+        canonical_tags(&self.aliases().await?, tags)
+    }
+
+    /// Replace aliases in a tag filter by the tags they stand for.
+    async fn canonical_filter(&self, tags: Option<TagExpr>) -> Result<Option<TagExpr>, Error> {
+        let Some(tags) = tags else {
+            return Ok(None);
+        };
+        let aliases = self.aliases().await?;
+        Ok(Some(tags.map_tags(|t| resolve_alias(&aliases, &t))))
+    }
+
+    /// Replace aliases in a query's tag filter.
+    async fn canonical_query(&self, query: NoteQuery) -> Result<NoteQuery, Error> {
+        let tags = self.canonical_filter(query.tags.clone()).await?;
+        Ok(NoteQuery { tags, ..query })
+    }
+
     /// Add a new note.
     pub async fn add_note(
         &self,
@@ -25,31 +215,17 @@ impl<D: Database> VetaService<D> {
             return Err(Error::Validation("title cannot be empty".into()));
         }
 
-        // Normalize tags: lowercase, trim, deduplicate, remove empty
-        let mut tags: Vec<String> = tags
-            .into_iter()
-            .map(|t| t.trim().to_lowercase())
-            .filter(|t| !t.is_empty())
-            .collect();
-        tags.sort();
-        tags.dedup();
-
-        // Normalize references: trim, deduplicate, remove empty
-        let mut references: Vec<String> = references
-            .into_iter()
-            .map(|r| r.trim().to_string())
-            .filter(|r| !r.is_empty())
-            .collect();
-        references.dedup();
-
-        self.db
+        let id = self
+            .db
             .add_note(CreateNote {
                 title,
                 body,
-                tags,
-                references,
+                tags: canonical_tags(&self.aliases().await?, tags)?,
+                references: normalize_references(references),
             })
-            .await
+            .await?;
+        self.db.push_operation(&Operation::Add { id }).await?;
+        Ok(id)
     }
 
     /// Get a note by ID.
@@ -57,24 +233,21 @@ impl<D: Database> VetaService<D> {
         self.db.get_note(id).await
     }
 
-    /// List notes with optional filters.
-    pub async fn list_notes(&self, query: NoteQuery) -> Result<Vec<NoteSummary>, Error> {
-        // Apply default limit if not specified (0 means no limit)
-        let query = NoteQuery {
-            limit: match query.limit {
-                Some(0) => None,
-                Some(n) => Some(n),
-                None => Some(100),
-            },
-            ..query
-        };
-        let notes = self.db.list_notes(query).await?;
-        Ok(notes.into_iter().map(|n| n.to_summary(140)).collect())
+    /// List a page of notes with optional filters.
+    ///
+    /// Pages hold 100 notes unless `query.limit` says otherwise (0 means no
+    /// limit). Pass the returned cursor as `query.after` to get the next page.
+    pub async fn list_notes(&self, query: NoteQuery) -> Result<NotePage, Error> {
+        let query = page_query(self.canonical_query(query).await?)?;
+        let notes = self.db.list_notes(lookahead(&query)).await?;
+        Ok(into_page(notes, &query))
     }
 
-    /// Count notes matching the query (ignores limit).
+    /// Count notes matching the query (ignores limit and offset).
     pub async fn count_notes(&self, query: NoteQuery) -> Result<i64, Error> {
-        self.db.count_notes(query).await
+        self.db
+            .count_notes(self.canonical_query(query).await?)
+            .await
     }
 
     /// Update an existing note.
@@ -87,36 +260,248 @@ impl<D: Database> VetaService<D> {
         }
 
         // Normalize tags if provided
+        let tags = match update.tags {
+            Some(tags) => Some(canonical_tags(&self.aliases().await?, tags)?),
+            None => None,
+        };
         let update = UpdateNote {
             title: update.title.map(|t| t.trim().to_string()),
             body: update.body,
-            tags: update.tags.map(|tags| {
-                let mut tags: Vec<String> = tags
-                    .into_iter()
-                    .map(|t| t.trim().to_lowercase())
-                    .filter(|t| !t.is_empty())
-                    .collect();
-                tags.sort();
-                tags.dedup();
-                tags
-            }),
-            references: update.references.map(|refs| {
-                let mut refs: Vec<String> = refs
-                    .into_iter()
-                    .map(|r| r.trim().to_string())
-                    .filter(|r| !r.is_empty())
-                    .collect();
-                refs.dedup();
-                refs
-            }),
+            tags,
+            references: update.references.map(normalize_references),
         };
 
-        self.db.update_note(id, update).await
+        if !self.db.update_note(id, update).await? {
+            return Ok(false);
+        }
+        // The version that was replaced is now the latest revision
+        if let Some(revision) = self.db.list_revisions(id).await?.first() {
+            let operation = Operation::Update {
+                id,
+                revision: revision.revision,
+            };
+            self.db.push_operation(&operation).await?;
+        }
+        Ok(true)
     }
 
-    /// Delete a note by ID.
+    /// Move a note to the trash.
     pub async fn delete_note(&self, id: i64) -> Result<bool, Error> {
-        self.db.delete_note(id).await
+        Ok(!self.delete_notes(&[id]).await?.is_empty())
+    }
+
+    /// Move notes to the trash. Returns the IDs that were deleted; the
+    /// others were not found.
+    pub async fn delete_notes(&self, ids: &[i64]) -> Result<Vec<i64>, Error> {
+        let mut deleted = Vec::new();
+        for &id in ids {
+            if self.db.delete_note(id).await? {
+                deleted.push(id);
+            }
+        }
+        if !deleted.is_empty() {
+            let operation = Operation::Delete {
+                ids: deleted.clone(),
+            };
+            self.db.push_operation(&operation).await?;
+        }
+        Ok(deleted)
+    }
+
+    /// List notes in the trash, most recently deleted first.
+    pub async fn list_trash(&self) -> Result<Vec<TrashedNote>, Error> {
+        self.db.list_trash().await
+    }
+
+    /// Restore a note from the trash.
+    pub async fn restore_note(&self, id: i64) -> Result<bool, Error> {
+        Ok(!self.restore_notes(&[id]).await?.is_empty())
+    }
+
+    /// Restore notes from the trash. Returns the IDs that were restored; the
+    /// others were not in the trash.
+    pub async fn restore_notes(&self, ids: &[i64]) -> Result<Vec<i64>, Error> {
+        let mut restored = Vec::new();
+        for &id in ids {
+            if self.db.restore_note(id).await? {
+                restored.push(id);
+            }
+        }
+        if !restored.is_empty() {
+            let operation = Operation::Restore {
+                ids: restored.clone(),
+            };
+            self.db.push_operation(&operation).await?;
+        }
+        Ok(restored)
+    }
+
+    /// Permanently delete a note in the trash. This cannot be undone.
+    pub async fn purge_note(&self, id: i64) -> Result<bool, Error> {
+        self.db.purge_note(id).await
+    }
+
+    /// Permanently delete every note in the trash. Returns the number of
+    /// notes deleted.
+    pub async fn empty_trash(&self) -> Result<usize, Error> {
+        let mut count = 0;
+        for trashed in self.db.list_trash().await? {
+            if self.db.purge_note(trashed.note.id).await? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Reverse the most recent mutating operation. Returns the operation that
+    /// was undone, or `None` if the journal is empty.
+    ///
+    /// Undoing an add moves the note to the trash, and undoing an edit puts
+    /// back the replaced revision. Undo itself is not journaled.
+    pub async fn undo(&self) -> Result<Option<Operation>, Error> {
+        if let Some(undone) = self.db.undo().await {
+            return undone;
+        }
+        let Some(operation) = self.db.pop_operation().await? else {
+            return Ok(None);
+        };
+        match &operation {
+            Operation::Add { id } => {
+                self.db.delete_note(*id).await?;
+            }
+            Operation::Update { id, revision } => {
+                let old = self.db.get_revision(*id, *revision).await?.ok_or_else(|| {
+                    Error::NotFound(format!("revision {} of note {}", revision, id))
+                })?;
+                let update = UpdateNote {
+                    title: Some(old.title),
+                    body: Some(old.body),
+                    tags: Some(old.tags),
+                    references: Some(old.references),
+                };
+                if !self.db.update_note(*id, update).await? {
+                    return Err(Error::NotFound(format!("note {}", id)));
+                }
+            }
+            Operation::Delete { ids } => {
+                for &id in ids {
+                    self.db.restore_note(id).await?;
+                }
+            }
+            Operation::Restore { ids } => {
+                for &id in ids {
+                    self.db.delete_note(id).await?;
+                }
+            }
+            Operation::Retag { notes } => self.restore_tags(notes).await?,
+            Operation::Alias {
+                alias,
+                previous,
+                notes,
+            } => {
+                self.db.set_tag_alias(alias, previous.as_deref()).await?;
+                self.restore_tags(notes).await?;
+            }
+        }
+        Ok(Some(operation))
+    }
+
+    /// Give notes back the tags they had before a tag change.
+    async fn restore_tags(&self, notes: &[PreviousTags]) -> Result<(), Error> {
+        for note in notes {
+            let update = UpdateNote {
+                tags: Some(note.tags.clone()),
+                ..Default::default()
+            };
+            self.db.update_note(note.id, update).await?;
+        }
+        Ok(())
+    }
+
+    /// Record the tags notes had before a tag change, which are now their
+    /// latest revisions. Notes already in `notes` keep their earlier tags.
+    async fn previous_tags(&self, ids: &[i64], notes: &mut Vec<PreviousTags>) -> Result<(), Error> {
+        for &id in ids {
+            if notes.iter().any(|note| note.id == id) {
+                continue;
+            }
+            if let Some(revision) = self.db.list_revisions(id).await?.first() {
+                notes.push(PreviousTags {
+                    id,
+                    tags: revision.tags.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Journal a tag change that changed the notes `ids`.
+    async fn journal_retag(&self, ids: &[i64]) -> Result<(), Error> {
+        let mut notes = Vec::new();
+        self.previous_tags(ids, &mut notes).await?;
+        if !notes.is_empty() {
+            self.db.push_operation(&Operation::Retag { notes }).await?;
+        }
+        Ok(())
+    }
+
+    /// List saved revisions of a note, newest first.
+    pub async fn list_revisions(&self, id: i64) -> Result<Vec<Revision>, Error> {
+        self.db.list_revisions(id).await
+    }
+
+    /// Get a saved revision of a note.
+    pub async fn get_revision(&self, id: i64, revision: i64) -> Result<Option<Revision>, Error> {
+        self.db.get_revision(id, revision).await
+    }
+
+    /// Look up a revision, or the latest one if `revision` is `None`.
+    async fn find_revision(&self, id: i64, revision: Option<i64>) -> Result<Revision, Error> {
+        let found = match revision {
+            Some(revision) => self.db.get_revision(id, revision).await?,
+            None => self.db.list_revisions(id).await?.into_iter().next(),
+        };
+        found.ok_or_else(|| match revision {
+            Some(revision) => Error::NotFound(format!("revision {} of note {}", revision, id)),
+            None => Error::NotFound(format!("note {} has no revisions", id)),
+        })
+    }
+
+    /// Diff a saved revision (the latest if `revision` is `None`) against the
+    /// current version of a note. Returns an empty string if nothing changed.
+    pub async fn diff_revision(&self, id: i64, revision: Option<i64>) -> Result<String, Error> {
+        let note = self
+            .db
+            .get_note(id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("note {}", id)))?;
+        let old = self.find_revision(id, revision).await?;
+
+        Ok(diff::unified_diff(
+            &old.to_note(),
+            &format!("note {} (revision {})", id, old.revision),
+            &note,
+            &format!("note {} (current)", id),
+        ))
+    }
+
+    /// Restore a note to a saved revision.
+    ///
+    /// The current version is saved as a new revision first, so a restore
+    /// can itself be undone.
+    pub async fn restore_revision(&self, id: i64, revision: i64) -> Result<(), Error> {
+        let old = self.find_revision(id, Some(revision)).await?;
+        let update = UpdateNote {
+            title: Some(old.title),
+            body: Some(old.body),
+            tags: Some(old.tags),
+            references: Some(old.references),
+        };
+        if self.update_note(id, update).await? {
+            Ok(())
+        } else {
+            Err(Error::NotFound(format!("note {}", id)))
+        }
     }
 
     /// List all tags with counts.
@@ -124,17 +509,420 @@ impl<D: Database> VetaService<D> {
         self.db.list_tags().await
     }
 
-    /// Search notes by pattern.
+    /// Rename a tag on every note that has it. Returns the IDs of the changed
+    /// notes.
+    ///
+    /// Fails if `to` is already a tag, so two tags aren't merged by mistake;
+    /// use [`Self::merge_tags`] for that.
+    pub async fn rename_tag(&self, from: &str, to: &str) -> Result<Vec<i64>, Error> {
+        let from = normalize_tag(from)?;
+        let to = resolve_alias(&self.aliases().await?, &normalize_tag(to)?);
+        if from == to {
+            return Err(Error::Validation(format!(
+                "tag '{}' already has that name",
+                from
+            )));
+        }
+        let tags = self.db.list_tags().await?;
+        if !tags.iter().any(|t| t.name == from) {
+            return Err(Error::NotFound(format!("tag '{}'", from)));
+        }
+        if tags.iter().any(|t| t.name == to) {
+            return Err(Error::Validation(format!(
+                "tag '{}' already exists; merge '{}' into it instead",
+                to, from
+            )));
+        }
+        let ids = self.db.merge_tags(&[from], &to).await?;
+        self.journal_retag(&ids).await?;
+        Ok(ids)
+    }
+
+    /// Merge tags into `into`, which needn't exist yet: every note tagged
+    /// with one of `tags` is tagged `into` instead. Returns the IDs of the
+    /// changed notes.
+    ///
+    /// [`Self::undo`] gives the notes back their previous tags, but doesn't
+    /// move the tag descriptions back or retag notes in the trash.
+    pub async fn merge_tags(&self, tags: &[String], into: &str) -> Result<Vec<i64>, Error> {
+        let into = resolve_alias(&self.aliases().await?, &normalize_tag(into)?);
+        let mut sources = Vec::new();
+        for tag in tags {
+            let tag = normalize_tag(tag)?;
+            if tag != into && !sources.contains(&tag) {
+                sources.push(tag);
+            }
+        }
+        if sources.is_empty() {
+            return Err(Error::Validation(
+                "no tags to merge: name tags other than the one to merge into".into(),
+            ));
+        }
+        let existing = self.db.list_tags().await?;
+        for tag in &sources {
+            if !existing.iter().any(|t| &t.name == tag) {
+                return Err(Error::NotFound(format!("tag '{}'", tag)));
+            }
+        }
+        let ids = self.db.merge_tags(&sources, &into).await?;
+        self.journal_retag(&ids).await?;
+        Ok(ids)
+    }
+
+    /// Remove a tag from every note that has it, without deleting the notes.
+    /// Returns the IDs of the changed notes.
+    pub async fn remove_tag(&self, tag: &str) -> Result<Vec<i64>, Error> {
+        let tag = normalize_tag(tag)?;
+        if !self.db.list_tags().await?.iter().any(|t| t.name == tag) {
+            return Err(Error::NotFound(format!("tag '{}'", tag)));
+        }
+        let ids = self.db.remove_tag(&tag).await?;
+        self.journal_retag(&ids).await?;
+        Ok(ids)
+    }
+
+    /// Describe what a tag is for, shown with [`Self::list_tags`]. An empty
+    /// description clears it.
+    pub async fn describe_tag(&self, tag: &str, description: &str) -> Result<(), Error> {
+        let tag = resolve_alias(&self.aliases().await?, &normalize_tag(tag)?);
+        let description = description.trim();
+        let description = (!description.is_empty()).then_some(description);
+        if self.db.set_tag_description(&tag, description).await? {
+            Ok(())
+        } else {
+            Err(Error::NotFound(format!("tag '{}'", tag)))
+        }
+    }
+
+    /// List tag aliases, by alias.
+    pub async fn list_tag_aliases(&self) -> Result<Vec<TagAlias>, Error> {
+        self.db.list_tag_aliases().await
+    }
+
+    /// Make `alias` another name for `tag`. From then on, notes written with
+    /// `alias` are tagged `tag`, and filters on `alias` match `tag`; children
+    /// such as `alias/x` become `tag/x`. Notes already tagged `alias` or one
+    /// of its children are retagged, as by [`Self::merge_tags`]. Returns the
+    /// IDs of the retagged notes.
+    pub async fn add_tag_alias(&self, alias: &str, tag: &str) -> Result<Vec<i64>, Error> {
+        let alias = normalize_tag(alias)?;
+        let mut aliases = self.aliases().await?;
+        // Aliases stand for tags, never for other aliases
+        let tag = resolve_alias(&aliases, &normalize_tag(tag)?);
+        if tagexpr::tag_matches(&alias, &tag) {
+            return Err(Error::Validation(format!(
+                "'{}' can't be an alias of '{}', which is under it",
+                alias, tag
+            )));
+        }
+        if let Some((other, target)) = aliases
+            .iter()
+            .find(|(_, t)| tagexpr::tag_matches(&alias, t))
+        {
+            return Err(Error::Validation(format!(
+                "'{}' is an alias of '{}', so '{}' can't be an alias",
+                other, target, alias
+            )));
+        }
+        let previous = aliases.insert(alias.clone(), tag.clone());
+
+        let mut ids = Vec::new();
+        let mut notes = Vec::new();
+        for existing in self.db.list_tags().await? {
+            if tagexpr::tag_matches(&alias, &existing.name) {
+                let target = resolve_alias(&aliases, &existing.name);
+                let retagged = self.db.merge_tags(&[existing.name], &target).await?;
+                // A note may be retagged more than once; the first revision
+                // holds the tags it had before
+                self.previous_tags(&retagged, &mut notes).await?;
+                ids.extend(retagged);
+            }
+        }
+        ids.sort();
+        ids.dedup();
+        self.db.set_tag_alias(&alias, Some(&tag)).await?;
+        let operation = Operation::Alias {
+            alias,
+            previous,
+            notes,
+        };
+        self.db.push_operation(&operation).await?;
+        Ok(ids)
+    }
+
+    /// Remove a tag alias. Notes that were retagged keep their new tags.
+    pub async fn remove_tag_alias(&self, alias: &str) -> Result<(), Error> {
+        let alias = normalize_tag(alias)?;
+        if self.db.set_tag_alias(&alias, None).await? {
+            Ok(())
+        } else {
+            Err(Error::NotFound(format!("tag alias '{}'", alias)))
+        }
+    }
+
+    /// Links from and to a note. Returns `None` if the note doesn't exist.
+    pub async fn list_links(&self, id: i64) -> Result<Option<Vec<NoteLink>>, Error> {
+        if self.db.get_note(id).await?.is_none() {
+            return Ok(None);
+        }
+        self.db.list_links(id).await.map(Some)
+    }
+
+    /// Link note `from` to note `to`. Returns false if they were already
+    /// linked that way.
+    pub async fn link_notes(&self, from: i64, to: i64, kind: LinkKind) -> Result<bool, Error> {
+        if from == to {
+            return Err(Error::Validation("a note can't link to itself".into()));
+        }
+        for id in [from, to] {
+            if self.db.get_note(id).await?.is_none() {
+                return Err(Error::NotFound(format!("note {}", id)));
+            }
+        }
+        self.db.add_link(&NoteLink { from, to, kind }).await
+    }
+
+    /// Remove the links from note `from` to note `to`, or only the one of
+    /// the given kind. Returns the kinds of the links removed.
+    pub async fn unlink_notes(
+        &self,
+        from: i64,
+        to: i64,
+        kind: Option<LinkKind>,
+    ) -> Result<Vec<LinkKind>, Error> {
+        let kinds = match kind {
+            Some(kind) => vec![kind],
+            None => LinkKind::ALL.to_vec(),
+        };
+        let mut removed = Vec::new();
+        for kind in kinds {
+            if self.db.remove_link(from, to, kind).await? {
+                removed.push(kind);
+            }
+        }
+        if removed.is_empty() {
+            let what = kind.map(|kind| format!("{} ", kind)).unwrap_or_default();
+            return Err(Error::NotFound(format!(
+                "{}link from note {} to note {}",
+                what, from, to
+            )));
+        }
+        Ok(removed)
+    }
+
+    /// Search notes matching the query by pattern, paged like [`Self::list_notes`].
     pub async fn grep(
         &self,
         pattern: &str,
-        tags: Option<Vec<String>>,
+        query: NoteQuery,
         case_sensitive: bool,
-    ) -> Result<Vec<NoteSummary>, Error> {
+    ) -> Result<NotePage, Error> {
+        let query = page_query(self.canonical_query(query).await?)?;
         let notes = self
             .db
-            .grep(pattern, tags.as_deref(), case_sensitive)
+            .grep(pattern, &lookahead(&query), case_sensitive)
             .await?;
-        Ok(notes.into_iter().map(|n| n.to_summary(140)).collect())
+        Ok(into_page(notes, &query))
+    }
+
+    /// Full-text search, ranked by relevance.
+    pub async fn search(
+        &self,
+        query: &str,
+        tags: Option<TagExpr>,
+        limit: Option<i64>,
+    ) -> Result<Vec<SearchResult>, Error> {
+        if search::tokenize(query).is_empty() {
+            return Err(Error::Validation("search query cannot be empty".into()));
+        }
+
+        // Apply default limit if not specified (0 means no limit)
+        let limit = match limit {
+            Some(0) => None,
+            Some(n) => Some(n),
+            None => Some(20),
+        };
+
+        let tags = self.canonical_filter(tags).await?;
+        let hits = self.db.search(query, tags.as_ref(), limit).await?;
+        Ok(hits
+            .into_iter()
+            .map(|hit| SearchResult {
+                note: hit.note.to_summary(140),
+                score: hit.score,
+            })
+            .collect())
+    }
+
+    /// Every note in the namespace with its full body, by ID, for
+    /// [`crate::archive::write_archive`].
+    pub async fn export(&self) -> Result<Vec<Note>, Error> {
+        if let Some(notes) = self.db.export().await {
+            return notes;
+        }
+        let mut notes = self.db.list_notes(NoteQuery::default()).await?;
+        notes.sort_by_key(|note| note.id);
+        Ok(notes)
+    }
+
+    /// Import notes, e.g. from [`crate::archive::read_archive`], keeping
+    /// their timestamps. `mode` decides what happens to their IDs.
+    ///
+    /// With [`ImportMode::Keep`], nothing is imported if an ID is already
+    /// used. With [`ImportMode::Merge`], a note replaces the one with its ID
+    /// only if it was updated later; the replaced version is saved as a
+    /// revision. Notes in the trash are never replaced. Imports can't be
+    /// undone.
+    pub async fn import(&self, notes: Vec<Note>, mode: ImportMode) -> Result<ImportSummary, Error> {
+        let aliases = self.aliases().await?;
+        let mut seen = BTreeMap::new();
+        let notes = notes
+            .into_iter()
+            .map(|note| {
+                let title = note.title.trim().to_string();
+                if title.is_empty() {
+                    return Err(Error::Validation(format!(
+                        "note {}: title cannot be empty",
+                        note.id
+                    )));
+                }
+                if mode != ImportMode::Renumber && seen.insert(note.id, ()).is_some() {
+                    return Err(Error::Validation(format!(
+                        "note {} appears more than once",
+                        note.id
+                    )));
+                }
+                Ok(Note {
+                    title,
+                    tags: canonical_tags(&aliases, note.tags)?,
+                    references: normalize_references(note.references),
+                    ..note
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(summary) = self.db.import(&notes, mode).await {
+            return summary;
+        }
+
+        let mut summary = ImportSummary::default();
+        if mode == ImportMode::Renumber {
+            for note in notes {
+                let id = self
+                    .db
+                    .add_note(CreateNote {
+                        title: note.title.clone(),
+                        body: note.body.clone(),
+                        tags: note.tags.clone(),
+                        references: note.references.clone(),
+                    })
+                    .await?;
+                self.db.put_note(&Note { id, ..note }).await?;
+                summary.added += 1;
+            }
+            return Ok(summary);
+        }
+
+        // IDs in use, with their notes unless they're in the trash
+        let mut existing: BTreeMap<i64, Option<Note>> = self
+            .db
+            .list_trash()
+            .await?
+            .into_iter()
+            .map(|trashed| (trashed.note.id, None))
+            .collect();
+        for note in self.db.list_notes(NoteQuery::default()).await? {
+            existing.insert(note.id, Some(note));
+        }
+        if mode == ImportMode::Keep {
+            if let Some(note) = notes.iter().find(|n| existing.contains_key(&n.id)) {
+                return Err(Error::Validation(format!(
+                    "note {} already exists; import with renumber or merge instead",
+                    note.id
+                )));
+            }
+        }
+
+        for note in notes {
+            match existing.get(&note.id) {
+                None => {
+                    self.db.put_note(&note).await?;
+                    summary.added += 1;
+                }
+                Some(Some(current))
+                    if note.updated_at > current.updated_at
+                        && (&note.title, &note.body, &note.tags, &note.references)
+                            != (
+                                &current.title,
+                                &current.body,
+                                &current.tags,
+                                &current.references,
+                            ) =>
+                {
+                    // Update first, so the replaced version is kept as a revision
+                    let update = UpdateNote {
+                        title: Some(note.title.clone()),
+                        body: Some(note.body.clone()),
+                        tags: Some(note.tags.clone()),
+                        references: Some(note.references.clone()),
+                    };
+                    self.db.update_note(note.id, update).await?;
+                    self.db.put_note(&note).await?;
+                    summary.updated += 1;
+                }
+                Some(_) => summary.skipped += 1,
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Store a new API key, given the hash of the key from [`auth::hash_key`].
+    pub async fn add_api_key(
+        &self,
+        name: String,
+        scope: Scope,
+        tags: Vec<String>,
+        namespaces: Vec<String>,
+        key_hash: String,
+    ) -> Result<ApiKey, Error> {
+        let name = name.trim().to_string();
+        if name.is_empty() {
+            return Err(Error::Validation("API key name cannot be empty".into()));
+        }
+        if !auth::is_key_hash(&key_hash) {
+            return Err(Error::Validation(
+                "key hash must be 64 lowercase hex characters".into(),
+            ));
+        }
+
+        // Normalize tags the same way as note tags, so they match
+        let tags = canonical_tags(&self.aliases().await?, tags)?;
+        for namespace in &namespaces {
+            namespace::validate_namespace(namespace)?;
+        }
+        self.db
+            .add_api_key(CreateApiKey {
+                name,
+                key_hash,
+                scope,
+                tags,
+                namespaces,
+            })
+            .await
+    }
+
+    /// Look up the stored API key for `key`.
+    pub async fn authenticate(&self, key: &str) -> Result<Option<ApiKey>, Error> {
+        self.db.find_api_key(&auth::hash_key(key)).await
+    }
+
+    /// List API keys, oldest first.
+    pub async fn list_api_keys(&self) -> Result<Vec<ApiKey>, Error> {
+        self.db.list_api_keys().await
+    }
+
+    /// Revoke an API key. Returns false if it doesn't exist.
+    pub async fn revoke_api_key(&self, id: i64) -> Result<bool, Error> {
+        self.db.revoke_api_key(id).await
     }
 }
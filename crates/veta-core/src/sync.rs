@@ -0,0 +1,632 @@
+//! Sync notes between two stores, e.g. a local `.veta` directory and a worker.
+//!
+//! A [`SyncState`], kept by the caller between runs, maps local note IDs to
+//! remote ones and records a hash of each side's content as of the last
+//! sync. A note whose hash differs from the recorded one has changed since;
+//! if both sides of a pair have changed, the [`ConflictPolicy`] decides which
+//! version wins. A note that disappeared (was deleted) on one side is deleted
+//! on the other, unless the other side changed it in the meantime, in which
+//! case it is copied back as a new note.
+//!
+//! Sync writes to the stores directly rather than through [`VetaService`],
+//! so copied notes don't end up in the undo journal. Copies follow the
+//! target's tag aliases, keep the source's `created_at` where the target can
+//! store it, and have their `note:N` references and links rewritten to the
+//! IDs of the target's copies. A note copied before a note it refers to is
+//! copied again once every note has been copied; references and links that
+//! still have no copy on the other side are left out and reported as
+//! [`SyncChange::Unmapped`].
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{CreateNote, Database, Error, Note, NoteLink, Reference, UpdateNote, VetaService};
+
+/// Which way notes are copied.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Copy local changes to the remote store.
+    Push,
+    /// Copy remote changes to the local store.
+    Pull,
+    /// Copy changes both ways.
+    #[default]
+    Both,
+}
+
+impl SyncMode {
+    fn pushes(self) -> bool {
+        self != SyncMode::Pull
+    }
+
+    fn pulls(self) -> bool {
+        self != SyncMode::Push
+    }
+}
+
+impl fmt::Display for SyncMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncMode::Push => write!(f, "push"),
+            SyncMode::Pull => write!(f, "pull"),
+            SyncMode::Both => write!(f, "both"),
+        }
+    }
+}
+
+impl FromStr for SyncMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "push" => Ok(SyncMode::Push),
+            "pull" => Ok(SyncMode::Pull),
+            "both" => Ok(SyncMode::Both),
+            _ => Err(Error::Validation(format!(
+                "invalid sync mode '{}': expected push, pull or both",
+                s
+            ))),
+        }
+    }
+}
+
+/// How to resolve a note that changed on both sides since the last sync.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Overwrite the remote note with the local one.
+    KeepLocal,
+    /// Overwrite the local note with the remote one.
+    KeepRemote,
+    /// Copy each version to the other side as a separate note.
+    #[default]
+    KeepBoth,
+}
+
+impl fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictPolicy::KeepLocal => write!(f, "keep-local"),
+            ConflictPolicy::KeepRemote => write!(f, "keep-remote"),
+            ConflictPolicy::KeepBoth => write!(f, "keep-both"),
+        }
+    }
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "keep-local" => Ok(ConflictPolicy::KeepLocal),
+            "keep-remote" => Ok(ConflictPolicy::KeepRemote),
+            "keep-both" => Ok(ConflictPolicy::KeepBoth),
+            _ => Err(Error::Validation(format!(
+                "invalid conflict policy '{}': expected keep-local, keep-remote or keep-both",
+                s
+            ))),
+        }
+    }
+}
+
+/// A pair of notes that were identical after the last sync.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncedNote {
+    pub local_id: i64,
+    pub remote_id: i64,
+    /// The local note's [`content_hash`] when it was last synced.
+    pub local_hash: String,
+    /// The remote note's [`content_hash`] when it was last synced.
+    pub remote_hash: String,
+}
+
+/// Sync state between a local and a remote store.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncState {
+    pub notes: Vec<SyncedNote>,
+}
+
+impl SyncState {
+    /// Record a synced pair, replacing any pair either note was part of.
+    fn record(&mut self, synced: SyncedNote) {
+        self.notes
+            .retain(|n| n.local_id != synced.local_id && n.remote_id != synced.remote_id);
+        self.notes.push(synced);
+        self.notes.sort_by_key(|n| n.local_id);
+    }
+
+    fn forget(&mut self, local_id: i64) {
+        self.notes.retain(|n| n.local_id != local_id);
+    }
+
+    /// The ID of the remote copy of a local note.
+    fn remote_id(&self, local_id: i64) -> Option<i64> {
+        let synced = self.notes.iter().find(|n| n.local_id == local_id);
+        synced.map(|n| n.remote_id)
+    }
+
+    /// The ID of the local copy of a remote note.
+    fn local_id(&self, remote_id: i64) -> Option<i64> {
+        let synced = self.notes.iter().find(|n| n.remote_id == remote_id);
+        synced.map(|n| n.local_id)
+    }
+}
+
+/// A change made by [`sync`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncChange {
+    /// A local note was copied to the remote store.
+    Pushed { local_id: i64, remote_id: i64 },
+    /// A remote note was copied to the local store.
+    Pulled { local_id: i64, remote_id: i64 },
+    /// A remote note was deleted because its local note was.
+    DeletedRemote { local_id: i64, remote_id: i64 },
+    /// A local note was deleted because its remote note was.
+    DeletedLocal { local_id: i64, remote_id: i64 },
+    /// Both notes changed; they were resolved with `policy`.
+    Conflict {
+        local_id: i64,
+        remote_id: i64,
+        policy: ConflictPolicy,
+    },
+    /// A reference or link to `note`, which has no copy on the other side,
+    /// was left out of a copy. `note` is a local note if the copy was
+    /// pushed, and a remote one if it was pulled.
+    Unmapped {
+        local_id: i64,
+        remote_id: i64,
+        note: i64,
+        pushed: bool,
+    },
+}
+
+impl fmt::Display for SyncChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncChange::Pushed {
+                local_id,
+                remote_id,
+            } => write!(f, "Pushed note {} to remote note {}", local_id, remote_id),
+            SyncChange::Pulled {
+                local_id,
+                remote_id,
+            } => write!(f, "Pulled remote note {} to note {}", remote_id, local_id),
+            SyncChange::DeletedRemote {
+                local_id,
+                remote_id,
+            } => write!(
+                f,
+                "Deleted remote note {} (note {} was deleted)",
+                remote_id, local_id
+            ),
+            SyncChange::DeletedLocal {
+                local_id,
+                remote_id,
+            } => write!(
+                f,
+                "Deleted note {} (remote note {} was deleted)",
+                local_id, remote_id
+            ),
+            SyncChange::Conflict {
+                local_id,
+                remote_id,
+                policy,
+            } => write!(
+                f,
+                "Conflict: note {} and remote note {} both changed ({})",
+                local_id, remote_id, policy
+            ),
+            SyncChange::Unmapped {
+                local_id,
+                remote_id,
+                note,
+                pushed: true,
+            } => write!(
+                f,
+                "Left note {} out of remote note {}: note {} refers to it, but it isn't synced",
+                note, remote_id, local_id
+            ),
+            SyncChange::Unmapped {
+                local_id,
+                remote_id,
+                note,
+                pushed: false,
+            } => write!(
+                f,
+                "Left remote note {} out of note {}: remote note {} refers to it, but it isn't synced",
+                note, local_id, remote_id
+            ),
+        }
+    }
+}
+
+/// Hash of a note's title, body, tags and references, as lowercase hex.
+///
+/// Timestamps only have second resolution, so they can't tell an edit made
+/// in the same second as a sync from the synced version; the content can.
+pub fn content_hash(note: &Note) -> String {
+    let content = serde_json::json!([note.title, note.body, note.tags, note.references]);
+    Sha256::digest(content.to_string().as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The [`content_hash`] of every note in a store, by ID.
+async fn content_hashes<D: Database>(
+    service: &VetaService<D>,
+) -> Result<BTreeMap<i64, String>, Error> {
+    let notes = service.export().await?;
+    Ok(notes
+        .iter()
+        .map(|note| (note.id, content_hash(note)))
+        .collect())
+}
+
+/// A note copied by [`copy_note`].
+struct Copied {
+    /// The [`content_hash`] of the source note.
+    from_hash: String,
+    to_id: i64,
+    /// The [`content_hash`] of the copy, as stored.
+    to_hash: String,
+    /// Notes the source refers or links to that have no copy, and so were
+    /// left out of the copy.
+    unmapped: Vec<i64>,
+}
+
+/// Copy a note from one store to another, overwriting `to_id` if given.
+/// `copy_of` gives the ID of the copy of a note in the source store, for
+/// `note:N` references and links.
+async fn copy_note<A: Database, B: Database>(
+    from: &VetaService<A>,
+    from_id: i64,
+    to: &VetaService<B>,
+    to_id: Option<i64>,
+    copy_of: impl Fn(i64) -> Option<i64>,
+) -> Result<Copied, Error> {
+    let note = from
+        .get_note(from_id)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("note {}", from_id)))?;
+    let from_hash = content_hash(&note);
+
+    let mut unmapped = Vec::new();
+    let mut map = |id: i64| {
+        let copy = copy_of(id);
+        if copy.is_none() && !unmapped.contains(&id) {
+            unmapped.push(id);
+        }
+        copy
+    };
+    let mut references = Vec::new();
+    for reference in &note.references {
+        match Reference::parse(reference) {
+            Reference::Note { id } => {
+                references.extend(map(id).map(|id| Reference::Note { id }.to_string()))
+            }
+            _ => references.push(reference.clone()),
+        }
+    }
+    let mut links = Vec::new();
+    for link in from.db().list_links(from_id).await? {
+        if link.from == from_id {
+            links.extend(map(link.to).map(|to| (to, link.kind)));
+        }
+    }
+    let tags = to.canonicalize_tags(note.tags.clone()).await?;
+
+    let to_id = match to_id {
+        Some(id) => {
+            let update = UpdateNote {
+                title: Some(note.title),
+                body: Some(note.body),
+                tags: Some(tags),
+                references: Some(references),
+            };
+            if !to.db().update_note(id, update).await? {
+                return Err(Error::NotFound(format!("note {}", id)));
+            }
+            id
+        }
+        None => {
+            let create = CreateNote {
+                title: note.title.clone(),
+                body: note.body.clone(),
+                tags: tags.clone(),
+                references: references.clone(),
+            };
+            let id = to.db().add_note(create).await?;
+            // Keep the note's timestamps. A remote store can't store a note
+            // as it is, and keeps its own
+            let copy = Note {
+                id,
+                tags,
+                references,
+                ..note
+            };
+            match to.db().put_note(&copy).await {
+                Ok(()) | Err(Error::Internal(_)) => {}
+                Err(e) => return Err(e),
+            }
+            id
+        }
+    };
+
+    // The copy's links are replaced by the source's
+    let existing: Vec<NoteLink> = to
+        .db()
+        .list_links(to_id)
+        .await?
+        .into_iter()
+        .filter(|link| link.from == to_id)
+        .collect();
+    for link in &existing {
+        if !links.contains(&(link.to, link.kind)) {
+            to.db().remove_link(to_id, link.to, link.kind).await?;
+        }
+    }
+    for (target, kind) in links {
+        if !existing.iter().any(|l| l.to == target && l.kind == kind) {
+            let link = NoteLink {
+                from: to_id,
+                to: target,
+                kind,
+            };
+            to.db().add_link(&link).await?;
+        }
+    }
+
+    // The store may have normalized the copy, so hash it as stored
+    let copy = to
+        .get_note(to_id)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("note {}", to_id)))?;
+    Ok(Copied {
+        from_hash,
+        to_id,
+        to_hash: content_hash(&copy),
+        unmapped,
+    })
+}
+
+/// Notes copied with references or links left out, to copy again once
+/// every note has been copied: `(local_id, remote_id, pushed)`.
+type Retry = Vec<(i64, i64, bool)>;
+
+async fn push<L: Database, R: Database>(
+    local: &VetaService<L>,
+    remote: &VetaService<R>,
+    state: &mut SyncState,
+    retry: &mut Retry,
+    local_id: i64,
+    remote_id: Option<i64>,
+) -> Result<SyncChange, Error> {
+    let copied = copy_note(local, local_id, remote, remote_id, |id| state.remote_id(id)).await?;
+    let remote_id = copied.to_id;
+    state.record(SyncedNote {
+        local_id,
+        remote_id,
+        local_hash: copied.from_hash,
+        remote_hash: copied.to_hash,
+    });
+    if !copied.unmapped.is_empty() {
+        retry.push((local_id, remote_id, true));
+    }
+    Ok(SyncChange::Pushed {
+        local_id,
+        remote_id,
+    })
+}
+
+async fn pull<L: Database, R: Database>(
+    local: &VetaService<L>,
+    remote: &VetaService<R>,
+    state: &mut SyncState,
+    retry: &mut Retry,
+    local_id: Option<i64>,
+    remote_id: i64,
+) -> Result<SyncChange, Error> {
+    let copied = copy_note(remote, remote_id, local, local_id, |id| state.local_id(id)).await?;
+    let local_id = copied.to_id;
+    state.record(SyncedNote {
+        local_id,
+        remote_id,
+        local_hash: copied.to_hash,
+        remote_hash: copied.from_hash,
+    });
+    if !copied.unmapped.is_empty() {
+        retry.push((local_id, remote_id, false));
+    }
+    Ok(SyncChange::Pulled {
+        local_id,
+        remote_id,
+    })
+}
+
+/// Copy again the notes in `retry`, now that the notes they refer to may
+/// have copies, and report references and links that still don't.
+async fn retry_unmapped<L: Database, R: Database>(
+    local: &VetaService<L>,
+    remote: &VetaService<R>,
+    state: &mut SyncState,
+    retry: Retry,
+    changes: &mut Vec<SyncChange>,
+) -> Result<(), Error> {
+    for (local_id, remote_id, pushed) in retry {
+        let unmapped = if pushed {
+            let copied = copy_note(local, local_id, remote, Some(remote_id), |id| {
+                state.remote_id(id)
+            })
+            .await?;
+            state.record(SyncedNote {
+                local_id,
+                remote_id,
+                local_hash: copied.from_hash,
+                remote_hash: copied.to_hash,
+            });
+            copied.unmapped
+        } else {
+            let copied = copy_note(remote, remote_id, local, Some(local_id), |id| {
+                state.local_id(id)
+            })
+            .await?;
+            state.record(SyncedNote {
+                local_id,
+                remote_id,
+                local_hash: copied.to_hash,
+                remote_hash: copied.from_hash,
+            });
+            copied.unmapped
+        };
+        for note in unmapped {
+            changes.push(SyncChange::Unmapped {
+                local_id,
+                remote_id,
+                note,
+                pushed,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Sync notes between `local` and `remote`, and update `state` to match.
+///
+/// `state` is updated as notes are copied, so if syncing fails halfway it
+/// still describes the notes that were synced and should be saved.
+pub async fn sync<L: Database, R: Database>(
+    local: &VetaService<L>,
+    remote: &VetaService<R>,
+    state: &mut SyncState,
+    mode: SyncMode,
+    policy: ConflictPolicy,
+) -> Result<Vec<SyncChange>, Error> {
+    let mut local_notes = content_hashes(local).await?;
+    let mut remote_notes = content_hashes(remote).await?;
+    let mut changes = Vec::new();
+    let mut retry = Retry::new();
+
+    for synced in state.notes.clone() {
+        let (local_id, remote_id) = (synced.local_id, synced.remote_id);
+        let local_hash = local_notes.remove(&local_id);
+        let remote_hash = remote_notes.remove(&remote_id);
+        let local_changed = local_hash.as_ref() != Some(&synced.local_hash);
+        let remote_changed = remote_hash.as_ref() != Some(&synced.remote_hash);
+
+        match (local_hash, remote_hash) {
+            (None, None) => state.forget(local_id),
+            // Deleted locally: delete the remote note too, unless it changed
+            (None, Some(remote_hash)) => {
+                if remote_changed {
+                    state.forget(local_id);
+                    remote_notes.insert(remote_id, remote_hash);
+                } else if mode.pushes() {
+                    remote.db().delete_note(remote_id).await?;
+                    state.forget(local_id);
+                    changes.push(SyncChange::DeletedRemote {
+                        local_id,
+                        remote_id,
+                    });
+                }
+            }
+            (Some(local_hash), None) => {
+                if local_changed {
+                    state.forget(local_id);
+                    local_notes.insert(local_id, local_hash);
+                } else if mode.pulls() {
+                    local.db().delete_note(local_id).await?;
+                    state.forget(local_id);
+                    changes.push(SyncChange::DeletedLocal {
+                        local_id,
+                        remote_id,
+                    });
+                }
+            }
+            (Some(_), Some(_)) => match (local_changed, remote_changed) {
+                (false, false) => {}
+                (true, false) => {
+                    if mode.pushes() {
+                        let change =
+                            push(local, remote, state, &mut retry, local_id, Some(remote_id))
+                                .await?;
+                        changes.push(change);
+                    }
+                }
+                (false, true) => {
+                    if mode.pulls() {
+                        let change =
+                            pull(local, remote, state, &mut retry, Some(local_id), remote_id)
+                                .await?;
+                        changes.push(change);
+                    }
+                }
+                (true, true) => {
+                    changes.push(SyncChange::Conflict {
+                        local_id,
+                        remote_id,
+                        policy,
+                    });
+                    let resolved =
+                        resolve_conflict(local, remote, state, &mut retry, &synced, mode, policy);
+                    changes.extend(resolved.await?);
+                }
+            },
+        }
+    }
+
+    // Notes that haven't been synced yet
+    if mode.pushes() {
+        for &local_id in local_notes.keys() {
+            changes.push(push(local, remote, state, &mut retry, local_id, None).await?);
+        }
+    }
+    if mode.pulls() {
+        for &remote_id in remote_notes.keys() {
+            changes.push(pull(local, remote, state, &mut retry, None, remote_id).await?);
+        }
+    }
+
+    retry_unmapped(local, remote, state, retry, &mut changes).await?;
+    Ok(changes)
+}
+
+async fn resolve_conflict<L: Database, R: Database>(
+    local: &VetaService<L>,
+    remote: &VetaService<R>,
+    state: &mut SyncState,
+    retry: &mut Retry,
+    synced: &SyncedNote,
+    mode: SyncMode,
+    policy: ConflictPolicy,
+) -> Result<Vec<SyncChange>, Error> {
+    let (local_id, remote_id) = (synced.local_id, synced.remote_id);
+    let mut changes = Vec::new();
+    match policy {
+        ConflictPolicy::KeepLocal => {
+            if mode.pushes() {
+                changes.push(push(local, remote, state, retry, local_id, Some(remote_id)).await?);
+            }
+        }
+        ConflictPolicy::KeepRemote => {
+            if mode.pulls() {
+                changes.push(pull(local, remote, state, retry, Some(local_id), remote_id).await?);
+            }
+        }
+        // Each note gets a new copy on the other side. The pair is split,
+        // so in a one-way sync the other note is copied on the next sync
+        // the other way.
+        ConflictPolicy::KeepBoth => {
+            if mode.pushes() {
+                changes.push(push(local, remote, state, retry, local_id, None).await?);
+            }
+            if mode.pulls() {
+                changes.push(pull(local, remote, state, retry, None, remote_id).await?);
+            }
+        }
+    }
+    Ok(changes)
+}
@@ -0,0 +1,366 @@
+//! Boolean tag filter expressions.
+//!
+//! Syntax: comma-separated alternatives, each a `+`-joined list of tags that
+//! must all be present. A `-` prefix excludes a tag. Alternatives made up of
+//! only excluded tags apply to every note.
+//!
+//! ```text
+//! architecture,auth          architecture OR auth
+//! architecture+auth          architecture AND auth
+//! architecture+auth,-old     (architecture AND auth) AND NOT old
+//! api+-internal,docs         (api AND NOT internal) OR docs
+//! ```
+//!
+//! In URLs `+` decodes to a space, so whitespace is accepted in place of `+`.
+//!
+//! Tags are hierarchical: `lang` matches notes tagged `lang` and notes tagged
+//! with a child such as `lang/rust`.
+
+use std::fmt;
+
+use crate::Error;
+
+/// One alternative of a tag expression: a conjunction of required and
+/// excluded tags.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagClause {
+    /// Tags the note must have.
+    pub include: Vec<String>,
+    /// Tags the note must not have.
+    pub exclude: Vec<String>,
+}
+
+/// A boolean tag filter, evaluated identically by every backend.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagExpr {
+    /// Alternatives; a note must match at least one (any note matches if empty).
+    pub any: Vec<TagClause>,
+    /// Tags a note must not have, regardless of which alternative matched.
+    pub exclude: Vec<String>,
+}
+
+impl TagExpr {
+    /// Parse a tag expression. Returns `None` if the input contains no tags.
+    pub fn parse(input: &str) -> Result<Option<Self>, Error> {
+        let mut expr = TagExpr::default();
+
+        for clause in input.split(',') {
+            let mut include = Vec::new();
+            let mut exclude = Vec::new();
+
+            for literal in clause.split(|c: char| c == '+' || c.is_whitespace()) {
+                let literal = literal.trim();
+                if literal.is_empty() {
+                    continue;
+                }
+                match literal.strip_prefix('-') {
+                    Some(tag) => {
+                        let tag = tag.trim().to_lowercase();
+                        if tag.is_empty() {
+                            return Err(Error::Validation(format!(
+                                "invalid tag expression '{}': '-' must be followed by a tag",
+                                input
+                            )));
+                        }
+                        exclude.push(tag);
+                    }
+                    None => include.push(literal.to_lowercase()),
+                }
+            }
+
+            if include.is_empty() {
+                expr.exclude.extend(exclude);
+            } else {
+                expr.any.push(TagClause { include, exclude });
+            }
+        }
+
+        if expr.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(expr))
+        }
+    }
+
+    /// Match notes that have any of the given tags.
+    pub fn any_of<I, S>(tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        TagExpr {
+            any: tags
+                .into_iter()
+                .map(|t| TagClause {
+                    include: vec![t.into()],
+                    exclude: Vec::new(),
+                })
+                .collect(),
+            exclude: Vec::new(),
+        }
+    }
+
+    /// Combine two expressions so that a note must match both.
+    pub fn and(self, other: TagExpr) -> TagExpr {
+        let any = if self.any.is_empty() {
+            other.any
+        } else if other.any.is_empty() {
+            self.any
+        } else {
+            // (a OR b) AND (c OR d) = ac OR ad OR bc OR bd
+            let mut any = Vec::new();
+            for left in &self.any {
+                for right in &other.any {
+                    any.push(TagClause {
+                        include: union(&left.include, &right.include),
+                        exclude: union(&left.exclude, &right.exclude),
+                    });
+                }
+            }
+            any
+        };
+
+        TagExpr {
+            any,
+            exclude: union(&self.exclude, &other.exclude),
+        }
+    }
+
+    /// True if the expression doesn't constrain anything.
+    pub fn is_empty(&self) -> bool {
+        self.any.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Apply `f` to every tag in the expression.
+    pub fn map_tags(self, f: impl Fn(String) -> String) -> TagExpr {
+        TagExpr {
+            any: self
+                .any
+                .into_iter()
+                .map(|c| TagClause {
+                    include: c.include.into_iter().map(&f).collect(),
+                    exclude: c.exclude.into_iter().map(&f).collect(),
+                })
+                .collect(),
+            exclude: self.exclude.into_iter().map(&f).collect(),
+        }
+    }
+
+    /// Evaluate the expression against a note's tags.
+    pub fn matches(&self, tags: &[String]) -> bool {
+        let has = |filter: &String| tags.iter().any(|tag| tag_matches(filter, tag));
+
+        if self.exclude.iter().any(has) {
+            return false;
+        }
+
+        self.any.is_empty()
+            || self
+                .any
+                .iter()
+                .any(|c| c.include.iter().all(has) && !c.exclude.iter().any(has))
+    }
+
+    /// Render the expression as an SQL condition on notes aliased as `n`.
+    ///
+    /// Tag names are bound as numbered parameters (`?N`), appended to `params`.
+    pub fn to_sql(&self, params: &mut Vec<String>) -> String {
+        let mut conditions = Vec::new();
+
+        if !self.any.is_empty() {
+            let mut alternatives = Vec::new();
+            for clause in &self.any {
+                let mut parts = Vec::new();
+                for tag in &clause.include {
+                    parts.push(sql_has_tag(tag, false, params));
+                }
+                for tag in &clause.exclude {
+                    parts.push(sql_has_tag(tag, true, params));
+                }
+                alternatives.push(format!("({})", parts.join(" AND ")));
+            }
+            conditions.push(format!("({})", alternatives.join(" OR ")));
+        }
+
+        for tag in &self.exclude {
+            conditions.push(sql_has_tag(tag, true, params));
+        }
+
+        if conditions.is_empty() {
+            "1 = 1".to_string()
+        } else {
+            conditions.join(" AND ")
+        }
+    }
+}
+
+/// True if `tag` is `filter` or one of its children, like `lang/rust` for
+/// `lang`.
+pub fn tag_matches(filter: &str, tag: &str) -> bool {
+    tag.strip_prefix(filter)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Tags in either list, in order of first appearance.
+fn union(left: &[String], right: &[String]) -> Vec<String> {
+    let mut tags = left.to_vec();
+    for tag in right {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+    tags
+}
+
+/// SQL condition for whether note `n` has (or, if `negate`, lacks) a tag or
+/// one of its children.
+fn sql_has_tag(tag: &str, negate: bool, params: &mut Vec<String>) -> String {
+    params.push(tag.to_string());
+    format!(
+        "n.row_id {}IN (SELECT ntx.note_id FROM note_tags ntx
+                   JOIN tags tx ON ntx.tag_id = tx.id
+                   WHERE tx.name = ?{n} OR substr(tx.name, 1, length(?{n}) + 1) = ?{n} || '/')",
+        if negate { "NOT " } else { "" },
+        n = params.len()
+    )
+}
+
+impl fmt::Display for TagExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let clauses = self
+            .any
+            .iter()
+            .map(|c| {
+                c.include
+                    .iter()
+                    .cloned()
+                    .chain(c.exclude.iter().map(|t| format!("-{}", t)))
+                    .collect::<Vec<_>>()
+                    .join("+")
+            })
+            .chain(self.exclude.iter().map(|t| format!("-{}", t)));
+        write!(f, "{}", clauses.collect::<Vec<_>>().join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn parse(input: &str) -> TagExpr {
+        TagExpr::parse(input).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_comma_is_or() {
+        let expr = parse("architecture,auth");
+        assert!(expr.matches(&tags(&["auth"])));
+        assert!(expr.matches(&tags(&["architecture"])));
+        assert!(!expr.matches(&tags(&["other"])));
+    }
+
+    #[test]
+    fn test_plus_is_and() {
+        let expr = parse("architecture+auth");
+        assert!(expr.matches(&tags(&["architecture", "auth"])));
+        assert!(!expr.matches(&tags(&["auth"])));
+    }
+
+    #[test]
+    fn test_negative_clause_applies_globally() {
+        let expr = parse("architecture+auth,-deprecated");
+        assert!(expr.matches(&tags(&["architecture", "auth"])));
+        assert!(!expr.matches(&tags(&["architecture", "auth", "deprecated"])));
+        assert!(!expr.matches(&tags(&["architecture"])));
+    }
+
+    #[test]
+    fn test_only_negative() {
+        let expr = parse("-deprecated");
+        assert!(expr.matches(&tags(&[])));
+        assert!(expr.matches(&tags(&["auth"])));
+        assert!(!expr.matches(&tags(&["deprecated"])));
+    }
+
+    #[test]
+    fn test_negation_inside_clause() {
+        let expr = parse("api+-internal,docs");
+        assert!(expr.matches(&tags(&["api"])));
+        assert!(!expr.matches(&tags(&["api", "internal"])));
+        assert!(expr.matches(&tags(&["docs", "internal"])));
+    }
+
+    #[test]
+    fn test_space_is_and() {
+        // `+` in a URL query string decodes to a space
+        assert_eq!(
+            parse("architecture auth,-old"),
+            parse("architecture+auth,-old")
+        );
+    }
+
+    #[test]
+    fn test_normalizes_case() {
+        assert!(parse("Auth").matches(&tags(&["auth"])));
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(TagExpr::parse("").unwrap().is_none());
+        assert!(TagExpr::parse(" , ").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_dangling_minus_is_error() {
+        assert!(TagExpr::parse("auth,-").is_err());
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for input in ["a,b", "a+b,-c", "a+-b,c", "-c,-d"] {
+            assert_eq!(parse(input).to_string(), input);
+            assert_eq!(parse(&parse(input).to_string()), parse(input));
+        }
+    }
+
+    #[test]
+    fn test_and() {
+        let expr = parse("a,b").and(parse("c,-d"));
+        assert!(expr.matches(&tags(&["a", "c"])));
+        assert!(expr.matches(&tags(&["b", "c"])));
+        assert!(!expr.matches(&tags(&["a"])));
+        assert!(!expr.matches(&tags(&["a", "c", "d"])));
+
+        let expr = parse("-d").and(parse("a"));
+        assert_eq!(expr, parse("a,-d"));
+    }
+
+    #[test]
+    fn test_children_match() {
+        let expr = parse("lang,-lang/go");
+        assert!(expr.matches(&tags(&["lang"])));
+        assert!(expr.matches(&tags(&["lang/rust"])));
+        assert!(!expr.matches(&tags(&["language"])));
+        assert!(!expr.matches(&tags(&["lang/go/generics"])));
+        assert!(!parse("lang/rust").matches(&tags(&["lang"])));
+    }
+
+    #[test]
+    fn test_map_tags() {
+        let expr = parse("debug+api,-old").map_tags(|t| t.replace("debug", "debugging"));
+        assert_eq!(expr, parse("debugging+api,-old"));
+    }
+
+    #[test]
+    fn test_to_sql_numbers_params() {
+        let mut params = vec!["existing".to_string()];
+        let sql = parse("a+b,-c").to_sql(&mut params);
+        assert_eq!(params, tags(&["existing", "a", "b", "c"]));
+        assert!(sql.contains("?2") && sql.contains("?3") && sql.contains("?4"));
+        assert!(sql.contains("n.row_id NOT IN"));
+    }
+}
@@ -0,0 +1,303 @@
+//! Sync between two in-memory SQLite stores.
+
+use veta_core::sync::{self, ConflictPolicy::*, SyncChange, SyncMode, SyncState};
+use veta_core::{ImportMode, LinkKind, NoteLink, NoteQuery, UpdateNote, VetaService};
+use veta_sqlite::SqliteDatabase;
+
+fn service() -> VetaService<SqliteDatabase> {
+    VetaService::new(SqliteDatabase::open_in_memory().unwrap())
+}
+
+async fn bodies(service: &VetaService<SqliteDatabase>) -> Vec<String> {
+    let query = NoteQuery {
+        limit: Some(0),
+        ..Default::default()
+    };
+    let mut bodies = vec![];
+    for note in service.list_notes(query).await.unwrap().notes {
+        bodies.push(service.get_note(note.id).await.unwrap().unwrap().body);
+    }
+    bodies.sort();
+    bodies
+}
+
+async fn edit(service: &VetaService<SqliteDatabase>, id: i64, body: &str) {
+    let update = UpdateNote {
+        body: Some(body.to_string()),
+        ..Default::default()
+    };
+    assert!(service.update_note(id, update).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_sync_copies_changes() {
+    let (local, remote) = (service(), service());
+    let mut state = SyncState::default();
+    let a = local
+        .add_note("A".into(), "Local".into(), vec![], vec![])
+        .await
+        .unwrap();
+    let b = remote
+        .add_note("B".into(), "Remote".into(), vec![], vec![])
+        .await
+        .unwrap();
+
+    // Push only copies local notes
+    let changes = sync::sync(&local, &remote, &mut state, SyncMode::Push, KeepBoth)
+        .await
+        .unwrap();
+    assert_eq!(
+        changes,
+        vec![SyncChange::Pushed {
+            local_id: a,
+            remote_id: 2
+        }]
+    );
+    assert_eq!(bodies(&local).await, vec!["Local"]);
+
+    let changes = sync::sync(&local, &remote, &mut state, SyncMode::Both, KeepBoth)
+        .await
+        .unwrap();
+    assert_eq!(
+        changes,
+        vec![SyncChange::Pulled {
+            local_id: 2,
+            remote_id: b
+        }]
+    );
+    assert_eq!(bodies(&local).await, bodies(&remote).await);
+    assert!(
+        sync::sync(&local, &remote, &mut state, SyncMode::Both, KeepBoth)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    // An edit on one side is copied, a delete on the other is mirrored
+    edit(&local, a, "Edited").await;
+    remote.delete_note(b).await.unwrap();
+    let changes = sync::sync(&local, &remote, &mut state, SyncMode::Both, KeepBoth)
+        .await
+        .unwrap();
+    assert_eq!(
+        changes,
+        vec![
+            SyncChange::Pushed {
+                local_id: a,
+                remote_id: 2
+            },
+            SyncChange::DeletedLocal {
+                local_id: 2,
+                remote_id: b
+            },
+        ]
+    );
+    assert_eq!(bodies(&local).await, vec!["Edited"]);
+    assert_eq!(bodies(&remote).await, vec!["Edited"]);
+    assert_eq!(state.notes.len(), 1);
+}
+
+#[tokio::test]
+async fn test_sync_sees_edits_right_after_a_sync() {
+    let (local, remote) = (service(), service());
+    let mut state = SyncState::default();
+    let id = remote
+        .add_note("A".into(), "Original".into(), vec![], vec![])
+        .await
+        .unwrap();
+    sync::sync(&local, &remote, &mut state, SyncMode::Both, KeepBoth)
+        .await
+        .unwrap();
+
+    // Well within the second the notes were synced in
+    edit(&local, id, "Edited").await;
+    let changes = sync::sync(&local, &remote, &mut state, SyncMode::Both, KeepBoth)
+        .await
+        .unwrap();
+    assert_eq!(
+        changes,
+        vec![SyncChange::Pushed {
+            local_id: id,
+            remote_id: id
+        }]
+    );
+    assert_eq!(bodies(&remote).await, vec!["Edited"]);
+
+    // Notes written by sync aren't in the undo journal, so undo only
+    // reverts the edit
+    let undone = local.undo().await.unwrap();
+    assert!(undone.is_some());
+    assert_eq!(local.undo().await.unwrap(), None);
+    assert_eq!(bodies(&local).await, vec!["Original"]);
+}
+
+#[tokio::test]
+async fn test_sync_conflicts() {
+    for (policy, local_bodies, remote_bodies) in [
+        (KeepLocal, vec!["Local edit"], vec!["Local edit"]),
+        (KeepRemote, vec!["Remote edit"], vec!["Remote edit"]),
+        (
+            KeepBoth,
+            vec!["Local edit", "Remote edit"],
+            vec!["Local edit", "Remote edit"],
+        ),
+    ] {
+        let (local, remote) = (service(), service());
+        let mut state = SyncState::default();
+        let id = local
+            .add_note("A".into(), "Original".into(), vec![], vec![])
+            .await
+            .unwrap();
+        sync::sync(&local, &remote, &mut state, SyncMode::Both, policy)
+            .await
+            .unwrap();
+
+        edit(&local, id, "Local edit").await;
+        edit(&remote, id, "Remote edit").await;
+        let changes = sync::sync(&local, &remote, &mut state, SyncMode::Both, policy)
+            .await
+            .unwrap();
+        assert_eq!(
+            changes[0],
+            SyncChange::Conflict {
+                local_id: id,
+                remote_id: id,
+                policy
+            }
+        );
+        assert_eq!(bodies(&local).await, local_bodies, "{}", policy);
+        assert_eq!(bodies(&remote).await, remote_bodies, "{}", policy);
+        assert_eq!(state.notes.len(), local_bodies.len());
+    }
+}
+
+#[tokio::test]
+async fn test_sync_follows_the_targets_tag_aliases() {
+    let (local, remote) = (service(), service());
+    let mut state = SyncState::default();
+    let source = service();
+    source
+        .add_note("A".into(), "".into(), vec!["bug".into()], vec![])
+        .await
+        .unwrap();
+    let mut notes = source.export().await.unwrap();
+    notes[0].created_at = "2020-01-01 00:00:00".into();
+    local.import(notes, ImportMode::Keep).await.unwrap();
+    remote.add_tag_alias("bug", "defect").await.unwrap();
+
+    sync::sync(&local, &remote, &mut state, SyncMode::Push, KeepBoth)
+        .await
+        .unwrap();
+    let copy = remote.get_note(1).await.unwrap().unwrap();
+    assert_eq!(copy.tags, ["defect"]);
+    assert_eq!(copy.created_at, "2020-01-01 00:00:00");
+    assert!(
+        sync::sync(&local, &remote, &mut state, SyncMode::Both, KeepBoth)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_sync_maps_note_references_and_links() {
+    let (local, remote) = (service(), service());
+    let mut state = SyncState::default();
+    remote
+        .add_note("Remote only".into(), "".into(), vec![], vec![])
+        .await
+        .unwrap();
+    // Note 1 refers to note 2, which is copied after it
+    for (title, references) in [("A", vec!["note:2".to_string()]), ("B", vec![])] {
+        local
+            .add_note(title.into(), "".into(), vec![], references)
+            .await
+            .unwrap();
+    }
+    local.link_notes(2, 1, LinkKind::Supersedes).await.unwrap();
+
+    let changes = sync::sync(&local, &remote, &mut state, SyncMode::Push, KeepBoth)
+        .await
+        .unwrap();
+    assert_eq!(changes.len(), 2);
+    let a = remote.get_note(2).await.unwrap().unwrap();
+    assert_eq!(
+        (a.title.as_str(), a.references.as_slice()),
+        ("A", &["note:3".to_string()][..])
+    );
+    let link = NoteLink {
+        from: 3,
+        to: 2,
+        kind: LinkKind::Supersedes,
+    };
+    assert_eq!(remote.list_links(3).await.unwrap().unwrap(), [link]);
+
+    // Pulled back, the references are local IDs again
+    let c = remote
+        .add_note("C".into(), "".into(), vec![], vec!["note:2".into()])
+        .await
+        .unwrap();
+    remote.link_notes(c, 1, LinkKind::RelatesTo).await.unwrap();
+    sync::sync(&local, &remote, &mut state, SyncMode::Both, KeepBoth)
+        .await
+        .unwrap();
+    let c = local.get_note(4).await.unwrap().unwrap();
+    assert_eq!(
+        (c.title.as_str(), c.references.as_slice()),
+        ("C", &["note:1".to_string()][..])
+    );
+    let link = NoteLink {
+        from: 4,
+        to: 3,
+        kind: LinkKind::RelatesTo,
+    };
+    assert_eq!(local.list_links(4).await.unwrap().unwrap(), [link]);
+    assert!(
+        sync::sync(&local, &remote, &mut state, SyncMode::Both, KeepBoth)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_sync_reports_unmapped_references() {
+    let (local, remote) = (service(), service());
+    let mut state = SyncState::default();
+    remote
+        .add_note("Remote only".into(), "".into(), vec![], vec![])
+        .await
+        .unwrap();
+    let id = local
+        .add_note(
+            "A".into(),
+            "".into(),
+            vec![],
+            vec!["note:9".into(), "README.md".into()],
+        )
+        .await
+        .unwrap();
+
+    let changes = sync::sync(&local, &remote, &mut state, SyncMode::Push, KeepBoth)
+        .await
+        .unwrap();
+    let unmapped = SyncChange::Unmapped {
+        local_id: id,
+        remote_id: 2,
+        note: 9,
+        pushed: true,
+    };
+    assert_eq!(changes[1], unmapped);
+    assert_eq!(
+        unmapped.to_string(),
+        "Left note 9 out of remote note 2: note 1 refers to it, but it isn't synced"
+    );
+    let copy = remote.get_note(2).await.unwrap().unwrap();
+    assert_eq!(copy.references, ["README.md"]);
+    assert!(
+        sync::sync(&local, &remote, &mut state, SyncMode::Push, KeepBoth)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
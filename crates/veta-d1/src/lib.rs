@@ -2,21 +2,29 @@
 
 use regex::Regex;
 use serde::Deserialize;
+use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use veta_core::journal::JOURNAL_LIMIT;
+use veta_core::search::{fts5_query, BODY_WEIGHT, TITLE_WEIGHT};
 use veta_core::{
-    get_pending_migrations, CreateNote, Database, Error, Note, NoteQuery, TagCount, UpdateNote,
-    SCHEMA_VERSION,
+    get_pending_migrations, is_add_column, ApiKey, CreateApiKey, CreateNote, Database, Error,
+    LinkKind, NamespaceCount, Note, NoteLink, NoteQuery, Operation, Revision, SearchHit, TagAlias,
+    TagCount, TagExpr, TrashedNote, UpdateNote, DEFAULT_NAMESPACE, SCHEMA_VERSION,
 };
 use wasm_bindgen::JsValue;
-use worker::d1::D1Database;
+use worker::d1::{D1Database, D1PreparedStatement};
 
 /// Track whether we've already checked migrations in this isolate.
 /// This avoids redundant checks on every request within the same worker instance.
 static MIGRATIONS_CHECKED: AtomicBool = AtomicBool::new(false);
 
 /// D1-backed database implementation.
+///
+/// Handles for other namespaces, from [`Database::with_namespace`], share
+/// the binding.
 pub struct D1DatabaseWrapper {
-    db: D1Database,
+    db: Rc<D1Database>,
+    namespace: String,
     initialized: bool,
 }
 
@@ -28,7 +36,8 @@ struct MetaRow {
 impl D1DatabaseWrapper {
     pub fn new(db: D1Database) -> Self {
         Self {
-            db,
+            db: Rc::new(db),
+            namespace: DEFAULT_NAMESPACE.to_string(),
             initialized: false,
         }
     }
@@ -83,33 +92,45 @@ impl D1DatabaseWrapper {
             return Ok(());
         }
 
-        // Run pending migrations
+        // Run pending migrations. Each one is a single batch, which D1 runs
+        // as a transaction, so a failure leaves the database at the last
+        // completed version
         for migration in get_pending_migrations(current_version) {
+            let mut batch = Vec::new();
             for statement in migration.statements {
                 // Skip _veta_meta creation (already done above)
                 if statement.contains("_veta_meta") {
                     continue;
                 }
-                // ALTER TABLE doesn't support IF NOT EXISTS, so ignore errors for those
-                if statement.starts_with("ALTER TABLE") {
-                    let _ = self.db.prepare(*statement).run().await;
+                // ADD COLUMN doesn't support IF NOT EXISTS, and one error
+                // fails the whole batch, so columns are added first on their
+                // own and a column that already exists is not an error
+                if is_add_column(statement) {
+                    if let Err(e) = self.db.prepare(*statement).run().await {
+                        if !e.to_string().contains("duplicate column name") {
+                            return Err(Error::Database(format!(
+                                "Migration {} failed: {}",
+                                migration.name, e
+                            )));
+                        }
+                    }
                 } else {
-                    self.db.prepare(*statement).run().await.map_err(|e| {
-                        Error::Database(format!("Migration {} failed: {}", migration.name, e))
-                    })?;
+                    batch.push(self.db.prepare(*statement));
                 }
             }
+            batch.push(
+                self.db
+                    .prepare(
+                        "INSERT OR REPLACE INTO _veta_meta (key, value) VALUES ('schema_version', ?1)",
+                    )
+                    .bind(&[migration.version.to_string().into()])
+                    .map_err(|e| Error::Database(e.to_string()))?,
+            );
+            self.db.batch(batch).await.map_err(|e| {
+                Error::Database(format!("Migration {} failed: {}", migration.name, e))
+            })?;
         }
 
-        // Update schema version
-        self.db
-            .prepare("INSERT OR REPLACE INTO _veta_meta (key, value) VALUES ('schema_version', ?1)")
-            .bind(&[SCHEMA_VERSION.to_string().into()])
-            .map_err(|e| Error::Database(e.to_string()))?
-            .run()
-            .await
-            .map_err(|e| Error::Database(e.to_string()))?;
-
         Ok(())
     }
 
@@ -135,6 +156,181 @@ impl D1DatabaseWrapper {
     fn serialize_references(refs: &[String]) -> String {
         serde_json::to_string(refs).unwrap_or_else(|_| "[]".to_string())
     }
+
+    /// Build the WHERE clause and its parameters for a note query.
+    /// Notes in other namespaces and in the trash are always excluded.
+    fn query_conditions(&self, query: &NoteQuery) -> (String, Vec<String>) {
+        let mut params = vec![self.namespace.clone()];
+        let where_clause = format!(
+            "WHERE n.namespace = ?1 AND n.deleted_at IS NULL AND {}",
+            query.to_sql(&mut params)
+        );
+        (where_clause, params)
+    }
+
+    /// The `row_id` of note `id` in this namespace, the key other tables
+    /// use for it, if it's live or, with `trashed`, in the trash.
+    async fn row_id(&self, id: i64, trashed: bool) -> Result<Option<i64>, Error> {
+        let sql = if trashed {
+            "SELECT row_id FROM notes WHERE id = ?1 AND namespace = ?2 AND deleted_at IS NOT NULL"
+        } else {
+            "SELECT row_id FROM notes WHERE id = ?1 AND namespace = ?2 AND deleted_at IS NULL"
+        };
+        let row = self
+            .db
+            .prepare(sql)
+            .bind(&[JsValue::from_f64(id as f64), self.ns()])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .first::<RowIdRow>(None)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(row.map(|r| r.row_id))
+    }
+
+    /// Statements that replace the tags of the note whose `row_id` is
+    /// `row_id`.
+    fn tag_statements(
+        &self,
+        row_id: i64,
+        tags: &[String],
+    ) -> Result<Vec<D1PreparedStatement>, Error> {
+        let row_id = JsValue::from_f64(row_id as f64);
+        let mut statements = vec![self
+            .db
+            .prepare("DELETE FROM note_tags WHERE note_id = ?1")
+            .bind(std::slice::from_ref(&row_id))
+            .map_err(|e| Error::Database(e.to_string()))?];
+        for tag in tags {
+            statements.push(
+                self.db
+                    .prepare(
+                        "INSERT INTO tags (namespace, name) VALUES (?1, ?2)
+                         ON CONFLICT (namespace, name) DO NOTHING",
+                    )
+                    .bind(&[self.ns(), JsValue::from_str(tag)])
+                    .map_err(|e| Error::Database(e.to_string()))?,
+            );
+            statements.push(
+                self.db
+                    .prepare(
+                        "INSERT INTO note_tags (note_id, tag_id)
+                         SELECT ?1, id FROM tags WHERE namespace = ?2 AND name = ?3",
+                    )
+                    .bind(&[row_id.clone(), self.ns(), JsValue::from_str(tag)])
+                    .map_err(|e| Error::Database(e.to_string()))?,
+            );
+        }
+        Ok(statements)
+    }
+
+    /// Replace the tags `sources` with `target`, or remove them, on every
+    /// note in the namespace, in one batch (which D1 runs as a transaction).
+    /// See [`Database::merge_tags`].
+    async fn retag(&self, sources: &[String], target: Option<&str>) -> Result<Vec<i64>, Error> {
+        let sources = serde_json::to_string(sources).map_err(|e| Error::Internal(e.to_string()))?;
+        let sources = JsValue::from_str(&sources);
+        let prepare = |sql: &str, params: &[JsValue]| {
+            self.db
+                .prepare(sql)
+                .bind(params)
+                .map_err(|e| Error::Database(e.to_string()))
+        };
+
+        let mut statements = vec![
+            // Save the current versions of the notes outside the trash as revisions
+            prepare(
+                "INSERT INTO note_revisions (note_id, revision, title, body, tags, \"references\", updated_at)
+                 SELECT n.row_id,
+                        COALESCE((SELECT MAX(r.revision) FROM note_revisions r WHERE r.note_id = n.row_id), 0) + 1,
+                        n.title,
+                        n.body,
+                        (SELECT json_group_array(t.name) FROM note_tags nt
+                         JOIN tags t ON nt.tag_id = t.id
+                         WHERE nt.note_id = n.row_id),
+                        n.\"references\",
+                        n.updated_at
+                 FROM notes n
+                 WHERE n.namespace = ?1 AND n.deleted_at IS NULL AND n.row_id IN
+                     (SELECT nt.note_id FROM note_tags nt JOIN tags t ON nt.tag_id = t.id
+                      WHERE t.namespace = ?1 AND t.name IN (SELECT value FROM json_each(?2)))",
+                &[self.ns(), sources.clone()],
+            )?,
+            prepare(
+                "UPDATE notes SET updated_at = datetime('now')
+                 WHERE namespace = ?1 AND deleted_at IS NULL AND row_id IN
+                     (SELECT nt.note_id FROM note_tags nt JOIN tags t ON nt.tag_id = t.id
+                      WHERE t.namespace = ?1 AND t.name IN (SELECT value FROM json_each(?2)))
+                 RETURNING id",
+                &[self.ns(), sources.clone()],
+            )?,
+        ];
+
+        if let Some(target) = target {
+            let target = JsValue::from_str(target);
+            statements.push(prepare(
+                "INSERT INTO tags (namespace, name) VALUES (?1, ?2)
+                 ON CONFLICT (namespace, name) DO NOTHING",
+                &[self.ns(), target.clone()],
+            )?);
+            statements.push(prepare(
+                "INSERT OR IGNORE INTO note_tags (note_id, tag_id)
+                 SELECT nt.note_id, (SELECT id FROM tags WHERE namespace = ?1 AND name = ?3)
+                 FROM note_tags nt
+                 JOIN tags t ON nt.tag_id = t.id
+                 WHERE t.namespace = ?1 AND t.name IN (SELECT value FROM json_each(?2))",
+                &[self.ns(), sources.clone(), target.clone()],
+            )?);
+            // The target keeps its description, or takes a source's
+            statements.push(prepare(
+                "INSERT OR IGNORE INTO tag_descriptions (namespace, name, description)
+                 SELECT namespace, ?3, description FROM tag_descriptions
+                 WHERE namespace = ?1 AND name IN (SELECT value FROM json_each(?2))
+                 ORDER BY name LIMIT 1",
+                &[self.ns(), sources.clone(), target],
+            )?);
+        }
+
+        statements.push(prepare(
+            "DELETE FROM note_tags
+             WHERE tag_id IN (SELECT id FROM tags
+                              WHERE namespace = ?1 AND name IN (SELECT value FROM json_each(?2)))",
+            &[self.ns(), sources.clone()],
+        )?);
+        statements.push(prepare(
+            "DELETE FROM tag_descriptions
+             WHERE namespace = ?1 AND name IN (SELECT value FROM json_each(?2))",
+            &[self.ns(), sources],
+        )?);
+        // Drop the namespace's tags that no note has anymore
+        statements.push(prepare(
+            "DELETE FROM tags WHERE namespace = ?1 AND id NOT IN (SELECT tag_id FROM note_tags)",
+            &[self.ns()],
+        )?);
+
+        let results = self
+            .db
+            .batch(statements)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+        let rows: Vec<NoteIdRow> = results
+            .get(1)
+            .ok_or_else(|| Error::Database("missing result of retagging notes".into()))?
+            .results()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let mut ids: Vec<i64> = rows.into_iter().map(|r| r.id).collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn ns(&self) -> JsValue {
+        JsValue::from_str(&self.namespace)
+    }
+
+    fn bindings(params: &[String]) -> Vec<JsValue> {
+        params.iter().map(|p| JsValue::from_str(p)).collect()
+    }
 }
 
 #[derive(Deserialize)]
@@ -142,11 +338,23 @@ struct NoteIdRow {
     id: i64,
 }
 
+#[derive(Deserialize)]
+struct RowIdRow {
+    row_id: i64,
+}
+
+#[derive(Deserialize)]
+struct NoteKeyRow {
+    row_id: i64,
+    id: i64,
+}
+
 #[derive(Deserialize)]
 struct NoteRow {
     id: i64,
     title: String,
     body: String,
+    created_at: String,
     updated_at: String,
     references: Option<String>,
     tags: Option<String>,
@@ -158,6 +366,7 @@ impl NoteRow {
             id: self.id,
             title: self.title,
             body: self.body,
+            created_at: self.created_at,
             updated_at: self.updated_at,
             references: D1DatabaseWrapper::parse_references(self.references),
             tags: D1DatabaseWrapper::parse_tags(self.tags),
@@ -165,10 +374,105 @@ impl NoteRow {
     }
 }
 
+#[derive(Deserialize)]
+struct SearchRow {
+    id: i64,
+    title: String,
+    body: String,
+    created_at: String,
+    updated_at: String,
+    references: Option<String>,
+    tags: Option<String>,
+    score: f64,
+}
+
+impl SearchRow {
+    fn into_hit(self) -> SearchHit {
+        SearchHit {
+            note: NoteRow {
+                id: self.id,
+                title: self.title,
+                body: self.body,
+                created_at: self.created_at,
+                updated_at: self.updated_at,
+                references: self.references,
+                tags: self.tags,
+            }
+            .into_note(),
+            score: self.score,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RevisionRow {
+    note_id: i64,
+    revision: i64,
+    title: String,
+    body: String,
+    tags: Option<String>,
+    references: Option<String>,
+    updated_at: String,
+}
+
+impl RevisionRow {
+    fn into_revision(self) -> Revision {
+        // Revision tags are stored as a JSON array, like references
+        let mut tags = D1DatabaseWrapper::parse_references(self.tags);
+        tags.sort();
+        Revision {
+            note_id: self.note_id,
+            revision: self.revision,
+            title: self.title,
+            body: self.body,
+            tags,
+            references: D1DatabaseWrapper::parse_references(self.references),
+            updated_at: self.updated_at,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TrashRow {
+    id: i64,
+    title: String,
+    body: String,
+    created_at: String,
+    updated_at: String,
+    references: Option<String>,
+    tags: Option<String>,
+    deleted_at: String,
+}
+
+impl TrashRow {
+    fn into_trashed_note(self) -> TrashedNote {
+        TrashedNote {
+            note: NoteRow {
+                id: self.id,
+                title: self.title,
+                body: self.body,
+                created_at: self.created_at,
+                updated_at: self.updated_at,
+                references: self.references,
+                tags: self.tags,
+            }
+            .into_note(),
+            deleted_at: self.deleted_at,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JournalRow {
+    id: i64,
+    operation: String,
+}
+
 #[derive(Deserialize)]
 struct TagCountRow {
     name: String,
     count: i64,
+    description: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -176,75 +480,194 @@ struct CountRow {
     count: i64,
 }
 
+#[derive(Deserialize)]
+struct NamespaceRow {
+    namespace: String,
+    count: i64,
+}
+
+#[derive(Deserialize)]
+struct ApiKeyRow {
+    id: i64,
+    name: String,
+    scope: String,
+    tags: Option<String>,
+    namespaces: Option<String>,
+    created_at: String,
+}
+
+impl ApiKeyRow {
+    fn into_api_key(self) -> Result<ApiKey, Error> {
+        Ok(ApiKey {
+            id: self.id,
+            name: self.name,
+            scope: self
+                .scope
+                .parse()
+                .map_err(|e| Error::Database(format!("invalid API key scope: {}", e)))?,
+            tags: D1DatabaseWrapper::parse_references(self.tags),
+            namespaces: D1DatabaseWrapper::parse_references(self.namespaces),
+            created_at: self.created_at,
+        })
+    }
+}
+
 #[async_trait::async_trait(?Send)]
 impl Database for D1DatabaseWrapper {
-    async fn add_note(&self, note: CreateNote) -> Result<i64, Error> {
-        let refs_json = Self::serialize_references(&note.references);
+    fn namespace(&self) -> &str {
+        &self.namespace
+    }
 
-        // Insert the note
-        let stmt = self
+    fn with_namespace(&self, namespace: &str) -> Result<Self, Error> {
+        Ok(D1DatabaseWrapper {
+            db: Rc::clone(&self.db),
+            namespace: namespace.to_string(),
+            initialized: self.initialized,
+        })
+    }
+
+    async fn list_namespaces(&self) -> Result<Vec<NamespaceCount>, Error> {
+        let result = self
             .db
             .prepare(
-                "INSERT INTO notes (title, body, \"references\") VALUES (?1, ?2, ?3) RETURNING id",
+                "SELECT namespace, COUNT(*) as count FROM notes
+                 WHERE deleted_at IS NULL
+                 GROUP BY namespace
+                 ORDER BY namespace",
             )
-            .bind(&[
-                JsValue::from_str(&note.title),
-                JsValue::from_str(&note.body),
-                JsValue::from_str(&refs_json),
-            ])
+            .all()
+            .await
             .map_err(|e| Error::Database(e.to_string()))?;
 
-        let result = stmt
-            .first::<NoteIdRow>(None)
+        let rows: Vec<NamespaceRow> = result
+            .results()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| NamespaceCount {
+                name: r.namespace,
+                count: r.count,
+            })
+            .collect())
+    }
+
+    async fn add_note(&self, note: CreateNote) -> Result<i64, Error> {
+        let refs_json = Self::serialize_references(&note.references);
+
+        // Take the namespace's next ID and insert the note with it, in one
+        // batch so no other note gets the same ID
+        let statements = vec![
+            self.db
+                .prepare(
+                    "INSERT INTO note_ids (namespace, last_id) VALUES (?1, 1)
+                     ON CONFLICT (namespace) DO UPDATE SET last_id = last_id + 1",
+                )
+                .bind(&[self.ns()])
+                .map_err(|e| Error::Database(e.to_string()))?,
+            self.db
+                .prepare(
+                    "INSERT INTO notes (id, title, body, \"references\", namespace)
+                     VALUES ((SELECT last_id FROM note_ids WHERE namespace = ?4), ?1, ?2, ?3, ?4)
+                     RETURNING row_id, id",
+                )
+                .bind(&[
+                    JsValue::from_str(&note.title),
+                    JsValue::from_str(&note.body),
+                    JsValue::from_str(&refs_json),
+                    self.ns(),
+                ])
+                .map_err(|e| Error::Database(e.to_string()))?,
+        ];
+        let results = self
+            .db
+            .batch(statements)
             .await
-            .map_err(|e| Error::Database(e.to_string()))?
+            .map_err(|e| Error::Database(e.to_string()))?;
+        let rows: Vec<NoteKeyRow> = results
+            .get(1)
+            .ok_or_else(|| Error::Database("Failed to insert note".into()))?
+            .results()
+            .map_err(|e| Error::Database(e.to_string()))?;
+        let inserted = rows
+            .into_iter()
+            .next()
             .ok_or_else(|| Error::Database("Failed to insert note".into()))?;
 
-        let note_id = result.id;
-
-        // Insert tags using batch for efficiency
         if !note.tags.is_empty() {
-            let mut statements = Vec::new();
-
-            for tag in &note.tags {
-                let tag_stmt = self
-                    .db
-                    .prepare("INSERT INTO tags (name) VALUES (?1) ON CONFLICT (name) DO NOTHING")
-                    .bind(&[JsValue::from_str(tag)])
-                    .map_err(|e| Error::Database(e.to_string()))?;
-                statements.push(tag_stmt);
-
-                let link_stmt = self
-                    .db
-                    .prepare(
-                        "INSERT INTO note_tags (note_id, tag_id) SELECT ?1, id FROM tags WHERE name = ?2",
-                    )
-                    .bind(&[JsValue::from_f64(note_id as f64), JsValue::from_str(tag)])
-                    .map_err(|e| Error::Database(e.to_string()))?;
-                statements.push(link_stmt);
-            }
-
             self.db
-                .batch(statements)
+                .batch(self.tag_statements(inserted.row_id, &note.tags)?)
                 .await
                 .map_err(|e| Error::Database(e.to_string()))?;
         }
 
-        Ok(note_id)
+        Ok(inserted.id)
+    }
+
+    async fn put_note(&self, note: &Note) -> Result<(), Error> {
+        let refs_json = Self::serialize_references(&note.references);
+
+        // New notes mustn't take this note's ID
+        self.db
+            .prepare(
+                "INSERT INTO note_ids (namespace, last_id) VALUES (?1, ?2)
+                 ON CONFLICT (namespace) DO UPDATE SET last_id = MAX(last_id, excluded.last_id)",
+            )
+            .bind(&[self.ns(), JsValue::from_f64(note.id as f64)])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .run()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let stored = self
+            .db
+            .prepare(
+                "INSERT INTO notes (id, title, body, \"references\", namespace, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT (namespace, id) DO UPDATE SET
+                     title = excluded.title,
+                     body = excluded.body,
+                     \"references\" = excluded.\"references\",
+                     created_at = excluded.created_at,
+                     updated_at = excluded.updated_at,
+                     deleted_at = NULL
+                 RETURNING row_id",
+            )
+            .bind(&[
+                JsValue::from_f64(note.id as f64),
+                JsValue::from_str(&note.title),
+                JsValue::from_str(&note.body),
+                JsValue::from_str(&refs_json),
+                self.ns(),
+                JsValue::from_str(&note.created_at),
+                JsValue::from_str(&note.updated_at),
+            ])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .first::<RowIdRow>(None)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?
+            .ok_or_else(|| Error::Database("Failed to store note".into()))?;
+
+        self.db
+            .batch(self.tag_statements(stored.row_id, &note.tags)?)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(())
     }
 
     async fn get_note(&self, id: i64) -> Result<Option<Note>, Error> {
         let stmt = self
             .db
             .prepare(
-                "SELECT n.id, n.title, n.body, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
+                "SELECT n.id, n.title, n.body, n.created_at, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
                  FROM notes n
-                 LEFT JOIN note_tags nt ON n.id = nt.note_id
+                 LEFT JOIN note_tags nt ON n.row_id = nt.note_id
                  LEFT JOIN tags t ON nt.tag_id = t.id
-                 WHERE n.id = ?1
-                 GROUP BY n.id",
+                 WHERE n.id = ?1 AND n.namespace = ?2 AND n.deleted_at IS NULL
+                 GROUP BY n.row_id",
             )
-            .bind(&[JsValue::from_f64(id as f64)])
+            .bind(&[JsValue::from_f64(id as f64), self.ns()])
             .map_err(|e| Error::Database(e.to_string()))?;
 
         let row = stmt
@@ -256,72 +679,29 @@ impl Database for D1DatabaseWrapper {
     }
 
     async fn list_notes(&self, query: NoteQuery) -> Result<Vec<Note>, Error> {
-        // Build query - D1 doesn't support dynamic parameter binding well,
-        // so we need to be careful here. For safety, we'll use simple queries.
-        let limit = query.limit.unwrap_or(20);
-
-        let result = if let Some(ref tags) = query.tags {
-            if !tags.is_empty() {
-                // Query with tag filter - use IN clause with escaped values
-                let tags_list = tags
-                    .iter()
-                    .map(|t| format!("'{}'", t.replace('\'', "''")))
-                    .collect::<Vec<_>>()
-                    .join(",");
-
-                let sql = format!(
-                    "SELECT n.id, n.title, n.body, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
-                     FROM notes n
-                     LEFT JOIN note_tags nt ON n.id = nt.note_id
-                     LEFT JOIN tags t ON nt.tag_id = t.id
-                     WHERE n.id IN (
-                         SELECT note_id FROM note_tags nt2
-                         JOIN tags t2 ON nt2.tag_id = t2.id
-                         WHERE t2.name IN ({})
-                     )
-                     GROUP BY n.id
-                     ORDER BY n.updated_at DESC, n.id DESC
-                     LIMIT {}",
-                    tags_list, limit
-                );
+        let (where_clause, params) = self.query_conditions(&query);
+
+        let mut sql = format!(
+            "SELECT n.id, n.title, n.body, n.created_at, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
+             FROM notes n
+             LEFT JOIN note_tags nt ON n.row_id = nt.note_id
+             LEFT JOIN tags t ON nt.tag_id = t.id
+             {}
+             GROUP BY n.row_id
+             ORDER BY {}",
+            where_clause,
+            query.order_by_sql()
+        );
+        sql.push_str(&query.limit_sql());
 
-                self.db
-                    .prepare(&sql)
-                    .all()
-                    .await
-                    .map_err(|e| Error::Database(e.to_string()))?
-            } else {
-                self.db
-                    .prepare(format!(
-                        "SELECT n.id, n.title, n.body, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
-                         FROM notes n
-                         LEFT JOIN note_tags nt ON n.id = nt.note_id
-                         LEFT JOIN tags t ON nt.tag_id = t.id
-                         GROUP BY n.id
-                         ORDER BY n.updated_at DESC, n.id DESC
-                         LIMIT {}",
-                        limit
-                    ))
-                    .all()
-                    .await
-                    .map_err(|e| Error::Database(e.to_string()))?
-            }
-        } else {
-            self.db
-                .prepare(format!(
-                    "SELECT n.id, n.title, n.body, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
-                     FROM notes n
-                     LEFT JOIN note_tags nt ON n.id = nt.note_id
-                     LEFT JOIN tags t ON nt.tag_id = t.id
-                     GROUP BY n.id
-                     ORDER BY n.updated_at DESC, n.id DESC
-                     LIMIT {}",
-                    limit
-                ))
-                .all()
-                .await
-                .map_err(|e| Error::Database(e.to_string()))?
-        };
+        let result = self
+            .db
+            .prepare(&sql)
+            .bind(&Self::bindings(&params))
+            .map_err(|e| Error::Database(e.to_string()))?
+            .all()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
 
         let rows: Vec<NoteRow> = result
             .results()
@@ -331,71 +711,60 @@ impl Database for D1DatabaseWrapper {
     }
 
     async fn count_notes(&self, query: NoteQuery) -> Result<i64, Error> {
-        let result = if let Some(ref tags) = query.tags {
-            if !tags.is_empty() {
-                let tags_list = tags
-                    .iter()
-                    .map(|t| format!("'{}'", t.replace('\'', "''")))
-                    .collect::<Vec<_>>()
-                    .join(",");
-
-                let sql = format!(
-                    "SELECT COUNT(DISTINCT n.id) as count FROM notes n
-                     WHERE n.id IN (
-                         SELECT note_id FROM note_tags nt2
-                         JOIN tags t2 ON nt2.tag_id = t2.id
-                         WHERE t2.name IN ({})
-                     )",
-                    tags_list
-                );
+        let (where_clause, params) = self.query_conditions(&query);
 
-                self.db
-                    .prepare(&sql)
-                    .first::<CountRow>(None)
-                    .await
-                    .map_err(|e| Error::Database(e.to_string()))?
-            } else {
-                self.db
-                    .prepare("SELECT COUNT(*) as count FROM notes")
-                    .first::<CountRow>(None)
-                    .await
-                    .map_err(|e| Error::Database(e.to_string()))?
-            }
-        } else {
-            self.db
-                .prepare("SELECT COUNT(*) as count FROM notes")
-                .first::<CountRow>(None)
-                .await
-                .map_err(|e| Error::Database(e.to_string()))?
-        };
+        let sql = format!(
+            "SELECT COUNT(DISTINCT n.id) as count FROM notes n {}",
+            where_clause
+        );
+
+        let result = self
+            .db
+            .prepare(&sql)
+            .bind(&Self::bindings(&params))
+            .map_err(|e| Error::Database(e.to_string()))?
+            .first::<CountRow>(None)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
 
         Ok(result.map(|r| r.count).unwrap_or(0))
     }
 
     async fn update_note(&self, id: i64, update: UpdateNote) -> Result<bool, Error> {
-        // Check if note exists
-        let count_stmt = self
-            .db
-            .prepare("SELECT COUNT(*) as count FROM notes WHERE id = ?1")
-            .bind(&[JsValue::from_f64(id as f64)])
-            .map_err(|e| Error::Database(e.to_string()))?;
+        let Some(row_id) = self.row_id(id, false).await? else {
+            return Ok(false);
+        };
+        let key = JsValue::from_f64(row_id as f64);
 
-        let exists = count_stmt
-            .first::<CountRow>(None)
-            .await
+        // Save the current version as a revision before changing anything
+        self.db
+            .prepare(
+                "INSERT INTO note_revisions (note_id, revision, title, body, tags, \"references\", updated_at)
+                 SELECT n.row_id,
+                        COALESCE((SELECT MAX(r.revision) FROM note_revisions r WHERE r.note_id = n.row_id), 0) + 1,
+                        n.title,
+                        n.body,
+                        (SELECT json_group_array(t.name) FROM note_tags nt
+                         JOIN tags t ON nt.tag_id = t.id
+                         WHERE nt.note_id = n.row_id),
+                        n.\"references\",
+                        n.updated_at
+                 FROM notes n
+                 WHERE n.row_id = ?1",
+            )
+            .bind(std::slice::from_ref(&key))
             .map_err(|e| Error::Database(e.to_string()))?
-            .map(|r| r.count > 0)
-            .unwrap_or(false);
-
-        if !exists {
-            return Ok(false);
-        }
+            .run()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
 
         // Update title if provided
         if let Some(ref title) = update.title {
             self.db
-                .prepare("UPDATE notes SET title = ?1, updated_at = datetime('now') WHERE id = ?2")
-                .bind(&[JsValue::from_str(title), JsValue::from_f64(id as f64)])
+                .prepare(
+                    "UPDATE notes SET title = ?1, updated_at = datetime('now') WHERE row_id = ?2",
+                )
+                .bind(&[JsValue::from_str(title), key.clone()])
                 .map_err(|e| Error::Database(e.to_string()))?
                 .run()
                 .await
@@ -405,8 +774,10 @@ impl Database for D1DatabaseWrapper {
         // Update body if provided
         if let Some(ref body) = update.body {
             self.db
-                .prepare("UPDATE notes SET body = ?1, updated_at = datetime('now') WHERE id = ?2")
-                .bind(&[JsValue::from_str(body), JsValue::from_f64(id as f64)])
+                .prepare(
+                    "UPDATE notes SET body = ?1, updated_at = datetime('now') WHERE row_id = ?2",
+                )
+                .bind(&[JsValue::from_str(body), key.clone()])
                 .map_err(|e| Error::Database(e.to_string()))?
                 .run()
                 .await
@@ -415,49 +786,15 @@ impl Database for D1DatabaseWrapper {
 
         // Update tags if provided
         if let Some(ref tags) = update.tags {
-            // Delete existing tags
             self.db
-                .prepare("DELETE FROM note_tags WHERE note_id = ?1")
-                .bind(&[JsValue::from_f64(id as f64)])
-                .map_err(|e| Error::Database(e.to_string()))?
-                .run()
+                .batch(self.tag_statements(row_id, tags)?)
                 .await
                 .map_err(|e| Error::Database(e.to_string()))?;
 
-            // Insert new tags
-            if !tags.is_empty() {
-                let mut statements = Vec::new();
-
-                for tag in tags {
-                    let tag_stmt = self
-                        .db
-                        .prepare(
-                            "INSERT INTO tags (name) VALUES (?1) ON CONFLICT (name) DO NOTHING",
-                        )
-                        .bind(&[JsValue::from_str(tag)])
-                        .map_err(|e| Error::Database(e.to_string()))?;
-                    statements.push(tag_stmt);
-
-                    let link_stmt = self
-                        .db
-                        .prepare(
-                            "INSERT INTO note_tags (note_id, tag_id) SELECT ?1, id FROM tags WHERE name = ?2",
-                        )
-                        .bind(&[JsValue::from_f64(id as f64), JsValue::from_str(tag)])
-                        .map_err(|e| Error::Database(e.to_string()))?;
-                    statements.push(link_stmt);
-                }
-
-                self.db
-                    .batch(statements)
-                    .await
-                    .map_err(|e| Error::Database(e.to_string()))?;
-            }
-
             // Update timestamp
             self.db
-                .prepare("UPDATE notes SET updated_at = datetime('now') WHERE id = ?1")
-                .bind(&[JsValue::from_f64(id as f64)])
+                .prepare("UPDATE notes SET updated_at = datetime('now') WHERE row_id = ?1")
+                .bind(std::slice::from_ref(&key))
                 .map_err(|e| Error::Database(e.to_string()))?
                 .run()
                 .await
@@ -468,8 +805,8 @@ impl Database for D1DatabaseWrapper {
         if let Some(ref references) = update.references {
             let refs_json = Self::serialize_references(references);
             self.db
-                .prepare("UPDATE notes SET \"references\" = ?1, updated_at = datetime('now') WHERE id = ?2")
-                .bind(&[JsValue::from_str(&refs_json), JsValue::from_f64(id as f64)])
+                .prepare("UPDATE notes SET \"references\" = ?1, updated_at = datetime('now') WHERE row_id = ?2")
+                .bind(&[JsValue::from_str(&refs_json), key.clone()])
                 .map_err(|e| Error::Database(e.to_string()))?
                 .run()
                 .await
@@ -480,28 +817,87 @@ impl Database for D1DatabaseWrapper {
     }
 
     async fn delete_note(&self, id: i64) -> Result<bool, Error> {
-        // Check if note exists first
-        let count_stmt = self
-            .db
-            .prepare("SELECT COUNT(*) as count FROM notes WHERE id = ?1")
-            .bind(&[JsValue::from_f64(id as f64)])
-            .map_err(|e| Error::Database(e.to_string()))?;
-
-        let exists = count_stmt
-            .first::<CountRow>(None)
+        let Some(row_id) = self.row_id(id, false).await? else {
+            return Ok(false);
+        };
+
+        self.db
+            .prepare("UPDATE notes SET deleted_at = datetime('now') WHERE row_id = ?1")
+            .bind(&[JsValue::from_f64(row_id as f64)])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .run()
             .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(true)
+    }
+
+    async fn list_trash(&self) -> Result<Vec<TrashedNote>, Error> {
+        let result = self
+            .db
+            .prepare(
+                "SELECT n.id, n.title, n.body, n.created_at, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags, n.deleted_at
+                 FROM notes n
+                 LEFT JOIN note_tags nt ON n.row_id = nt.note_id
+                 LEFT JOIN tags t ON nt.tag_id = t.id
+                 WHERE n.namespace = ?1 AND n.deleted_at IS NOT NULL
+                 GROUP BY n.row_id
+                 ORDER BY n.deleted_at DESC, n.id DESC",
+            )
+            .bind(&[self.ns()])
             .map_err(|e| Error::Database(e.to_string()))?
-            .map(|r| r.count > 0)
-            .unwrap_or(false);
+            .all()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
 
-        if !exists {
+        let rows: Vec<TrashRow> = result
+            .results()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(rows.into_iter().map(|r| r.into_trashed_note()).collect())
+    }
+
+    async fn restore_note(&self, id: i64) -> Result<bool, Error> {
+        let Some(row_id) = self.row_id(id, true).await? else {
             return Ok(false);
-        }
+        };
+
+        self.db
+            .prepare("UPDATE notes SET deleted_at = NULL WHERE row_id = ?1")
+            .bind(&[JsValue::from_f64(row_id as f64)])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .run()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(true)
+    }
+
+    async fn purge_note(&self, id: i64) -> Result<bool, Error> {
+        let Some(row_id) = self.row_id(id, true).await? else {
+            return Ok(false);
+        };
+
+        // Delete note_tags, revisions and links first (foreign keys)
+        self.db
+            .prepare("DELETE FROM note_links WHERE from_id = ?1 OR to_id = ?1")
+            .bind(&[JsValue::from_f64(row_id as f64)])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .run()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
 
-        // Delete note_tags first (foreign key)
         self.db
             .prepare("DELETE FROM note_tags WHERE note_id = ?1")
-            .bind(&[JsValue::from_f64(id as f64)])
+            .bind(&[JsValue::from_f64(row_id as f64)])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .run()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        self.db
+            .prepare("DELETE FROM note_revisions WHERE note_id = ?1")
+            .bind(&[JsValue::from_f64(row_id as f64)])
             .map_err(|e| Error::Database(e.to_string()))?
             .run()
             .await
@@ -509,8 +905,8 @@ impl Database for D1DatabaseWrapper {
 
         // Delete note
         self.db
-            .prepare("DELETE FROM notes WHERE id = ?1")
-            .bind(&[JsValue::from_f64(id as f64)])
+            .prepare("DELETE FROM notes WHERE row_id = ?1")
+            .bind(&[JsValue::from_f64(row_id as f64)])
             .map_err(|e| Error::Database(e.to_string()))?
             .run()
             .await
@@ -519,17 +915,121 @@ impl Database for D1DatabaseWrapper {
         Ok(true)
     }
 
+    async fn push_operation(&self, operation: &Operation) -> Result<(), Error> {
+        let json = serde_json::to_string(operation).map_err(|e| Error::Internal(e.to_string()))?;
+
+        self.db
+            .prepare("INSERT INTO journal (operation, namespace) VALUES (?1, ?2)")
+            .bind(&[JsValue::from_str(&json), self.ns()])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .run()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        self.db
+            .prepare(
+                "DELETE FROM journal WHERE namespace = ?1 AND id NOT IN
+                 (SELECT id FROM journal WHERE namespace = ?1 ORDER BY id DESC LIMIT ?2)",
+            )
+            .bind(&[self.ns(), JsValue::from_f64(JOURNAL_LIMIT as f64)])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .run()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn pop_operation(&self) -> Result<Option<Operation>, Error> {
+        let row = self
+            .db
+            .prepare(
+                "SELECT id, operation FROM journal WHERE namespace = ?1 ORDER BY id DESC LIMIT 1",
+            )
+            .bind(&[self.ns()])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .first::<JournalRow>(None)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        self.db
+            .prepare("DELETE FROM journal WHERE id = ?1")
+            .bind(&[JsValue::from_f64(row.id as f64)])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .run()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let operation = serde_json::from_str(&row.operation)
+            .map_err(|e| Error::Internal(format!("invalid journal entry: {}", e)))?;
+        Ok(Some(operation))
+    }
+
+    async fn list_revisions(&self, note_id: i64) -> Result<Vec<Revision>, Error> {
+        let result = self
+            .db
+            .prepare(
+                "SELECT n.id AS note_id, r.revision, r.title, r.body, r.tags, r.\"references\", r.updated_at
+                 FROM note_revisions r
+                 JOIN notes n ON n.row_id = r.note_id
+                 WHERE n.id = ?1 AND n.namespace = ?2
+                 ORDER BY r.revision DESC",
+            )
+            .bind(&[JsValue::from_f64(note_id as f64), self.ns()])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .all()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let rows: Vec<RevisionRow> = result
+            .results()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(rows.into_iter().map(|r| r.into_revision()).collect())
+    }
+
+    async fn get_revision(&self, note_id: i64, revision: i64) -> Result<Option<Revision>, Error> {
+        let row = self
+            .db
+            .prepare(
+                "SELECT n.id AS note_id, r.revision, r.title, r.body, r.tags, r.\"references\", r.updated_at
+                 FROM note_revisions r
+                 JOIN notes n ON n.row_id = r.note_id
+                 WHERE n.id = ?1 AND r.revision = ?2 AND n.namespace = ?3",
+            )
+            .bind(&[
+                JsValue::from_f64(note_id as f64),
+                JsValue::from_f64(revision as f64),
+                self.ns(),
+            ])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .first::<RevisionRow>(None)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(row.map(|r| r.into_revision()))
+    }
+
     async fn list_tags(&self) -> Result<Vec<TagCount>, Error> {
         let result = self
             .db
             .prepare(
-                "SELECT t.name, COUNT(nt.note_id) as count
+                "SELECT t.name, COUNT(n.row_id) as count, d.description
                  FROM tags t
                  LEFT JOIN note_tags nt ON t.id = nt.tag_id
+                 LEFT JOIN notes n ON nt.note_id = n.row_id AND n.deleted_at IS NULL
+                 LEFT JOIN tag_descriptions d ON d.namespace = t.namespace AND d.name = t.name
+                 WHERE t.namespace = ?1
                  GROUP BY t.id
                  HAVING count > 0
                  ORDER BY count DESC, t.name",
             )
+            .bind(&[self.ns()])
+            .map_err(|e| Error::Database(e.to_string()))?
             .all()
             .await
             .map_err(|e| Error::Database(e.to_string()))?;
@@ -543,14 +1043,175 @@ impl Database for D1DatabaseWrapper {
             .map(|r| TagCount {
                 name: r.name,
                 count: r.count,
+                description: r.description,
             })
             .collect())
     }
 
+    async fn merge_tags(&self, sources: &[String], target: &str) -> Result<Vec<i64>, Error> {
+        self.retag(sources, Some(target)).await
+    }
+
+    async fn remove_tag(&self, tag: &str) -> Result<Vec<i64>, Error> {
+        self.retag(&[tag.to_string()], None).await
+    }
+
+    async fn set_tag_description(
+        &self,
+        tag: &str,
+        description: Option<&str>,
+    ) -> Result<bool, Error> {
+        let row = self
+            .db
+            .prepare(
+                "SELECT COUNT(*) as count FROM notes n
+                 JOIN note_tags nt ON nt.note_id = n.row_id
+                 JOIN tags t ON nt.tag_id = t.id
+                 WHERE n.namespace = ?1 AND n.deleted_at IS NULL AND t.name = ?2",
+            )
+            .bind(&[self.ns(), JsValue::from_str(tag)])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .first::<CountRow>(None)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+        if row.map(|r| r.count).unwrap_or(0) == 0 {
+            return Ok(false);
+        }
+
+        let statement = match description {
+            Some(description) => self
+                .db
+                .prepare(
+                    "INSERT INTO tag_descriptions (namespace, name, description) VALUES (?1, ?2, ?3)
+                     ON CONFLICT (namespace, name) DO UPDATE SET description = excluded.description",
+                )
+                .bind(&[self.ns(), JsValue::from_str(tag), JsValue::from_str(description)]),
+            None => self
+                .db
+                .prepare("DELETE FROM tag_descriptions WHERE namespace = ?1 AND name = ?2")
+                .bind(&[self.ns(), JsValue::from_str(tag)]),
+        };
+        statement
+            .map_err(|e| Error::Database(e.to_string()))?
+            .run()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(true)
+    }
+
+    async fn list_tag_aliases(&self) -> Result<Vec<TagAlias>, Error> {
+        let result = self
+            .db
+            .prepare("SELECT alias, tag FROM tag_aliases WHERE namespace = ?1 ORDER BY alias")
+            .bind(&[self.ns()])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .all()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        result.results().map_err(|e| Error::Database(e.to_string()))
+    }
+
+    async fn set_tag_alias(&self, alias: &str, tag: Option<&str>) -> Result<bool, Error> {
+        let statement = match tag {
+            Some(tag) => self
+                .db
+                .prepare(
+                    "INSERT INTO tag_aliases (namespace, alias, tag) VALUES (?1, ?2, ?3)
+                     ON CONFLICT (namespace, alias) DO UPDATE SET tag = excluded.tag
+                     RETURNING alias, tag",
+                )
+                .bind(&[self.ns(), JsValue::from_str(alias), JsValue::from_str(tag)]),
+            None => self
+                .db
+                .prepare(
+                    "DELETE FROM tag_aliases WHERE namespace = ?1 AND alias = ?2 RETURNING alias, tag",
+                )
+                .bind(&[self.ns(), JsValue::from_str(alias)]),
+        };
+        let row = statement
+            .map_err(|e| Error::Database(e.to_string()))?
+            .first::<TagAlias>(None)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(row.is_some())
+    }
+
+    async fn list_links(&self, id: i64) -> Result<Vec<NoteLink>, Error> {
+        let result = self
+            .db
+            .prepare(
+                "SELECT f.id AS \"from\", t.id AS \"to\", l.kind
+                 FROM note_links l
+                 JOIN notes f ON f.row_id = l.from_id
+                 JOIN notes t ON t.row_id = l.to_id
+                 WHERE (f.id = ?1 OR t.id = ?1)
+                   AND f.namespace = ?2 AND f.deleted_at IS NULL
+                   AND t.namespace = ?2 AND t.deleted_at IS NULL
+                 ORDER BY f.id, t.id, l.kind",
+            )
+            .bind(&[JsValue::from_f64(id as f64), self.ns()])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .all()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        result.results().map_err(|e| Error::Database(e.to_string()))
+    }
+
+    async fn add_link(&self, link: &NoteLink) -> Result<bool, Error> {
+        let row = self
+            .db
+            .prepare(
+                "INSERT INTO note_links (from_id, to_id, kind)
+                 SELECT f.row_id, t.row_id, ?3 FROM notes f, notes t
+                 WHERE f.namespace = ?4 AND f.id = ?1 AND t.namespace = ?4 AND t.id = ?2
+                 ON CONFLICT DO NOTHING
+                 RETURNING from_id AS row_id",
+            )
+            .bind(&[
+                JsValue::from_f64(link.from as f64),
+                JsValue::from_f64(link.to as f64),
+                JsValue::from_str(&link.kind.to_string()),
+                self.ns(),
+            ])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .first::<RowIdRow>(None)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(row.is_some())
+    }
+
+    async fn remove_link(&self, from: i64, to: i64, kind: LinkKind) -> Result<bool, Error> {
+        let row = self
+            .db
+            .prepare(
+                "DELETE FROM note_links WHERE kind = ?3
+                 AND from_id = (SELECT row_id FROM notes WHERE namespace = ?4 AND id = ?1)
+                 AND to_id = (SELECT row_id FROM notes WHERE namespace = ?4 AND id = ?2)
+                 RETURNING from_id AS row_id",
+            )
+            .bind(&[
+                JsValue::from_f64(from as f64),
+                JsValue::from_f64(to as f64),
+                JsValue::from_str(&kind.to_string()),
+                self.ns(),
+            ])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .first::<RowIdRow>(None)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(row.is_some())
+    }
+
     async fn grep(
         &self,
         pattern: &str,
-        tags: Option<&[String]>,
+        query: &NoteQuery,
         case_sensitive: bool,
     ) -> Result<Vec<Note>, Error> {
         // Build regex for client-side filtering
@@ -561,73 +1222,167 @@ impl Database for D1DatabaseWrapper {
                 .map_err(|e| Error::Validation(format!("invalid regex: {}", e)))?
         };
 
-        // Query all notes (with tag filter if provided)
-        let result = if let Some(tag_list) = tags {
-            if !tag_list.is_empty() {
-                let tags_str = tag_list
-                    .iter()
-                    .map(|t| format!("'{}'", t.replace('\'', "''")))
-                    .collect::<Vec<_>>()
-                    .join(",");
-
-                let sql = format!(
-                    "SELECT n.id, n.title, n.body, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
-                     FROM notes n
-                     LEFT JOIN note_tags nt ON n.id = nt.note_id
-                     LEFT JOIN tags t ON nt.tag_id = t.id
-                     WHERE n.id IN (SELECT note_id FROM note_tags nt2 
-                                    JOIN tags t2 ON nt2.tag_id = t2.id 
-                                    WHERE t2.name IN ({}))
-                     GROUP BY n.id
-                     ORDER BY n.updated_at DESC, n.id DESC",
-                    tags_str
-                );
+        // Query all notes matching the filters
+        let (where_clause, params) = self.query_conditions(query);
+
+        let sql = format!(
+            "SELECT n.id, n.title, n.body, n.created_at, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
+             FROM notes n
+             LEFT JOIN note_tags nt ON n.row_id = nt.note_id
+             LEFT JOIN tags t ON nt.tag_id = t.id
+             {}
+             GROUP BY n.row_id
+             ORDER BY {}",
+            where_clause,
+            query.order_by_sql()
+        );
 
-                self.db
-                    .prepare(&sql)
-                    .all()
-                    .await
-                    .map_err(|e| Error::Database(e.to_string()))?
-            } else {
-                self.db
-                    .prepare(
-                        "SELECT n.id, n.title, n.body, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
-                         FROM notes n
-                         LEFT JOIN note_tags nt ON n.id = nt.note_id
-                         LEFT JOIN tags t ON nt.tag_id = t.id
-                         GROUP BY n.id
-                         ORDER BY n.updated_at DESC, n.id DESC",
-                    )
-                    .all()
-                    .await
-                    .map_err(|e| Error::Database(e.to_string()))?
-            }
-        } else {
-            self.db
-                .prepare(
-                    "SELECT n.id, n.title, n.body, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
-                     FROM notes n
-                     LEFT JOIN note_tags nt ON n.id = nt.note_id
-                     LEFT JOIN tags t ON nt.tag_id = t.id
-                     GROUP BY n.id
-                     ORDER BY n.updated_at DESC, n.id DESC",
-                )
-                .all()
-                .await
-                .map_err(|e| Error::Database(e.to_string()))?
-        };
+        let result = self
+            .db
+            .prepare(&sql)
+            .bind(&Self::bindings(&params))
+            .map_err(|e| Error::Database(e.to_string()))?
+            .all()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
 
         let rows: Vec<NoteRow> = result
             .results()
             .map_err(|e| Error::Database(e.to_string()))?;
 
         // Filter by regex client-side
-        let matching: Vec<Note> = rows
+        let mut matching: Vec<Note> = rows
             .into_iter()
             .map(|r| r.into_note())
             .filter(|note| regex.is_match(&note.title) || regex.is_match(&note.body))
             .collect();
 
+        query.paginate(&mut matching);
+
         Ok(matching)
     }
+
+    async fn search(
+        &self,
+        query: &str,
+        tags: Option<&TagExpr>,
+        limit: Option<i64>,
+    ) -> Result<Vec<SearchHit>, Error> {
+        let match_expr = match fts5_query(query) {
+            Some(expr) => expr,
+            None => return Ok(Vec::new()),
+        };
+
+        // bm25() returns lower-is-better scores, so negate it
+        let mut sql = format!(
+            "SELECT n.id, n.title, n.body, n.created_at, n.updated_at, n.\"references\",
+                    (SELECT GROUP_CONCAT(t.name) FROM note_tags nt
+                     JOIN tags t ON nt.tag_id = t.id
+                     WHERE nt.note_id = n.row_id) as tags,
+                    -bm25(notes_fts, {}, {}) as score
+             FROM notes_fts
+             JOIN notes n ON n.row_id = notes_fts.rowid
+             WHERE notes_fts MATCH ?1 AND n.namespace = ?2 AND n.deleted_at IS NULL",
+            TITLE_WEIGHT, BODY_WEIGHT
+        );
+
+        let mut params = vec![match_expr, self.namespace.clone()];
+
+        if let Some(tags) = tags {
+            sql.push_str(" AND ");
+            sql.push_str(&tags.to_sql(&mut params));
+        }
+
+        sql.push_str(" ORDER BY score DESC, n.id DESC");
+
+        if let Some(limit) = limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+
+        let result = self
+            .db
+            .prepare(&sql)
+            .bind(&Self::bindings(&params))
+            .map_err(|e| Error::Database(e.to_string()))?
+            .all()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let rows: Vec<SearchRow> = result
+            .results()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(rows.into_iter().map(|r| r.into_hit()).collect())
+    }
+
+    async fn add_api_key(&self, key: CreateApiKey) -> Result<ApiKey, Error> {
+        let tags_json = Self::serialize_references(&key.tags);
+        let namespaces_json = Self::serialize_references(&key.namespaces);
+
+        self.db
+            .prepare(
+                "INSERT INTO api_keys (name, key_hash, scope, tags, namespaces)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 RETURNING id, name, scope, tags, namespaces, created_at",
+            )
+            .bind(&[
+                JsValue::from_str(&key.name),
+                JsValue::from_str(&key.key_hash),
+                JsValue::from_str(&key.scope.to_string()),
+                JsValue::from_str(&tags_json),
+                JsValue::from_str(&namespaces_json),
+            ])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .first::<ApiKeyRow>(None)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?
+            .ok_or_else(|| Error::Database("Failed to insert API key".into()))?
+            .into_api_key()
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, Error> {
+        let row = self
+            .db
+            .prepare(
+                "SELECT id, name, scope, tags, namespaces, created_at FROM api_keys
+                 WHERE key_hash = ?1",
+            )
+            .bind(&[JsValue::from_str(key_hash)])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .first::<ApiKeyRow>(None)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        row.map(ApiKeyRow::into_api_key).transpose()
+    }
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>, Error> {
+        let result = self
+            .db
+            .prepare(
+                "SELECT id, name, scope, tags, namespaces, created_at FROM api_keys ORDER BY id",
+            )
+            .all()
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let rows: Vec<ApiKeyRow> = result
+            .results()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        rows.into_iter().map(ApiKeyRow::into_api_key).collect()
+    }
+
+    async fn revoke_api_key(&self, id: i64) -> Result<bool, Error> {
+        let row = self
+            .db
+            .prepare("DELETE FROM api_keys WHERE id = ?1 RETURNING id")
+            .bind(&[JsValue::from_f64(id as f64)])
+            .map_err(|e| Error::Database(e.to_string()))?
+            .first::<NoteIdRow>(None)
+            .await
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(row.is_some())
+    }
 }
@@ -0,0 +1,244 @@
+//! Encrypted mode, for .veta directories on shared machines or in shared
+//! repositories.
+//!
+//! Note files, saved revisions, trashed notes and the search index are
+//! encrypted with XChaCha20-Poly1305, and stored as a line of base64 after
+//! a `veta-encrypted:1:` prefix. Tag directories are named after an HMAC of
+//! the tag, with the tag's name encrypted in a `.name` file inside, so tag
+//! names don't leak either. Note IDs, timestamps of files and which notes
+//! share a tag are still visible.
+//!
+//! The 32-byte key is given as base64, and is looked for in order in:
+//!
+//! - the `VETA_KEY` environment variable,
+//! - the file named by the `VETA_KEY_FILE` environment variable,
+//! - `.veta/key`, which `veta init --encrypt` creates and lists in
+//!   `.veta/.gitignore`, so it isn't committed with the notes.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use veta_core::Error;
+
+/// Environment variable holding the key.
+pub const KEY_ENV: &str = "VETA_KEY";
+
+/// Environment variable holding the path of a file with the key.
+pub const KEY_FILE_ENV: &str = "VETA_KEY_FILE";
+
+/// Name of the key file in the .veta directory.
+pub const KEY_FILE: &str = "key";
+
+const PREFIX: &str = "veta-encrypted:1:";
+const NONCE_LEN: usize = 24;
+
+/// A new random key, as base64.
+pub fn generate_key() -> String {
+    BASE64.encode(XChaCha20Poly1305::generate_key(&mut OsRng))
+}
+
+/// Find the key for the .veta directory at `root`, as base64, and where it
+/// came from. Returns None if there isn't one.
+pub(crate) fn find_key(root: &Path) -> Result<Option<(String, String)>, Error> {
+    if let Ok(key) = std::env::var(KEY_ENV) {
+        return Ok(Some((key, KEY_ENV.to_string())));
+    }
+    let path = match std::env::var_os(KEY_FILE_ENV) {
+        Some(path) => PathBuf::from(path),
+        None => root.join(KEY_FILE),
+    };
+    match fs::read_to_string(&path) {
+        Ok(key) => Ok(Some((key, path.display().to_string()))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && path == root.join(KEY_FILE) => {
+            Ok(None)
+        }
+        Err(e) => Err(Error::Database(format!(
+            "Failed to read key from {}: {}",
+            path.display(),
+            e
+        ))),
+    }
+}
+
+/// Write a key to `.veta/key`, readable only by its owner.
+pub(crate) fn write_key_file(root: &Path, key: &str) -> Result<(), Error> {
+    let path = root.join(KEY_FILE);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let write = |mut file: fs::File| {
+        use std::io::Write;
+        writeln!(file, "{}", key)
+    };
+    options
+        .open(&path)
+        .and_then(write)
+        .map_err(|e| Error::Database(format!("Failed to write key file: {}", e)))
+}
+
+/// Make sure the `.gitignore` in `root` lists the key file, so the key isn't
+/// committed along with a .veta directory that's inside a repository.
+pub(crate) fn ignore_key_file(root: &Path) -> Result<(), Error> {
+    let path = root.join(".gitignore");
+    let mut contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(Error::Database(format!("Failed to read .gitignore: {}", e))),
+    };
+    if contents.lines().any(|line| line.trim() == KEY_FILE) {
+        return Ok(());
+    }
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents.push_str(KEY_FILE);
+    contents.push('\n');
+    fs::write(&path, contents)
+        .map_err(|e| Error::Database(format!("Failed to write .gitignore: {}", e)))
+}
+
+/// Encrypts and decrypts the contents of a .veta directory.
+pub(crate) struct Cipher {
+    aead: XChaCha20Poly1305,
+    /// Key for naming tag directories, derived from the key.
+    tag_key: [u8; 32],
+    check: String,
+}
+
+impl Cipher {
+    /// A cipher for a base64 key.
+    pub(crate) fn new(key: &str) -> Result<Self, Error> {
+        let key = BASE64
+            .decode(key.trim())
+            .ok()
+            .filter(|key| key.len() == 32)
+            .ok_or_else(|| {
+                Error::Validation("invalid key: expected 32 bytes encoded as base64".to_string())
+            })?;
+        let derive = |label: &str| hmac(&key, &[label.as_bytes()]);
+        Ok(Cipher {
+            aead: XChaCha20Poly1305::new(&derive("veta notes").into()),
+            tag_key: derive("veta tags"),
+            check: hex(&derive("veta key check")[..8]),
+        })
+    }
+
+    /// A value stored in `config.json` to tell a wrong key from a right one,
+    /// which says nothing about the key itself.
+    pub(crate) fn check(&self) -> &str {
+        &self.check
+    }
+
+    /// Encrypt the contents of a file.
+    pub(crate) fn seal(&self, plaintext: &str) -> String {
+        self.seal_with_nonce(&XChaCha20Poly1305::generate_nonce(&mut OsRng), plaintext)
+    }
+
+    fn seal_with_nonce(&self, nonce: &XNonce, plaintext: &str) -> String {
+        let ciphertext = self
+            .aead
+            .encrypt(nonce, plaintext.as_bytes())
+            .expect("encrypting into a Vec can't fail");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        format!("{}{}\n", PREFIX, BASE64.encode(sealed))
+    }
+
+    /// Decrypt the contents of a file written by `seal`.
+    pub(crate) fn open(&self, contents: &str) -> Result<String, String> {
+        let encoded = contents
+            .trim_end()
+            .strip_prefix(PREFIX)
+            .ok_or("not encrypted")?;
+        let sealed = BASE64
+            .decode(encoded)
+            .ok()
+            .filter(|sealed| sealed.len() >= NONCE_LEN)
+            .ok_or("corrupt encrypted file")?;
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = self
+            .aead
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "can't decrypt with this key, or the file was modified")?;
+        String::from_utf8(plaintext).map_err(|_| "not UTF-8".to_string())
+    }
+
+    /// The name of a tag's directory.
+    pub(crate) fn tag_dir_name(&self, tag: &str) -> String {
+        hex(&self.tag_mac("dir", tag)[..16])
+    }
+
+    /// Encrypt a tag name for its directory's `.name` file. The nonce comes
+    /// from the tag, so every clone writes the same file for the same tag and
+    /// git merges don't conflict.
+    pub(crate) fn seal_tag_name(&self, tag: &str) -> String {
+        let mac = self.tag_mac("nonce", tag);
+        self.seal_with_nonce(XNonce::from_slice(&mac[..NONCE_LEN]), tag)
+    }
+
+    fn tag_mac(&self, purpose: &str, tag: &str) -> [u8; 32] {
+        hmac(&self.tag_key, &[purpose.as_bytes(), &[0], tag.as_bytes()])
+    }
+}
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes any key size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let cipher = Cipher::new(&generate_key()).unwrap();
+        let sealed = cipher.seal("customer: ACME");
+        assert!(sealed.starts_with(PREFIX));
+        assert!(!sealed.contains("ACME"));
+        assert_ne!(sealed, cipher.seal("customer: ACME"));
+        assert_eq!(cipher.open(&sealed).unwrap(), "customer: ACME");
+
+        let other = Cipher::new(&generate_key()).unwrap();
+        assert!(other.open(&sealed).is_err());
+        assert_ne!(other.check(), cipher.check());
+        assert!(cipher.open("{\"title\": \"plain\"}").is_err());
+    }
+
+    #[test]
+    fn test_tag_names() {
+        let cipher = Cipher::new(&generate_key()).unwrap();
+        let dir = cipher.tag_dir_name("acme-prod");
+        assert_eq!(dir.len(), 32);
+        assert_eq!(dir, cipher.tag_dir_name("acme-prod"));
+        assert_ne!(dir, cipher.tag_dir_name("acme-dev"));
+
+        let name = cipher.seal_tag_name("acme-prod");
+        assert_eq!(name, cipher.seal_tag_name("acme-prod"));
+        assert_eq!(cipher.open(&name).unwrap(), "acme-prod");
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        assert!(Cipher::new("too short").is_err());
+        assert!(Cipher::new(&BASE64.encode([0u8; 16])).is_err());
+        assert!(Cipher::new(&format!("{}\n", generate_key())).is_ok());
+    }
+}
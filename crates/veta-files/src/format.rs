@@ -0,0 +1,225 @@
+//! On-disk formats for note files.
+//!
+//! A `.veta` directory stores every note in one format, recorded in
+//! `config.json` when the directory is created:
+//!
+//! - `json` (the default): `notes/<id>.json`, a pretty-printed object.
+//! - `markdown`: `notes/<id>.md`, the body as plain Markdown after TOML
+//!   frontmatter between `+++` lines, so notes read well in editors and diffs:
+//!
+//! ```text
+//! +++
+//! title = "Deploy"
+//! created = "2026-01-01 10:00:00"
+//! modified = "2026-01-02 10:00:00"
+//! references = ["wrangler.toml"]
+//! +++
+//!
+//! Uses wrangler.
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use veta_core::Error;
+
+use crate::NoteFile;
+
+const FRONTMATTER_DELIMITER: &str = "+++";
+
+/// How note files are stored.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoteFormat {
+    #[default]
+    Json,
+    Markdown,
+}
+
+impl NoteFormat {
+    pub const ALL: [NoteFormat; 2] = [NoteFormat::Json, NoteFormat::Markdown];
+
+    /// File extension of notes in this format, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            NoteFormat::Json => "json",
+            NoteFormat::Markdown => "md",
+        }
+    }
+
+    pub(crate) fn parse(self, contents: &str) -> Result<NoteFile, Error> {
+        match self {
+            NoteFormat::Json => serde_json::from_str(contents)
+                .map_err(|e| Error::Database(format!("Failed to parse note: {}", e))),
+            NoteFormat::Markdown => parse_markdown(contents),
+        }
+    }
+
+    pub(crate) fn render(self, note: &NoteFile) -> Result<String, Error> {
+        match self {
+            NoteFormat::Json => serde_json::to_string_pretty(note)
+                .map_err(|e| Error::Database(format!("Failed to serialize note: {}", e))),
+            NoteFormat::Markdown => render_markdown(note),
+        }
+    }
+}
+
+impl fmt::Display for NoteFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoteFormat::Json => write!(f, "json"),
+            NoteFormat::Markdown => write!(f, "markdown"),
+        }
+    }
+}
+
+impl FromStr for NoteFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "json" => Ok(NoteFormat::Json),
+            "markdown" | "md" => Ok(NoteFormat::Markdown),
+            _ => Err(Error::Validation(format!(
+                "invalid note format '{}': expected json or markdown",
+                s
+            ))),
+        }
+    }
+}
+
+/// Everything in a note file except the body.
+#[derive(Serialize, Deserialize)]
+struct Frontmatter {
+    title: String,
+    #[serde(default)]
+    created: String,
+    #[serde(default)]
+    modified: String,
+    #[serde(default)]
+    references: Vec<String>,
+}
+
+/// Split a Markdown document into its TOML frontmatter, between `+++`
+/// lines, and the body after it.
+pub fn split_frontmatter(contents: &str) -> Result<(String, String), String> {
+    // Editors on Windows may have saved the file with CRLF line endings
+    let contents = contents.replace("\r\n", "\n");
+    let rest = contents
+        .strip_prefix(FRONTMATTER_DELIMITER)
+        .and_then(|rest| rest.strip_prefix('\n'))
+        .ok_or("missing +++ frontmatter")?;
+
+    let closing = format!("\n{}", FRONTMATTER_DELIMITER);
+    let end = rest
+        .find(&format!("{}\n", closing))
+        .or_else(|| rest.strip_suffix(&closing).map(|header| header.len()))
+        .ok_or("unterminated +++ frontmatter")?;
+    let header = &rest[..end];
+    let body = rest[end + closing.len()..]
+        .strip_prefix('\n')
+        .unwrap_or_default();
+    // A blank line separates the frontmatter from the body, and the file
+    // ends with a newline
+    let body = body.strip_prefix('\n').unwrap_or(body);
+    let body = body.strip_suffix('\n').unwrap_or(body);
+    Ok((header.to_string(), body.to_string()))
+}
+
+/// Put TOML frontmatter, as written by `toml::to_string`, before a body.
+pub fn join_frontmatter(header: &str, body: &str) -> String {
+    format!(
+        "{delim}\n{header}{delim}\n\n{body}\n",
+        delim = FRONTMATTER_DELIMITER,
+        header = header,
+        body = body
+    )
+}
+
+fn parse_markdown(contents: &str) -> Result<NoteFile, Error> {
+    let invalid = |reason: String| Error::Database(format!("Failed to parse note: {}", reason));
+
+    let (header, body) = split_frontmatter(contents).map_err(invalid)?;
+    let frontmatter: Frontmatter = toml::from_str(&header).map_err(|e| invalid(e.to_string()))?;
+    Ok(NoteFile {
+        title: frontmatter.title,
+        body,
+        references: frontmatter.references,
+        created: frontmatter.created,
+        modified: frontmatter.modified,
+    })
+}
+
+fn render_markdown(note: &NoteFile) -> Result<String, Error> {
+    let frontmatter = Frontmatter {
+        title: note.title.clone(),
+        created: note.created.clone(),
+        modified: note.modified.clone(),
+        references: note.references.clone(),
+    };
+    let header = toml::to_string(&frontmatter)
+        .map_err(|e| Error::Database(format!("Failed to serialize note: {}", e)))?;
+    Ok(join_frontmatter(&header, &note.body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(body: &str) -> NoteFile {
+        NoteFile {
+            title: "Deploy \"prod\"".to_string(),
+            body: body.to_string(),
+            references: vec!["wrangler.toml".to_string()],
+            created: "2026-01-01 10:00:00".to_string(),
+            modified: "2026-01-02 10:00:00".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_markdown_round_trip() {
+        for body in [
+            "Uses wrangler\n\n+++\nnot frontmatter",
+            "\nLeading newline",
+            "Trailing newline\n",
+            "",
+        ] {
+            let rendered = NoteFormat::Markdown.render(&note(body)).unwrap();
+            let parsed = NoteFormat::Markdown.parse(&rendered).unwrap();
+            assert_eq!(parsed.body, body);
+            assert_eq!(parsed.title, "Deploy \"prod\"");
+            assert_eq!(parsed.references, vec!["wrangler.toml"]);
+            assert_eq!(parsed.modified, "2026-01-02 10:00:00");
+        }
+
+        let rendered = NoteFormat::Markdown.render(&note("Uses wrangler")).unwrap();
+        assert!(rendered.starts_with("+++\ntitle = "));
+        assert!(rendered.ends_with("+++\n\nUses wrangler\n"));
+    }
+
+    #[test]
+    fn test_parse_hand_written_markdown() {
+        let parsed = NoteFormat::Markdown
+            .parse("+++\r\ntitle = \"Auth\"\r\n+++\r\n\r\nJWT tokens\r\n")
+            .unwrap();
+        assert_eq!(parsed.title, "Auth");
+        assert_eq!(parsed.body, "JWT tokens");
+        assert!(parsed.modified.is_empty());
+
+        assert!(NoteFormat::Markdown
+            .parse("+++\ntitle = \"Auth\"\n+++")
+            .is_ok());
+        assert!(NoteFormat::Markdown.parse("# Auth\n\nJWT tokens").is_err());
+        assert!(NoteFormat::Markdown
+            .parse("+++\ntitle = \"Auth\"\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_note_format() {
+        assert_eq!("md".parse::<NoteFormat>().unwrap(), NoteFormat::Markdown);
+        assert_eq!(NoteFormat::Markdown.to_string(), "markdown");
+        assert!("yaml".parse::<NoteFormat>().is_err());
+    }
+}
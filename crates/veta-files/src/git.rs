@@ -0,0 +1,306 @@
+//! Git mode, for .veta directories that are committed to a repository.
+//!
+//! Numbered note files collide when two branches each add a note, so in git
+//! mode a note's files are named after a random key instead, like
+//! `notes/3f9a2c41d07b.json`. Veta commands still take numeric IDs: each
+//! clone numbers the keys it sees in `aliases.json`, which isn't committed,
+//! so the same note can have different IDs in different clones.
+//!
+//! Notes edited on two branches are merged field by field by `veta merge`,
+//! which `veta init --git` registers as the git merge driver for note files.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fs2::FileExt;
+use veta_core::Error;
+
+use crate::{NoteFile, NoteFormat};
+
+/// Files that only make sense in one clone, and the key of encrypted notes.
+pub(crate) const GITIGNORE: &str = "\
+.lock
+*.tmp
+aliases.json
+aliases.lock
+counter
+journal.jsonl
+key
+note-index.json
+search-index.json
+sync.json
+";
+
+/// Use `veta merge` for notes changed on both sides of a merge.
+pub(crate) const GITATTRIBUTES: &str = "\
+notes/* merge=veta
+";
+
+/// A new random key for a note. Keys always include a letter, so they can't
+/// be mistaken for a numeric ID.
+pub(crate) fn new_key() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    loop {
+        // RandomState is seeded from the OS's random number generator
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u128(nanos);
+        hasher.write_u32(std::process::id());
+        let key = format!("{:016x}", hasher.finish())[..12].to_string();
+        if key.bytes().any(|b| b.is_ascii_alphabetic()) {
+            return key;
+        }
+    }
+}
+
+/// The numeric IDs this clone uses for note keys, stored in `aliases.json`.
+/// Aliases are only ever added, so an ID always means the same note.
+#[derive(Debug, Default)]
+pub(crate) struct Aliases {
+    keys: BTreeMap<i64, String>,
+    ids: HashMap<String, i64>,
+}
+
+impl Aliases {
+    pub(crate) fn load(root: &Path) -> Result<Self, Error> {
+        let keys: BTreeMap<i64, String> = match fs::read_to_string(root.join("aliases.json")) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| Error::Database(format!("Failed to parse aliases: {}", e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(Error::Database(format!("Failed to read aliases: {}", e))),
+        };
+        let ids = keys.iter().map(|(&id, key)| (key.clone(), id)).collect();
+        Ok(Aliases { keys, ids })
+    }
+
+    pub(crate) fn key(&self, id: i64) -> Option<&str> {
+        self.keys.get(&id).map(String::as_str)
+    }
+
+    pub(crate) fn id(&self, key: &str) -> Option<i64> {
+        self.ids.get(key).copied()
+    }
+
+    /// Reload the aliases and give `key` an ID if it doesn't have one yet,
+    /// holding a lock so concurrent commands don't hand out the same ID.
+    /// With `id`, use that ID unless it's taken.
+    pub(crate) fn assign(&mut self, root: &Path, key: &str, id: Option<i64>) -> Result<i64, Error> {
+        let lock = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(root.join("aliases.lock"))
+            .map_err(|e| Error::Database(format!("Failed to open alias lock: {}", e)))?;
+        lock.lock_exclusive()
+            .map_err(|e| Error::Database(format!("Failed to lock aliases: {}", e)))?;
+
+        *self = Self::load(root)?;
+        if let Some(id) = self.id(key) {
+            return Ok(id);
+        }
+        let next = self.keys.keys().next_back().copied().unwrap_or(0) + 1;
+        let id = id.filter(|id| !self.keys.contains_key(id)).unwrap_or(next);
+        self.keys.insert(id, key.to_string());
+        self.ids.insert(key.to_string(), id);
+
+        let contents = serde_json::to_string_pretty(&self.keys)
+            .map_err(|e| Error::Database(format!("Failed to serialize aliases: {}", e)))?;
+        let temp_path = root.join("aliases.json.tmp");
+        fs::write(&temp_path, contents)
+            .map_err(|e| Error::Database(format!("Failed to write aliases: {}", e)))?;
+        fs::rename(&temp_path, root.join("aliases.json"))
+            .map_err(|e| Error::Database(format!("Failed to rename aliases: {}", e)))?;
+
+        let _ = lock.unlock();
+        Ok(id)
+    }
+}
+
+/// Commit everything that changed in the .veta directory. Failing to commit
+/// doesn't fail the change that was made, so it's only reported.
+pub(crate) fn commit(root: &Path, message: &str) {
+    let git = |args: &[&str]| Command::new("git").args(args).current_dir(root).output();
+
+    let result = git(&["add", "-A", "--", "."]).and_then(|output| {
+        if !output.status.success() {
+            return Ok(output);
+        }
+        // Nothing to commit, e.g. an edit that didn't change anything
+        let staged = git(&["diff", "--cached", "--quiet", "--", "."])?;
+        if staged.status.success() {
+            return Ok(staged);
+        }
+        git(&["commit", "-q", "-m", message, "--", "."])
+    });
+    match result {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => eprintln!(
+            "Warning: failed to commit to git: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => eprintln!("Warning: failed to run git: {}", e),
+    }
+}
+
+/// Merge two versions of a note file that both changed since `base`, as a
+/// git merge driver. Returns the merged file, in the format of `ours`, and
+/// whether there were conflicts.
+///
+/// Fields changed on one side only take that side's value, and references
+/// added or removed on either side are added or removed. If both sides
+/// changed the title, the newer title wins; if both changed the body, the
+/// merged body holds both versions between conflict markers, to be fixed
+/// with `veta edit`.
+pub fn merge_note_files(base: &str, ours: &str, theirs: &str) -> Result<(String, bool), Error> {
+    let format = detect_format(ours);
+    let ours = format.parse(ours)?;
+    let theirs = detect_format(theirs).parse(theirs)?;
+    // Both sides added the file (unlikely with random keys)
+    let base = if base.trim().is_empty() {
+        NoteFile {
+            title: String::new(),
+            body: String::new(),
+            references: Vec::new(),
+            created: String::new(),
+            modified: String::new(),
+        }
+    } else {
+        detect_format(base).parse(base)?
+    };
+    let theirs_newer = theirs.modified > ours.modified;
+
+    let title = match merge_field(&base.title, &ours.title, &theirs.title) {
+        Some(title) => title.clone(),
+        None if theirs_newer => theirs.title.clone(),
+        None => ours.title.clone(),
+    };
+
+    let mut conflict = false;
+    let body = match merge_field(&base.body, &ours.body, &theirs.body) {
+        Some(body) => body.clone(),
+        None => {
+            conflict = true;
+            format!(
+                "<<<<<<< ours\n{}\n=======\n{}\n>>>>>>> theirs",
+                ours.body.trim_end(),
+                theirs.body.trim_end()
+            )
+        }
+    };
+
+    let mut references: Vec<String> = ours
+        .references
+        .iter()
+        .filter(|r| theirs.references.contains(r) || !base.references.contains(r))
+        .cloned()
+        .collect();
+    for reference in &theirs.references {
+        if !base.references.contains(reference) && !references.contains(reference) {
+            references.push(reference.clone());
+        }
+    }
+
+    let merged = NoteFile {
+        title,
+        body,
+        references,
+        created: ours.created.clone(),
+        modified: ours.modified.clone().max(theirs.modified.clone()),
+    };
+    Ok((format.render(&merged)?, conflict))
+}
+
+fn detect_format(contents: &str) -> NoteFormat {
+    if contents.trim_start().starts_with('{') {
+        NoteFormat::Json
+    } else {
+        NoteFormat::Markdown
+    }
+}
+
+/// The merged value of a field, or None if both sides changed it differently.
+fn merge_field<'a, T: PartialEq>(base: &T, ours: &'a T, theirs: &'a T) -> Option<&'a T> {
+    if ours == theirs || theirs == base {
+        Some(ours)
+    } else if ours == base {
+        Some(theirs)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(title: &str, body: &str, references: &[&str], modified: &str) -> String {
+        let note = NoteFile {
+            title: title.to_string(),
+            body: body.to_string(),
+            references: references.iter().map(|r| r.to_string()).collect(),
+            created: "2026-01-01 10:00:00".to_string(),
+            modified: modified.to_string(),
+        };
+        NoteFormat::Json.render(&note).unwrap()
+    }
+
+    #[test]
+    fn test_merge_note_files() {
+        let base = note(
+            "Deploy",
+            "Uses wrangler",
+            &["a", "b"],
+            "2026-01-01 10:00:00",
+        );
+        let ours = note("Deploy", "Uses terraform", &["a"], "2026-01-02 10:00:00");
+        let theirs = note(
+            "Deploying",
+            "Uses wrangler",
+            &["a", "b", "c"],
+            "2026-01-03 10:00:00",
+        );
+
+        let (merged, conflict) = merge_note_files(&base, &ours, &theirs).unwrap();
+        assert!(!conflict);
+        let merged = NoteFormat::Json.parse(&merged).unwrap();
+        assert_eq!(merged.title, "Deploying");
+        assert_eq!(merged.body, "Uses terraform");
+        assert_eq!(merged.references, vec!["a", "c"]);
+        assert_eq!(merged.modified, "2026-01-03 10:00:00");
+    }
+
+    #[test]
+    fn test_merge_conflicting_bodies() {
+        let base = note("Deploy", "Uses wrangler", &[], "2026-01-01 10:00:00");
+        let ours = note(
+            "Deploy (ours)",
+            "Uses terraform",
+            &[],
+            "2026-01-03 10:00:00",
+        );
+        let theirs = note("Deploy (theirs)", "Uses pulumi", &[], "2026-01-02 10:00:00");
+
+        let (merged, conflict) = merge_note_files(&base, &ours, &theirs).unwrap();
+        assert!(conflict);
+        let merged = NoteFormat::Json.parse(&merged).unwrap();
+        assert_eq!(merged.title, "Deploy (ours)");
+        assert_eq!(
+            merged.body,
+            "<<<<<<< ours\nUses terraform\n=======\nUses pulumi\n>>>>>>> theirs"
+        );
+    }
+
+    #[test]
+    fn test_new_key() {
+        let key = new_key();
+        assert_eq!(key.len(), 12);
+        assert!(key.parse::<i64>().is_err());
+        assert_ne!(key, new_key());
+    }
+}
@@ -1,28 +1,71 @@
 //! File-based storage implementation for Veta.
 //!
-//! Stores notes as JSON files with symlinks for tag organization:
+//! Stores notes as JSON or Markdown files (see [`NoteFormat`]) with symlinks
+//! for tag organization:
 //!
 //! ```text
 //! .veta/
 //!   .lock                    # Lock file for atomic operations
+//!   config.json              # Note format, git and encrypted mode, chosen when the directory is created
+//!   search-index.json        # Full-text search index (rebuilt on demand)
+//!   note-index.json          # Everything but note bodies, for listing (rebuilt on demand)
+//!   journal.jsonl            # Recent operations, used by undo
+//!   tag-descriptions.json    # What tags are for, by tag name
+//!   tag-aliases.json         # Other names for tags, by alias
+//!   api_keys.json            # Hashed keys for the HTTP API
+//!   links/                   # Links from each note to others
+//!     2.json                 # [{"to": "1", "kind": "supersedes"}]
 //!   notes/
 //!     1.json
 //!     2.json
+//!   history/                 # Previous versions, saved before each update
+//!     1/
+//!       1.json
+//!       2.json
+//!   trash/                   # Deleted notes, including their tags
+//!     3.json
 //!   tags/
 //!     architecture/
 //!       1.json → ../notes/1.json
 //!     testing/
 //!       2.json → ../notes/2.json
+//!     lang/
+//!       rust/                # The hierarchical tag lang/rust
+//!         2.json → ../../notes/2.json
 //! ```
+//!
+//! In git mode (see the `git` module), files are named after random keys
+//! instead of note IDs. In encrypted mode (see the `crypto` module), notes
+//! are encrypted and tag directories are named after a hash of the tag.
+
+mod crypto;
+mod format;
+mod git;
+mod note_index;
+mod search_index;
 
 use chrono::Utc;
+use crypto::Cipher;
 use fs2::FileExt;
+use git::Aliases;
+use note_index::NoteIndex;
 use regex::Regex;
+use search_index::{FileStamp, SearchIndex};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use veta_core::{CreateNote, Database, Error, Note, NoteQuery, TagCount, UpdateNote};
+use std::sync::Mutex;
+use veta_core::journal::JOURNAL_LIMIT;
+use veta_core::{
+    ApiKey, CreateApiKey, CreateNote, Database, Error, LinkKind, Note, NoteLink, NoteQuery,
+    Operation, Revision, SearchHit, TagAlias, TagCount, TagExpr, TrashedNote, UpdateNote,
+};
+
+pub use crypto::{generate_key, KEY_ENV, KEY_FILE, KEY_FILE_ENV};
+pub use format::{join_frontmatter, split_frontmatter, NoteFormat};
+pub use git::merge_note_files;
 
 fn default_modified() -> String {
     Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
@@ -35,13 +78,95 @@ struct NoteFile {
     body: String,
     #[serde(default)]
     references: Vec<String>,
-    #[serde(default = "default_modified")]
+    /// Backfilled from `modified` for notes written before this was stored.
+    #[serde(default)]
+    created: String,
+    /// Repaired with the current time if missing.
+    #[serde(default)]
+    modified: String,
+}
+
+/// Settings of a .veta directory, stored in `config.json`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilesConfig {
+    #[serde(default)]
+    pub format: NoteFormat,
+    /// Name files after random keys, so notes added on different git
+    /// branches don't collide.
+    #[serde(default)]
+    pub git: bool,
+    /// In git mode, commit every change to the notes.
+    #[serde(default)]
+    pub auto_commit: bool,
+    /// Encrypt notes and tag names with a key from `VETA_KEY`,
+    /// `VETA_KEY_FILE` or `.veta/key`.
+    #[serde(default)]
+    pub encrypted: bool,
+}
+
+/// `config.json`: the settings, and in encrypted mode a value to check the
+/// key against.
+#[derive(Serialize, Deserialize)]
+struct ConfigFile {
+    #[serde(flatten)]
+    config: FilesConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_check: Option<String>,
+}
+
+/// In encrypted mode, the file in a tag directory holding the tag's name.
+const TAG_NAME_FILE: &str = ".name";
+
+/// A previous version of a note, stored in `history/<id>/<revision>.json`.
+#[derive(Debug, Serialize, Deserialize)]
+struct RevisionFile {
+    title: String,
+    body: String,
+    tags: Vec<String>,
+    #[serde(default)]
+    references: Vec<String>,
+    modified: String,
+}
+
+/// A deleted note, stored in `trash/<id>.json`. Tags are kept in the file
+/// since the note's tag symlinks are removed.
+#[derive(Debug, Serialize, Deserialize)]
+struct TrashFile {
+    title: String,
+    body: String,
+    tags: Vec<String>,
+    #[serde(default)]
+    references: Vec<String>,
+    #[serde(default)]
+    created: String,
     modified: String,
+    deleted: String,
+}
+
+/// A link from a note, stored in `links/<id>.json` with the note's other
+/// links. The linked note is named like its file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct LinkEntry {
+    to: String,
+    kind: LinkKind,
+}
+
+/// An API key as stored in `api_keys.json`.
+#[derive(Serialize, Deserialize)]
+struct ApiKeyFile {
+    #[serde(flatten)]
+    key: ApiKey,
+    key_hash: String,
 }
 
 /// File-based database implementation.
 pub struct FilesDatabase {
     root: PathBuf,
+    config: FilesConfig,
+    /// Numeric IDs of note keys, in git mode.
+    aliases: Mutex<Aliases>,
+    /// In encrypted mode, encrypts and decrypts files.
+    cipher: Option<Cipher>,
 }
 
 impl FilesDatabase {
@@ -58,7 +183,169 @@ impl FilesDatabase {
         fs::create_dir_all(&tags_dir)
             .map_err(|e| Error::Database(format!("Failed to create tags dir: {}", e)))?;
 
-        Ok(Self { root })
+        let ConfigFile { config, key_check } = Self::read_config(&root)?;
+        let aliases = if config.git {
+            Aliases::load(&root)?
+        } else {
+            Aliases::default()
+        };
+        let cipher = if config.encrypted {
+            let (key, source) = crypto::find_key(&root)?.ok_or_else(|| {
+                Error::Database(format!(
+                    "The notes are encrypted. Set {} to the key, or {} to a file with the key",
+                    KEY_ENV, KEY_FILE_ENV
+                ))
+            })?;
+            let cipher = Cipher::new(&key)?;
+            if key_check.as_deref() != Some(cipher.check()) {
+                return Err(Error::Database(format!(
+                    "The key from {} isn't the key the notes are encrypted with",
+                    source
+                )));
+            }
+            Some(cipher)
+        } else {
+            None
+        };
+        Ok(Self {
+            root,
+            config,
+            aliases: Mutex::new(aliases),
+            cipher,
+        })
+    }
+
+    /// Create a file-based database with the given settings. Directories
+    /// without a `config.json` use the default settings.
+    ///
+    /// In encrypted mode, the key is looked for as when opening the
+    /// directory, and if there isn't one a new key is written to `.veta/key`,
+    /// which `.veta/.gitignore` lists.
+    pub fn create<P: AsRef<Path>>(root: P, config: FilesConfig) -> Result<Self, Error> {
+        let mut db = Self::open(root)?;
+        if config.encrypted != db.config.encrypted && !db.list_note_ids()?.is_empty() {
+            return Err(Error::Validation(
+                "can't change whether notes are encrypted once there are notes; export and import them instead".to_string(),
+            ));
+        }
+        db.cipher = match (config.encrypted, crypto::find_key(&db.root)?) {
+            (false, _) => None,
+            (true, Some((key, _))) => Some(Cipher::new(&key)?),
+            (true, None) => {
+                let key = generate_key();
+                crypto::write_key_file(&db.root, &key)?;
+                Some(Cipher::new(&key)?)
+            }
+        };
+        db.write_config(&config)?;
+        if config.git {
+            for (name, contents) in [
+                (".gitignore", git::GITIGNORE),
+                (".gitattributes", git::GITATTRIBUTES),
+            ] {
+                fs::write(db.root.join(name), contents)
+                    .map_err(|e| Error::Database(format!("Failed to write {}: {}", name, e)))?;
+            }
+        } else if config.encrypted {
+            crypto::ignore_key_file(&db.root)?;
+        }
+        db.config = config;
+        Ok(db)
+    }
+
+    /// The directory's settings.
+    pub fn config(&self) -> &FilesConfig {
+        &self.config
+    }
+
+    /// The format note files are stored in.
+    pub fn format(&self) -> NoteFormat {
+        self.config.format
+    }
+
+    /// Rewrite every note file in another format, and record it as the
+    /// directory's format. Returns the number of notes converted.
+    ///
+    /// The new files are written before the format is switched, so if
+    /// converting is interrupted the notes can still be read, and running it
+    /// again finishes the job.
+    pub fn convert(&mut self, format: NoteFormat) -> Result<usize, Error> {
+        let _lock = self.lock()?;
+
+        let mut converted = Vec::new();
+        for id in self.list_note_ids()? {
+            if let Some(note_file) = self.read_note_file(id)? {
+                self.write_note_file_in(id, &note_file, format)?;
+                converted.push(id);
+            }
+        }
+
+        let config = FilesConfig {
+            format,
+            ..self.config.clone()
+        };
+        self.write_config(&config)?;
+        self.config = config;
+
+        // Point the tag links at the new files and remove the old ones
+        for &id in &converted {
+            let tags = self.get_note_tags(id)?;
+            self.update_tags(id, &tags)?;
+            for other in NoteFormat::ALL.iter().filter(|&&f| f != format) {
+                let _ = fs::remove_file(self.note_path_in(id, *other));
+            }
+        }
+
+        // Rebuild the indexes when they're next used
+        let _ = fs::remove_file(self.search_index_path());
+        let _ = fs::remove_file(self.note_index_path());
+        self.auto_commit(&format!("Convert notes to {}", format));
+
+        Ok(converted.len())
+    }
+
+    /// Merge two versions of a note file in this directory, like
+    /// [`merge_note_files`], decrypting them first in encrypted mode.
+    pub fn merge_note_files(
+        &self,
+        base: &str,
+        ours: &str,
+        theirs: &str,
+    ) -> Result<(String, bool), Error> {
+        let open = |contents: &str| match &self.cipher {
+            Some(cipher) if !contents.trim().is_empty() => cipher
+                .open(contents)
+                .map_err(|e| Error::Database(format!("Failed to read note: {}", e))),
+            _ => Ok(contents.to_string()),
+        };
+        let (merged, conflict) = merge_note_files(&open(base)?, &open(ours)?, &open(theirs)?)?;
+        Ok((self.seal(merged), conflict))
+    }
+
+    fn read_config(root: &Path) -> Result<ConfigFile, Error> {
+        match fs::read_to_string(root.join("config.json")) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| Error::Database(format!("Failed to parse config: {}", e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ConfigFile {
+                config: FilesConfig::default(),
+                key_check: None,
+            }),
+            Err(e) => Err(Error::Database(format!("Failed to read config: {}", e))),
+        }
+    }
+
+    fn write_config(&self, config: &FilesConfig) -> Result<(), Error> {
+        let config_file = ConfigFile {
+            config: config.clone(),
+            key_check: self.cipher.as_ref().map(|c| c.check().to_string()),
+        };
+        let contents = serde_json::to_string_pretty(&config_file)
+            .map_err(|e| Error::Database(format!("Failed to serialize config: {}", e)))?;
+        let temp_path = self.root.join("config.json.tmp");
+        fs::write(&temp_path, contents)
+            .map_err(|e| Error::Database(format!("Failed to write config: {}", e)))?;
+        fs::rename(&temp_path, self.root.join("config.json"))
+            .map_err(|e| Error::Database(format!("Failed to rename config: {}", e)))
     }
 
     /// Acquire an exclusive lock on the database.
@@ -77,23 +364,107 @@ impl FilesDatabase {
         Ok(FileLock { file })
     }
 
+    /// Read a file, decrypting it in encrypted mode.
+    fn read_file(&self, path: &Path) -> std::io::Result<String> {
+        let contents = fs::read_to_string(path)?;
+        match &self.cipher {
+            Some(cipher) => cipher.open(&contents).map_err(|e| {
+                let path = path.strip_prefix(&self.root).unwrap_or(path);
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            }),
+            None => Ok(contents),
+        }
+    }
+
+    /// The contents to write to a file, encrypted in encrypted mode.
+    fn seal(&self, contents: String) -> String {
+        match &self.cipher {
+            Some(cipher) => cipher.seal(&contents),
+            None => contents,
+        }
+    }
+
+    /// The name a note's files are stored under: its ID, or in git mode its
+    /// key. IDs without a key get a name that no file has.
+    fn key(&self, id: i64) -> String {
+        if !self.config.git {
+            return id.to_string();
+        }
+        let mut aliases = self.aliases.lock().unwrap();
+        if aliases.key(id).is_none() {
+            // Another command may have added the note since we loaded them
+            if let Ok(loaded) = Aliases::load(&self.root) {
+                *aliases = loaded;
+            }
+        }
+        aliases
+            .key(id)
+            .map(str::to_string)
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    /// The ID of the note stored under `name`, or None if it isn't a note's
+    /// name. In git mode, keys without an ID (notes that came from another
+    /// clone) are given one.
+    fn id_for(&self, name: &str) -> Result<Option<i64>, Error> {
+        if !self.config.git {
+            return Ok(name.parse().ok());
+        }
+        if name.parse::<i64>().is_ok() {
+            return Ok(None);
+        }
+        let mut aliases = self.aliases.lock().unwrap();
+        match aliases.id(name) {
+            Some(id) => Ok(Some(id)),
+            None => aliases.assign(&self.root, name, None).map(Some),
+        }
+    }
+
     /// Get the path to a note file.
     fn note_path(&self, id: i64) -> PathBuf {
-        self.root.join("notes").join(format!("{}.json", id))
+        self.note_path_in(id, self.format())
+    }
+
+    fn note_path_in(&self, id: i64, format: NoteFormat) -> PathBuf {
+        self.root
+            .join("notes")
+            .join(format!("{}.{}", self.key(id), format.extension()))
+    }
+
+    /// Paths a note's link in a tag directory may have. Links are named like
+    /// the note file, but links to notes in either format are recognized, in
+    /// case converting the directory was interrupted.
+    fn tag_links(&self, tag_dir: &Path, id: i64) -> Vec<PathBuf> {
+        let key = self.key(id);
+        NoteFormat::ALL
+            .into_iter()
+            .map(|format| tag_dir.join(format!("{}.{}", key, format.extension())))
+            .collect()
+    }
+
+    /// Commit the .veta directory if auto-commit is on.
+    fn auto_commit(&self, message: &str) {
+        if self.config.git && self.config.auto_commit {
+            git::commit(&self.root, message);
+        }
     }
 
-    /// Scan the notes directory for the highest note ID.
+    /// Scan the notes and trash directories for the highest note ID.
     fn max_note_id(&self) -> i64 {
-        let notes_dir = self.root.join("notes");
         let mut max_id: i64 = 0;
 
-        if let Ok(entries) = fs::read_dir(&notes_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if let Some(stem) = path.file_stem() {
-                    if let Some(stem_str) = stem.to_str() {
-                        if let Ok(id) = stem_str.parse::<i64>() {
-                            max_id = max_id.max(id);
+        for dir in ["notes", "trash"] {
+            if let Ok(entries) = fs::read_dir(self.root.join(dir)) {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    if let Some(stem) = path.file_stem() {
+                        if let Some(stem_str) = stem.to_str() {
+                            if let Ok(id) = stem_str.parse::<i64>() {
+                                max_id = max_id.max(id);
+                            }
                         }
                     }
                 }
@@ -106,15 +477,14 @@ impl FilesDatabase {
     /// Uses a counter file to ensure IDs always increase, even after deletions.
     /// Also validates against actual note files to detect manually created notes.
     fn next_id(&self) -> Result<i64, Error> {
-        let counter_path = self.root.join("counter");
+        // In git mode, the highest alias is the counter
+        if self.config.git {
+            let key = git::new_key();
+            return self.aliases.lock().unwrap().assign(&self.root, &key, None);
+        }
 
-        let counter_value = if counter_path.exists() {
-            let contents = fs::read_to_string(&counter_path)
-                .map_err(|e| Error::Database(format!("Failed to read counter: {}", e)))?;
-            contents.trim().parse::<i64>().unwrap_or(0)
-        } else {
-            0
-        };
+        let counter_path = self.root.join("counter");
+        let counter_value = self.read_counter()?;
 
         // Always check actual note files to detect manually created notes
         let max_note = self.max_note_id();
@@ -139,6 +509,33 @@ impl FilesDatabase {
         Ok(next_id)
     }
 
+    fn read_counter(&self) -> Result<i64, Error> {
+        let counter_path = self.root.join("counter");
+        if !counter_path.exists() {
+            return Ok(0);
+        }
+        let contents = fs::read_to_string(&counter_path)
+            .map_err(|e| Error::Database(format!("Failed to read counter: {}", e)))?;
+        Ok(contents.trim().parse::<i64>().unwrap_or(0))
+    }
+
+    /// Move the counter up to `id`, so an imported note's ID isn't handed
+    /// out again.
+    fn raise_counter(&self, id: i64) -> Result<(), Error> {
+        if self.config.git {
+            let mut aliases = self.aliases.lock().unwrap();
+            if aliases.key(id).is_none() {
+                aliases.assign(&self.root, &git::new_key(), Some(id))?;
+            }
+            return Ok(());
+        }
+        if self.read_counter()? < id {
+            fs::write(self.root.join("counter"), id.to_string())
+                .map_err(|e| Error::Database(format!("Failed to write counter: {}", e)))?;
+        }
+        Ok(())
+    }
+
     /// Read a note file from disk. Auto-repairs files missing the `modified` field.
     fn read_note_file(&self, id: i64) -> Result<Option<NoteFile>, Error> {
         let path = self.note_path(id);
@@ -147,26 +544,32 @@ impl FilesDatabase {
             return Ok(None);
         }
 
-        let mut file = File::open(&path)
-            .map_err(|e| Error::Database(format!("Failed to open note: {}", e)))?;
-
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)
+        let contents = self
+            .read_file(&path)
             .map_err(|e| Error::Database(format!("Failed to read note: {}", e)))?;
 
-        // Check if the file is missing the `modified` field so we can repair it
-        let needs_repair = !contents.contains("\"modified\"");
-
-        let note: NoteFile = serde_json::from_str(&contents)
-            .map_err(|e| Error::Database(format!("Failed to parse note: {}", e)))?;
+        let mut note = self.format().parse(&contents)?;
 
+        // Repair files that are missing the `modified` field
+        let needs_repair = note.modified.is_empty();
         if needs_repair {
+            note.modified = default_modified();
             eprintln!(
                 "Warning: note {} is missing the `modified` field. Repairing.",
                 id
             );
             eprintln!("Only use the `veta` command to add notes.");
-            // Re-write the file with the defaulted `modified` timestamp
+        }
+
+        // Notes written by older versions don't have a creation time, so use
+        // the best estimate we have
+        let needs_backfill = note.created.is_empty();
+        if needs_backfill {
+            note.created = note.modified.clone();
+        }
+
+        if needs_repair || needs_backfill {
+            // Re-write the file with the defaulted timestamps
             self.write_note_file(id, &note)?;
         }
 
@@ -175,11 +578,19 @@ impl FilesDatabase {
 
     /// Write a note file to disk atomically.
     fn write_note_file(&self, id: i64, note: &NoteFile) -> Result<(), Error> {
-        let path = self.note_path(id);
-        let temp_path = self.root.join("notes").join(format!("{}.json.tmp", id));
+        self.write_note_file_in(id, note, self.format())
+    }
 
-        let contents = serde_json::to_string_pretty(note)
-            .map_err(|e| Error::Database(format!("Failed to serialize note: {}", e)))?;
+    fn write_note_file_in(
+        &self,
+        id: i64,
+        note: &NoteFile,
+        format: NoteFormat,
+    ) -> Result<(), Error> {
+        let path = self.note_path_in(id, format);
+        let temp_path = path.with_extension(format!("{}.tmp", format.extension()));
+
+        let contents = self.seal(format.render(note)?);
 
         // Write to temp file
         let mut file = File::create(&temp_path)
@@ -198,139 +609,597 @@ impl FilesDatabase {
         Ok(())
     }
 
-    /// Get tags for a note by scanning tag directories.
-    fn get_note_tags(&self, id: i64) -> Result<Vec<String>, Error> {
-        let tags_dir = self.root.join("tags");
-        let mut tags = Vec::new();
+    /// Get the directory holding a note's revisions.
+    fn history_dir(&self, id: i64) -> PathBuf {
+        self.root.join("history").join(self.key(id))
+    }
+
+    /// Revision numbers saved for a note, in ascending order.
+    fn revision_numbers(&self, id: i64) -> Vec<i64> {
+        let mut numbers: Vec<i64> = fs::read_dir(self.history_dir(id))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|entry| {
+                        entry
+                            .file_name()
+                            .to_str()?
+                            .strip_suffix(".json")?
+                            .parse()
+                            .ok()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        numbers.sort();
+        numbers
+    }
 
-        let entries = match fs::read_dir(&tags_dir) {
-            Ok(entries) => entries,
-            Err(_) => return Ok(tags),
+    /// Save the current version of a note as its next revision.
+    fn save_revision(&self, id: i64, note_file: &NoteFile) -> Result<(), Error> {
+        let dir = self.history_dir(id);
+        fs::create_dir_all(&dir)
+            .map_err(|e| Error::Database(format!("Failed to create history dir: {}", e)))?;
+
+        let revision = self.revision_numbers(id).last().copied().unwrap_or(0) + 1;
+        let revision_file = RevisionFile {
+            title: note_file.title.clone(),
+            body: note_file.body.clone(),
+            tags: self.get_note_tags(id)?,
+            references: note_file.references.clone(),
+            modified: note_file.modified.clone(),
         };
+        let contents = serde_json::to_string_pretty(&revision_file)
+            .map_err(|e| Error::Database(format!("Failed to serialize revision: {}", e)))?;
+        let contents = self.seal(contents);
+
+        let path = dir.join(format!("{}.json", revision));
+        let temp_path = dir.join(format!("{}.json.tmp", revision));
+        fs::write(&temp_path, contents)
+            .map_err(|e| Error::Database(format!("Failed to write revision: {}", e)))?;
+        fs::rename(&temp_path, &path)
+            .map_err(|e| Error::Database(format!("Failed to rename revision: {}", e)))?;
 
-        for entry in entries {
-            let entry =
-                entry.map_err(|e| Error::Database(format!("Failed to read dir entry: {}", e)))?;
-            let path = entry.path();
+        Ok(())
+    }
+
+    /// Read a saved revision of a note.
+    fn read_revision(&self, id: i64, revision: i64) -> Result<Option<Revision>, Error> {
+        let path = self.history_dir(id).join(format!("{}.json", revision));
+        let contents = match self.read_file(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Database(format!("Failed to read revision: {}", e))),
+        };
+        let revision_file: RevisionFile = serde_json::from_str(&contents)
+            .map_err(|e| Error::Database(format!("Failed to parse revision: {}", e)))?;
+
+        Ok(Some(Revision {
+            note_id: id,
+            revision,
+            title: revision_file.title,
+            body: revision_file.body,
+            tags: revision_file.tags,
+            references: revision_file.references,
+            updated_at: revision_file.modified,
+        }))
+    }
+
+    /// Get the path to a trashed note file.
+    fn trash_path(&self, id: i64) -> PathBuf {
+        self.root
+            .join("trash")
+            .join(format!("{}.json", self.key(id)))
+    }
+
+    /// Read a trashed note file from disk.
+    fn read_trash_file(&self, id: i64) -> Result<Option<TrashFile>, Error> {
+        let contents = match self.read_file(&self.trash_path(id)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Database(format!("Failed to read trash: {}", e))),
+        };
+        let trash_file = serde_json::from_str(&contents)
+            .map_err(|e| Error::Database(format!("Failed to parse trash: {}", e)))?;
+        Ok(Some(trash_file))
+    }
+
+    /// Write a trashed note file to disk atomically.
+    fn write_trash_file(&self, id: i64, trash_file: &TrashFile) -> Result<(), Error> {
+        let contents = serde_json::to_string_pretty(trash_file)
+            .map_err(|e| Error::Database(format!("Failed to serialize trash: {}", e)))?;
+        let contents = self.seal(contents);
+        fs::create_dir_all(self.root.join("trash"))
+            .map_err(|e| Error::Database(format!("Failed to create trash dir: {}", e)))?;
+
+        let path = self.trash_path(id);
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, contents)
+            .map_err(|e| Error::Database(format!("Failed to write trash: {}", e)))?;
+        fs::rename(&temp_path, &path)
+            .map_err(|e| Error::Database(format!("Failed to rename trash: {}", e)))
+    }
 
-            if path.is_dir() {
-                let symlink_path = path.join(format!("{}.json", id));
-                if self.symlink_exists(&symlink_path) {
-                    if let Some(tag_name) = path.file_name().and_then(|n| n.to_str()) {
-                        tags.push(tag_name.to_string());
+    /// IDs of the notes in the trash.
+    fn trash_ids(&self) -> Result<Vec<i64>, Error> {
+        let mut ids = Vec::new();
+        if let Ok(entries) = fs::read_dir(self.root.join("trash")) {
+            for entry in entries.flatten() {
+                let file_name = entry.file_name();
+                if let Some(stem) = file_name.to_str().and_then(|n| n.strip_suffix(".json")) {
+                    if let Some(id) = self.id_for(stem)? {
+                        ids.push(id);
                     }
                 }
             }
         }
-
-        tags.sort();
-        Ok(tags)
+        Ok(ids)
     }
 
-    /// Check if a symlink exists (works on both Unix and Windows).
-    fn symlink_exists(&self, path: &Path) -> bool {
-        // On Unix, this checks for symlinks
-        // On Windows, this checks for either real symlinks or our text-file-based symlinks
-        if path.exists() {
-            return true;
+    /// Remove all tag symlinks for a note.
+    fn remove_tag_links(&self, id: i64) -> Result<(), Error> {
+        for tag_path in self.tag_dirs() {
+            for symlink_path in self.tag_links(&tag_path, id) {
+                let _ = fs::remove_file(&symlink_path);
+            }
         }
 
-        // Check if it's a symlink that points to a non-existent target
-        path.symlink_metadata().is_ok()
+        // Clean up empty tag directories
+        self.cleanup_empty_tag_dirs()
     }
 
-    /// Create a symlink (or text file on Windows if symlinks fail).
-    fn create_symlink(&self, target: &Path, link: &Path) -> Result<(), Error> {
-        // Remove existing if any
-        let _ = fs::remove_file(link);
-
-        // Calculate relative path from link to target
-        let link_parent = link
-            .parent()
-            .ok_or_else(|| Error::Database("Symlink path has no parent".to_string()))?;
+    /// Get the path to the operation journal.
+    fn journal_path(&self) -> PathBuf {
+        self.root.join("journal.jsonl")
+    }
 
-        // We want a relative path like "../notes/1.json"
-        let relative_target =
-            pathdiff::diff_paths(target, link_parent).unwrap_or_else(|| target.to_path_buf());
+    /// Read all operations in the journal, oldest first.
+    /// Must be called while holding the lock.
+    fn read_journal(&self) -> Result<Vec<Operation>, Error> {
+        let contents = match fs::read_to_string(self.journal_path()) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::Database(format!("Failed to read journal: {}", e))),
+        };
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .map_err(|e| Error::Database(format!("Failed to parse journal: {}", e)))
+            })
+            .collect()
+    }
 
-        #[cfg(unix)]
-        {
-            std::os::unix::fs::symlink(&relative_target, link)
-                .map_err(|e| Error::Database(format!("Failed to create symlink: {}", e)))?;
+    /// Replace the journal with the given operations.
+    /// Must be called while holding the lock.
+    fn write_journal(&self, operations: &[Operation]) -> Result<(), Error> {
+        let mut contents = String::new();
+        for operation in operations {
+            let line = serde_json::to_string(operation)
+                .map_err(|e| Error::Database(format!("Failed to serialize journal: {}", e)))?;
+            contents.push_str(&line);
+            contents.push('\n');
         }
 
-        #[cfg(windows)]
-        {
-            // Try to create a real symlink first
-            match std::os::windows::fs::symlink_file(&relative_target, link) {
-                Ok(()) => {}
-                Err(_) => {
-                    // Fall back to text file containing the path
-                    let mut file = File::create(link).map_err(|e| {
-                        Error::Database(format!("Failed to create link file: {}", e))
-                    })?;
-                    // Use forward slashes for consistency
-                    let path_str = relative_target.to_string_lossy().replace('\\', "/");
-                    file.write_all(path_str.as_bytes()).map_err(|e| {
-                        Error::Database(format!("Failed to write link file: {}", e))
-                    })?;
-                }
-            }
-        }
+        let path = self.journal_path();
+        let temp_path = self.root.join("journal.jsonl.tmp");
+        fs::write(&temp_path, contents)
+            .map_err(|e| Error::Database(format!("Failed to write journal: {}", e)))?;
+        fs::rename(&temp_path, &path)
+            .map_err(|e| Error::Database(format!("Failed to rename journal: {}", e)))?;
 
         Ok(())
     }
 
-    /// Resolve a symlink to get the actual file path (handles Windows text-file symlinks).
-    #[allow(dead_code)]
-    fn resolve_symlink(&self, link: &Path) -> Result<PathBuf, Error> {
-        // Try to read as a real symlink first
-        if let Ok(target) = fs::read_link(link) {
-            // Resolve relative to the link's parent directory
-            let link_parent = link
-                .parent()
-                .ok_or_else(|| Error::Database("Symlink path has no parent".to_string()))?;
-            return Ok(link_parent.join(target));
+    /// Read a map of tag names kept in `file`, such as the tag descriptions.
+    /// `what` names it in errors.
+    /// Must be called while holding the lock.
+    fn read_tag_map(&self, file: &str, what: &str) -> Result<BTreeMap<String, String>, Error> {
+        let contents = match self.read_file(&self.root.join(file)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(Error::Database(format!("Failed to read {}: {}", what, e))),
+        };
+        serde_json::from_str(&contents)
+            .map_err(|e| Error::Database(format!("Failed to parse {}: {}", what, e)))
+    }
+
+    /// Replace a map read with [`Self::read_tag_map`], removing the file if
+    /// the map is empty.
+    /// Must be called while holding the lock.
+    fn write_tag_map(
+        &self,
+        file: &str,
+        what: &str,
+        map: &BTreeMap<String, String>,
+    ) -> Result<(), Error> {
+        let path = self.root.join(file);
+        if map.is_empty() {
+            return match fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(Error::Database(format!("Failed to delete {}: {}", what, e)))
+                }
+                _ => Ok(()),
+            };
         }
 
-        // Fall back to reading as a text file (Windows without symlink support)
-        let mut file = File::open(link)
-            .map_err(|e| Error::Database(format!("Failed to open link file: {}", e)))?;
+        let contents = serde_json::to_string_pretty(map)
+            .map_err(|e| Error::Database(format!("Failed to serialize {}: {}", what, e)))?;
+        let temp_path = self.root.join(format!("{}.tmp", file));
+        fs::write(&temp_path, self.seal(contents))
+            .map_err(|e| Error::Database(format!("Failed to write {}: {}", what, e)))?;
+        fs::rename(&temp_path, &path)
+            .map_err(|e| Error::Database(format!("Failed to rename {}: {}", what, e)))
+    }
 
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)
-            .map_err(|e| Error::Database(format!("Failed to read link file: {}", e)))?;
+    /// Read the tag descriptions, by tag name.
+    /// Must be called while holding the lock.
+    fn read_tag_descriptions(&self) -> Result<BTreeMap<String, String>, Error> {
+        self.read_tag_map("tag-descriptions.json", "tag descriptions")
+    }
 
-        let link_parent = link
-            .parent()
-            .ok_or_else(|| Error::Database("Symlink path has no parent".to_string()))?;
+    /// Replace the tag descriptions.
+    /// Must be called while holding the lock.
+    fn write_tag_descriptions(&self, descriptions: &BTreeMap<String, String>) -> Result<(), Error> {
+        self.write_tag_map("tag-descriptions.json", "tag descriptions", descriptions)
+    }
 
-        Ok(link_parent.join(contents.trim()))
+    /// Read the tag aliases, mapped to the tags they stand for.
+    /// Must be called while holding the lock.
+    fn read_tag_aliases(&self) -> Result<BTreeMap<String, String>, Error> {
+        self.read_tag_map("tag-aliases.json", "tag aliases")
     }
 
-    /// Update symlinks for a note's tags.
-    fn update_tags(&self, id: i64, tags: &[String]) -> Result<(), Error> {
-        let tags_dir = self.root.join("tags");
-        let note_path = self.note_path(id);
+    /// Replace the tag aliases.
+    /// Must be called while holding the lock.
+    fn write_tag_aliases(&self, aliases: &BTreeMap<String, String>) -> Result<(), Error> {
+        self.write_tag_map("tag-aliases.json", "tag aliases", aliases)
+    }
 
-        // Remove all existing tag symlinks for this note
-        if let Ok(entries) = fs::read_dir(&tags_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_dir() {
-                    let symlink_path = path.join(format!("{}.json", id));
-                    let _ = fs::remove_file(&symlink_path);
-                }
-            }
-        }
+    fn links_path(&self, id: i64) -> PathBuf {
+        self.root
+            .join("links")
+            .join(format!("{}.json", self.key(id)))
+    }
 
-        // Create new tag symlinks
+    /// Read the links from a note.
+    fn read_links(&self, id: i64) -> Result<Vec<LinkEntry>, Error> {
+        let contents = match self.read_file(&self.links_path(id)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::Database(format!("Failed to read links: {}", e))),
+        };
+        serde_json::from_str(&contents)
+            .map_err(|e| Error::Database(format!("Failed to parse links: {}", e)))
+    }
+
+    /// Replace the links from a note, removing the file if there are none.
+    /// Must be called while holding the lock.
+    fn write_links(&self, id: i64, links: &[LinkEntry]) -> Result<(), Error> {
+        let path = self.links_path(id);
+        if links.is_empty() {
+            return match fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(Error::Database(format!("Failed to delete links: {}", e)))
+                }
+                _ => Ok(()),
+            };
+        }
+
+        let contents = serde_json::to_string_pretty(links)
+            .map_err(|e| Error::Database(format!("Failed to serialize links: {}", e)))?;
+        fs::create_dir_all(self.root.join("links"))
+            .map_err(|e| Error::Database(format!("Failed to create links dir: {}", e)))?;
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, self.seal(contents))
+            .map_err(|e| Error::Database(format!("Failed to write links: {}", e)))?;
+        fs::rename(&temp_path, &path)
+            .map_err(|e| Error::Database(format!("Failed to rename links: {}", e)))
+    }
+
+    /// IDs of the notes with links from them.
+    fn linking_ids(&self) -> Result<Vec<i64>, Error> {
+        let mut ids = Vec::new();
+        if let Ok(entries) = fs::read_dir(self.root.join("links")) {
+            for entry in entries.flatten() {
+                let file_name = entry.file_name();
+                if let Some(stem) = file_name.to_str().and_then(|n| n.strip_suffix(".json")) {
+                    if let Some(id) = self.id_for(stem)? {
+                        ids.push(id);
+                    }
+                }
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Replace the tags `sources` with `target`, or remove them, on every
+    /// note. See [`Database::merge_tags`].
+    fn retag(&self, sources: &[String], target: Option<&str>) -> Result<Vec<i64>, Error> {
+        let _lock = self.lock()?;
+
+        let retag = |tags: &mut Vec<String>| {
+            tags.retain(|t| !sources.contains(t));
+            tags.extend(target.map(str::to_string));
+            tags.sort();
+            tags.dedup();
+        };
+
+        let mut ids = BTreeSet::new();
+        for source in sources {
+            for link in Self::links_in(&self.tag_dir(source)) {
+                if let Some(stem) = link.file_stem().and_then(|s| s.to_str()) {
+                    ids.extend(self.id_for(stem)?);
+                }
+            }
+        }
+
+        let mut changed = Vec::new();
+        for id in ids {
+            let mut note_file = match self.read_note_file(id)? {
+                Some(nf) => nf,
+                None => continue,
+            };
+            self.save_revision(id, &note_file)?;
+
+            let mut tags = self.get_note_tags(id)?;
+            retag(&mut tags);
+            note_file.modified = Self::now();
+            self.write_note_file(id, &note_file)?;
+            self.reindex_note(id, Some(&note_file))?;
+            self.update_tags(id, &tags)?;
+            changed.push(id);
+        }
+
+        // Notes in the trash keep their tags in the trash file
+        for id in self.trash_ids()? {
+            if let Some(mut trash_file) = self.read_trash_file(id)? {
+                if trash_file.tags.iter().any(|t| sources.contains(t)) {
+                    retag(&mut trash_file.tags);
+                    self.write_trash_file(id, &trash_file)?;
+                }
+            }
+        }
+
+        let mut descriptions = self.read_tag_descriptions()?;
+        let moved = descriptions
+            .iter()
+            .find(|(name, _)| sources.contains(name))
+            .map(|(_, description)| description.clone());
+        descriptions.retain(|name, _| !sources.contains(name));
+        if let (Some(target), Some(description)) = (target, moved) {
+            descriptions
+                .entry(target.to_string())
+                .or_insert(description);
+        }
+        self.write_tag_descriptions(&descriptions)?;
+
+        self.auto_commit(&match target {
+            Some(target) => format!("Retag {} as {}", sources.join(", "), target),
+            None => format!("Remove tag {}", sources.join(", ")),
+        });
+        Ok(changed)
+    }
+
+    /// Get the path to the API key list.
+    fn api_keys_path(&self) -> PathBuf {
+        self.root.join("api_keys.json")
+    }
+
+    /// Read all API keys, oldest first.
+    /// Must be called while holding the lock.
+    fn read_api_keys(&self) -> Result<Vec<ApiKeyFile>, Error> {
+        let contents = match fs::read_to_string(self.api_keys_path()) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::Database(format!("Failed to read API keys: {}", e))),
+        };
+        serde_json::from_str(&contents)
+            .map_err(|e| Error::Database(format!("Failed to parse API keys: {}", e)))
+    }
+
+    /// Replace the API key list.
+    /// Must be called while holding the lock.
+    fn write_api_keys(&self, keys: &[ApiKeyFile]) -> Result<(), Error> {
+        let contents = serde_json::to_string_pretty(keys)
+            .map_err(|e| Error::Database(format!("Failed to serialize API keys: {}", e)))?;
+
+        let temp_path = self.root.join("api_keys.json.tmp");
+        fs::write(&temp_path, contents)
+            .map_err(|e| Error::Database(format!("Failed to write API keys: {}", e)))?;
+        fs::rename(&temp_path, self.api_keys_path())
+            .map_err(|e| Error::Database(format!("Failed to rename API keys: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Get tags for a note by scanning tag directories.
+    fn get_note_tags(&self, id: i64) -> Result<Vec<String>, Error> {
+        let mut tags = Vec::new();
+
+        for path in self.tag_dirs() {
+            if self
+                .tag_links(&path, id)
+                .iter()
+                .any(|link| self.symlink_exists(link))
+            {
+                if let Some(tag_name) = self.tag_name(&path) {
+                    tags.push(tag_name);
+                }
+            }
+        }
+
+        tags.sort();
+        Ok(tags)
+    }
+
+    /// Every directory in `tags/`, including those of hierarchical tags
+    /// like `tags/lang/rust`, children before their parents.
+    fn tag_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        let mut pending = vec![self.root.join("tags")];
+        while let Some(dir) = pending.pop() {
+            if let Ok(entries) = fs::read_dir(&dir) {
+                for entry in entries.flatten() {
+                    if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                        pending.push(entry.path());
+                        dirs.push(entry.path());
+                    }
+                }
+            }
+        }
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        dirs
+    }
+
+    /// The directory holding links to a tag's notes.
+    fn tag_dir(&self, tag: &str) -> PathBuf {
+        let name = match &self.cipher {
+            Some(cipher) => cipher.tag_dir_name(tag),
+            None => tag.to_string(),
+        };
+        self.root.join("tags").join(name)
+    }
+
+    /// The tag that a directory in `tags/` is for.
+    fn tag_name(&self, tag_dir: &Path) -> Option<String> {
+        match &self.cipher {
+            Some(cipher) => {
+                let contents = fs::read_to_string(tag_dir.join(TAG_NAME_FILE)).ok()?;
+                cipher.open(&contents).ok()
+            }
+            None => {
+                let parts = tag_dir
+                    .strip_prefix(self.root.join("tags"))
+                    .ok()?
+                    .components()
+                    .map(|part| part.as_os_str().to_str())
+                    .collect::<Option<Vec<_>>>()?;
+                Some(parts.join("/"))
+            }
+        }
+    }
+
+    /// The note links in a tag directory.
+    fn links_in(tag_dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(tag_dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| entry.file_name() != TAG_NAME_FILE)
+                    // Directories of child tags, like rust/ in lang/
+                    .filter(|entry| !entry.file_type().is_ok_and(|t| t.is_dir()))
+                    .map(|entry| entry.path())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Check if a symlink exists (works on both Unix and Windows).
+    fn symlink_exists(&self, path: &Path) -> bool {
+        // On Unix, this checks for symlinks
+        // On Windows, this checks for either real symlinks or our text-file-based symlinks
+        if path.exists() {
+            return true;
+        }
+
+        // Check if it's a symlink that points to a non-existent target
+        path.symlink_metadata().is_ok()
+    }
+
+    /// Create a symlink (or text file on Windows if symlinks fail).
+    fn create_symlink(&self, target: &Path, link: &Path) -> Result<(), Error> {
+        // Remove existing if any
+        let _ = fs::remove_file(link);
+
+        // Calculate relative path from link to target
+        let link_parent = link
+            .parent()
+            .ok_or_else(|| Error::Database("Symlink path has no parent".to_string()))?;
+
+        // We want a relative path like "../notes/1.json"
+        let relative_target =
+            pathdiff::diff_paths(target, link_parent).unwrap_or_else(|| target.to_path_buf());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&relative_target, link)
+                .map_err(|e| Error::Database(format!("Failed to create symlink: {}", e)))?;
+        }
+
+        #[cfg(windows)]
+        {
+            // Try to create a real symlink first
+            match std::os::windows::fs::symlink_file(&relative_target, link) {
+                Ok(()) => {}
+                Err(_) => {
+                    // Fall back to text file containing the path
+                    let mut file = File::create(link).map_err(|e| {
+                        Error::Database(format!("Failed to create link file: {}", e))
+                    })?;
+                    // Use forward slashes for consistency
+                    let path_str = relative_target.to_string_lossy().replace('\\', "/");
+                    file.write_all(path_str.as_bytes()).map_err(|e| {
+                        Error::Database(format!("Failed to write link file: {}", e))
+                    })?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolve a symlink to get the actual file path (handles Windows text-file symlinks).
+    #[allow(dead_code)]
+    fn resolve_symlink(&self, link: &Path) -> Result<PathBuf, Error> {
+        // Try to read as a real symlink first
+        if let Ok(target) = fs::read_link(link) {
+            // Resolve relative to the link's parent directory
+            let link_parent = link
+                .parent()
+                .ok_or_else(|| Error::Database("Symlink path has no parent".to_string()))?;
+            return Ok(link_parent.join(target));
+        }
+
+        // Fall back to reading as a text file (Windows without symlink support)
+        let mut file = File::open(link)
+            .map_err(|e| Error::Database(format!("Failed to open link file: {}", e)))?;
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .map_err(|e| Error::Database(format!("Failed to read link file: {}", e)))?;
+
+        let link_parent = link
+            .parent()
+            .ok_or_else(|| Error::Database("Symlink path has no parent".to_string()))?;
+
+        Ok(link_parent.join(contents.trim()))
+    }
+
+    /// Update symlinks for a note's tags.
+    fn update_tags(&self, id: i64, tags: &[String]) -> Result<(), Error> {
+        let note_path = self.note_path(id);
+
+        // Remove all existing tag symlinks for this note
+        for path in self.tag_dirs() {
+            for symlink_path in self.tag_links(&path, id) {
+                let _ = fs::remove_file(&symlink_path);
+            }
+        }
+
+        // Create new tag symlinks
         for tag in tags {
-            let tag_dir = tags_dir.join(tag);
+            let tag_dir = self.tag_dir(tag);
             fs::create_dir_all(&tag_dir)
                 .map_err(|e| Error::Database(format!("Failed to create tag dir: {}", e)))?;
+            if let Some(cipher) = &self.cipher {
+                fs::write(tag_dir.join(TAG_NAME_FILE), cipher.seal_tag_name(tag))
+                    .map_err(|e| Error::Database(format!("Failed to write tag name: {}", e)))?;
+            }
 
-            let symlink_path = tag_dir.join(format!("{}.json", id));
+            let symlink_path = tag_dir.join(note_path.file_name().unwrap_or_default());
             self.create_symlink(&note_path, &symlink_path)?;
         }
 
@@ -342,19 +1211,12 @@ impl FilesDatabase {
 
     /// Remove empty tag directories.
     fn cleanup_empty_tag_dirs(&self) -> Result<(), Error> {
-        let tags_dir = self.root.join("tags");
-
-        if let Ok(entries) = fs::read_dir(&tags_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_dir() {
-                    // Check if directory is empty
-                    if let Ok(mut dir_entries) = fs::read_dir(&path) {
-                        if dir_entries.next().is_none() {
-                            let _ = fs::remove_dir(&path);
-                        }
-                    }
-                }
+        for path in self.tag_dirs() {
+            // Check if directory is empty, apart from the tag's name. A parent
+            // of tags that are left isn't empty, so removing it fails.
+            if Self::links_in(&path).is_empty() {
+                let _ = fs::remove_file(path.join(TAG_NAME_FILE));
+                let _ = fs::remove_dir(&path);
             }
         }
 
@@ -374,12 +1236,14 @@ impl FilesDatabase {
                 entry.map_err(|e| Error::Database(format!("Failed to read dir entry: {}", e)))?;
             let path = entry.path();
 
-            if path.extension().map(|e| e == "json").unwrap_or(false) {
-                if let Some(stem) = path.file_stem() {
-                    if let Some(stem_str) = stem.to_str() {
-                        if let Ok(id) = stem_str.parse::<i64>() {
-                            ids.push(id);
-                        }
+            if path
+                .extension()
+                .map(|e| e == self.format().extension())
+                .unwrap_or(false)
+            {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    if let Some(id) = self.id_for(stem)? {
+                        ids.push(id);
                     }
                 }
             }
@@ -388,31 +1252,66 @@ impl FilesDatabase {
         Ok(ids)
     }
 
-    /// List note IDs that have a specific tag.
-    fn list_note_ids_with_tag(&self, tag: &str) -> Result<Vec<i64>, Error> {
-        let tag_dir = self.root.join("tags").join(tag);
-        let mut ids = Vec::new();
-
-        let entries = match fs::read_dir(&tag_dir) {
-            Ok(entries) => entries,
-            Err(_) => return Ok(ids),
+    /// Notes matching the query's filters, sorted, from the note index.
+    /// Note files are only read if the query looks at the body. Otherwise
+    /// notes whose body is too long for the index have a preview instead;
+    /// their IDs are returned too, for [`Self::read_bodies`].
+    fn find_notes(&self, query: &NoteQuery) -> Result<(Vec<Note>, HashSet<i64>), Error> {
+        let index = {
+            let _lock = self.lock()?;
+            self.refresh_note_index()?
         };
+        let mut tag_map = index.note_tags();
+        let needs_body = !query.text.is_empty();
 
-        for entry in entries {
-            let entry =
-                entry.map_err(|e| Error::Database(format!("Failed to read dir entry: {}", e)))?;
-            let path = entry.path();
-
-            if let Some(stem) = path.file_stem() {
-                if let Some(stem_str) = stem.to_str() {
-                    if let Ok(id) = stem_str.parse::<i64>() {
-                        ids.push(id);
-                    }
+        let mut notes = Vec::new();
+        let mut previews = HashSet::new();
+        for (id, entry) in index.notes() {
+            let tags = tag_map.remove(&id).unwrap_or_default();
+            let note = match entry.to_note(id, tags.clone()) {
+                Some(note) => note,
+                None if needs_body => match self.load_note_with_tags(id, tags)? {
+                    Some(note) => note,
+                    None => continue,
+                },
+                None => {
+                    previews.insert(id);
+                    entry.to_note_without_body(id, tags)
                 }
+            };
+            if query.matches(&note) {
+                notes.push(note);
             }
         }
+        query.sort(&mut notes);
+        Ok((notes, previews))
+    }
 
-        Ok(ids)
+    /// Replace the previews of notes returned by [`Self::find_notes`] with
+    /// their bodies. Notes deleted since are dropped.
+    fn read_bodies(&self, notes: Vec<Note>, previews: &HashSet<i64>) -> Result<Vec<Note>, Error> {
+        let mut read = Vec::with_capacity(notes.len());
+        for note in notes {
+            if !previews.contains(&note.id) {
+                read.push(note);
+            } else if let Some(note) = self.load_note_with_tags(note.id, note.tags)? {
+                read.push(note);
+            }
+        }
+        Ok(read)
+    }
+
+    /// Load a Note from disk, with tags that are already known.
+    fn load_note_with_tags(&self, id: i64, tags: Vec<String>) -> Result<Option<Note>, Error> {
+        Ok(self.read_note_file(id)?.map(|note_file| Note {
+            id,
+            title: note_file.title,
+            body: note_file.body,
+            references: note_file.references,
+            tags,
+            created_at: note_file.created,
+            updated_at: note_file.modified,
+        }))
     }
 
     /// Load a full Note from disk (note file + tags from symlinks).
@@ -430,10 +1329,139 @@ impl FilesDatabase {
             body: note_file.body,
             references: note_file.references,
             tags,
+            created_at: note_file.created,
             updated_at: note_file.modified,
         }))
     }
 
+    /// Get the path to the note index.
+    fn note_index_path(&self) -> PathBuf {
+        self.root.join("note-index.json")
+    }
+
+    /// Load the note index and bring it up to date with the note files and
+    /// tag directories. Only notes and tags that changed since the index was
+    /// saved are read again.
+    /// Must be called while holding the lock.
+    fn refresh_note_index(&self) -> Result<NoteIndex, Error> {
+        let mut index = NoteIndex::load(&self.note_index_path(), self.cipher.as_ref());
+
+        let tags_root = self.root.join("tags");
+        let mut tag_dirs = HashSet::new();
+        for path in self.tag_dirs() {
+            let dir = match path.strip_prefix(&tags_root) {
+                Ok(dir) => dir.to_string_lossy().into_owned(),
+                Err(_) => continue,
+            };
+            // Stamp before reading, so a change made meanwhile is seen next time
+            let stamp = match FileStamp::of(&path) {
+                Some(stamp) => stamp,
+                None => continue,
+            };
+            let name = match self.tag_name(&path) {
+                Some(name) => name,
+                None => continue,
+            };
+            tag_dirs.insert(dir.clone());
+            if index.is_tag_fresh(&dir, stamp) {
+                continue;
+            }
+            let mut ids = BTreeSet::new();
+            for link in Self::links_in(&path) {
+                if let Some(stem) = link.file_stem().and_then(|s| s.to_str()) {
+                    ids.extend(self.id_for(stem)?);
+                }
+            }
+            index.insert_tag(&dir, name, stamp, ids);
+        }
+        index.retain_tags(|dir| tag_dirs.contains(dir));
+
+        let ids = self.list_note_ids()?;
+        let existing: HashSet<i64> = ids.iter().copied().collect();
+        for id in index.ids() {
+            if !existing.contains(&id) {
+                index.remove(id);
+            }
+        }
+        for id in ids {
+            let stamp = match FileStamp::of(&self.note_path(id)) {
+                Some(stamp) => stamp,
+                None => continue,
+            };
+            if index.is_fresh(id, stamp) {
+                continue;
+            }
+            if let Some(note_file) = self.read_note_file(id)? {
+                // Reading may have repaired (and rewritten) the file, so re-stamp
+                let stamp = FileStamp::of(&self.note_path(id)).unwrap_or(stamp);
+                index.insert(id, stamp, &note_file);
+            }
+        }
+
+        index.save(&self.note_index_path(), self.cipher.as_ref())?;
+        Ok(index)
+    }
+
+    /// Get the path to the full-text search index.
+    fn search_index_path(&self) -> PathBuf {
+        self.root.join("search-index.json")
+    }
+
+    /// Load the search index and bring it up to date with the note files.
+    /// Notes that were added, removed or edited by hand are re-indexed.
+    /// Must be called while holding the lock.
+    fn refresh_search_index(&self) -> Result<SearchIndex, Error> {
+        let mut index = SearchIndex::load(&self.search_index_path(), self.cipher.as_ref());
+
+        let ids = self.list_note_ids()?;
+        let existing: HashSet<i64> = ids.iter().copied().collect();
+
+        for id in index.ids() {
+            if !existing.contains(&id) {
+                index.remove(id);
+            }
+        }
+
+        for id in ids {
+            let stamp = match FileStamp::of(&self.note_path(id)) {
+                Some(stamp) => stamp,
+                None => continue,
+            };
+            if index.is_fresh(id, stamp) {
+                continue;
+            }
+            if let Some(note_file) = self.read_note_file(id)? {
+                // Reading may have repaired (and rewritten) the file, so re-stamp
+                let stamp = FileStamp::of(&self.note_path(id)).unwrap_or(stamp);
+                index.insert(id, stamp, &note_file.title, &note_file.body);
+            }
+        }
+
+        index.save(&self.search_index_path(), self.cipher.as_ref())?;
+        Ok(index)
+    }
+
+    /// Update a single note in the search index after it was written or deleted.
+    /// Does nothing if the index hasn't been built yet. The note index isn't
+    /// updated here, since rewriting it for every note would make imports
+    /// slow; it notices the new file stamp instead.
+    /// Must be called while holding the lock.
+    fn reindex_note(&self, id: i64, note_file: Option<&NoteFile>) -> Result<(), Error> {
+        let path = self.search_index_path();
+        if !path.exists() {
+            return Ok(());
+        }
+
+        let mut index = SearchIndex::load(&path, self.cipher.as_ref());
+        match (note_file, FileStamp::of(&self.note_path(id))) {
+            (Some(note_file), Some(stamp)) => {
+                index.insert(id, stamp, &note_file.title, &note_file.body)
+            }
+            _ => index.remove(id),
+        }
+        index.save(&path, self.cipher.as_ref())
+    }
+
     /// Get current timestamp in ISO 8601 format.
     fn now() -> String {
         Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
@@ -457,86 +1485,68 @@ impl Database for FilesDatabase {
         let _lock = self.lock()?;
 
         let id = self.next_id()?;
+        let now = Self::now();
         let note_file = NoteFile {
             title: note.title,
             body: note.body,
             references: note.references,
-            modified: Self::now(),
+            created: now.clone(),
+            modified: now,
         };
 
         self.write_note_file(id, &note_file)?;
         self.update_tags(id, &note.tags)?;
+        self.reindex_note(id, Some(&note_file))?;
+        self.auto_commit(&format!("Add note: {}", note_file.title));
 
         Ok(id)
     }
 
+    async fn put_note(&self, note: &Note) -> Result<(), Error> {
+        let _lock = self.lock()?;
+
+        // Before writing, so that in git mode the ID has a key
+        self.raise_counter(note.id)?;
+
+        let note_file = NoteFile {
+            title: note.title.clone(),
+            body: note.body.clone(),
+            references: note.references.clone(),
+            created: note.created_at.clone(),
+            modified: note.updated_at.clone(),
+        };
+        self.write_note_file(note.id, &note_file)?;
+        self.update_tags(note.id, &note.tags)?;
+        self.reindex_note(note.id, Some(&note_file))?;
+
+        // A note in the trash is replaced too
+        let trash_path = self.trash_path(note.id);
+        if trash_path.exists() {
+            fs::remove_file(&trash_path)
+                .map_err(|e| Error::Database(format!("Failed to delete trash: {}", e)))?;
+        }
+
+        self.auto_commit(&format!("Import note: {}", note.title));
+        Ok(())
+    }
+
     async fn get_note(&self, id: i64) -> Result<Option<Note>, Error> {
         self.load_note(id)
     }
 
     async fn list_notes(&self, query: NoteQuery) -> Result<Vec<Note>, Error> {
-        // Get candidate note IDs based on tag filter
-        let ids = if let Some(ref tags) = query.tags {
-            if tags.is_empty() {
-                self.list_note_ids()?
-            } else {
-                // Get IDs that have ANY of the specified tags
-                let mut all_ids = std::collections::HashSet::new();
-                for tag in tags {
-                    for id in self.list_note_ids_with_tag(tag)? {
-                        all_ids.insert(id);
-                    }
-                }
-                all_ids.into_iter().collect()
-            }
-        } else {
-            self.list_note_ids()?
-        };
-
-        // Load all notes
-        let mut notes = Vec::new();
-        for id in ids {
-            if let Some(note) = self.load_note(id)? {
-                // Apply date filters
-                if let Some(ref from) = query.from {
-                    if note.updated_at < *from {
-                        continue;
-                    }
-                }
-                if let Some(ref to) = query.to {
-                    if note.updated_at > *to {
-                        continue;
-                    }
-                }
-                notes.push(note);
-            }
-        }
-
-        // Sort by updated_at DESC, then by id DESC
-        notes.sort_by(|a, b| {
-            b.updated_at
-                .cmp(&a.updated_at)
-                .then_with(|| b.id.cmp(&a.id))
-        });
-
-        // Apply limit
-        if let Some(limit) = query.limit {
-            if limit > 0 {
-                notes.truncate(limit as usize);
-            }
-        }
-
-        Ok(notes)
-    }
+        let (mut notes, previews) = self.find_notes(&query)?;
+        query.paginate(&mut notes);
+        self.read_bodies(notes, &previews)
+    }
 
     async fn count_notes(&self, query: NoteQuery) -> Result<i64, Error> {
-        // Reuse list_notes logic but just count (could be optimized)
-        let notes = self
-            .list_notes(NoteQuery {
-                limit: None,
-                ..query
-            })
-            .await?;
+        let (mut notes, _) = self.find_notes(&query)?;
+        NoteQuery {
+            limit: None,
+            ..query
+        }
+        .paginate(&mut notes);
         Ok(notes.len() as i64)
     }
 
@@ -549,6 +1559,9 @@ impl Database for FilesDatabase {
             None => return Ok(false),
         };
 
+        // Save the current version before changing anything
+        self.save_revision(id, &note_file)?;
+
         // Apply updates
         if let Some(title) = update.title {
             note_file.title = title;
@@ -565,11 +1578,13 @@ impl Database for FilesDatabase {
 
         // Write back
         self.write_note_file(id, &note_file)?;
+        self.reindex_note(id, Some(&note_file))?;
 
         // Update tags if provided
         if let Some(tags) = update.tags {
             self.update_tags(id, &tags)?;
         }
+        self.auto_commit(&format!("Edit note: {}", note_file.title));
 
         Ok(true)
     }
@@ -577,63 +1592,172 @@ impl Database for FilesDatabase {
     async fn delete_note(&self, id: i64) -> Result<bool, Error> {
         let _lock = self.lock()?;
 
-        let path = self.note_path(id);
+        let note_file = match self.read_note_file(id)? {
+            Some(nf) => nf,
+            None => return Ok(false),
+        };
+
+        // Move the note to the trash, keeping its tags
+        let message = format!("Delete note: {}", note_file.title);
+        let trash_file = TrashFile {
+            title: note_file.title,
+            body: note_file.body,
+            tags: self.get_note_tags(id)?,
+            references: note_file.references,
+            created: note_file.created,
+            modified: note_file.modified,
+            deleted: Self::now(),
+        };
+        self.write_trash_file(id, &trash_file)?;
+
+        // Remove the note file
+        fs::remove_file(self.note_path(id))
+            .map_err(|e| Error::Database(format!("Failed to delete note: {}", e)))?;
+
+        self.remove_tag_links(id)?;
+        self.reindex_note(id, None)?;
+        self.auto_commit(&message);
+
+        Ok(true)
+    }
+
+    async fn list_trash(&self) -> Result<Vec<TrashedNote>, Error> {
+        let mut notes = Vec::new();
+        for id in self.trash_ids()? {
+            if let Some(trash_file) = self.read_trash_file(id)? {
+                notes.push(TrashedNote {
+                    note: Note {
+                        id,
+                        title: trash_file.title,
+                        body: trash_file.body,
+                        tags: trash_file.tags,
+                        references: trash_file.references,
+                        created_at: trash_file.created,
+                        updated_at: trash_file.modified,
+                    },
+                    deleted_at: trash_file.deleted,
+                });
+            }
+        }
+
+        // Sort by deleted_at DESC, then by id DESC
+        notes.sort_by(|a, b| {
+            b.deleted_at
+                .cmp(&a.deleted_at)
+                .then_with(|| b.note.id.cmp(&a.note.id))
+        });
+
+        Ok(notes)
+    }
+
+    async fn restore_note(&self, id: i64) -> Result<bool, Error> {
+        let _lock = self.lock()?;
+
+        let trash_file = match self.read_trash_file(id)? {
+            Some(tf) => tf,
+            None => return Ok(false),
+        };
+
+        let note_file = NoteFile {
+            title: trash_file.title,
+            body: trash_file.body,
+            references: trash_file.references,
+            created: trash_file.created,
+            modified: trash_file.modified,
+        };
+        self.write_note_file(id, &note_file)?;
+        self.update_tags(id, &trash_file.tags)?;
+        self.reindex_note(id, Some(&note_file))?;
+
+        fs::remove_file(self.trash_path(id))
+            .map_err(|e| Error::Database(format!("Failed to delete trash: {}", e)))?;
+        self.auto_commit(&format!("Restore note: {}", note_file.title));
+
+        Ok(true)
+    }
+
+    async fn purge_note(&self, id: i64) -> Result<bool, Error> {
+        let _lock = self.lock()?;
+
+        let path = self.trash_path(id);
         if !path.exists() {
             return Ok(false);
         }
 
-        // Remove the note file
         fs::remove_file(&path)
-            .map_err(|e| Error::Database(format!("Failed to delete note: {}", e)))?;
+            .map_err(|e| Error::Database(format!("Failed to delete trash: {}", e)))?;
 
-        // Remove all tag symlinks for this note
-        let tags_dir = self.root.join("tags");
-        if let Ok(entries) = fs::read_dir(&tags_dir) {
-            for entry in entries.flatten() {
-                let tag_path = entry.path();
-                if tag_path.is_dir() {
-                    let symlink_path = tag_path.join(format!("{}.json", id));
-                    let _ = fs::remove_file(&symlink_path);
-                }
-            }
+        let history_dir = self.history_dir(id);
+        if history_dir.exists() {
+            fs::remove_dir_all(&history_dir)
+                .map_err(|e| Error::Database(format!("Failed to delete history: {}", e)))?;
         }
 
-        // Clean up empty tag directories
-        self.cleanup_empty_tag_dirs()?;
+        // Remove links from and to the note
+        self.write_links(id, &[])?;
+        let key = self.key(id);
+        for from in self.linking_ids()? {
+            let mut links = self.read_links(from)?;
+            let count = links.len();
+            links.retain(|link| link.to != key);
+            if links.len() != count {
+                self.write_links(from, &links)?;
+            }
+        }
+        self.auto_commit("Purge note from the trash");
 
         Ok(true)
     }
 
-    async fn list_tags(&self) -> Result<Vec<TagCount>, Error> {
-        let tags_dir = self.root.join("tags");
-        let mut tag_counts = Vec::new();
+    async fn push_operation(&self, operation: &Operation) -> Result<(), Error> {
+        let _lock = self.lock()?;
 
-        let entries = match fs::read_dir(&tags_dir) {
-            Ok(entries) => entries,
-            Err(_) => return Ok(tag_counts),
-        };
+        let mut operations = self.read_journal()?;
+        operations.push(operation.clone());
+        let excess = operations.len().saturating_sub(JOURNAL_LIMIT);
+        self.write_journal(&operations[excess..])
+    }
 
-        for entry in entries {
-            let entry =
-                entry.map_err(|e| Error::Database(format!("Failed to read dir entry: {}", e)))?;
-            let path = entry.path();
+    async fn pop_operation(&self) -> Result<Option<Operation>, Error> {
+        let _lock = self.lock()?;
 
-            if path.is_dir() {
-                if let Some(tag_name) = path.file_name().and_then(|n| n.to_str()) {
-                    // Count symlinks in the tag directory
-                    let count = fs::read_dir(&path)
-                        .map(|entries| entries.count())
-                        .unwrap_or(0);
-
-                    if count > 0 {
-                        tag_counts.push(TagCount {
-                            name: tag_name.to_string(),
-                            count: count as i64,
-                        });
-                    }
-                }
+        let mut operations = self.read_journal()?;
+        let operation = operations.pop();
+        if operation.is_some() {
+            self.write_journal(&operations)?;
+        }
+        Ok(operation)
+    }
+
+    async fn list_revisions(&self, note_id: i64) -> Result<Vec<Revision>, Error> {
+        let mut revisions = Vec::new();
+        for revision in self.revision_numbers(note_id).into_iter().rev() {
+            if let Some(r) = self.read_revision(note_id, revision)? {
+                revisions.push(r);
             }
         }
+        Ok(revisions)
+    }
+
+    async fn get_revision(&self, note_id: i64, revision: i64) -> Result<Option<Revision>, Error> {
+        self.read_revision(note_id, revision)
+    }
+
+    async fn list_tags(&self) -> Result<Vec<TagCount>, Error> {
+        let (index, descriptions) = {
+            let _lock = self.lock()?;
+            (self.refresh_note_index()?, self.read_tag_descriptions()?)
+        };
+        let mut tag_counts: Vec<TagCount> = index
+            .tag_counts()
+            .into_iter()
+            .filter(|&(_, count)| count > 0)
+            .map(|(name, count)| TagCount {
+                name: name.to_string(),
+                count: count as i64,
+                description: descriptions.get(name).cloned(),
+            })
+            .collect();
 
         // Sort by count DESC, then by name ASC
         tag_counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
@@ -641,10 +1765,138 @@ impl Database for FilesDatabase {
         Ok(tag_counts)
     }
 
+    async fn merge_tags(&self, sources: &[String], target: &str) -> Result<Vec<i64>, Error> {
+        self.retag(sources, Some(target))
+    }
+
+    async fn remove_tag(&self, tag: &str) -> Result<Vec<i64>, Error> {
+        self.retag(&[tag.to_string()], None)
+    }
+
+    async fn set_tag_description(
+        &self,
+        tag: &str,
+        description: Option<&str>,
+    ) -> Result<bool, Error> {
+        let _lock = self.lock()?;
+
+        if Self::links_in(&self.tag_dir(tag)).is_empty() {
+            return Ok(false);
+        }
+        let mut descriptions = self.read_tag_descriptions()?;
+        match description {
+            Some(description) => descriptions.insert(tag.to_string(), description.to_string()),
+            None => descriptions.remove(tag),
+        };
+        self.write_tag_descriptions(&descriptions)?;
+        self.auto_commit(&format!("Describe tag {}", tag));
+
+        Ok(true)
+    }
+
+    async fn list_tag_aliases(&self) -> Result<Vec<TagAlias>, Error> {
+        let aliases = {
+            let _lock = self.lock()?;
+            self.read_tag_aliases()?
+        };
+        Ok(aliases
+            .into_iter()
+            .map(|(alias, tag)| TagAlias { alias, tag })
+            .collect())
+    }
+
+    async fn set_tag_alias(&self, alias: &str, tag: Option<&str>) -> Result<bool, Error> {
+        let _lock = self.lock()?;
+
+        let mut aliases = self.read_tag_aliases()?;
+        let changed = match tag {
+            Some(tag) => {
+                aliases.insert(alias.to_string(), tag.to_string());
+                true
+            }
+            None => aliases.remove(alias).is_some(),
+        };
+        if changed {
+            self.write_tag_aliases(&aliases)?;
+            self.auto_commit(&match tag {
+                Some(tag) => format!("Alias tag {} as {}", tag, alias),
+                None => format!("Remove tag alias {}", alias),
+            });
+        }
+
+        Ok(changed)
+    }
+
+    async fn list_links(&self, id: i64) -> Result<Vec<NoteLink>, Error> {
+        let _lock = self.lock()?;
+
+        let key = self.key(id);
+        let mut links = Vec::new();
+        for from in self.linking_ids()? {
+            for link in self.read_links(from)? {
+                if from != id && link.to != key {
+                    continue;
+                }
+                if let Some(to) = self.id_for(&link.to)? {
+                    links.push(NoteLink {
+                        from,
+                        to,
+                        kind: link.kind,
+                    });
+                }
+            }
+        }
+
+        // Leave out links to or from notes in the trash
+        links.retain(|link| self.note_path(link.from).exists() && self.note_path(link.to).exists());
+        links.sort_by_key(|link| (link.from, link.to, link.kind));
+        Ok(links)
+    }
+
+    async fn add_link(&self, link: &NoteLink) -> Result<bool, Error> {
+        let _lock = self.lock()?;
+
+        let mut links = self.read_links(link.from)?;
+        let entry = LinkEntry {
+            to: self.key(link.to),
+            kind: link.kind,
+        };
+        if links.contains(&entry) {
+            return Ok(false);
+        }
+        links.push(entry);
+        self.write_links(link.from, &links)?;
+        self.auto_commit(&format!(
+            "Link note {} {} note {}",
+            link.from, link.kind, link.to
+        ));
+
+        Ok(true)
+    }
+
+    async fn remove_link(&self, from: i64, to: i64, kind: LinkKind) -> Result<bool, Error> {
+        let _lock = self.lock()?;
+
+        let mut links = self.read_links(from)?;
+        let entry = LinkEntry {
+            to: self.key(to),
+            kind,
+        };
+        let count = links.len();
+        links.retain(|link| *link != entry);
+        if links.len() == count {
+            return Ok(false);
+        }
+        self.write_links(from, &links)?;
+        self.auto_commit(&format!("Unlink note {} {} note {}", from, kind, to));
+
+        Ok(true)
+    }
+
     async fn grep(
         &self,
         pattern: &str,
-        tags: Option<&[String]>,
+        query: &NoteQuery,
         case_sensitive: bool,
     ) -> Result<Vec<Note>, Error> {
         // Build regex
@@ -655,42 +1907,106 @@ impl Database for FilesDatabase {
                 .map_err(|e| Error::Validation(format!("invalid regex: {}", e)))?
         };
 
-        // Get candidate note IDs based on tag filter
-        let ids = if let Some(tag_list) = tags {
-            if tag_list.is_empty() {
-                self.list_note_ids()?
-            } else {
-                // Get IDs that have ANY of the specified tags
-                let mut all_ids = std::collections::HashSet::new();
-                for tag in tag_list {
-                    for id in self.list_note_ids_with_tag(tag)? {
-                        all_ids.insert(id);
-                    }
-                }
-                all_ids.into_iter().collect()
-            }
-        } else {
-            self.list_note_ids()?
+        // Find candidate notes in the index, then match the pattern
+        let (notes, previews) = self.find_notes(query)?;
+        let mut notes = self.read_bodies(notes, &previews)?;
+        notes.retain(|note| regex.is_match(&note.title) || regex.is_match(&note.body));
+
+        query.paginate(&mut notes);
+
+        Ok(notes)
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        tags: Option<&TagExpr>,
+        limit: Option<i64>,
+    ) -> Result<Vec<SearchHit>, Error> {
+        let (ranked, tag_map) = {
+            let _lock = self.lock()?;
+            let ranked = self.refresh_search_index()?.search(query);
+            // Only look at tags if we need to filter
+            let tag_map = match tags {
+                Some(_) => Some(self.refresh_note_index()?.note_tags()),
+                None => None,
+            };
+            (ranked, tag_map)
         };
+        let no_tags = Vec::new();
 
-        // Load and filter notes
-        let mut notes = Vec::new();
-        for id in ids {
-            if let Some(note) = self.load_note(id)? {
-                if regex.is_match(&note.title) || regex.is_match(&note.body) {
-                    notes.push(note);
+        let limit = limit.filter(|&l| l > 0).map(|l| l as usize);
+
+        let mut hits = Vec::new();
+        for (id, score) in ranked {
+            if limit.map(|l| hits.len() >= l).unwrap_or(false) {
+                break;
+            }
+            if let (Some(expr), Some(ref tag_map)) = (tags, &tag_map) {
+                if !expr.matches(tag_map.get(&id).unwrap_or(&no_tags)) {
+                    continue;
                 }
             }
+            if let Some(note) = self.load_note(id)? {
+                hits.push(SearchHit { note, score });
+            }
         }
 
-        // Sort by updated_at DESC, then by id DESC
-        notes.sort_by(|a, b| {
-            b.updated_at
-                .cmp(&a.updated_at)
-                .then_with(|| b.id.cmp(&a.id))
+        Ok(hits)
+    }
+
+    async fn add_api_key(&self, key: CreateApiKey) -> Result<ApiKey, Error> {
+        let _lock = self.lock()?;
+
+        let mut keys = self.read_api_keys()?;
+        if keys.iter().any(|k| k.key_hash == key.key_hash) {
+            return Err(Error::Database("API key already exists".into()));
+        }
+        let api_key = ApiKey {
+            id: keys.iter().map(|k| k.key.id).max().unwrap_or(0) + 1,
+            name: key.name,
+            scope: key.scope,
+            tags: key.tags,
+            namespaces: key.namespaces,
+            created_at: Self::now(),
+        };
+        keys.push(ApiKeyFile {
+            key: api_key.clone(),
+            key_hash: key.key_hash,
         });
+        self.write_api_keys(&keys)?;
 
-        Ok(notes)
+        Ok(api_key)
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, Error> {
+        let _lock = self.lock()?;
+
+        let keys = self.read_api_keys()?;
+        Ok(keys
+            .into_iter()
+            .find(|k| k.key_hash == key_hash)
+            .map(|k| k.key))
+    }
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>, Error> {
+        let _lock = self.lock()?;
+
+        Ok(self.read_api_keys()?.into_iter().map(|k| k.key).collect())
+    }
+
+    async fn revoke_api_key(&self, id: i64) -> Result<bool, Error> {
+        let _lock = self.lock()?;
+
+        let mut keys = self.read_api_keys()?;
+        let count = keys.len();
+        keys.retain(|k| k.key.id != id);
+        if keys.len() == count {
+            return Ok(false);
+        }
+        self.write_api_keys(&keys)?;
+
+        Ok(true)
     }
 }
 
@@ -699,6 +2015,7 @@ mod tests {
     use super::*;
     use std::thread;
     use tempfile::TempDir;
+    use veta_core::Scope;
 
     fn setup() -> (TempDir, FilesDatabase) {
         let temp_dir = TempDir::new().unwrap();
@@ -762,7 +2079,7 @@ mod tests {
 
         let alpha_notes = db
             .list_notes(NoteQuery {
-                tags: Some(vec!["alpha".to_string()]),
+                tags: Some(TagExpr::any_of(["alpha"])),
                 ..Default::default()
             })
             .await
@@ -825,63 +2142,944 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_list_tags() {
-        let (_temp, db) = setup();
+    async fn test_update_saves_revisions() {
+        let (temp, db) = setup();
 
-        db.add_note(CreateNote {
-            title: "Note 1".to_string(),
-            body: "Body".to_string(),
-            tags: vec!["alpha".to_string()],
-            references: vec![],
-        })
+        let id = db
+            .add_note(CreateNote {
+                title: "Deploy".to_string(),
+                body: "Uses wrangler".to_string(),
+                tags: vec!["cloudflare".to_string(), "ops".to_string()],
+                references: vec![],
+            })
+            .await
+            .unwrap();
+        db.update_note(
+            id,
+            UpdateNote {
+                body: Some("Uses terraform".to_string()),
+                tags: Some(vec!["ops".to_string()]),
+                ..Default::default()
+            },
+        )
         .await
         .unwrap();
-
-        db.add_note(CreateNote {
-            title: "Note 2".to_string(),
-            body: "Body".to_string(),
-            tags: vec!["alpha".to_string(), "beta".to_string()],
-            references: vec![],
-        })
+        db.update_note(
+            id,
+            UpdateNote {
+                title: Some("Deployment".to_string()),
+                ..Default::default()
+            },
+        )
         .await
         .unwrap();
 
-        let tags = db.list_tags().await.unwrap();
-        assert_eq!(tags.len(), 2);
-        assert_eq!(tags[0].name, "alpha");
-        assert_eq!(tags[0].count, 2);
-        assert_eq!(tags[1].name, "beta");
-        assert_eq!(tags[1].count, 1);
+        let revisions = db.list_revisions(id).await.unwrap();
+        let numbers: Vec<i64> = revisions.iter().map(|r| r.revision).collect();
+        assert_eq!(numbers, vec![2, 1]);
+
+        let first = db.get_revision(id, 1).await.unwrap().unwrap();
+        assert_eq!(first.title, "Deploy");
+        assert_eq!(first.body, "Uses wrangler");
+        assert_eq!(first.tags, vec!["cloudflare", "ops"]);
+        assert_eq!(revisions[0].body, "Uses terraform");
+        assert_eq!(revisions[0].tags, vec!["ops"]);
+        assert!(temp.path().join("history/1/2.json").exists());
+
+        assert!(db.get_revision(id, 3).await.unwrap().is_none());
+
+        db.delete_note(id).await.unwrap();
+        assert_eq!(db.list_revisions(id).await.unwrap().len(), 2);
+        db.purge_note(id).await.unwrap();
+        assert!(db.list_revisions(id).await.unwrap().is_empty());
     }
 
     #[tokio::test]
-    async fn test_grep() {
-        let (_temp, db) = setup();
-
-        db.add_note(CreateNote {
-            title: "Hello world".to_string(),
-            body: "This is a test".to_string(),
-            tags: vec!["greeting".to_string()],
-            references: vec![],
-        })
-        .await
-        .unwrap();
+    async fn test_created_backfilled_from_modified() {
+        let (temp, db) = setup();
 
-        db.add_note(CreateNote {
-            title: "Goodbye".to_string(),
-            body: "Farewell".to_string(),
-            tags: vec!["farewell".to_string()],
-            references: vec![],
-        })
-        .await
+        fs::write(
+            temp.path().join("notes/1.json"),
+            r#"{"title": "Old", "body": "Body", "modified": "2025-06-01 10:00:00"}"#,
+        )
         .unwrap();
 
-        let matches = db.grep("hello", None, false).await.unwrap();
-        assert_eq!(matches.len(), 1);
-        assert_eq!(matches[0].title, "Hello world");
+        let note = db.get_note(1).await.unwrap().unwrap();
+        assert_eq!(note.created_at, "2025-06-01 10:00:00");
+        let contents = fs::read_to_string(temp.path().join("notes/1.json")).unwrap();
+        assert!(contents.contains(r#""created": "2025-06-01 10:00:00""#));
 
-        let matches = db.grep("HELLO", None, true).await.unwrap();
-        assert_eq!(matches.len(), 0);
+        // New notes are created and modified at the same time
+        let id = db
+            .add_note(CreateNote {
+                title: "New".to_string(),
+                body: "Body".to_string(),
+                tags: vec![],
+                references: vec![],
+            })
+            .await
+            .unwrap();
+        let note = db.get_note(id).await.unwrap().unwrap();
+        assert_eq!(note.created_at, note.updated_at);
+
+        let notes = db
+            .list_notes(NoteQuery {
+                time_field: veta_core::TimeField::Created,
+                order: veta_core::SortOrder::Asc,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let ids: Vec<i64> = notes.iter().map(|n| n.id).collect();
+        assert_eq!(ids, vec![1, id]);
+    }
+
+    #[tokio::test]
+    async fn test_pagination() {
+        let (_temp, db) = setup();
+        for i in 1..=4 {
+            db.add_note(CreateNote {
+                title: format!("Note {}", i),
+                body: "Paged".to_string(),
+                tags: vec![],
+                references: vec![],
+            })
+            .await
+            .unwrap();
+        }
+        let ids = |notes: Vec<Note>| notes.iter().map(|n| n.id).collect::<Vec<_>>();
+
+        // Notes added within the same second tie on time and are ordered by ID
+        let first = db
+            .list_notes(NoteQuery {
+                limit: Some(2),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let after = veta_core::Cursor::after(
+            first.last().unwrap(),
+            Default::default(),
+            Default::default(),
+        );
+        let rest = db
+            .grep(
+                "paged",
+                &NoteQuery {
+                    after: Some(after),
+                    ..Default::default()
+                },
+                false,
+            )
+            .await
+            .unwrap();
+        let mut all = ids(first);
+        all.extend(ids(rest));
+        assert_eq!(all, ids(db.list_notes(NoteQuery::default()).await.unwrap()));
+        assert_eq!(all.len(), 4);
+
+        let page = db
+            .list_notes(NoteQuery {
+                limit: Some(2),
+                offset: Some(3),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_trash_restore_and_purge() {
+        let (temp, db) = setup();
+
+        let id = db
+            .add_note(CreateNote {
+                title: "Deploy".to_string(),
+                body: "Uses wrangler".to_string(),
+                tags: vec!["ops".to_string(), "cloudflare".to_string()],
+                references: vec!["wrangler.toml".to_string()],
+            })
+            .await
+            .unwrap();
+
+        assert!(db.delete_note(id).await.unwrap());
+        assert!(temp.path().join("trash/1.json").exists());
+        assert!(!temp.path().join("tags/ops").exists());
+        assert!(db.list_tags().await.unwrap().is_empty());
+
+        let trash = db.list_trash().await.unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].note.tags, vec!["cloudflare", "ops"]);
+        assert_eq!(trash[0].note.references, vec!["wrangler.toml"]);
+
+        // Trashed IDs are not reused
+        let other = db
+            .add_note(CreateNote {
+                title: "Other".to_string(),
+                body: "Body".to_string(),
+                tags: vec![],
+                references: vec![],
+            })
+            .await
+            .unwrap();
+        assert_eq!(other, 2);
+
+        assert!(db.restore_note(id).await.unwrap());
+        assert!(!db.restore_note(id).await.unwrap());
+        let note = db.get_note(id).await.unwrap().unwrap();
+        assert_eq!(note.tags, vec!["cloudflare", "ops"]);
+        assert_eq!(db.search("wrangler", None, None).await.unwrap().len(), 1);
+
+        assert!(!db.purge_note(id).await.unwrap());
+        db.delete_note(id).await.unwrap();
+        assert!(db.purge_note(id).await.unwrap());
+        assert!(db.list_trash().await.unwrap().is_empty());
+        assert!(!temp.path().join("trash/1.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_journal() {
+        let (temp, db) = setup();
+
+        assert!(db.pop_operation().await.unwrap().is_none());
+        for id in 0..(JOURNAL_LIMIT as i64 + 5) {
+            db.push_operation(&Operation::Add { id }).await.unwrap();
+        }
+        db.push_operation(&Operation::Delete { ids: vec![1, 2] })
+            .await
+            .unwrap();
+
+        let contents = fs::read_to_string(temp.path().join("journal.jsonl")).unwrap();
+        assert_eq!(contents.lines().count(), JOURNAL_LIMIT);
+
+        assert_eq!(
+            db.pop_operation().await.unwrap(),
+            Some(Operation::Delete { ids: vec![1, 2] })
+        );
+        assert_eq!(
+            db.pop_operation().await.unwrap(),
+            Some(Operation::Add {
+                id: JOURNAL_LIMIT as i64 + 4
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_note() {
+        let (_temp, db) = setup();
+        let note = Note {
+            id: 5,
+            title: "Imported".into(),
+            body: "From an archive".into(),
+            tags: vec!["ops".into()],
+            references: vec!["src/lib.rs".into()],
+            created_at: "2020-01-01 00:00:00".into(),
+            updated_at: "2020-01-02 00:00:00".into(),
+        };
+        db.put_note(&note).await.unwrap();
+        let stored = db.get_note(5).await.unwrap().unwrap();
+        assert_eq!(
+            (stored.title, stored.tags),
+            (note.title.clone(), note.tags.clone())
+        );
+        assert_eq!(
+            (stored.created_at, stored.updated_at),
+            (note.created_at.clone(), note.updated_at.clone())
+        );
+        assert_eq!(db.list_tags().await.unwrap()[0].name, "ops");
+
+        // New notes get IDs after the imported one, without a warning about
+        // the counter
+        let id = db
+            .add_note(CreateNote {
+                title: "New".into(),
+                body: String::new(),
+                tags: vec![],
+                references: vec![],
+            })
+            .await
+            .unwrap();
+        assert_eq!(id, 6);
+
+        // Putting a note that's in the trash takes it out
+        db.delete_note(5).await.unwrap();
+        db.put_note(&note).await.unwrap();
+        assert!(db.list_trash().await.unwrap().is_empty());
+        assert_eq!(db.search("archive", None, None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_markdown_format() {
+        let temp = TempDir::new().unwrap();
+        let config = FilesConfig {
+            format: NoteFormat::Markdown,
+            ..Default::default()
+        };
+        let db = FilesDatabase::create(temp.path(), config).unwrap();
+        let id = db
+            .add_note(CreateNote {
+                title: "Deploy".into(),
+                body: "Uses wrangler\n\nSee the docs".into(),
+                tags: vec!["ops".into()],
+                references: vec!["wrangler.toml".into()],
+            })
+            .await
+            .unwrap();
+
+        let contents = fs::read_to_string(temp.path().join("notes/1.md")).unwrap();
+        assert!(contents.starts_with("+++\ntitle = \"Deploy\"\n"));
+        assert!(contents.ends_with("+++\n\nUses wrangler\n\nSee the docs\n"));
+        assert!(temp.path().join("tags/ops/1.md").exists());
+
+        // The format is kept when the directory is opened again
+        let db = FilesDatabase::open(temp.path()).unwrap();
+        assert_eq!(db.format(), NoteFormat::Markdown);
+        let note = db.get_note(id).await.unwrap().unwrap();
+        assert_eq!(note.body, "Uses wrangler\n\nSee the docs");
+        assert_eq!(note.tags, vec!["ops"]);
+        assert_eq!(
+            db.grep("docs", &NoteQuery::default(), false)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_convert() {
+        let (temp, mut db) = setup();
+        for (title, tags) in [("Deploy", vec!["ops".to_string()]), ("Auth", vec![])] {
+            db.add_note(CreateNote {
+                title: title.into(),
+                body: "Line one\nline two".into(),
+                tags,
+                references: vec![],
+            })
+            .await
+            .unwrap();
+        }
+        let before = db.get_note(1).await.unwrap().unwrap();
+        assert_eq!(db.search("line", None, None).await.unwrap().len(), 2);
+
+        assert_eq!(db.convert(NoteFormat::Markdown).unwrap(), 2);
+        assert!(temp.path().join("notes/1.md").exists());
+        assert!(!temp.path().join("notes/1.json").exists());
+        assert!(temp.path().join("tags/ops/1.md").exists());
+        assert!(!temp.path().join("tags/ops/1.json").exists());
+
+        let db = FilesDatabase::open(temp.path()).unwrap();
+        let after = db.get_note(1).await.unwrap().unwrap();
+        assert_eq!(
+            (after.body, after.tags, after.updated_at),
+            (before.body, before.tags, before.updated_at)
+        );
+        assert_eq!(db.list_tags().await.unwrap()[0].count, 1);
+        assert_eq!(db.search("line", None, None).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_git_mode() {
+        let temp = TempDir::new().unwrap();
+        let config = FilesConfig {
+            git: true,
+            ..Default::default()
+        };
+        let db = FilesDatabase::create(temp.path(), config).unwrap();
+        let id = db
+            .add_note(CreateNote {
+                title: "Deploy".into(),
+                body: "Uses wrangler".into(),
+                tags: vec!["ops".into()],
+                references: vec![],
+            })
+            .await
+            .unwrap();
+        assert_eq!(id, 1);
+
+        // The note is stored under a key, which the ID is an alias for
+        let key = db.key(1);
+        assert_ne!(key, "1");
+        assert!(temp.path().join(format!("notes/{}.json", key)).exists());
+        assert!(temp.path().join(format!("tags/ops/{}.json", key)).exists());
+        assert!(fs::read_to_string(temp.path().join(".gitignore"))
+            .unwrap()
+            .contains("aliases.json"));
+
+        // A note from another clone gets the next ID
+        fs::write(
+            temp.path().join("notes/0a1b2c3d4e5f.json"),
+            r#"{"title": "Auth", "body": "JWT", "modified": "2026-01-01 10:00:00"}"#,
+        )
+        .unwrap();
+        let db = FilesDatabase::open(temp.path()).unwrap();
+        let notes = db.list_notes(NoteQuery::default()).await.unwrap();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(db.get_note(2).await.unwrap().unwrap().title, "Auth");
+
+        // Edits, deletes and imports use the key too
+        db.update_note(
+            2,
+            UpdateNote {
+                title: Some("JWT auth".into()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(db.list_revisions(2).await.unwrap().len(), 1);
+        db.delete_note(1).await.unwrap();
+        assert!(temp.path().join(format!("trash/{}.json", key)).exists());
+        assert_eq!(db.list_trash().await.unwrap()[0].note.id, 1);
+
+        let mut note = db.get_note(2).await.unwrap().unwrap();
+        note.id = 7;
+        db.put_note(&note).await.unwrap();
+        assert_eq!(db.get_note(7).await.unwrap().unwrap().title, "JWT auth");
+        let id = db
+            .add_note(CreateNote {
+                title: "Next".into(),
+                body: String::new(),
+                tags: vec![],
+                references: vec![],
+            })
+            .await
+            .unwrap();
+        assert_eq!(id, 8);
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_mode() {
+        let temp = TempDir::new().unwrap();
+        let config = FilesConfig {
+            encrypted: true,
+            ..Default::default()
+        };
+        let db = FilesDatabase::create(temp.path(), config).unwrap();
+        assert!(temp.path().join(KEY_FILE).exists());
+        db.add_note(CreateNote {
+            title: "ACME database".into(),
+            body: "Runs on db1.acme.internal".into(),
+            tags: vec!["acme".into(), "ops".into()],
+            references: vec![],
+        })
+        .await
+        .unwrap();
+        db.update_note(
+            1,
+            UpdateNote {
+                body: Some("Runs on db2.acme.internal".into()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(db.search("acme", None, None).await.unwrap().len(), 1);
+
+        // Nothing but the key gives away the note or its tags
+        fn assert_no_plaintext(dir: &Path) {
+            for entry in fs::read_dir(dir).unwrap().flatten() {
+                let path = entry.path();
+                assert!(!entry.file_name().to_string_lossy().contains("ops"));
+                if path.is_dir() {
+                    assert_no_plaintext(&path);
+                } else if path.file_name().unwrap() != KEY_FILE {
+                    let contents = fs::read_to_string(&path).unwrap_or_default();
+                    assert!(!contents.contains("acme"), "{:?}", path);
+                }
+            }
+        }
+        assert_no_plaintext(temp.path());
+
+        // Reading works through decryption
+        let db = FilesDatabase::open(temp.path()).unwrap();
+        let tags = db.list_tags().await.unwrap();
+        let names: Vec<_> = tags.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["acme", "ops"]);
+        let notes = db.grep("db2", &NoteQuery::default(), false).await.unwrap();
+        assert_eq!(notes[0].tags, vec!["acme", "ops"]);
+        assert_eq!(db.list_revisions(1).await.unwrap()[0].tags.len(), 2);
+        db.delete_note(1).await.unwrap();
+        assert_eq!(
+            db.list_trash().await.unwrap()[0].note.title,
+            "ACME database"
+        );
+        assert!(fs::read_dir(temp.path().join("tags"))
+            .unwrap()
+            .next()
+            .is_none());
+
+        // Opening with another key fails instead of mixing keys
+        fs::write(temp.path().join(KEY_FILE), generate_key()).unwrap();
+        let error = FilesDatabase::open(temp.path()).err().unwrap();
+        assert!(error.to_string().contains("isn't the key"), "{}", error);
+        fs::remove_file(temp.path().join(KEY_FILE)).unwrap();
+        assert!(FilesDatabase::open(temp.path()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_api_keys() {
+        let (temp, db) = setup();
+
+        let key = db
+            .add_api_key(CreateApiKey {
+                name: "ci".into(),
+                key_hash: "ab".repeat(32),
+                scope: Scope::Read,
+                tags: vec!["public".into()],
+                namespaces: vec![],
+            })
+            .await
+            .unwrap();
+        assert_eq!(key.id, 1);
+        assert!(temp.path().join("api_keys.json").exists());
+
+        let found = db.find_api_key(&"ab".repeat(32)).await.unwrap();
+        assert_eq!(found, Some(key.clone()));
+        assert!(db.find_api_key(&"cd".repeat(32)).await.unwrap().is_none());
+        assert_eq!(db.list_api_keys().await.unwrap(), vec![key]);
+
+        assert!(db.revoke_api_key(1).await.unwrap());
+        assert!(!db.revoke_api_key(1).await.unwrap());
+        assert!(db.list_api_keys().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_tags() {
+        let (_temp, db) = setup();
+
+        db.add_note(CreateNote {
+            title: "Note 1".to_string(),
+            body: "Body".to_string(),
+            tags: vec!["alpha".to_string()],
+            references: vec![],
+        })
+        .await
+        .unwrap();
+
+        db.add_note(CreateNote {
+            title: "Note 2".to_string(),
+            body: "Body".to_string(),
+            tags: vec!["alpha".to_string(), "beta".to_string()],
+            references: vec![],
+        })
+        .await
+        .unwrap();
+
+        let tags = db.list_tags().await.unwrap();
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0].name, "alpha");
+        assert_eq!(tags[0].count, 2);
+        assert_eq!(tags[1].name, "beta");
+        assert_eq!(tags[1].count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_tag_changes() {
+        let (temp, db) = setup();
+        let add = |title: &str, tags: &[&str]| {
+            db.add_note(CreateNote {
+                title: title.to_string(),
+                body: "Body".to_string(),
+                tags: tags.iter().map(|t| t.to_string()).collect(),
+                references: vec![],
+            })
+        };
+        let a = add("Panic", &["debugging", "rust"]).await.unwrap();
+        let b = add("Gdb", &["debug"]).await.unwrap();
+        let c = add("Trace", &["debugging"]).await.unwrap();
+        db.delete_note(c).await.unwrap();
+        assert!(db
+            .set_tag_description("debugging", Some("Finding bugs"))
+            .await
+            .unwrap());
+        assert!(!db.set_tag_description("nope", Some("?")).await.unwrap());
+
+        let changed = db
+            .merge_tags(&["debugging".to_string()], "debug")
+            .await
+            .unwrap();
+        assert_eq!(changed, vec![a]);
+        assert_eq!(
+            db.get_note(a).await.unwrap().unwrap().tags,
+            ["debug", "rust"]
+        );
+        assert_eq!(db.get_note(b).await.unwrap().unwrap().tags, ["debug"]);
+        assert_eq!(
+            db.list_revisions(a).await.unwrap()[0].tags,
+            ["debugging", "rust"]
+        );
+        assert!(!temp.path().join("tags/debugging").exists());
+        assert_eq!(db.list_trash().await.unwrap()[0].note.tags, ["debug"]);
+
+        let tags = db.list_tags().await.unwrap();
+        assert_eq!(tags[0].name, "debug");
+        assert_eq!(tags[0].count, 2);
+        assert_eq!(tags[0].description.as_deref(), Some("Finding bugs"));
+        assert_eq!(tags[1].description, None);
+
+        assert_eq!(db.remove_tag("debug").await.unwrap(), vec![a, b]);
+        assert_eq!(
+            db.get_note(b).await.unwrap().unwrap().tags,
+            Vec::<String>::new()
+        );
+        assert!(db.list_trash().await.unwrap()[0].note.tags.is_empty());
+        assert!(!temp.path().join("tag-descriptions.json").exists());
+        let tags = db.list_tags().await.unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].name, "rust");
+    }
+
+    #[tokio::test]
+    async fn test_hierarchical_tags() {
+        let (temp, db) = setup();
+        let add = |tags: &[&str]| {
+            db.add_note(CreateNote {
+                title: "Note".to_string(),
+                body: "Body".to_string(),
+                tags: tags.iter().map(|t| t.to_string()).collect(),
+                references: vec![],
+            })
+        };
+        let a = add(&["lang", "lang/rust"]).await.unwrap();
+        let b = add(&["lang/go/generics"]).await.unwrap();
+        assert!(temp.path().join("tags/lang/rust/1.json").exists());
+        assert_eq!(
+            db.get_note(a).await.unwrap().unwrap().tags,
+            ["lang", "lang/rust"]
+        );
+
+        let names: Vec<String> = db
+            .list_tags()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(names, ["lang", "lang/go/generics", "lang/rust"]);
+
+        let query = |tags: &str| NoteQuery {
+            tags: TagExpr::parse(tags).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(db.count_notes(query("lang")).await.unwrap(), 2);
+        assert_eq!(db.count_notes(query("lang/go")).await.unwrap(), 1);
+        assert_eq!(db.count_notes(query("-lang/rust")).await.unwrap(), 1);
+
+        db.delete_note(b).await.unwrap();
+        assert!(!temp.path().join("tags/lang/go").exists());
+        assert!(temp.path().join("tags/lang/rust").exists());
+    }
+
+    #[tokio::test]
+    async fn test_tag_aliases() {
+        let (temp, db) = setup();
+        assert!(db.list_tag_aliases().await.unwrap().is_empty());
+        assert!(db.set_tag_alias("debug", Some("debugging")).await.unwrap());
+        assert!(db.set_tag_alias("rust", Some("lang/rust")).await.unwrap());
+        assert!(temp.path().join("tag-aliases.json").exists());
+        assert_eq!(
+            db.list_tag_aliases().await.unwrap(),
+            [
+                TagAlias {
+                    alias: "debug".to_string(),
+                    tag: "debugging".to_string()
+                },
+                TagAlias {
+                    alias: "rust".to_string(),
+                    tag: "lang/rust".to_string()
+                },
+            ]
+        );
+
+        assert!(db.set_tag_alias("debug", None).await.unwrap());
+        assert!(!db.set_tag_alias("debug", None).await.unwrap());
+        assert!(db.set_tag_alias("rust", None).await.unwrap());
+        assert!(!temp.path().join("tag-aliases.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_links() {
+        let (temp, db) = setup();
+        let mut ids = vec![];
+        for title in ["New", "Old", "Related"] {
+            ids.push(
+                db.add_note(CreateNote {
+                    title: title.into(),
+                    body: "".into(),
+                    tags: vec![],
+                    references: vec![],
+                })
+                .await
+                .unwrap(),
+            );
+        }
+        let link = |from: i64, to: i64, kind: LinkKind| NoteLink { from, to, kind };
+        assert!(db
+            .add_link(&link(1, 2, LinkKind::Supersedes))
+            .await
+            .unwrap());
+        assert!(!db
+            .add_link(&link(1, 2, LinkKind::Supersedes))
+            .await
+            .unwrap());
+        assert!(db.add_link(&link(3, 1, LinkKind::RelatesTo)).await.unwrap());
+        assert!(temp.path().join("links/1.json").exists());
+        assert_eq!(
+            db.list_links(1).await.unwrap(),
+            [
+                link(1, 2, LinkKind::Supersedes),
+                link(3, 1, LinkKind::RelatesTo)
+            ]
+        );
+        assert_eq!(
+            db.list_links(2).await.unwrap(),
+            [link(1, 2, LinkKind::Supersedes)]
+        );
+
+        // Links to a note in the trash are hidden until it's purged
+        db.delete_note(2).await.unwrap();
+        assert_eq!(db.list_links(1).await.unwrap().len(), 1);
+        db.restore_note(2).await.unwrap();
+        assert_eq!(db.list_links(1).await.unwrap().len(), 2);
+        db.delete_note(2).await.unwrap();
+        db.purge_note(2).await.unwrap();
+        assert!(!temp.path().join("links/1.json").exists());
+
+        assert!(db.remove_link(3, 1, LinkKind::RelatesTo).await.unwrap());
+        assert!(!db.remove_link(3, 1, LinkKind::RelatesTo).await.unwrap());
+        assert!(db.list_links(1).await.unwrap().is_empty());
+        assert!(!temp.path().join("links").read_dir().unwrap().any(|_| true));
+    }
+
+    #[tokio::test]
+    async fn test_note_index() {
+        let (temp, db) = setup();
+        let long_body = "x".repeat(1000);
+        for (title, body, tags) in [
+            ("Deploy", "Uses wrangler", vec!["ops".to_string()]),
+            ("Design", long_body.as_str(), vec![]),
+        ] {
+            db.add_note(CreateNote {
+                title: title.into(),
+                body: body.into(),
+                tags,
+                references: vec![],
+            })
+            .await
+            .unwrap();
+        }
+
+        let notes = db.list_notes(NoteQuery::default()).await.unwrap();
+        assert_eq!(notes.len(), 2);
+        assert!(temp.path().join("note-index.json").exists());
+        // Long bodies aren't in the index, but are still listed in full
+        assert_eq!(notes.iter().find(|n| n.id == 2).unwrap().body, long_body);
+
+        // Edits made with veta and by hand are both picked up
+        db.update_note(
+            1,
+            UpdateNote {
+                tags: Some(vec!["infra".into()]),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        fs::write(
+            temp.path().join("notes/2.json"),
+            r#"{"title": "Architecture", "body": "Short now", "modified": "2026-01-01 10:00:00"}"#,
+        )
+        .unwrap();
+        fs::create_dir(temp.path().join("tags/arch")).unwrap();
+        db.create_symlink(
+            &temp.path().join("notes/2.json"),
+            &temp.path().join("tags/arch/2.json"),
+        )
+        .unwrap();
+
+        let query = NoteQuery {
+            tags: TagExpr::parse("arch,infra").unwrap(),
+            ..Default::default()
+        };
+        let notes = db.list_notes(query.clone()).await.unwrap();
+        let titles: Vec<_> = notes.iter().map(|n| n.title.as_str()).collect();
+        assert_eq!(titles, vec!["Deploy", "Architecture"]);
+        assert_eq!(db.count_notes(query).await.unwrap(), 2);
+        let tags = db.list_tags().await.unwrap();
+        let names: Vec<_> = tags.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["arch", "infra"]);
+
+        // A deleted index is rebuilt
+        db.delete_note(1).await.unwrap();
+        fs::remove_file(temp.path().join("note-index.json")).unwrap();
+        let notes = db.list_notes(NoteQuery::default()).await.unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].tags, vec!["arch"]);
+    }
+
+    #[tokio::test]
+    async fn test_grep() {
+        let (_temp, db) = setup();
+
+        db.add_note(CreateNote {
+            title: "Hello world".to_string(),
+            body: "This is a test".to_string(),
+            tags: vec!["greeting".to_string()],
+            references: vec![],
+        })
+        .await
+        .unwrap();
+
+        db.add_note(CreateNote {
+            title: "Goodbye".to_string(),
+            body: "Farewell".to_string(),
+            tags: vec!["farewell".to_string()],
+            references: vec![],
+        })
+        .await
+        .unwrap();
+
+        let matches = db
+            .grep("hello", &NoteQuery::default(), false)
+            .await
+            .unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].title, "Hello world");
+
+        let matches = db.grep("HELLO", &NoteQuery::default(), true).await.unwrap();
+        assert_eq!(matches.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_list_notes_with_structured_query() {
+        let (_temp, db) = setup();
+
+        db.add_note(CreateNote {
+            title: "JWT handling".to_string(),
+            body: "Token expiry is 15 minutes".to_string(),
+            tags: vec!["auth".to_string()],
+            references: vec!["src/auth.rs:10".to_string()],
+        })
+        .await
+        .unwrap();
+        db.add_note(CreateNote {
+            title: "Old JWT notes".to_string(),
+            body: "Token expiry was 5 minutes".to_string(),
+            tags: vec!["auth".to_string(), "old".to_string()],
+            references: vec![],
+        })
+        .await
+        .unwrap();
+
+        let query = veta_core::parse_query(r#"title:jwt -tag:old ref:src/auth.rs "token expiry""#)
+            .unwrap()
+            .query;
+        let notes = db.list_notes(query.clone()).await.unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].title, "JWT handling");
+        assert_eq!(db.count_notes(query.clone()).await.unwrap(), 1);
+
+        let notes = db.grep("minutes", &query, false).await.unwrap();
+        assert_eq!(notes.len(), 1);
+
+        let query = veta_core::parse_query("title:old tag:auth").unwrap().query;
+        let notes = db.list_notes(query).await.unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].title, "Old JWT notes");
+    }
+
+    #[tokio::test]
+    async fn test_search_ranks_by_relevance() {
+        let (_temp, db) = setup();
+
+        let body_match = db
+            .add_note(CreateNote {
+                title: "Session handling".to_string(),
+                body: "Sessions are refreshed with a JWT before they expire".to_string(),
+                tags: vec!["auth".to_string()],
+                references: vec![],
+            })
+            .await
+            .unwrap();
+
+        let title_match = db
+            .add_note(CreateNote {
+                title: "JWT expiry".to_string(),
+                body: "Tokens expire after 15 minutes".to_string(),
+                tags: vec!["auth".to_string()],
+                references: vec![],
+            })
+            .await
+            .unwrap();
+
+        db.add_note(CreateNote {
+            title: "Unrelated".to_string(),
+            body: "Nothing to see here".to_string(),
+            tags: vec!["misc".to_string()],
+            references: vec![],
+        })
+        .await
+        .unwrap();
+
+        let hits = db.search("jwt", None, None).await.unwrap();
+        let ids: Vec<i64> = hits.iter().map(|h| h.note.id).collect();
+        assert_eq!(ids, vec![title_match, body_match]);
+        assert!(hits[0].score > hits[1].score);
+
+        // All terms must match
+        let hits = db.search("jwt minutes", None, None).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].note.id, title_match);
+
+        let hits = db
+            .search("jwt", Some(&TagExpr::any_of(["misc"])), None)
+            .await
+            .unwrap();
+        assert!(hits.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_index_tracks_changes() {
+        let (temp, db) = setup();
+
+        let id = db
+            .add_note(CreateNote {
+                title: "Deploy".to_string(),
+                body: "Uses wrangler".to_string(),
+                tags: vec![],
+                references: vec![],
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(db.search("wrangler", None, None).await.unwrap().len(), 1);
+        assert!(temp.path().join("search-index.json").exists());
+
+        db.update_note(
+            id,
+            UpdateNote {
+                body: Some("Uses terraform".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert!(db.search("wrangler", None, None).await.unwrap().is_empty());
+        assert_eq!(db.search("terraform", None, None).await.unwrap().len(), 1);
+
+        // Edit the note file by hand, bypassing the index
+        fs::write(
+            temp.path().join("notes").join(format!("{}.json", id)),
+            r#"{"title": "Deploy", "body": "Uses pulumi now", "modified": "2026-01-01 00:00:00"}"#,
+        )
+        .unwrap();
+        assert!(db.search("terraform", None, None).await.unwrap().is_empty());
+        assert_eq!(db.search("pulumi", None, None).await.unwrap().len(), 1);
+
+        db.delete_note(id).await.unwrap();
+        assert!(db.search("pulumi", None, None).await.unwrap().is_empty());
     }
 
     #[tokio::test]
@@ -0,0 +1,270 @@
+//! Persistent index of everything but note bodies, so notes can be listed,
+//! counted and filtered by tag without reading every note file.
+//!
+//! Stored as `.veta/note-index.json`. Like the search index, each note
+//! records the modification time and size of its file, and each tag the
+//! modification time of its directory, so notes edited and tags changed by
+//! hand are picked up the next time the index is used. Deleting the index is
+//! always safe; it's rebuilt when needed.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+use veta_core::{Error, Note};
+
+use crate::crypto::Cipher;
+use crate::search_index::FileStamp;
+use crate::NoteFile;
+
+/// Bump when the on-disk layout changes to force a rebuild.
+const INDEX_VERSION: u32 = 1;
+
+/// Characters of the body kept in the index. Shorter notes are listed
+/// without reading their files at all.
+const PREVIEW_LEN: usize = 280;
+
+/// A note in the index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct IndexedNote {
+    stamp: FileStamp,
+    title: String,
+    created: String,
+    modified: String,
+    references: Vec<String>,
+    /// The start of the body, or all of it if `complete`.
+    preview: String,
+    complete: bool,
+}
+
+impl IndexedNote {
+    /// The note with `tags`, if its whole body is in the index.
+    pub(crate) fn to_note(&self, id: i64, tags: Vec<String>) -> Option<Note> {
+        self.complete.then(|| self.to_note_without_body(id, tags))
+    }
+
+    /// The note with `tags` and the preview as its body, for filters that
+    /// don't look at the body.
+    pub(crate) fn to_note_without_body(&self, id: i64, tags: Vec<String>) -> Note {
+        Note {
+            id,
+            title: self.title.clone(),
+            body: self.preview.clone(),
+            tags,
+            references: self.references.clone(),
+            created_at: self.created.clone(),
+            updated_at: self.modified.clone(),
+        }
+    }
+}
+
+/// A tag directory in the index, by directory name.
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexedTag {
+    name: String,
+    stamp: FileStamp,
+    ids: BTreeSet<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct NoteIndex {
+    version: u32,
+    notes: BTreeMap<i64, IndexedNote>,
+    tags: BTreeMap<String, IndexedTag>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl Default for NoteIndex {
+    fn default() -> Self {
+        Self {
+            version: INDEX_VERSION,
+            notes: BTreeMap::new(),
+            tags: BTreeMap::new(),
+            dirty: true,
+        }
+    }
+}
+
+impl NoteIndex {
+    /// Load the index from disk. A missing, corrupt or outdated index is
+    /// replaced by an empty one that will be rebuilt.
+    pub(crate) fn load(path: &Path, cipher: Option<&Cipher>) -> Self {
+        let index: Option<NoteIndex> = fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| match cipher {
+                Some(cipher) => cipher.open(&contents).ok(),
+                None => Some(contents),
+            })
+            .and_then(|contents| serde_json::from_str(&contents).ok());
+        match index {
+            Some(index) if index.version == INDEX_VERSION => index,
+            _ => Self::default(),
+        }
+    }
+
+    /// Write the index to disk atomically, if it has changed.
+    pub(crate) fn save(&mut self, path: &Path, cipher: Option<&Cipher>) -> Result<(), Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let contents = serde_json::to_string(self)
+            .map_err(|e| Error::Database(format!("Failed to serialize note index: {}", e)))?;
+        let contents = match cipher {
+            Some(cipher) => cipher.seal(&contents),
+            None => contents,
+        };
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, contents)
+            .map_err(|e| Error::Database(format!("Failed to write note index: {}", e)))?;
+        fs::rename(&temp_path, path)
+            .map_err(|e| Error::Database(format!("Failed to rename note index: {}", e)))?;
+
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// IDs of all indexed notes.
+    pub(crate) fn ids(&self) -> Vec<i64> {
+        self.notes.keys().copied().collect()
+    }
+
+    /// All indexed notes, by ID.
+    pub(crate) fn notes(&self) -> impl Iterator<Item = (i64, &IndexedNote)> {
+        self.notes.iter().map(|(&id, note)| (id, note))
+    }
+
+    /// Whether the note is indexed with the given file stamp.
+    pub(crate) fn is_fresh(&self, id: i64, stamp: FileStamp) -> bool {
+        self.notes.get(&id).is_some_and(|note| note.stamp == stamp)
+    }
+
+    /// Add or replace a note.
+    pub(crate) fn insert(&mut self, id: i64, stamp: FileStamp, note: &NoteFile) {
+        let mut preview: String = note.body.chars().take(PREVIEW_LEN + 1).collect();
+        let complete = preview.chars().count() <= PREVIEW_LEN;
+        if !complete {
+            preview.pop();
+        }
+        self.notes.insert(
+            id,
+            IndexedNote {
+                stamp,
+                title: note.title.clone(),
+                created: note.created.clone(),
+                modified: note.modified.clone(),
+                references: note.references.clone(),
+                preview,
+                complete,
+            },
+        );
+        self.dirty = true;
+    }
+
+    pub(crate) fn remove(&mut self, id: i64) {
+        if self.notes.remove(&id).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    /// Whether the tag directory is indexed with the given stamp.
+    pub(crate) fn is_tag_fresh(&self, dir: &str, stamp: FileStamp) -> bool {
+        self.tags.get(dir).is_some_and(|tag| tag.stamp == stamp)
+    }
+
+    /// Add or replace a tag directory and the notes linked from it.
+    pub(crate) fn insert_tag(
+        &mut self,
+        dir: &str,
+        name: String,
+        stamp: FileStamp,
+        ids: BTreeSet<i64>,
+    ) {
+        self.tags
+            .insert(dir.to_string(), IndexedTag { name, stamp, ids });
+        self.dirty = true;
+    }
+
+    /// Remove tag directories that no longer exist.
+    pub(crate) fn retain_tags(&mut self, exists: impl Fn(&str) -> bool) {
+        let before = self.tags.len();
+        self.tags.retain(|dir, _| exists(dir));
+        if self.tags.len() != before {
+            self.dirty = true;
+        }
+    }
+
+    /// The number of notes with each tag.
+    pub(crate) fn tag_counts(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for tag in self.tags.values() {
+            *counts.entry(tag.name.as_str()).or_default() += tag.ids.len();
+        }
+        counts
+    }
+
+    /// Map every tagged note ID to its sorted tags.
+    pub(crate) fn note_tags(&self) -> HashMap<i64, Vec<String>> {
+        let mut map: HashMap<i64, BTreeSet<&str>> = HashMap::new();
+        for tag in self.tags.values() {
+            for &id in &tag.ids {
+                map.entry(id).or_default().insert(&tag.name);
+            }
+        }
+        map.into_iter()
+            .map(|(id, tags)| (id, tags.into_iter().map(str::to_string).collect()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("note.json");
+        fs::write(&path, "{}").unwrap();
+        let stamp = FileStamp::of(&path).unwrap();
+
+        let mut index = NoteIndex::default();
+        let note = |body: String| NoteFile {
+            title: "Deploy".to_string(),
+            body,
+            references: vec![],
+            created: String::new(),
+            modified: String::new(),
+        };
+        index.insert(1, stamp, &note("é".repeat(PREVIEW_LEN)));
+        index.insert(2, stamp, &note("é".repeat(PREVIEW_LEN + 1)));
+        assert_eq!(
+            index.notes[&1]
+                .to_note(1, vec![])
+                .unwrap()
+                .body
+                .chars()
+                .count(),
+            PREVIEW_LEN
+        );
+        assert!(index.notes[&2].to_note(2, vec![]).is_none());
+        assert_eq!(
+            index.notes[&2]
+                .to_note_without_body(2, vec![])
+                .body
+                .chars()
+                .count(),
+            PREVIEW_LEN
+        );
+        assert!(index.is_fresh(1, stamp));
+
+        let ids = BTreeSet::from([1, 2]);
+        index.insert_tag("ops", "ops".to_string(), stamp, ids);
+        index.insert_tag("api", "api".to_string(), stamp, BTreeSet::from([2]));
+        assert_eq!(index.note_tags()[&2], vec!["api", "ops"]);
+        assert_eq!(index.tag_counts()["ops"], 2);
+        index.retain_tags(|dir| dir != "api");
+        assert_eq!(index.note_tags()[&2], vec!["ops"]);
+    }
+}
@@ -0,0 +1,214 @@
+//! Persistent inverted index for full-text search over note files.
+//!
+//! Stored as `.veta/search-index.json`. Each indexed note records the
+//! modification time and size of its file, so notes edited by hand are
+//! re-indexed the next time the index is used. In encrypted mode the index
+//! is encrypted too, since it holds the words of every note.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use veta_core::search::{bm25_idf, bm25_score, tokenize, BODY_WEIGHT, TITLE_WEIGHT};
+use veta_core::Error;
+
+use crate::crypto::Cipher;
+
+/// Bump when the on-disk layout changes to force a rebuild.
+const INDEX_VERSION: u32 = 1;
+
+/// Modification time and size of a note file when it was indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileStamp {
+    mtime_ns: u64,
+    size: u64,
+}
+
+impl FileStamp {
+    /// Stamp the file at `path`, or `None` if it can't be read.
+    pub(crate) fn of(path: &Path) -> Option<Self> {
+        let meta = fs::metadata(path).ok()?;
+        let mtime_ns = meta
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_nanos() as u64;
+        Some(Self {
+            mtime_ns,
+            size: meta.len(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexedDoc {
+    stamp: FileStamp,
+    /// Total number of tokens in title and body.
+    len: usize,
+}
+
+/// Term frequencies of one term in one note: `[title, body]`.
+type Posting = [u32; 2];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SearchIndex {
+    version: u32,
+    docs: BTreeMap<i64, IndexedDoc>,
+    postings: BTreeMap<String, BTreeMap<i64, Posting>>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl Default for SearchIndex {
+    fn default() -> Self {
+        Self {
+            version: INDEX_VERSION,
+            docs: BTreeMap::new(),
+            postings: BTreeMap::new(),
+            dirty: true,
+        }
+    }
+}
+
+impl SearchIndex {
+    /// Load the index from disk. A missing, corrupt or outdated index is
+    /// replaced by an empty one that will be rebuilt.
+    pub(crate) fn load(path: &Path, cipher: Option<&Cipher>) -> Self {
+        let index: Option<SearchIndex> = fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| match cipher {
+                Some(cipher) => cipher.open(&contents).ok(),
+                None => Some(contents),
+            })
+            .and_then(|contents| serde_json::from_str(&contents).ok());
+        match index {
+            Some(index) if index.version == INDEX_VERSION => index,
+            _ => Self::default(),
+        }
+    }
+
+    /// Write the index to disk atomically, if it has changed.
+    pub(crate) fn save(&mut self, path: &Path, cipher: Option<&Cipher>) -> Result<(), Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let temp_path = path.with_extension("json.tmp");
+        let contents = serde_json::to_string(self)
+            .map_err(|e| Error::Database(format!("Failed to serialize search index: {}", e)))?;
+        let contents = match cipher {
+            Some(cipher) => cipher.seal(&contents),
+            None => contents,
+        };
+
+        let mut file = File::create(&temp_path)
+            .map_err(|e| Error::Database(format!("Failed to create search index: {}", e)))?;
+        file.write_all(contents.as_bytes())
+            .map_err(|e| Error::Database(format!("Failed to write search index: {}", e)))?;
+        fs::rename(&temp_path, path)
+            .map_err(|e| Error::Database(format!("Failed to rename search index: {}", e)))?;
+
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// IDs of all indexed notes.
+    pub(crate) fn ids(&self) -> Vec<i64> {
+        self.docs.keys().copied().collect()
+    }
+
+    /// Whether the note is indexed with the given file stamp.
+    pub(crate) fn is_fresh(&self, id: i64, stamp: FileStamp) -> bool {
+        self.docs
+            .get(&id)
+            .map(|d| d.stamp == stamp)
+            .unwrap_or(false)
+    }
+
+    /// Index (or re-index) a note.
+    pub(crate) fn insert(&mut self, id: i64, stamp: FileStamp, title: &str, body: &str) {
+        self.remove(id);
+
+        let title_tokens = tokenize(title);
+        let body_tokens = tokenize(body);
+        let len = title_tokens.len() + body_tokens.len();
+
+        for (column, tokens) in [title_tokens, body_tokens].into_iter().enumerate() {
+            for token in tokens {
+                let posting = self
+                    .postings
+                    .entry(token)
+                    .or_default()
+                    .entry(id)
+                    .or_insert([0, 0]);
+                posting[column] += 1;
+            }
+        }
+
+        self.docs.insert(id, IndexedDoc { stamp, len });
+        self.dirty = true;
+    }
+
+    /// Remove a note from the index.
+    pub(crate) fn remove(&mut self, id: i64) {
+        if self.docs.remove(&id).is_none() {
+            return;
+        }
+        self.postings.retain(|_, docs| {
+            docs.remove(&id);
+            !docs.is_empty()
+        });
+        self.dirty = true;
+    }
+
+    /// Find notes containing every token of `query`, with their BM25 scores,
+    /// ordered by descending score and then descending ID.
+    pub(crate) fn search(&self, query: &str) -> Vec<(i64, f64)> {
+        let terms: BTreeSet<String> = tokenize(query).into_iter().collect();
+        if terms.is_empty() || self.docs.is_empty() {
+            return Vec::new();
+        }
+
+        let mut term_postings = Vec::new();
+        for term in &terms {
+            match self.postings.get(term) {
+                Some(docs) => term_postings.push(docs),
+                // A missing term means no note can contain all of them
+                None => return Vec::new(),
+            }
+        }
+
+        let total_docs = self.docs.len();
+        let avg_len = self.docs.values().map(|d| d.len).sum::<usize>() as f64 / total_docs as f64;
+        let idfs: Vec<f64> = term_postings
+            .iter()
+            .map(|docs| bm25_idf(total_docs, docs.len()))
+            .collect();
+
+        // Start from the rarest term to keep the candidate set small
+        let rarest = term_postings
+            .iter()
+            .min_by_key(|docs| docs.len())
+            .expect("at least one term");
+
+        let mut hits: Vec<(i64, f64)> = rarest
+            .keys()
+            .filter_map(|id| {
+                let mut terms = Vec::with_capacity(term_postings.len());
+                for (docs, idf) in term_postings.iter().zip(&idfs) {
+                    let [title_tf, body_tf] = docs.get(id)?;
+                    let tf = TITLE_WEIGHT * *title_tf as f64 + BODY_WEIGHT * *body_tf as f64;
+                    terms.push((*idf, tf));
+                }
+                let len = self.docs.get(id)?.len;
+                Some((*id, bm25_score(&terms, len, avg_len)))
+            })
+            .collect();
+
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| b.0.cmp(&a.0)));
+        hits
+    }
+}
@@ -0,0 +1,901 @@
+//! HTTP client backend for Veta.
+//!
+//! Implements the [`Database`] trait by calling the HTTP API of a Veta
+//! worker (or `veta serve`), so the CLI can work with a remote knowledge base.
+//!
+//! The server runs its own [`veta_core::VetaService`], so it validates input
+//! and keeps the undo journal itself. Listings only carry body previews: notes
+//! returned by [`Database::list_notes`], [`Database::grep`] and
+//! [`Database::search`] have the preview as their body and no references.
+//!
+//! Servers that require API keys are reached with [`HttpDatabase::with_token`].
+//! [`Database::with_namespace`] talks to another namespace on the same server
+//! through its `/spaces/:ns` routes.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use veta_api::auth::{CreateKeyRequest, CreateKeyResponse};
+use veta_api::{
+    encode_path_segment, CountResponse, CreateNoteRequest, ErrorResponse, IdResponse, LinkRequest,
+    LinkResponse, MergeTagsRequest, OkResponse, TagAliasRequest, TagChangeResponse, UndoResponse,
+    UnlinkResponse, UpdateNoteRequest, UpdateTagRequest, ARCHIVE_CONTENT_TYPE,
+};
+use veta_core::archive::{read_archive, write_archive};
+use veta_core::{
+    ApiKey, CreateApiKey, CreateNote, Database, Error, ImportMode, ImportSummary, LinkKind,
+    NamespaceCount, Note, NoteLink, NotePage, NoteQuery, NoteSummary, Operation, Revision,
+    SearchHit, SearchResult, TagAlias, TagCount, TagExpr, TrashedNote, UpdateNote,
+    DEFAULT_NAMESPACE,
+};
+
+/// A Veta server reached over HTTP.
+pub struct HttpDatabase {
+    base_url: String,
+    agent: ureq::Agent,
+    token: Option<String>,
+    namespace: String,
+}
+
+impl HttpDatabase {
+    /// Connect to the API at `base_url`, e.g. `https://veta.example.com`.
+    pub fn new(base_url: &str) -> Self {
+        HttpDatabase {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            agent: ureq::Agent::new(),
+            token: None,
+            namespace: DEFAULT_NAMESPACE.to_string(),
+        }
+    }
+
+    /// Send `token` as the API key with every request.
+    pub fn with_token(self, token: &str) -> Self {
+        HttpDatabase {
+            token: Some(token.to_string()),
+            ..self
+        }
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        // The default namespace keeps the plain routes, which servers from
+        // before namespaces also understand
+        let url = if self.namespace == DEFAULT_NAMESPACE {
+            format!("{}{}", self.base_url, path)
+        } else {
+            format!(
+                "{}/spaces/{}{}",
+                self.base_url,
+                encode_path_segment(&self.namespace),
+                path
+            )
+        };
+        let request = self.agent.request(method, &url);
+        match self.token {
+            Some(ref token) => request.set("Authorization", &format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+
+    /// Send a request and parse the JSON response. Returns `None` if the
+    /// server responds with a plain 404, which it uses for missing notes.
+    fn send<T: DeserializeOwned>(
+        &self,
+        request: ureq::Request,
+        body: Option<&impl Serialize>,
+    ) -> Result<Option<T>, Error> {
+        let url = request.url().to_string();
+        let body = body
+            .map(|body| {
+                serde_json::to_string(body)
+                    .map_err(|e| Error::Internal(format!("failed to encode request: {}", e)))
+            })
+            .transpose()?;
+        let Some(text) = self.send_text(request, body.as_deref(), "application/json")? else {
+            return Ok(None);
+        };
+        serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| Error::Database(format!("invalid response from {}: {}", url, e)))
+    }
+
+    /// Send a request with a body of `content_type`, and return the response
+    /// body. Returns `None` for a plain 404, like [`HttpDatabase::send`].
+    fn send_text(
+        &self,
+        request: ureq::Request,
+        body: Option<&str>,
+        content_type: &str,
+    ) -> Result<Option<String>, Error> {
+        let url = request.url().to_string();
+        let result = match body {
+            Some(body) => request.set("Content-Type", content_type).send_string(body),
+            None => request.call(),
+        };
+        let response = match result {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                return match status_error(status, &body) {
+                    Error::NotFound(message) if message == "Not found" => Ok(None),
+                    error => Err(error),
+                };
+            }
+            Err(ureq::Error::Transport(e)) => {
+                return Err(Error::Database(format!("request to {} failed: {}", url, e)))
+            }
+        };
+        response
+            .into_string()
+            .map(Some)
+            .map_err(|e| Error::Database(format!("failed to read response from {}: {}", url, e)))
+    }
+
+    fn get<T: DeserializeOwned>(&self, request: ureq::Request) -> Result<Option<T>, Error> {
+        self.send(request, None::<&()>)
+    }
+
+    /// Send a request that answers `{"ok": true}`, or 404 if the note wasn't
+    /// found.
+    fn send_ok(
+        &self,
+        request: ureq::Request,
+        body: Option<&impl Serialize>,
+    ) -> Result<bool, Error> {
+        Ok(self.send::<OkResponse>(request, body)?.is_some())
+    }
+}
+
+/// Turn an error response back into the error the server's service returned.
+fn status_error(status: u16, body: &str) -> Error {
+    let message = serde_json::from_str::<ErrorResponse>(body)
+        .map(|e| e.error)
+        .unwrap_or_else(|_| body.to_string());
+    let strip = |prefix: &str| message.strip_prefix(prefix).unwrap_or(&message).to_string();
+    match status {
+        401 | 403 => Error::Forbidden(strip("forbidden: ")),
+        404 => Error::NotFound(strip("not found: ")),
+        400..=499 => Error::Validation(strip("validation error: ")),
+        _ if message.starts_with("internal error: ") => Error::Internal(strip("internal error: ")),
+        _ => Error::Database(strip("database error: ")),
+    }
+}
+
+/// Encode the query's filters, sort and paging as `/notes` and `/grep`
+/// parameters.
+fn query_params(mut request: ureq::Request, query: &NoteQuery) -> ureq::Request {
+    if let Some(ref tags) = query.tags {
+        request = request.query("tags", &tags.to_string());
+    }
+    for title in &query.title {
+        request = request.query("title", title);
+    }
+    for text in &query.text {
+        request = request.query("text", text);
+    }
+    for reference in &query.references {
+        request = request.query("ref", reference);
+    }
+    if let Some(ref from) = query.from {
+        request = request.query("from", from);
+    }
+    if let Some(ref to) = query.to {
+        request = request.query("to", to);
+    }
+    if let Some(ref after) = query.after {
+        request = request.query("after", &after.to_string());
+    }
+    if let Some(offset) = query.offset {
+        request = request.query("offset", &offset.to_string());
+    }
+    request
+        .query("sort", &query.time_field.to_string())
+        .query("order", &query.order.to_string())
+        // The server pages by default; 0 means no limit
+        .query("limit", &query.limit.unwrap_or(0).to_string())
+}
+
+/// A listed note, with its body preview standing in for the body.
+fn summary_note(summary: NoteSummary) -> Note {
+    Note {
+        id: summary.id,
+        title: summary.title,
+        body: summary.body_preview,
+        tags: summary.tags,
+        references: Vec::new(),
+        created_at: summary.created_at,
+        updated_at: summary.updated_at,
+    }
+}
+
+fn missing(what: &str) -> Error {
+    Error::Database(format!("unexpected 404 from {}", what))
+}
+
+#[async_trait::async_trait(?Send)]
+impl Database for HttpDatabase {
+    fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    fn with_namespace(&self, namespace: &str) -> Result<Self, Error> {
+        Ok(HttpDatabase {
+            base_url: self.base_url.clone(),
+            agent: self.agent.clone(),
+            token: self.token.clone(),
+            namespace: namespace.to_string(),
+        })
+    }
+
+    async fn list_namespaces(&self) -> Result<Vec<NamespaceCount>, Error> {
+        self.get(self.request("GET", "/spaces"))?
+            .ok_or_else(|| missing("GET /spaces"))
+    }
+
+    async fn add_note(&self, note: CreateNote) -> Result<i64, Error> {
+        let body = CreateNoteRequest {
+            title: note.title,
+            body: note.body,
+            tags: note.tags,
+            references: note.references,
+        };
+        let response: IdResponse = self
+            .send(self.request("POST", "/notes"), Some(&body))?
+            .ok_or_else(|| missing("POST /notes"))?;
+        Ok(response.id)
+    }
+
+    async fn put_note(&self, _note: &Note) -> Result<(), Error> {
+        Err(Error::Internal("notes are imported by the server".into()))
+    }
+
+    async fn get_note(&self, id: i64) -> Result<Option<Note>, Error> {
+        self.get(self.request("GET", &format!("/notes/{}", id)))
+    }
+
+    async fn list_notes(&self, query: NoteQuery) -> Result<Vec<Note>, Error> {
+        let page: NotePage = self
+            .get(query_params(self.request("GET", "/notes"), &query))?
+            .ok_or_else(|| missing("GET /notes"))?;
+        Ok(page.notes.into_iter().map(summary_note).collect())
+    }
+
+    async fn count_notes(&self, query: NoteQuery) -> Result<i64, Error> {
+        let response: CountResponse = self
+            .get(query_params(self.request("GET", "/notes/count"), &query))?
+            .ok_or_else(|| missing("GET /notes/count"))?;
+        Ok(response.count)
+    }
+
+    async fn update_note(&self, id: i64, update: UpdateNote) -> Result<bool, Error> {
+        let body = UpdateNoteRequest {
+            title: update.title,
+            body: update.body,
+            tags: update.tags,
+            references: update.references,
+        };
+        self.send_ok(
+            self.request("PATCH", &format!("/notes/{}", id)),
+            Some(&body),
+        )
+    }
+
+    async fn delete_note(&self, id: i64) -> Result<bool, Error> {
+        self.send_ok(
+            self.request("DELETE", &format!("/notes/{}", id)),
+            None::<&()>,
+        )
+    }
+
+    async fn list_trash(&self) -> Result<Vec<TrashedNote>, Error> {
+        self.get(self.request("GET", "/trash"))?
+            .ok_or_else(|| missing("GET /trash"))
+    }
+
+    async fn restore_note(&self, id: i64) -> Result<bool, Error> {
+        let request = self.request("POST", &format!("/notes/{}/restore", id));
+        self.send_ok(request, None::<&()>)
+    }
+
+    async fn purge_note(&self, id: i64) -> Result<bool, Error> {
+        self.send_ok(
+            self.request("DELETE", &format!("/trash/{}", id)),
+            None::<&()>,
+        )
+    }
+
+    /// The server journals the operations it performs, so there's nothing
+    /// to record on this side.
+    async fn push_operation(&self, _operation: &Operation) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn pop_operation(&self) -> Result<Option<Operation>, Error> {
+        Err(Error::Internal(
+            "the undo journal is kept by the server".into(),
+        ))
+    }
+
+    async fn undo(&self) -> Option<Result<Option<Operation>, Error>> {
+        let response = self
+            .send::<UndoResponse>(self.request("POST", "/undo"), None::<&()>)
+            .and_then(|response| response.ok_or_else(|| missing("POST /undo")));
+        Some(response.map(|response| response.undone))
+    }
+
+    async fn export(&self) -> Option<Result<Vec<Note>, Error>> {
+        let archive = match self.send_text(self.request("GET", "/export"), None, "") {
+            Ok(archive) => archive.ok_or_else(|| missing("GET /export")),
+            Err(e) => Err(e),
+        };
+        Some(archive.and_then(|archive| read_archive(&archive)))
+    }
+
+    async fn import(
+        &self,
+        notes: &[Note],
+        mode: ImportMode,
+    ) -> Option<Result<ImportSummary, Error>> {
+        let request = self
+            .request("POST", "/import")
+            .query("mode", &mode.to_string());
+        let archive = write_archive(notes);
+        let response = self.send_text(request, Some(&archive), ARCHIVE_CONTENT_TYPE);
+        Some(response.and_then(|text| {
+            let text = text.ok_or_else(|| missing("POST /import"))?;
+            serde_json::from_str(&text)
+                .map_err(|e| Error::Database(format!("invalid response to POST /import: {}", e)))
+        }))
+    }
+
+    async fn list_revisions(&self, note_id: i64) -> Result<Vec<Revision>, Error> {
+        let request = self.request("GET", &format!("/notes/{}/revisions", note_id));
+        Ok(self.get(request)?.unwrap_or_default())
+    }
+
+    async fn get_revision(&self, note_id: i64, revision: i64) -> Result<Option<Revision>, Error> {
+        let path = format!("/notes/{}/revisions/{}", note_id, revision);
+        self.get(self.request("GET", &path))
+    }
+
+    async fn list_tags(&self) -> Result<Vec<TagCount>, Error> {
+        self.get(self.request("GET", "/tags"))?
+            .ok_or_else(|| missing("GET /tags"))
+    }
+
+    /// Renames are merges into a tag that doesn't exist yet, which the
+    /// caller has checked.
+    async fn merge_tags(&self, sources: &[String], target: &str) -> Result<Vec<i64>, Error> {
+        let path = format!("/tags/{}/merge", encode_path_segment(target));
+        let body = MergeTagsRequest {
+            tags: sources.to_vec(),
+        };
+        let response: TagChangeResponse = self
+            .send(self.request("POST", &path), Some(&body))?
+            .ok_or_else(|| missing("POST /tags/:name/merge"))?;
+        Ok(response.ids)
+    }
+
+    async fn remove_tag(&self, tag: &str) -> Result<Vec<i64>, Error> {
+        let path = format!("/tags/{}", encode_path_segment(tag));
+        let response: TagChangeResponse = self
+            .send(self.request("DELETE", &path), None::<&()>)?
+            .ok_or_else(|| missing("DELETE /tags/:name"))?;
+        Ok(response.ids)
+    }
+
+    async fn set_tag_description(
+        &self,
+        tag: &str,
+        description: Option<&str>,
+    ) -> Result<bool, Error> {
+        let path = format!("/tags/{}", encode_path_segment(tag));
+        let body = UpdateTagRequest {
+            name: None,
+            description: Some(description.unwrap_or_default().to_string()),
+        };
+        let response: Option<TagChangeResponse> =
+            self.send(self.request("PATCH", &path), Some(&body))?;
+        Ok(response.is_some())
+    }
+
+    /// Servers from before tag aliases have none.
+    async fn list_tag_aliases(&self) -> Result<Vec<TagAlias>, Error> {
+        Ok(self
+            .get(self.request("GET", "/tags/aliases"))?
+            .unwrap_or_default())
+    }
+
+    /// The server retags notes that have the alias itself.
+    async fn set_tag_alias(&self, alias: &str, tag: Option<&str>) -> Result<bool, Error> {
+        let path = format!("/tags/aliases/{}", encode_path_segment(alias));
+        match tag {
+            Some(tag) => {
+                let body = TagAliasRequest {
+                    tag: tag.to_string(),
+                };
+                self.send::<TagChangeResponse>(self.request("PUT", &path), Some(&body))?
+                    .ok_or_else(|| missing("PUT /tags/aliases/:alias"))?;
+                Ok(true)
+            }
+            None => self.send_ok(self.request("DELETE", &path), None::<&()>),
+        }
+    }
+
+    async fn list_links(&self, id: i64) -> Result<Vec<NoteLink>, Error> {
+        let path = format!("/notes/{}/links", id);
+        Ok(self.get(self.request("GET", &path))?.unwrap_or_default())
+    }
+
+    async fn add_link(&self, link: &NoteLink) -> Result<bool, Error> {
+        let path = format!("/notes/{}/links", link.from);
+        let body = LinkRequest {
+            to: link.to,
+            kind: link.kind,
+        };
+        let response: LinkResponse = self
+            .send(self.request("POST", &path), Some(&body))?
+            .ok_or_else(|| missing("POST /notes/:id/links"))?;
+        Ok(response.added)
+    }
+
+    async fn remove_link(&self, from: i64, to: i64, kind: LinkKind) -> Result<bool, Error> {
+        let path = format!("/notes/{}/links/{}", from, to);
+        let request = self
+            .request("DELETE", &path)
+            .query("kind", &kind.to_string());
+        match self.send::<UnlinkResponse>(request, None::<&()>) {
+            Ok(response) => Ok(response.is_some()),
+            // The server says which link wasn't found
+            Err(Error::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn grep(
+        &self,
+        pattern: &str,
+        query: &NoteQuery,
+        case_sensitive: bool,
+    ) -> Result<Vec<Note>, Error> {
+        let request = self
+            .request("GET", "/grep")
+            .query("q", pattern)
+            .query("case_sensitive", &case_sensitive.to_string());
+        let page: NotePage = self
+            .get(query_params(request, query))?
+            .ok_or_else(|| missing("GET /grep"))?;
+        Ok(page.notes.into_iter().map(summary_note).collect())
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        tags: Option<&TagExpr>,
+        limit: Option<i64>,
+    ) -> Result<Vec<SearchHit>, Error> {
+        let mut request = self
+            .request("GET", "/search")
+            .query("q", query)
+            .query("limit", &limit.unwrap_or(0).to_string());
+        if let Some(tags) = tags {
+            request = request.query("tags", &tags.to_string());
+        }
+        let results: Vec<SearchResult> =
+            self.get(request)?.ok_or_else(|| missing("GET /search"))?;
+        Ok(results
+            .into_iter()
+            .map(|result| SearchHit {
+                note: summary_note(result.note),
+                score: result.score,
+            })
+            .collect())
+    }
+
+    async fn add_api_key(&self, key: CreateApiKey) -> Result<ApiKey, Error> {
+        let body = CreateKeyRequest {
+            name: key.name,
+            scope: key.scope,
+            tags: key.tags,
+            namespaces: key.namespaces,
+            key_hash: Some(key.key_hash),
+        };
+        let response: CreateKeyResponse = self
+            .send(self.request("POST", "/keys"), Some(&body))?
+            .ok_or_else(|| missing("POST /keys"))?;
+        Ok(response.api_key)
+    }
+
+    /// Keys are checked by the server when they're used.
+    async fn find_api_key(&self, _key_hash: &str) -> Result<Option<ApiKey>, Error> {
+        Err(Error::Internal("API keys are checked by the server".into()))
+    }
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>, Error> {
+        self.get(self.request("GET", "/keys"))?
+            .ok_or_else(|| missing("GET /keys"))
+    }
+
+    async fn revoke_api_key(&self, id: i64) -> Result<bool, Error> {
+        self.send_ok(
+            self.request("DELETE", &format!("/keys/{}", id)),
+            None::<&()>,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use veta_core::auth::hash_key;
+    use veta_core::{Cursor, Scope, VetaService};
+    use veta_sqlite::SqliteDatabase;
+
+    /// Start `veta serve` on an in-memory database and connect to it as
+    /// admin.
+    fn serve() -> VetaService<HttpDatabase> {
+        let url = start("admin-secret");
+        VetaService::new(HttpDatabase::new(&url).with_token("admin-secret"))
+    }
+
+    /// Start `veta serve` on an in-memory database and return its URL.
+    fn start(admin_token: &'static str) -> String {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let server = veta_api::server::Server::bind("127.0.0.1:0").unwrap();
+            tx.send(server.local_addr().unwrap()).unwrap();
+            let service = VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(server.run(&service, Some(admin_token)));
+        });
+        format!("http://{}/", rx.recv().unwrap())
+    }
+
+    async fn add(service: &VetaService<HttpDatabase>, title: &str, tags: &[&str]) -> i64 {
+        service
+            .add_note(
+                title.to_string(),
+                format!("Body of {}", title),
+                tags.iter().map(|t| t.to_string()).collect(),
+                vec!["src/lib.rs".to_string()],
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_notes() {
+        let service = serve();
+
+        let id = add(&service, "Deploy", &["ops"]).await;
+        let note = service.get_note(id).await.unwrap().unwrap();
+        assert_eq!(note.title, "Deploy");
+        assert_eq!(note.references, vec!["src/lib.rs"]);
+        assert!(service.get_note(99).await.unwrap().is_none());
+
+        let updated = service
+            .update_note(
+                id,
+                UpdateNote {
+                    body: Some("Uses terraform".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(updated);
+        assert!(!service
+            .update_note(99, UpdateNote::default())
+            .await
+            .unwrap());
+
+        let revisions = service.list_revisions(id).await.unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].body, "Body of Deploy");
+        assert!(service.list_revisions(99).await.unwrap().is_empty());
+        service
+            .restore_revision(id, revisions[0].revision)
+            .await
+            .unwrap();
+        let note = service.get_note(id).await.unwrap().unwrap();
+        assert_eq!(note.body, "Body of Deploy");
+
+        let tags = service.list_tags().await.unwrap();
+        assert_eq!(tags[0].name, "ops");
+        assert_eq!(tags[0].count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_tag_changes() {
+        let service = serve();
+        let a = add(&service, "Panic", &["debugging", "c++"]).await;
+        let b = add(&service, "Gdb", &["debug"]).await;
+
+        service.describe_tag("debugging", "Bugs").await.unwrap();
+        let merged = service.merge_tags(&["debugging".into()], "debug").await;
+        assert_eq!(merged.unwrap(), vec![a]);
+        assert_eq!(service.rename_tag("c++", "cpp").await.unwrap(), vec![a]);
+        let err = service.rename_tag("c++", "cpp").await.unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)));
+
+        let tags = service.list_tags().await.unwrap();
+        assert_eq!((tags[0].name.as_str(), tags[0].count), ("debug", 2));
+        assert_eq!(tags[0].description.as_deref(), Some("Bugs"));
+        assert_eq!(service.remove_tag("debug").await.unwrap(), vec![a, b]);
+        let err = service.describe_tag("debug", "Bugs").await.unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_tag_aliases() {
+        let service = serve();
+        let a = add(&service, "Panic", &["debug", "lang/rust"]).await;
+
+        let retagged = service.add_tag_alias("debug", "debugging").await;
+        assert_eq!(retagged.unwrap(), vec![a]);
+        let b = add(&service, "Gdb", &["debug", "lang/c++"]).await;
+        assert_eq!(
+            service.get_note(b).await.unwrap().unwrap().tags,
+            ["debugging", "lang/c++"]
+        );
+        let aliases = service.list_tag_aliases().await.unwrap();
+        assert_eq!(
+            (aliases[0].alias.as_str(), aliases[0].tag.as_str()),
+            ("debug", "debugging")
+        );
+
+        let query = NoteQuery {
+            tags: TagExpr::parse("lang+debug").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(service.count_notes(query).await.unwrap(), 2);
+
+        service.remove_tag_alias("debug").await.unwrap();
+        let err = service.remove_tag_alias("debug").await.unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn test_links() {
+        let service = serve();
+        let a = add(&service, "New", &[]).await;
+        let b = add(&service, "Old", &[]).await;
+
+        assert!(service
+            .link_notes(a, b, LinkKind::Supersedes)
+            .await
+            .unwrap());
+        assert!(!service
+            .link_notes(a, b, LinkKind::Supersedes)
+            .await
+            .unwrap());
+        let err = service.link_notes(a, 99, LinkKind::RelatesTo).await;
+        assert!(matches!(err, Err(Error::NotFound(_))));
+        let links = service.list_links(b).await.unwrap().unwrap();
+        assert_eq!(
+            links,
+            [NoteLink {
+                from: a,
+                to: b,
+                kind: LinkKind::Supersedes
+            }]
+        );
+
+        let err = service.unlink_notes(a, b, Some(LinkKind::RelatesTo)).await;
+        assert!(matches!(err, Err(Error::NotFound(_))));
+        let removed = service.unlink_notes(a, b, None).await.unwrap();
+        assert_eq!(removed, [LinkKind::Supersedes]);
+        assert!(service.list_links(a).await.unwrap().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_queries() {
+        let service = serve();
+        for title in ["One", "Two", "Three"] {
+            add(&service, title, &["paged"]).await;
+        }
+        add(&service, "Other", &["other"]).await;
+
+        let query = NoteQuery {
+            tags: TagExpr::parse("paged").unwrap(),
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = service.list_notes(query.clone()).await.unwrap();
+        assert_eq!(page.notes.len(), 2);
+        let next = service
+            .list_notes(NoteQuery {
+                after: Some(Cursor::parse(&page.next_cursor.unwrap()).unwrap()),
+                ..query.clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(next.notes.len(), 1);
+        assert!(next.next_cursor.is_none());
+        assert_eq!(service.count_notes(query).await.unwrap(), 3);
+
+        let titled = NoteQuery {
+            title: vec!["t".to_string()],
+            text: vec!["body of".to_string()],
+            ..Default::default()
+        };
+        let titles: Vec<String> = service
+            .list_notes(titled.clone())
+            .await
+            .unwrap()
+            .notes
+            .into_iter()
+            .map(|n| n.title)
+            .collect();
+        assert_eq!(titles, vec!["Other", "Three", "Two"]);
+
+        let matches = service.grep("^T", titled, true).await.unwrap();
+        assert_eq!(matches.notes.len(), 2);
+
+        let results = service.search("three", None, None).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].note.title, "Three");
+    }
+
+    #[tokio::test]
+    async fn test_trash_and_undo() {
+        let service = serve();
+        let id = add(&service, "Deploy", &["ops"]).await;
+
+        assert_eq!(service.delete_notes(&[id, 99]).await.unwrap(), vec![id]);
+        assert!(service.get_note(id).await.unwrap().is_none());
+        assert_eq!(service.list_trash().await.unwrap()[0].note.id, id);
+
+        // Undo happens on the server, which journaled the delete
+        assert_eq!(
+            service.undo().await.unwrap(),
+            Some(Operation::Delete { ids: vec![id] })
+        );
+        assert!(service.get_note(id).await.unwrap().is_some());
+
+        service.delete_note(id).await.unwrap();
+        assert!(!service.restore_note(99).await.unwrap());
+        assert!(service.purge_note(id).await.unwrap());
+        assert!(service.list_trash().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let service = serve();
+        add(&service, "Deploy", &["ops"]).await;
+
+        let err = service
+            .grep("[invalid", NoteQuery::default(), false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Validation(ref m) if m.starts_with("invalid regex")));
+
+        let err = service.restore_revision(1, 5).await.unwrap_err();
+        assert!(matches!(err, Error::NotFound(_)));
+
+        let unreachable = VetaService::new(HttpDatabase::new("http://127.0.0.1:1"));
+        let err = unreachable.list_tags().await.unwrap_err();
+        assert!(matches!(err, Error::Database(ref m) if m.contains("127.0.0.1:1")));
+    }
+
+    #[tokio::test]
+    async fn test_api_keys() {
+        let url = start("admin-secret");
+        let admin = VetaService::new(HttpDatabase::new(&url).with_token("admin-secret"));
+        let key = veta_api::auth::generate_key().unwrap();
+        let api_key = admin
+            .add_api_key(
+                "ci".into(),
+                Scope::Read,
+                vec![],
+                vec!["default".into()],
+                hash_key(&key),
+            )
+            .await
+            .unwrap();
+        assert_eq!(admin.list_api_keys().await.unwrap(), vec![api_key]);
+        add(&admin, "Deploy", &["ops"]).await;
+
+        let reader = VetaService::new(HttpDatabase::new(&url).with_token(&key));
+        assert_eq!(reader.list_tags().await.unwrap().len(), 1);
+        let err = reader
+            .add_note("Test".into(), String::new(), vec![], vec![])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Forbidden(ref m) if m == "this API key is read-only"));
+
+        let anonymous = VetaService::new(HttpDatabase::new(&url));
+        let err = anonymous.list_tags().await.unwrap_err();
+        assert!(matches!(err, Error::Forbidden(ref m) if m == "Missing API key"));
+
+        assert!(admin.revoke_api_key(1).await.unwrap());
+        assert!(!admin.revoke_api_key(1).await.unwrap());
+        assert!(reader.list_tags().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_export_and_import() {
+        let source = serve();
+        let body = "x".repeat(500);
+        source
+            .add_note("Long".into(), body.clone(), vec![], vec![])
+            .await
+            .unwrap();
+        add(&source, "Deploy", &["ops"]).await;
+
+        // Exports carry full bodies, unlike listings
+        let notes = source.export().await.unwrap();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].body, body);
+
+        let target = serve();
+        add(&target, "Existing", &[]).await;
+        let summary = target.import(notes, ImportMode::Renumber).await.unwrap();
+        assert_eq!(summary.added, 2);
+        let imported = target.get_note(2).await.unwrap().unwrap();
+        assert_eq!(
+            (imported.title.as_str(), imported.body.len()),
+            ("Long", 500)
+        );
+
+        let err = target
+            .import(target.export().await.unwrap(), ImportMode::Keep)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Validation(ref m) if m.contains("already exists")));
+    }
+
+    #[tokio::test]
+    async fn test_namespaces() {
+        let service = serve();
+        add(&service, "Deploy", &["ops"]).await;
+        let team = service.in_namespace("team").unwrap();
+        let id = add(&team, "Onboarding", &["docs"]).await;
+
+        assert_eq!(team.namespace(), "team");
+        assert_eq!(
+            team.get_note(id).await.unwrap().unwrap().title,
+            "Onboarding"
+        );
+        // Each namespace numbers its own notes
+        assert_eq!(id, 1);
+        assert_eq!(service.get_note(id).await.unwrap().unwrap().title, "Deploy");
+        assert_eq!(team.list_tags().await.unwrap()[0].name, "docs");
+        assert_eq!(
+            service.list_namespaces().await.unwrap(),
+            vec![
+                NamespaceCount {
+                    name: "default".into(),
+                    count: 1
+                },
+                NamespaceCount {
+                    name: "team".into(),
+                    count: 1
+                },
+            ]
+        );
+        assert!(service.in_namespace("Team").is_err());
+    }
+
+    #[test]
+    fn test_status_error() {
+        assert!(matches!(
+            status_error(400, r#"{"error": "validation error: title cannot be empty"}"#),
+            Error::Validation(ref m) if m == "title cannot be empty"
+        ));
+        assert!(matches!(
+            status_error(404, r#"{"error": "not found: note 3 has no revisions"}"#),
+            Error::NotFound(ref m) if m == "note 3 has no revisions"
+        ));
+        assert!(matches!(
+            status_error(500, r#"{"error": "database error: disk full"}"#),
+            Error::Database(ref m) if m == "disk full"
+        ));
+        assert!(matches!(
+            status_error(502, "Bad gateway"),
+            Error::Database(ref m) if m == "Bad gateway"
+        ));
+    }
+}
@@ -3,15 +3,22 @@
 use regex::Regex;
 use rusqlite::{params, Connection, OptionalExtension};
 use std::path::Path;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use veta_core::journal::JOURNAL_LIMIT;
+use veta_core::search::{fts5_query, BODY_WEIGHT, TITLE_WEIGHT};
 use veta_core::{
-    get_pending_migrations, CreateNote, Database, Error, Note, NoteQuery, TagCount, UpdateNote,
-    SCHEMA_VERSION,
+    get_pending_migrations, is_add_column, ApiKey, CreateApiKey, CreateNote, Database, Error,
+    LinkKind, NamespaceCount, Note, NoteLink, NoteQuery, Operation, Revision, SearchHit, TagAlias,
+    TagCount, TagExpr, TrashedNote, UpdateNote, DEFAULT_NAMESPACE, SCHEMA_VERSION,
 };
 
 /// SQLite-backed database implementation.
+///
+/// Handles for other namespaces, from [`Database::with_namespace`], share
+/// the connection.
 pub struct SqliteDatabase {
-    conn: Mutex<Connection>,
+    conn: Arc<Mutex<Connection>>,
+    namespace: String,
 }
 
 impl SqliteDatabase {
@@ -21,7 +28,8 @@ impl SqliteDatabase {
         conn.execute_batch("PRAGMA foreign_keys = ON;")
             .map_err(|e| Error::Database(e.to_string()))?;
         let db = Self {
-            conn: Mutex::new(conn),
+            conn: Arc::new(Mutex::new(conn)),
+            namespace: DEFAULT_NAMESPACE.to_string(),
         };
         db.run_migrations()?;
         Ok(db)
@@ -33,7 +41,8 @@ impl SqliteDatabase {
         conn.execute_batch("PRAGMA foreign_keys = ON;")
             .map_err(|e| Error::Database(e.to_string()))?;
         let db = Self {
-            conn: Mutex::new(conn),
+            conn: Arc::new(Mutex::new(conn)),
+            namespace: DEFAULT_NAMESPACE.to_string(),
         };
         db.run_migrations()?;
         Ok(db)
@@ -70,31 +79,38 @@ impl SqliteDatabase {
             return Ok(());
         }
 
-        // Run pending migrations
+        // Run pending migrations, each in its own transaction so a failure
+        // leaves the database at the last completed version
         for migration in get_pending_migrations(current_version) {
+            let tx = conn
+                .unchecked_transaction()
+                .map_err(|e| Error::Database(e.to_string()))?;
             for statement in migration.statements {
                 // Skip _veta_meta creation (already done above)
                 if statement.contains("_veta_meta") {
                     continue;
                 }
-                // ALTER TABLE doesn't support IF NOT EXISTS, so ignore errors for those
-                if statement.starts_with("ALTER TABLE") {
-                    let _ = conn.execute(statement, []);
-                } else {
-                    conn.execute(statement, []).map_err(|e| {
-                        Error::Database(format!("Migration {} failed: {}", migration.name, e))
-                    })?;
+                // ADD COLUMN doesn't support IF NOT EXISTS, so a column that
+                // already exists is not an error
+                match tx.execute(statement, []) {
+                    Ok(_) => {}
+                    Err(e) if is_add_column(statement) && Self::is_duplicate_column(&e) => {}
+                    Err(e) => {
+                        return Err(Error::Database(format!(
+                            "Migration {} failed: {}",
+                            migration.name, e
+                        )));
+                    }
                 }
             }
+            tx.execute(
+                "INSERT OR REPLACE INTO _veta_meta (key, value) VALUES ('schema_version', ?1)",
+                params![migration.version.to_string()],
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+            tx.commit().map_err(|e| Error::Database(e.to_string()))?;
         }
 
-        // Update schema version
-        conn.execute(
-            "INSERT OR REPLACE INTO _veta_meta (key, value) VALUES ('schema_version', ?1)",
-            params![SCHEMA_VERSION.to_string()],
-        )
-        .map_err(|e| Error::Database(e.to_string()))?;
-
         Ok(())
     }
 
@@ -120,40 +136,295 @@ impl SqliteDatabase {
     fn serialize_references(refs: &[String]) -> String {
         serde_json::to_string(refs).unwrap_or_else(|_| "[]".to_string())
     }
+
+    /// Replace the tags of the note whose `row_id` is `row_id`, with tags
+    /// of `namespace`.
+    fn set_tags(
+        conn: &Connection,
+        row_id: i64,
+        namespace: &str,
+        tags: &[String],
+    ) -> Result<(), Error> {
+        conn.execute("DELETE FROM note_tags WHERE note_id = ?1", params![row_id])
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        for tag in tags {
+            conn.execute(
+                "INSERT INTO tags (namespace, name) VALUES (?1, ?2)
+                 ON CONFLICT (namespace, name) DO NOTHING",
+                params![namespace, tag],
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+            conn.execute(
+                "INSERT INTO note_tags (note_id, tag_id)
+                 SELECT ?1, id FROM tags WHERE namespace = ?2 AND name = ?3",
+                params![row_id, namespace, tag],
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Replace the tags `sources` with `target`, or remove them, on every
+    /// note in the namespace, in one transaction. See [`Database::merge_tags`].
+    fn retag(&self, sources: &[String], target: Option<&str>) -> Result<Vec<i64>, Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn
+            .transaction()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let sources = serde_json::to_string(sources).map_err(|e| Error::Internal(e.to_string()))?;
+
+        // Save the current versions of the notes outside the trash as revisions
+        tx.execute(
+            "INSERT INTO note_revisions (note_id, revision, title, body, tags, \"references\", updated_at)
+             SELECT n.row_id,
+                    COALESCE((SELECT MAX(r.revision) FROM note_revisions r WHERE r.note_id = n.row_id), 0) + 1,
+                    n.title,
+                    n.body,
+                    (SELECT json_group_array(t.name) FROM note_tags nt
+                     JOIN tags t ON nt.tag_id = t.id
+                     WHERE nt.note_id = n.row_id),
+                    n.\"references\",
+                    n.updated_at
+             FROM notes n
+             WHERE n.namespace = ?1 AND n.deleted_at IS NULL AND n.row_id IN
+                 (SELECT nt.note_id FROM note_tags nt JOIN tags t ON nt.tag_id = t.id
+                  WHERE t.namespace = ?1 AND t.name IN (SELECT value FROM json_each(?2)))",
+            params![self.namespace, sources],
+        )
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+        let mut ids = {
+            let mut stmt = tx
+                .prepare(
+                    "UPDATE notes SET updated_at = datetime('now')
+                     WHERE namespace = ?1 AND deleted_at IS NULL AND row_id IN
+                         (SELECT nt.note_id FROM note_tags nt JOIN tags t ON nt.tag_id = t.id
+                          WHERE t.namespace = ?1 AND t.name IN (SELECT value FROM json_each(?2)))
+                     RETURNING id",
+                )
+                .map_err(|e| Error::Database(e.to_string()))?;
+            let ids = stmt
+                .query_map(params![self.namespace, sources], |row| row.get(0))
+                .map_err(|e| Error::Database(e.to_string()))?
+                .collect::<Result<Vec<i64>, _>>()
+                .map_err(|e| Error::Database(e.to_string()))?;
+            ids
+        };
+        ids.sort();
+
+        if let Some(target) = target {
+            tx.execute(
+                "INSERT INTO tags (namespace, name) VALUES (?1, ?2)
+                 ON CONFLICT (namespace, name) DO NOTHING",
+                params![self.namespace, target],
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+            tx.execute(
+                "INSERT OR IGNORE INTO note_tags (note_id, tag_id)
+                 SELECT nt.note_id, (SELECT id FROM tags WHERE namespace = ?1 AND name = ?3)
+                 FROM note_tags nt
+                 JOIN tags t ON nt.tag_id = t.id
+                 WHERE t.namespace = ?1 AND t.name IN (SELECT value FROM json_each(?2))",
+                params![self.namespace, sources, target],
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+            // The target keeps its description, or takes a source's
+            tx.execute(
+                "INSERT OR IGNORE INTO tag_descriptions (namespace, name, description)
+                 SELECT namespace, ?3, description FROM tag_descriptions
+                 WHERE namespace = ?1 AND name IN (SELECT value FROM json_each(?2))
+                 ORDER BY name LIMIT 1",
+                params![self.namespace, sources, target],
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+        }
+
+        tx.execute(
+            "DELETE FROM note_tags
+             WHERE tag_id IN (SELECT id FROM tags
+                              WHERE namespace = ?1 AND name IN (SELECT value FROM json_each(?2)))",
+            params![self.namespace, sources],
+        )
+        .map_err(|e| Error::Database(e.to_string()))?;
+        tx.execute(
+            "DELETE FROM tag_descriptions
+             WHERE namespace = ?1 AND name IN (SELECT value FROM json_each(?2))",
+            params![self.namespace, sources],
+        )
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+        // Drop the namespace's tags that no note has anymore
+        tx.execute(
+            "DELETE FROM tags WHERE namespace = ?1 AND id NOT IN (SELECT tag_id FROM note_tags)",
+            params![self.namespace],
+        )
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+        tx.commit().map_err(|e| Error::Database(e.to_string()))?;
+        Ok(ids)
+    }
+
+    /// Read a note from the columns `id, title, body, created_at, updated_at,
+    /// references, tags`.
+    fn row_to_note(row: &rusqlite::Row) -> rusqlite::Result<Note> {
+        Ok(Note {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            body: row.get(2)?,
+            created_at: row.get(3)?,
+            updated_at: row.get(4)?,
+            references: Self::parse_references(row.get(5)?),
+            tags: Self::parse_tags(row.get(6)?),
+        })
+    }
+
+    fn row_to_revision(row: &rusqlite::Row) -> rusqlite::Result<Revision> {
+        let mut tags = Self::parse_references(row.get(4)?);
+        tags.sort();
+        Ok(Revision {
+            note_id: row.get(0)?,
+            revision: row.get(1)?,
+            title: row.get(2)?,
+            body: row.get(3)?,
+            tags,
+            references: Self::parse_references(row.get(5)?),
+            updated_at: row.get(6)?,
+        })
+    }
+
+    /// Read an API key from the columns
+    /// `id, name, scope, tags, namespaces, created_at`.
+    fn row_to_api_key(row: &rusqlite::Row) -> rusqlite::Result<ApiKey> {
+        let scope: String = row.get(2)?;
+        let scope = scope.parse().map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, Box::new(e))
+        })?;
+        Ok(ApiKey {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            scope,
+            tags: Self::parse_references(row.get(3)?),
+            namespaces: Self::parse_references(row.get(4)?),
+            created_at: row.get(5)?,
+        })
+    }
+
+    fn is_duplicate_column(e: &rusqlite::Error) -> bool {
+        e.to_string().contains("duplicate column name")
+    }
 }
 
 #[async_trait::async_trait(?Send)]
 impl Database for SqliteDatabase {
+    fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    fn with_namespace(&self, namespace: &str) -> Result<Self, Error> {
+        Ok(SqliteDatabase {
+            conn: Arc::clone(&self.conn),
+            namespace: namespace.to_string(),
+        })
+    }
+
+    async fn list_namespaces(&self) -> Result<Vec<NamespaceCount>, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT namespace, COUNT(*) FROM notes
+                 WHERE deleted_at IS NULL
+                 GROUP BY namespace
+                 ORDER BY namespace",
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let namespaces = stmt
+            .query_map([], |row| {
+                Ok(NamespaceCount {
+                    name: row.get(0)?,
+                    count: row.get(1)?,
+                })
+            })
+            .map_err(|e| Error::Database(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(namespaces)
+    }
+
     async fn add_note(&self, note: CreateNote) -> Result<i64, Error> {
         let conn = self.conn.lock().unwrap();
 
         let refs_json = Self::serialize_references(&note.references);
 
+        // Take the namespace's next ID
+        let id: i64 = conn
+            .query_row(
+                "INSERT INTO note_ids (namespace, last_id) VALUES (?1, 1)
+                 ON CONFLICT (namespace) DO UPDATE SET last_id = last_id + 1
+                 RETURNING last_id",
+                params![self.namespace],
+                |row| row.get(0),
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+
         // Insert the note
         conn.execute(
-            "INSERT INTO notes (title, body, \"references\") VALUES (?1, ?2, ?3)",
-            params![note.title, note.body, refs_json],
+            "INSERT INTO notes (id, title, body, \"references\", namespace)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id, note.title, note.body, refs_json, self.namespace],
         )
         .map_err(|e| Error::Database(e.to_string()))?;
 
-        let note_id = conn.last_insert_rowid();
+        let row_id = conn.last_insert_rowid();
+        Self::set_tags(&conn, row_id, &self.namespace, &note.tags)?;
 
-        // Insert tags
-        for tag in &note.tags {
-            conn.execute(
-                "INSERT INTO tags (name) VALUES (?1) ON CONFLICT (name) DO NOTHING",
-                params![tag],
-            )
-            .map_err(|e| Error::Database(e.to_string()))?;
+        Ok(id)
+    }
 
-            conn.execute(
-                "INSERT INTO note_tags (note_id, tag_id) SELECT ?1, id FROM tags WHERE name = ?2",
-                params![note_id, tag],
+    async fn put_note(&self, note: &Note) -> Result<(), Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let refs_json = Self::serialize_references(&note.references);
+
+        // New notes mustn't take this note's ID
+        conn.execute(
+            "INSERT INTO note_ids (namespace, last_id) VALUES (?1, ?2)
+             ON CONFLICT (namespace) DO UPDATE SET last_id = MAX(last_id, excluded.last_id)",
+            params![self.namespace, note.id],
+        )
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+        let row_id: i64 = conn
+            .query_row(
+                "INSERT INTO notes (id, title, body, \"references\", namespace, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT (namespace, id) DO UPDATE SET
+                     title = excluded.title,
+                     body = excluded.body,
+                     \"references\" = excluded.\"references\",
+                     created_at = excluded.created_at,
+                     updated_at = excluded.updated_at,
+                     deleted_at = NULL
+                 RETURNING row_id",
+                params![
+                    note.id,
+                    note.title,
+                    note.body,
+                    refs_json,
+                    self.namespace,
+                    note.created_at,
+                    note.updated_at
+                ],
+                |row| row.get(0),
             )
             .map_err(|e| Error::Database(e.to_string()))?;
-        }
 
-        Ok(note_id)
+        Self::set_tags(&conn, row_id, &self.namespace, &note.tags)
     }
 
     async fn get_note(&self, id: i64) -> Result<Option<Note>, Error> {
@@ -161,23 +432,14 @@ impl Database for SqliteDatabase {
 
         let note = conn
             .query_row(
-                "SELECT n.id, n.title, n.body, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
+                "SELECT n.id, n.title, n.body, n.created_at, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
                  FROM notes n
-                 LEFT JOIN note_tags nt ON n.id = nt.note_id
+                 LEFT JOIN note_tags nt ON n.row_id = nt.note_id
                  LEFT JOIN tags t ON nt.tag_id = t.id
-                 WHERE n.id = ?1
-                 GROUP BY n.id",
-                params![id],
-                |row| {
-                    Ok(Note {
-                        id: row.get(0)?,
-                        title: row.get(1)?,
-                        body: row.get(2)?,
-                        updated_at: row.get(3)?,
-                        references: Self::parse_references(row.get(4)?),
-                        tags: Self::parse_tags(row.get(5)?),
-                    })
-                },
+                 WHERE n.id = ?1 AND n.namespace = ?2 AND n.deleted_at IS NULL
+                 GROUP BY n.row_id",
+                params![id, self.namespace],
+                Self::row_to_note,
             )
             .optional()
             .map_err(|e| Error::Database(e.to_string()))?;
@@ -189,48 +451,20 @@ impl Database for SqliteDatabase {
         let conn = self.conn.lock().unwrap();
 
         let mut sql = String::from(
-            "SELECT n.id, n.title, n.body, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
+            "SELECT n.id, n.title, n.body, n.created_at, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
              FROM notes n
-             LEFT JOIN note_tags nt ON n.id = nt.note_id
+             LEFT JOIN note_tags nt ON n.row_id = nt.note_id
              LEFT JOIN tags t ON nt.tag_id = t.id",
         );
 
-        let mut conditions = Vec::new();
-        let mut params_vec: Vec<String> = Vec::new();
-
-        if let Some(ref tags) = query.tags {
-            if !tags.is_empty() {
-                let placeholders: Vec<_> = (0..tags.len()).map(|i| format!("?{}", i + 1)).collect();
-                conditions.push(format!(
-                    "n.id IN (SELECT note_id FROM note_tags nt2 
-                              JOIN tags t2 ON nt2.tag_id = t2.id 
-                              WHERE t2.name IN ({}))",
-                    placeholders.join(",")
-                ));
-                params_vec.extend(tags.clone());
-            }
-        }
-
-        if let Some(ref from) = query.from {
-            conditions.push(format!("n.updated_at >= ?{}", params_vec.len() + 1));
-            params_vec.push(from.clone());
-        }
-
-        if let Some(ref to) = query.to {
-            conditions.push(format!("n.updated_at <= ?{}", params_vec.len() + 1));
-            params_vec.push(to.clone());
-        }
-
-        if !conditions.is_empty() {
-            sql.push_str(" WHERE ");
-            sql.push_str(&conditions.join(" AND "));
-        }
+        let mut params_vec: Vec<String> = vec![self.namespace.clone()];
 
-        sql.push_str(" GROUP BY n.id ORDER BY n.updated_at DESC, n.id DESC");
+        sql.push_str(" WHERE n.namespace = ?1 AND n.deleted_at IS NULL AND ");
+        sql.push_str(&query.to_sql(&mut params_vec));
 
-        if let Some(limit) = query.limit {
-            sql.push_str(&format!(" LIMIT {}", limit));
-        }
+        sql.push_str(" GROUP BY n.row_id ORDER BY ");
+        sql.push_str(&query.order_by_sql());
+        sql.push_str(&query.limit_sql());
 
         let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
             .iter()
@@ -242,16 +476,7 @@ impl Database for SqliteDatabase {
             .map_err(|e| Error::Database(e.to_string()))?;
 
         let notes = stmt
-            .query_map(params_refs.as_slice(), |row| {
-                Ok(Note {
-                    id: row.get(0)?,
-                    title: row.get(1)?,
-                    body: row.get(2)?,
-                    updated_at: row.get(3)?,
-                    references: Self::parse_references(row.get(4)?),
-                    tags: Self::parse_tags(row.get(5)?),
-                })
-            })
+            .query_map(params_refs.as_slice(), Self::row_to_note)
             .map_err(|e| Error::Database(e.to_string()))?
             .collect::<Result<Vec<_>, _>>()
             .map_err(|e| Error::Database(e.to_string()))?;
@@ -264,36 +489,10 @@ impl Database for SqliteDatabase {
 
         let mut sql = String::from("SELECT COUNT(DISTINCT n.id) FROM notes n");
 
-        let mut conditions = Vec::new();
-        let mut params_vec: Vec<String> = Vec::new();
-
-        if let Some(ref tags) = query.tags {
-            if !tags.is_empty() {
-                let placeholders: Vec<_> = (0..tags.len()).map(|i| format!("?{}", i + 1)).collect();
-                conditions.push(format!(
-                    "n.id IN (SELECT note_id FROM note_tags nt2 
-                              JOIN tags t2 ON nt2.tag_id = t2.id 
-                              WHERE t2.name IN ({}))",
-                    placeholders.join(",")
-                ));
-                params_vec.extend(tags.clone());
-            }
-        }
-
-        if let Some(ref from) = query.from {
-            conditions.push(format!("n.updated_at >= ?{}", params_vec.len() + 1));
-            params_vec.push(from.clone());
-        }
-
-        if let Some(ref to) = query.to {
-            conditions.push(format!("n.updated_at <= ?{}", params_vec.len() + 1));
-            params_vec.push(to.clone());
-        }
+        let mut params_vec: Vec<String> = vec![self.namespace.clone()];
 
-        if !conditions.is_empty() {
-            sql.push_str(" WHERE ");
-            sql.push_str(&conditions.join(" AND "));
-        }
+        sql.push_str(" WHERE n.namespace = ?1 AND n.deleted_at IS NULL AND ");
+        sql.push_str(&query.to_sql(&mut params_vec));
 
         let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
             .iter()
@@ -310,24 +509,42 @@ impl Database for SqliteDatabase {
     async fn update_note(&self, id: i64, update: UpdateNote) -> Result<bool, Error> {
         let conn = self.conn.lock().unwrap();
 
-        // Check if note exists
-        let exists: bool = conn
-            .query_row("SELECT 1 FROM notes WHERE id = ?1", params![id], |_| {
-                Ok(true)
-            })
+        let row_id: Option<i64> = conn
+            .query_row(
+                "SELECT row_id FROM notes WHERE id = ?1 AND namespace = ?2 AND deleted_at IS NULL",
+                params![id, self.namespace],
+                |row| row.get(0),
+            )
             .optional()
-            .map_err(|e| Error::Database(e.to_string()))?
-            .unwrap_or(false);
+            .map_err(|e| Error::Database(e.to_string()))?;
 
-        if !exists {
+        let Some(row_id) = row_id else {
             return Ok(false);
-        }
+        };
+
+        // Save the current version as a revision before changing anything
+        conn.execute(
+            "INSERT INTO note_revisions (note_id, revision, title, body, tags, \"references\", updated_at)
+             SELECT n.row_id,
+                    COALESCE((SELECT MAX(r.revision) FROM note_revisions r WHERE r.note_id = n.row_id), 0) + 1,
+                    n.title,
+                    n.body,
+                    (SELECT json_group_array(t.name) FROM note_tags nt
+                     JOIN tags t ON nt.tag_id = t.id
+                     WHERE nt.note_id = n.row_id),
+                    n.\"references\",
+                    n.updated_at
+             FROM notes n
+             WHERE n.row_id = ?1",
+            params![row_id],
+        )
+        .map_err(|e| Error::Database(e.to_string()))?;
 
         // Update title if provided
         if let Some(ref title) = update.title {
             conn.execute(
-                "UPDATE notes SET title = ?1, updated_at = datetime('now') WHERE id = ?2",
-                params![title, id],
+                "UPDATE notes SET title = ?1, updated_at = datetime('now') WHERE row_id = ?2",
+                params![title, row_id],
             )
             .map_err(|e| Error::Database(e.to_string()))?;
         }
@@ -335,37 +552,20 @@ impl Database for SqliteDatabase {
         // Update body if provided
         if let Some(ref body) = update.body {
             conn.execute(
-                "UPDATE notes SET body = ?1, updated_at = datetime('now') WHERE id = ?2",
-                params![body, id],
+                "UPDATE notes SET body = ?1, updated_at = datetime('now') WHERE row_id = ?2",
+                params![body, row_id],
             )
             .map_err(|e| Error::Database(e.to_string()))?;
         }
 
         // Update tags if provided
         if let Some(ref tags) = update.tags {
-            // Delete existing tags
-            conn.execute("DELETE FROM note_tags WHERE note_id = ?1", params![id])
-                .map_err(|e| Error::Database(e.to_string()))?;
-
-            // Insert new tags
-            for tag in tags {
-                conn.execute(
-                    "INSERT INTO tags (name) VALUES (?1) ON CONFLICT (name) DO NOTHING",
-                    params![tag],
-                )
-                .map_err(|e| Error::Database(e.to_string()))?;
-
-                conn.execute(
-                    "INSERT INTO note_tags (note_id, tag_id) SELECT ?1, id FROM tags WHERE name = ?2",
-                    params![id, tag],
-                )
-                .map_err(|e| Error::Database(e.to_string()))?;
-            }
+            Self::set_tags(&conn, row_id, &self.namespace, tags)?;
 
             // Update timestamp
             conn.execute(
-                "UPDATE notes SET updated_at = datetime('now') WHERE id = ?1",
-                params![id],
+                "UPDATE notes SET updated_at = datetime('now') WHERE row_id = ?1",
+                params![row_id],
             )
             .map_err(|e| Error::Database(e.to_string()))?;
         }
@@ -374,8 +574,8 @@ impl Database for SqliteDatabase {
         if let Some(ref references) = update.references {
             let refs_json = Self::serialize_references(references);
             conn.execute(
-                "UPDATE notes SET \"references\" = ?1, updated_at = datetime('now') WHERE id = ?2",
-                params![refs_json, id],
+                "UPDATE notes SET \"references\" = ?1, updated_at = datetime('now') WHERE row_id = ?2",
+                params![refs_json, row_id],
             )
             .map_err(|e| Error::Database(e.to_string()))?;
         }
@@ -387,20 +587,165 @@ impl Database for SqliteDatabase {
         let conn = self.conn.lock().unwrap();
 
         let rows = conn
-            .execute("DELETE FROM notes WHERE id = ?1", params![id])
+            .execute(
+                "UPDATE notes SET deleted_at = datetime('now')
+                 WHERE id = ?1 AND namespace = ?2 AND deleted_at IS NULL",
+                params![id, self.namespace],
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(rows > 0)
+    }
+
+    async fn list_trash(&self) -> Result<Vec<TrashedNote>, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT n.id, n.title, n.body, n.created_at, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags, n.deleted_at
+                 FROM notes n
+                 LEFT JOIN note_tags nt ON n.row_id = nt.note_id
+                 LEFT JOIN tags t ON nt.tag_id = t.id
+                 WHERE n.namespace = ?1 AND n.deleted_at IS NOT NULL
+                 GROUP BY n.row_id
+                 ORDER BY n.deleted_at DESC, n.id DESC",
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let notes = stmt
+            .query_map(params![self.namespace], |row| {
+                Ok(TrashedNote {
+                    note: Self::row_to_note(row)?,
+                    deleted_at: row.get(7)?,
+                })
+            })
+            .map_err(|e| Error::Database(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(notes)
+    }
+
+    async fn restore_note(&self, id: i64) -> Result<bool, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let rows = conn
+            .execute(
+                "UPDATE notes SET deleted_at = NULL
+                 WHERE id = ?1 AND namespace = ?2 AND deleted_at IS NOT NULL",
+                params![id, self.namespace],
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(rows > 0)
+    }
+
+    async fn purge_note(&self, id: i64) -> Result<bool, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        // Tags, revisions and links are removed by ON DELETE CASCADE
+        let rows = conn
+            .execute(
+                "DELETE FROM notes WHERE id = ?1 AND namespace = ?2 AND deleted_at IS NOT NULL",
+                params![id, self.namespace],
+            )
             .map_err(|e| Error::Database(e.to_string()))?;
 
         Ok(rows > 0)
     }
 
+    async fn push_operation(&self, operation: &Operation) -> Result<(), Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let json = serde_json::to_string(operation).map_err(|e| Error::Internal(e.to_string()))?;
+        conn.execute(
+            "INSERT INTO journal (operation, namespace) VALUES (?1, ?2)",
+            params![json, self.namespace],
+        )
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+        conn.execute(
+            "DELETE FROM journal WHERE namespace = ?1 AND id NOT IN
+             (SELECT id FROM journal WHERE namespace = ?1 ORDER BY id DESC LIMIT ?2)",
+            params![self.namespace, JOURNAL_LIMIT as i64],
+        )
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn pop_operation(&self) -> Result<Option<Operation>, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let entry: Option<(i64, String)> = conn
+            .query_row(
+                "SELECT id, operation FROM journal WHERE namespace = ?1 ORDER BY id DESC LIMIT 1",
+                params![self.namespace],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let Some((id, json)) = entry else {
+            return Ok(None);
+        };
+
+        conn.execute("DELETE FROM journal WHERE id = ?1", params![id])
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let operation = serde_json::from_str(&json)
+            .map_err(|e| Error::Internal(format!("invalid journal entry: {}", e)))?;
+        Ok(Some(operation))
+    }
+
+    async fn list_revisions(&self, note_id: i64) -> Result<Vec<Revision>, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT n.id, r.revision, r.title, r.body, r.tags, r.\"references\", r.updated_at
+                 FROM note_revisions r
+                 JOIN notes n ON n.row_id = r.note_id
+                 WHERE n.id = ?1 AND n.namespace = ?2
+                 ORDER BY r.revision DESC",
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let revisions = stmt
+            .query_map(params![note_id, self.namespace], Self::row_to_revision)
+            .map_err(|e| Error::Database(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(revisions)
+    }
+
+    async fn get_revision(&self, note_id: i64, revision: i64) -> Result<Option<Revision>, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.query_row(
+            "SELECT n.id, r.revision, r.title, r.body, r.tags, r.\"references\", r.updated_at
+             FROM note_revisions r
+             JOIN notes n ON n.row_id = r.note_id
+             WHERE n.id = ?1 AND r.revision = ?2 AND n.namespace = ?3",
+            params![note_id, revision, self.namespace],
+            Self::row_to_revision,
+        )
+        .optional()
+        .map_err(|e| Error::Database(e.to_string()))
+    }
+
     async fn list_tags(&self) -> Result<Vec<TagCount>, Error> {
         let conn = self.conn.lock().unwrap();
 
         let mut stmt = conn
             .prepare(
-                "SELECT t.name, COUNT(nt.note_id) as count
+                "SELECT t.name, COUNT(n.row_id) as count, d.description
                  FROM tags t
                  LEFT JOIN note_tags nt ON t.id = nt.tag_id
+                 LEFT JOIN notes n ON nt.note_id = n.row_id AND n.deleted_at IS NULL
+                 LEFT JOIN tag_descriptions d ON d.namespace = t.namespace AND d.name = t.name
+                 WHERE t.namespace = ?1
                  GROUP BY t.id
                  HAVING count > 0
                  ORDER BY count DESC, t.name",
@@ -408,10 +753,11 @@ impl Database for SqliteDatabase {
             .map_err(|e| Error::Database(e.to_string()))?;
 
         let tags = stmt
-            .query_map([], |row| {
+            .query_map(params![self.namespace], |row| {
                 Ok(TagCount {
                     name: row.get(0)?,
                     count: row.get(1)?,
+                    description: row.get(2)?,
                 })
             })
             .map_err(|e| Error::Database(e.to_string()))?
@@ -421,10 +767,162 @@ impl Database for SqliteDatabase {
         Ok(tags)
     }
 
+    async fn merge_tags(&self, sources: &[String], target: &str) -> Result<Vec<i64>, Error> {
+        self.retag(sources, Some(target))
+    }
+
+    async fn remove_tag(&self, tag: &str) -> Result<Vec<i64>, Error> {
+        self.retag(&[tag.to_string()], None)
+    }
+
+    async fn set_tag_description(
+        &self,
+        tag: &str,
+        description: Option<&str>,
+    ) -> Result<bool, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let tagged: bool = conn
+            .query_row(
+                "SELECT EXISTS (
+                     SELECT 1 FROM notes n
+                     JOIN note_tags nt ON nt.note_id = n.row_id
+                     JOIN tags t ON nt.tag_id = t.id
+                     WHERE n.namespace = ?1 AND n.deleted_at IS NULL AND t.name = ?2
+                 )",
+                params![self.namespace, tag],
+                |row| row.get(0),
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+        if !tagged {
+            return Ok(false);
+        }
+
+        match description {
+            Some(description) => conn.execute(
+                "INSERT INTO tag_descriptions (namespace, name, description) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (namespace, name) DO UPDATE SET description = excluded.description",
+                params![self.namespace, tag, description],
+            ),
+            None => conn.execute(
+                "DELETE FROM tag_descriptions WHERE namespace = ?1 AND name = ?2",
+                params![self.namespace, tag],
+            ),
+        }
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(true)
+    }
+
+    async fn list_tag_aliases(&self) -> Result<Vec<TagAlias>, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn
+            .prepare("SELECT alias, tag FROM tag_aliases WHERE namespace = ?1 ORDER BY alias")
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let aliases = stmt
+            .query_map(params![self.namespace], |row| {
+                Ok(TagAlias {
+                    alias: row.get(0)?,
+                    tag: row.get(1)?,
+                })
+            })
+            .map_err(|e| Error::Database(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(aliases)
+    }
+
+    async fn set_tag_alias(&self, alias: &str, tag: Option<&str>) -> Result<bool, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let changed = match tag {
+            Some(tag) => conn.execute(
+                "INSERT INTO tag_aliases (namespace, alias, tag) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (namespace, alias) DO UPDATE SET tag = excluded.tag",
+                params![self.namespace, alias, tag],
+            ),
+            None => conn.execute(
+                "DELETE FROM tag_aliases WHERE namespace = ?1 AND alias = ?2",
+                params![self.namespace, alias],
+            ),
+        }
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(changed > 0)
+    }
+
+    async fn list_links(&self, id: i64) -> Result<Vec<NoteLink>, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT f.id, t.id, l.kind
+                 FROM note_links l
+                 JOIN notes f ON f.row_id = l.from_id
+                 JOIN notes t ON t.row_id = l.to_id
+                 WHERE (f.id = ?1 OR t.id = ?1)
+                   AND f.namespace = ?2 AND f.deleted_at IS NULL
+                   AND t.namespace = ?2 AND t.deleted_at IS NULL
+                 ORDER BY f.id, t.id, l.kind",
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let rows = stmt
+            .query_map(params![id, self.namespace], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?))
+            })
+            .map_err(|e| Error::Database(e.to_string()))?
+            .collect::<Result<Vec<(i64, i64, String)>, _>>()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        rows.into_iter()
+            .map(|(from, to, kind)| {
+                Ok(NoteLink {
+                    from,
+                    to,
+                    kind: kind.parse()?,
+                })
+            })
+            .collect()
+    }
+
+    async fn add_link(&self, link: &NoteLink) -> Result<bool, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let rows = conn
+            .execute(
+                "INSERT OR IGNORE INTO note_links (from_id, to_id, kind)
+                 SELECT f.row_id, t.row_id, ?3 FROM notes f, notes t
+                 WHERE f.namespace = ?4 AND f.id = ?1 AND t.namespace = ?4 AND t.id = ?2",
+                params![link.from, link.to, link.kind.to_string(), self.namespace],
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(rows > 0)
+    }
+
+    async fn remove_link(&self, from: i64, to: i64, kind: LinkKind) -> Result<bool, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let rows = conn
+            .execute(
+                "DELETE FROM note_links WHERE kind = ?3
+                 AND from_id = (SELECT row_id FROM notes WHERE namespace = ?4 AND id = ?1)
+                 AND to_id = (SELECT row_id FROM notes WHERE namespace = ?4 AND id = ?2)",
+                params![from, to, kind.to_string(), self.namespace],
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(rows > 0)
+    }
+
     async fn grep(
         &self,
         pattern: &str,
-        tags: Option<&[String]>,
+        query: &NoteQuery,
         case_sensitive: bool,
     ) -> Result<Vec<Note>, Error> {
         let conn = self.conn.lock().unwrap();
@@ -437,31 +935,20 @@ impl Database for SqliteDatabase {
                 .map_err(|e| Error::Validation(format!("invalid regex: {}", e)))?
         };
 
-        // Query all notes (with tag filter if provided)
+        // Query all notes matching the filters
         let mut sql = String::from(
-            "SELECT n.id, n.title, n.body, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
+            "SELECT n.id, n.title, n.body, n.created_at, n.updated_at, n.\"references\", GROUP_CONCAT(t.name) as tags
              FROM notes n
-             LEFT JOIN note_tags nt ON n.id = nt.note_id
+             LEFT JOIN note_tags nt ON n.row_id = nt.note_id
              LEFT JOIN tags t ON nt.tag_id = t.id",
         );
 
-        let mut params_vec: Vec<String> = Vec::new();
-
-        if let Some(tag_list) = tags {
-            if !tag_list.is_empty() {
-                let placeholders: Vec<_> =
-                    (0..tag_list.len()).map(|i| format!("?{}", i + 1)).collect();
-                sql.push_str(&format!(
-                    " WHERE n.id IN (SELECT note_id FROM note_tags nt2 
-                                     JOIN tags t2 ON nt2.tag_id = t2.id 
-                                     WHERE t2.name IN ({}))",
-                    placeholders.join(",")
-                ));
-                params_vec.extend(tag_list.iter().cloned());
-            }
-        }
+        let mut params_vec: Vec<String> = vec![self.namespace.clone()];
 
-        sql.push_str(" GROUP BY n.id ORDER BY n.updated_at DESC, n.id DESC");
+        sql.push_str(" WHERE n.namespace = ?1 AND n.deleted_at IS NULL AND ");
+        sql.push_str(&query.to_sql(&mut params_vec));
+        sql.push_str(" GROUP BY n.row_id ORDER BY ");
+        sql.push_str(&query.order_by_sql());
 
         let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
             .iter()
@@ -473,26 +960,1095 @@ impl Database for SqliteDatabase {
             .map_err(|e| Error::Database(e.to_string()))?;
 
         let all_notes: Vec<Note> = stmt
-            .query_map(params_refs.as_slice(), |row| {
-                Ok(Note {
-                    id: row.get(0)?,
-                    title: row.get(1)?,
-                    body: row.get(2)?,
-                    updated_at: row.get(3)?,
-                    references: Self::parse_references(row.get(4)?),
-                    tags: Self::parse_tags(row.get(5)?),
-                })
-            })
+            .query_map(params_refs.as_slice(), Self::row_to_note)
             .map_err(|e| Error::Database(e.to_string()))?
             .collect::<Result<Vec<_>, _>>()
             .map_err(|e| Error::Database(e.to_string()))?;
 
         // Filter by regex
-        let matching: Vec<Note> = all_notes
+        let mut matching: Vec<Note> = all_notes
             .into_iter()
             .filter(|note| regex.is_match(&note.title) || regex.is_match(&note.body))
             .collect();
 
+        query.paginate(&mut matching);
+
         Ok(matching)
     }
+
+    async fn search(
+        &self,
+        query: &str,
+        tags: Option<&TagExpr>,
+        limit: Option<i64>,
+    ) -> Result<Vec<SearchHit>, Error> {
+        let match_expr = match fts5_query(query) {
+            Some(expr) => expr,
+            None => return Ok(Vec::new()),
+        };
+
+        let conn = self.conn.lock().unwrap();
+
+        // bm25() returns lower-is-better scores, so negate it
+        let mut sql = format!(
+            "SELECT n.id, n.title, n.body, n.created_at, n.updated_at, n.\"references\",
+                    (SELECT GROUP_CONCAT(t.name) FROM note_tags nt
+                     JOIN tags t ON nt.tag_id = t.id
+                     WHERE nt.note_id = n.row_id) as tags,
+                    -bm25(notes_fts, {}, {}) as score
+             FROM notes_fts
+             JOIN notes n ON n.row_id = notes_fts.rowid
+             WHERE notes_fts MATCH ?1 AND n.namespace = ?2 AND n.deleted_at IS NULL",
+            TITLE_WEIGHT, BODY_WEIGHT
+        );
+
+        let mut params_vec: Vec<String> = vec![match_expr, self.namespace.clone()];
+
+        if let Some(tags) = tags {
+            sql.push_str(" AND ");
+            sql.push_str(&tags.to_sql(&mut params_vec));
+        }
+
+        sql.push_str(" ORDER BY score DESC, n.id DESC");
+
+        if let Some(limit) = limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec
+            .iter()
+            .map(|p| p as &dyn rusqlite::ToSql)
+            .collect();
+
+        let mut stmt = conn
+            .prepare(&sql)
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let hits = stmt
+            .query_map(params_refs.as_slice(), |row| {
+                Ok(SearchHit {
+                    note: Self::row_to_note(row)?,
+                    score: row.get(7)?,
+                })
+            })
+            .map_err(|e| Error::Database(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(hits)
+    }
+
+    async fn add_api_key(&self, key: CreateApiKey) -> Result<ApiKey, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let tags_json = Self::serialize_references(&key.tags);
+        let namespaces_json = Self::serialize_references(&key.namespaces);
+        conn.query_row(
+            "INSERT INTO api_keys (name, key_hash, scope, tags, namespaces)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             RETURNING id, name, scope, tags, namespaces, created_at",
+            params![
+                key.name,
+                key.key_hash,
+                key.scope.to_string(),
+                tags_json,
+                namespaces_json
+            ],
+            Self::row_to_api_key,
+        )
+        .map_err(|e| Error::Database(e.to_string()))
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.query_row(
+            "SELECT id, name, scope, tags, namespaces, created_at FROM api_keys WHERE key_hash = ?1",
+            params![key_hash],
+            Self::row_to_api_key,
+        )
+        .optional()
+        .map_err(|e| Error::Database(e.to_string()))
+    }
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, name, scope, tags, namespaces, created_at FROM api_keys ORDER BY id",
+            )
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let keys = stmt
+            .query_map([], Self::row_to_api_key)
+            .map_err(|e| Error::Database(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        Ok(keys)
+    }
+
+    async fn revoke_api_key(&self, id: i64) -> Result<bool, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        let changed = conn
+            .execute("DELETE FROM api_keys WHERE id = ?1", params![id])
+            .map_err(|e| Error::Database(e.to_string()))?;
+        Ok(changed > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use veta_core::{Cursor, ImportMode, SortOrder, TimeField, VetaService};
+
+    async fn add(db: &SqliteDatabase, title: &str, body: &str, tags: &[&str]) -> i64 {
+        db.add_note(CreateNote {
+            title: title.to_string(),
+            body: body.to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            references: vec![],
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_search_ranks_by_relevance() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+
+        let body_match = add(
+            &db,
+            "Session handling",
+            "Sessions are refreshed with a JWT before they expire",
+            &["auth"],
+        )
+        .await;
+        let title_match = add(
+            &db,
+            "JWT expiry",
+            "Tokens expire after 15 minutes",
+            &["auth"],
+        )
+        .await;
+        add(&db, "Unrelated", "Nothing to see here", &["misc"]).await;
+
+        let hits = db.search("jwt", None, None).await.unwrap();
+        let ids: Vec<i64> = hits.iter().map(|h| h.note.id).collect();
+        assert_eq!(ids, vec![title_match, body_match]);
+        assert!(hits[0].score > hits[1].score);
+        assert_eq!(hits[0].note.tags, vec!["auth"]);
+
+        let hits = db.search("jwt minutes", None, None).await.unwrap();
+        assert_eq!(hits.len(), 1);
+
+        let hits = db
+            .search("jwt", Some(&TagExpr::any_of(["misc"])), None)
+            .await
+            .unwrap();
+        assert!(hits.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_follows_updates_and_deletes() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+
+        let id = add(&db, "Deploy", "Uses wrangler", &[]).await;
+        db.update_note(
+            id,
+            UpdateNote {
+                body: Some("Uses terraform".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(db.search("wrangler", None, None).await.unwrap().is_empty());
+        assert_eq!(db.search("terraform", None, None).await.unwrap().len(), 1);
+
+        db.delete_note(id).await.unwrap();
+        assert!(db.search("terraform", None, None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_structured_query_matches_core_semantics() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+
+        db.add_note(CreateNote {
+            title: "JWT handling".to_string(),
+            body: "Token expiry is 15 minutes".to_string(),
+            tags: vec!["auth".to_string()],
+            references: vec!["src/auth.rs:10".to_string()],
+        })
+        .await
+        .unwrap();
+        add(
+            &db,
+            "Old JWT notes",
+            "Token expiry was 5 minutes",
+            &["auth", "old"],
+        )
+        .await;
+        add(&db, "Deploy", "Uses wrangler", &["ops"]).await;
+
+        let all = db.list_notes(NoteQuery::default()).await.unwrap();
+        for input in [
+            r#"title:jwt tag:auth -tag:old ref:src/auth.rs "token expiry""#,
+            "title:JWT",
+            "TOKEN",
+            "-tag:auth",
+            "ref:auth",
+            "ref:AUTH",
+            "updated:>2000-01-01 updated:<2100-01-01",
+            "updated:<2000-01-01",
+        ] {
+            let query = veta_core::parse_query(input).unwrap().query;
+            let expected: Vec<i64> = all
+                .iter()
+                .filter(|n| query.matches(n))
+                .map(|n| n.id)
+                .collect();
+            let ids: Vec<i64> = db
+                .list_notes(query.clone())
+                .await
+                .unwrap()
+                .iter()
+                .map(|n| n.id)
+                .collect();
+            assert_eq!(ids, expected, "query {:?}", input);
+            assert_eq!(
+                db.count_notes(query).await.unwrap(),
+                expected.len() as i64,
+                "query {:?}",
+                input
+            );
+        }
+
+        let query = veta_core::parse_query("tag:auth").unwrap().query;
+        let notes = db.grep("minutes", &query, false).await.unwrap();
+        assert_eq!(notes.len(), 2);
+        let notes = db
+            .grep(
+                "minutes",
+                &NoteQuery {
+                    limit: Some(1),
+                    ..query
+                },
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(notes.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_update_saves_revisions() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+
+        let id = add(&db, "Deploy", "Uses wrangler", &["ops", "cloudflare"]).await;
+        db.update_note(
+            id,
+            UpdateNote {
+                body: Some("Uses terraform".to_string()),
+                tags: Some(vec!["ops".to_string()]),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        db.update_note(
+            id,
+            UpdateNote {
+                title: Some("Deployment".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let revisions = db.list_revisions(id).await.unwrap();
+        let numbers: Vec<i64> = revisions.iter().map(|r| r.revision).collect();
+        assert_eq!(numbers, vec![2, 1]);
+
+        let first = db.get_revision(id, 1).await.unwrap().unwrap();
+        assert_eq!(first.title, "Deploy");
+        assert_eq!(first.body, "Uses wrangler");
+        assert_eq!(first.tags, vec!["cloudflare", "ops"]);
+        assert_eq!(revisions[0].body, "Uses terraform");
+        assert_eq!(revisions[0].tags, vec!["ops"]);
+
+        assert!(db.get_revision(id, 3).await.unwrap().is_none());
+
+        db.delete_note(id).await.unwrap();
+        assert_eq!(db.list_revisions(id).await.unwrap().len(), 2);
+        db.purge_note(id).await.unwrap();
+        assert!(db.list_revisions(id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sort_by_created_at() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+
+        let first = add(&db, "First", "Created first, edited last", &[]).await;
+        let second = add(&db, "Second", "Created second", &[]).await;
+        db.conn
+            .lock()
+            .unwrap()
+            .execute_batch(
+                "UPDATE notes SET created_at = '2026-01-01 00:00:00', updated_at = '2026-03-01 00:00:00' WHERE id = 1;
+                 UPDATE notes SET created_at = '2026-02-01 00:00:00', updated_at = '2026-02-01 00:00:00' WHERE id = 2;",
+            )
+            .unwrap();
+
+        let ids = |notes: Vec<Note>| notes.iter().map(|n| n.id).collect::<Vec<_>>();
+        let by_created = NoteQuery {
+            time_field: TimeField::Created,
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(db.list_notes(NoteQuery::default()).await.unwrap()),
+            vec![first, second]
+        );
+        assert_eq!(
+            ids(db.list_notes(by_created.clone()).await.unwrap()),
+            vec![second, first]
+        );
+        assert_eq!(
+            ids(db
+                .list_notes(NoteQuery {
+                    order: SortOrder::Asc,
+                    from: Some("2026-01-15 00:00:00".to_string()),
+                    ..by_created
+                })
+                .await
+                .unwrap()),
+            vec![second]
+        );
+
+        // Updates leave the creation time alone
+        db.update_note(
+            first,
+            UpdateNote {
+                body: Some("Edited again".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let note = db.get_note(first).await.unwrap().unwrap();
+        assert_eq!(note.created_at, "2026-01-01 00:00:00");
+        assert_ne!(note.updated_at, "2026-03-01 00:00:00");
+    }
+
+    #[tokio::test]
+    async fn test_links() {
+        let service = VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+        let team = service.in_namespace("team").unwrap();
+        let add = |title: &str| service.add_note(title.into(), "".into(), vec![], vec![]);
+        let a = add("New").await.unwrap();
+        let b = add("Old").await.unwrap();
+        let c = add("Related").await.unwrap();
+        // An ID that only the other namespace has
+        let mut other = 0;
+        for _ in 0..4 {
+            other = team
+                .add_note("Theirs".into(), "".into(), vec![], vec![])
+                .await
+                .unwrap();
+        }
+
+        assert!(service
+            .link_notes(a, b, LinkKind::Supersedes)
+            .await
+            .unwrap());
+        assert!(!service
+            .link_notes(a, b, LinkKind::Supersedes)
+            .await
+            .unwrap());
+        service.link_notes(c, a, LinkKind::RelatesTo).await.unwrap();
+        assert!(matches!(
+            service.link_notes(a, a, LinkKind::RelatesTo).await,
+            Err(Error::Validation(_))
+        ));
+        assert!(matches!(
+            service.link_notes(a, other, LinkKind::RelatesTo).await,
+            Err(Error::NotFound(_))
+        ));
+        assert_eq!(
+            service.list_links(a).await.unwrap().unwrap(),
+            vec![
+                NoteLink {
+                    from: a,
+                    to: b,
+                    kind: LinkKind::Supersedes
+                },
+                NoteLink {
+                    from: c,
+                    to: a,
+                    kind: LinkKind::RelatesTo
+                },
+            ]
+        );
+        assert!(service.list_links(99).await.unwrap().is_none());
+
+        // Links to a note in the trash come back when it's restored, and go
+        // when it's purged
+        service.delete_note(b).await.unwrap();
+        assert_eq!(service.list_links(a).await.unwrap().unwrap().len(), 1);
+        service.restore_note(b).await.unwrap();
+        assert_eq!(service.list_links(a).await.unwrap().unwrap().len(), 2);
+        service.delete_note(b).await.unwrap();
+        service.purge_note(b).await.unwrap();
+        service.restore_note(b).await.unwrap();
+        assert_eq!(service.list_links(a).await.unwrap().unwrap().len(), 1);
+
+        assert_eq!(
+            service.unlink_notes(c, a, None).await.unwrap(),
+            vec![LinkKind::RelatesTo]
+        );
+        assert!(matches!(
+            service.unlink_notes(c, a, None).await,
+            Err(Error::NotFound(_))
+        ));
+        assert!(service.list_links(a).await.unwrap().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_service_pagination() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+        for i in 1..=5 {
+            add(&db, &format!("Note {}", i), "Paged", &[]).await;
+        }
+        // Notes 2-4 share a timestamp, so the cursor has to break ties by ID
+        db.conn
+            .lock()
+            .unwrap()
+            .execute_batch(
+                "UPDATE notes SET updated_at = '2026-01-01 00:00:00' WHERE id = 1;
+                 UPDATE notes SET updated_at = '2026-01-02 00:00:00' WHERE id IN (2, 3, 4);
+                 UPDATE notes SET updated_at = '2026-01-03 00:00:00' WHERE id = 5;",
+            )
+            .unwrap();
+        let service = veta_core::VetaService::new(db);
+
+        let mut ids = vec![];
+        let mut after = None;
+        loop {
+            let page = service
+                .list_notes(NoteQuery {
+                    limit: Some(2),
+                    after: after.map(|c: String| Cursor::parse(&c).unwrap()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            ids.extend(page.notes.iter().map(|n| n.id));
+            match page.next_cursor {
+                Some(cursor) => after = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(ids, vec![5, 4, 3, 2, 1]);
+
+        let page = service
+            .grep(
+                "paged",
+                NoteQuery {
+                    limit: Some(2),
+                    offset: Some(2),
+                    ..Default::default()
+                },
+                false,
+            )
+            .await
+            .unwrap();
+        let ids: Vec<i64> = page.notes.iter().map(|n| n.id).collect();
+        assert_eq!(ids, vec![3, 2]);
+        assert!(page.next_cursor.is_some());
+
+        // A cursor only makes sense for the sort it was created with
+        let cursor = Cursor::parse(&page.next_cursor.unwrap()).unwrap();
+        let err = service
+            .list_notes(NoteQuery {
+                order: SortOrder::Asc,
+                after: Some(cursor),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Validation(_)));
+    }
+
+    #[tokio::test]
+    async fn test_trash_restore_and_purge() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+
+        let id = add(&db, "Deploy", "Uses wrangler", &["ops"]).await;
+        let other = add(&db, "Other", "Still here", &["ops"]).await;
+
+        assert!(db.delete_note(id).await.unwrap());
+        assert!(!db.delete_note(id).await.unwrap());
+
+        assert!(db.get_note(id).await.unwrap().is_none());
+        let ids: Vec<i64> = db
+            .list_notes(NoteQuery::default())
+            .await
+            .unwrap()
+            .iter()
+            .map(|n| n.id)
+            .collect();
+        assert_eq!(ids, vec![other]);
+        assert_eq!(db.count_notes(NoteQuery::default()).await.unwrap(), 1);
+        assert!(db
+            .grep("wrangler", &NoteQuery::default(), false)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(db.search("wrangler", None, None).await.unwrap().is_empty());
+        assert_eq!(db.list_tags().await.unwrap()[0].count, 1);
+        assert!(!db.update_note(id, UpdateNote::default()).await.unwrap());
+
+        let trash = db.list_trash().await.unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].note.id, id);
+        assert_eq!(trash[0].note.tags, vec!["ops"]);
+
+        assert!(db.restore_note(id).await.unwrap());
+        assert!(!db.restore_note(id).await.unwrap());
+        assert_eq!(db.get_note(id).await.unwrap().unwrap().title, "Deploy");
+        assert!(db.list_trash().await.unwrap().is_empty());
+
+        // Only notes in the trash can be purged
+        assert!(!db.purge_note(id).await.unwrap());
+        db.delete_note(id).await.unwrap();
+        assert!(db.purge_note(id).await.unwrap());
+        assert!(db.list_trash().await.unwrap().is_empty());
+        assert!(!db.restore_note(id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_journal() {
+        let db = SqliteDatabase::open_in_memory().unwrap();
+
+        assert!(db.pop_operation().await.unwrap().is_none());
+        for id in 0..(JOURNAL_LIMIT as i64 + 5) {
+            db.push_operation(&Operation::Add { id }).await.unwrap();
+        }
+
+        let mut popped = Vec::new();
+        while let Some(operation) = db.pop_operation().await.unwrap() {
+            popped.push(operation);
+        }
+        assert_eq!(popped.len(), JOURNAL_LIMIT);
+        assert_eq!(
+            popped[0],
+            Operation::Add {
+                id: JOURNAL_LIMIT as i64 + 4
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_namespaces_are_isolated() {
+        let default = VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+        let team = default.in_namespace("team").unwrap();
+        assert_eq!(team.namespace(), "team");
+
+        let a = default
+            .add_note(
+                "Deploy".into(),
+                "wrangler".into(),
+                vec!["ops".into()],
+                vec![],
+            )
+            .await
+            .unwrap();
+        let b = team
+            .add_note(
+                "Deploy".into(),
+                "terraform".into(),
+                vec!["ops".into()],
+                vec![],
+            )
+            .await
+            .unwrap();
+        let c = team
+            .add_note("Auth".into(), "jwt".into(), vec!["api".into()], vec![])
+            .await
+            .unwrap();
+
+        // Each namespace numbers its notes from 1
+        assert_eq!((a, b, c), (1, 1, 2));
+        assert_eq!(default.get_note(a).await.unwrap().unwrap().body, "wrangler");
+        assert_eq!(team.get_note(b).await.unwrap().unwrap().body, "terraform");
+
+        // Notes, tags, search and counts only see their own namespace
+        assert!(default.get_note(c).await.unwrap().is_none());
+        assert_eq!(default.count_notes(NoteQuery::default()).await.unwrap(), 1);
+        assert_eq!(team.count_notes(NoteQuery::default()).await.unwrap(), 2);
+        let tags = default.list_tags().await.unwrap();
+        assert_eq!(tags.iter().map(|t| &t.name).collect::<Vec<_>>(), ["ops"]);
+        assert_eq!(tags[0].count, 1);
+        let hits = default.search("terraform", None, None).await.unwrap();
+        assert!(hits.is_empty());
+        assert!(!default.delete_note(c).await.unwrap());
+        assert!(!default.update_note(c, UpdateNote::default()).await.unwrap());
+
+        // So are tags: renaming one leaves the other namespace's alone
+        team.rename_tag("ops", "infra").await.unwrap();
+        assert_eq!(default.get_note(a).await.unwrap().unwrap().tags, ["ops"]);
+
+        // Each namespace has its own undo history
+        team.delete_note(b).await.unwrap();
+        assert!(default.list_trash().await.unwrap().is_empty());
+        assert_eq!(
+            default.undo().await.unwrap(),
+            Some(Operation::Add { id: a })
+        );
+        assert_eq!(
+            team.undo().await.unwrap(),
+            Some(Operation::Delete { ids: vec![b] })
+        );
+
+        assert_eq!(
+            team.list_namespaces().await.unwrap(),
+            vec![NamespaceCount {
+                name: "team".into(),
+                count: 2
+            }]
+        );
+        assert!(default.in_namespace("Not valid").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tag_changes() {
+        let service = VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+        let team = service.in_namespace("team").unwrap();
+        let tags = |t: &[&str]| t.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let a = service
+            .add_note(
+                "Panic".into(),
+                "".into(),
+                tags(&["debugging", "rust"]),
+                vec![],
+            )
+            .await
+            .unwrap();
+        let b = service
+            .add_note("Gdb".into(), "".into(), tags(&["debug", "tracing"]), vec![])
+            .await
+            .unwrap();
+        let c = service
+            .add_note("Trace".into(), "".into(), tags(&["debugging"]), vec![])
+            .await
+            .unwrap();
+        let other = team
+            .add_note("Panic".into(), "".into(), tags(&["debugging"]), vec![])
+            .await
+            .unwrap();
+        service.delete_note(c).await.unwrap();
+
+        service
+            .describe_tag("Debugging", " Finding bugs ")
+            .await
+            .unwrap();
+        assert!(matches!(
+            service.rename_tag("debugging", "debug").await,
+            Err(Error::Validation(_))
+        ));
+        assert!(matches!(
+            service.rename_tag("nope", "other").await,
+            Err(Error::NotFound(_))
+        ));
+
+        // Merging retags notes in the trash too, and moves the description
+        let changed = service
+            .merge_tags(&tags(&["debugging", "tracing"]), "debug")
+            .await
+            .unwrap();
+        assert_eq!(changed, vec![a, b]);
+        assert_eq!(
+            service.get_note(a).await.unwrap().unwrap().tags,
+            ["debug", "rust"]
+        );
+        assert_eq!(service.get_note(b).await.unwrap().unwrap().tags, ["debug"]);
+        assert_eq!(
+            service.list_revisions(a).await.unwrap()[0].tags,
+            ["debugging", "rust"]
+        );
+        assert!(service.list_revisions(c).await.unwrap().is_empty());
+        service.restore_note(c).await.unwrap();
+        assert_eq!(service.get_note(c).await.unwrap().unwrap().tags, ["debug"]);
+        let listed = service.list_tags().await.unwrap();
+        assert_eq!(listed[0].name, "debug");
+        assert_eq!(listed[0].count, 3);
+        assert_eq!(listed[0].description.as_deref(), Some("Finding bugs"));
+
+        // Other namespaces keep their tags
+        assert_eq!(
+            team.get_note(other).await.unwrap().unwrap().tags,
+            ["debugging"]
+        );
+
+        assert_eq!(
+            service.rename_tag("rust", "rustlang").await.unwrap(),
+            vec![a]
+        );
+        assert_eq!(service.remove_tag("debug").await.unwrap(), vec![a, b, c]);
+        assert_eq!(
+            service.get_note(b).await.unwrap().unwrap().tags,
+            Vec::<String>::new()
+        );
+        let listed = service.list_tags().await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "rustlang");
+        assert!(matches!(
+            service.describe_tag("debug", "gone").await,
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tag_aliases() {
+        let service = VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+        let team = service.in_namespace("team").unwrap();
+        let tags = |t: &[&str]| t.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let add = |t: &[&str]| service.add_note("Bug".into(), "".into(), tags(t), vec![]);
+        let a = add(&["debug", "rust/async"]).await.unwrap();
+        let b = add(&["Lang / Rust /", "ops"]).await.unwrap();
+        assert_eq!(
+            service.get_note(b).await.unwrap().unwrap().tags,
+            ["lang/rust", "ops"]
+        );
+        assert!(add(&["lang/../ops"]).await.is_err());
+
+        assert_eq!(
+            service.add_tag_alias("debug", "debugging").await.unwrap(),
+            vec![a]
+        );
+        assert_eq!(
+            service.add_tag_alias("rust", "lang/rust").await.unwrap(),
+            vec![a]
+        );
+        assert_eq!(
+            service.get_note(a).await.unwrap().unwrap().tags,
+            ["debugging", "lang/rust/async"]
+        );
+        // Aliases stand for tags, not other aliases
+        service.add_tag_alias("bug", "debug").await.unwrap();
+        assert!(service.add_tag_alias("debugging", "fix").await.is_err());
+        assert!(service.add_tag_alias("lang", "lang/go").await.is_err());
+        let aliases = service.list_tag_aliases().await.unwrap();
+        assert_eq!(aliases[0].alias, "bug");
+        assert_eq!(aliases[0].tag, "debugging");
+        assert!(team.list_tag_aliases().await.unwrap().is_empty());
+
+        let c = add(&["bug", "Rust"]).await.unwrap();
+        assert_eq!(
+            service.get_note(c).await.unwrap().unwrap().tags,
+            ["debugging", "lang/rust"]
+        );
+        service
+            .update_note(
+                c,
+                UpdateNote {
+                    tags: Some(tags(&["debug"])),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            service.get_note(c).await.unwrap().unwrap().tags,
+            ["debugging"]
+        );
+
+        // Filters match aliases and children
+        let count = |t: &str| {
+            service.count_notes(NoteQuery {
+                tags: TagExpr::parse(t).unwrap(),
+                ..Default::default()
+            })
+        };
+        assert_eq!(count("lang").await.unwrap(), 2);
+        assert_eq!(count("rust").await.unwrap(), 2);
+        assert_eq!(count("bug+-rust").await.unwrap(), 1);
+        let page = service
+            .list_notes(NoteQuery {
+                tags: TagExpr::parse("lang/rust/async").unwrap(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.notes.len(), 1);
+
+        service.remove_tag_alias("bug").await.unwrap();
+        assert!(matches!(
+            service.remove_tag_alias("bug").await,
+            Err(Error::NotFound(_))
+        ));
+        assert_eq!(service.list_tag_aliases().await.unwrap().len(), 2);
+    }
+
+    /// A database from when IDs and tags were shared by all namespaces.
+    fn schema_v11() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("PRAGMA foreign_keys = ON;").unwrap();
+        for migration in get_pending_migrations(0)
+            .into_iter()
+            .filter(|m| m.version < 12)
+        {
+            for statement in migration.statements {
+                let result = conn.execute(statement, []);
+                if !is_add_column(statement) {
+                    result.unwrap();
+                }
+            }
+        }
+        conn.execute_batch(
+            "INSERT INTO _veta_meta (key, value) VALUES ('schema_version', '11');
+             INSERT INTO notes (title, body, namespace)
+                 VALUES ('Deploy', 'wrangler', 'default'), ('Deploy', 'terraform', 'team');
+             INSERT INTO tags (name) VALUES ('ops');
+             INSERT INTO note_tags (note_id, tag_id) VALUES (1, 1), (2, 1);",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[tokio::test]
+    async fn test_migration_to_per_namespace_ids() {
+        let conn = schema_v11();
+        let db = SqliteDatabase {
+            conn: Arc::new(Mutex::new(conn)),
+            namespace: DEFAULT_NAMESPACE.to_string(),
+        };
+        db.run_migrations().unwrap();
+
+        // Notes keep their IDs, tags and search
+        let default = VetaService::new(db);
+        let team = default.in_namespace("team").unwrap();
+        let note = team.get_note(2).await.unwrap().unwrap();
+        assert_eq!(
+            (note.body.as_str(), note.tags.as_slice()),
+            ("terraform", &["ops".to_string()][..])
+        );
+        assert_eq!(team.search("terraform", None, None).await.unwrap().len(), 1);
+
+        // New notes carry on from each namespace's last ID
+        for (service, id) in [(&default, 2), (&team, 3)] {
+            let new = service.add_note("New".into(), "".into(), vec![], vec![]);
+            assert_eq!(new.await.unwrap(), id);
+        }
+
+        team.rename_tag("ops", "infra").await.unwrap();
+        assert_eq!(default.get_note(1).await.unwrap().unwrap().tags, ["ops"]);
+    }
+
+    #[test]
+    fn test_failed_migration_rolls_back() {
+        // The last statement of the rebuild fails, after the old tables are
+        // dropped and the new ones renamed
+        let conn = schema_v11();
+        conn.execute("CREATE TABLE note_ids (namespace TEXT PRIMARY KEY)", [])
+            .unwrap();
+        let db = SqliteDatabase {
+            conn: Arc::new(Mutex::new(conn)),
+            namespace: DEFAULT_NAMESPACE.to_string(),
+        };
+        let err = db.run_migrations().unwrap_err();
+        assert!(matches!(err, Error::Database(ref m) if m.contains("per_namespace_ids")));
+
+        let conn = db.conn.lock().unwrap();
+        let version: String = conn
+            .query_row(
+                "SELECT value FROM _veta_meta WHERE key = 'schema_version'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(version, "11");
+        let rebuilt: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('notes') WHERE name = 'row_id'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(rebuilt, 0);
+        let bodies: Vec<String> = conn
+            .prepare("SELECT body FROM notes ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(bodies, ["wrangler", "terraform"]);
+    }
+
+    #[tokio::test]
+    async fn test_import_modes() {
+        let source = VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+        for title in ["Deploy", "Rollback"] {
+            source
+                .add_note(title.into(), "wrangler".into(), vec!["ops".into()], vec![])
+                .await
+                .unwrap();
+        }
+        let mut notes = source.export().await.unwrap();
+        notes[0].created_at = "2020-01-01 00:00:00".into();
+        notes[0].updated_at = "2020-01-02 00:00:00".into();
+
+        let target = VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+        let summary = target
+            .import(notes.clone(), ImportMode::Keep)
+            .await
+            .unwrap();
+        assert_eq!(summary.added, 2);
+        let imported = target.get_note(1).await.unwrap().unwrap();
+        assert_eq!(imported.tags, vec!["ops"]);
+        assert_eq!(imported.updated_at, "2020-01-02 00:00:00");
+        let hits = target.search("wrangler", None, None).await.unwrap();
+        assert_eq!(hits.len(), 2);
+        assert!(target.undo().await.unwrap().is_none());
+
+        // IDs that are taken stop the whole import
+        let err = target.import(notes.clone(), ImportMode::Keep).await;
+        assert!(
+            matches!(err, Err(Error::Validation(ref m)) if m.contains("note 1 already exists"))
+        );
+
+        let summary = target
+            .import(notes.clone(), ImportMode::Renumber)
+            .await
+            .unwrap();
+        assert_eq!(summary.added, 2);
+        assert_eq!(target.get_note(3).await.unwrap().unwrap().title, "Deploy");
+
+        // Merging replaces only notes that were updated later
+        notes[0].body = "terraform".into();
+        notes[0].updated_at = "2030-01-01 00:00:00".into();
+        notes[1].body = "older".into();
+        notes[1].updated_at = "2000-01-01 00:00:00".into();
+        let summary = target.import(notes, ImportMode::Merge).await.unwrap();
+        assert_eq!((summary.added, summary.updated, summary.skipped), (0, 1, 1));
+        assert_eq!(target.get_note(1).await.unwrap().unwrap().body, "terraform");
+        assert_eq!(target.get_note(2).await.unwrap().unwrap().body, "wrangler");
+        assert_eq!(target.list_revisions(1).await.unwrap()[0].body, "wrangler");
+
+        // Each namespace has its own IDs, so another one can keep them
+        let team = target.in_namespace("team").unwrap();
+        let summary = team
+            .import(target.export().await.unwrap(), ImportMode::Keep)
+            .await
+            .unwrap();
+        assert_eq!(summary.added, 4);
+        assert_eq!(team.get_note(1).await.unwrap().unwrap().body, "terraform");
+        let next = team
+            .add_note("Next".into(), "".into(), vec![], vec![])
+            .await
+            .unwrap();
+        assert_eq!(next, 5);
+    }
+
+    #[tokio::test]
+    async fn test_service_undo() {
+        let service = veta_core::VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+
+        let id = service
+            .add_note("Deploy".into(), "Uses wrangler".into(), vec![], vec![])
+            .await
+            .unwrap();
+        service
+            .update_note(
+                id,
+                UpdateNote {
+                    body: Some("Uses terraform".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        service.delete_notes(&[id]).await.unwrap();
+
+        assert_eq!(
+            service.undo().await.unwrap(),
+            Some(Operation::Delete { ids: vec![id] })
+        );
+        assert_eq!(
+            service.get_note(id).await.unwrap().unwrap().body,
+            "Uses terraform"
+        );
+
+        assert_eq!(
+            service.undo().await.unwrap(),
+            Some(Operation::Update { id, revision: 1 })
+        );
+        assert_eq!(
+            service.get_note(id).await.unwrap().unwrap().body,
+            "Uses wrangler"
+        );
+
+        assert_eq!(service.undo().await.unwrap(), Some(Operation::Add { id }));
+        assert!(service.get_note(id).await.unwrap().is_none());
+        assert_eq!(service.list_trash().await.unwrap().len(), 1);
+
+        assert_eq!(service.undo().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_undo_tag_changes() {
+        let service = veta_core::VetaService::new(SqliteDatabase::open_in_memory().unwrap());
+        for (title, tags) in [
+            ("Deploy", ["ops", "wrangler"]),
+            ("Rollback", ["infra", "ops"]),
+        ] {
+            let tags = tags.iter().map(|t| t.to_string()).collect();
+            service
+                .add_note(title.into(), "".into(), tags, vec![])
+                .await
+                .unwrap();
+        }
+        let tags = |id| {
+            let service = &service;
+            async move { service.get_note(id).await.unwrap().unwrap().tags }
+        };
+
+        // Undo reverses the merge, not the add before it
+        service
+            .merge_tags(&["ops".into(), "infra".into()], "platform")
+            .await
+            .unwrap();
+        let undone = service.undo().await.unwrap().unwrap();
+        assert_eq!(undone.to_string(), "retag of notes 1, 2");
+        assert_eq!(tags(1).await, ["ops", "wrangler"]);
+        assert_eq!(tags(2).await, ["infra", "ops"]);
+
+        service.remove_tag("ops").await.unwrap();
+        service.undo().await.unwrap();
+        assert_eq!(tags(2).await, ["infra", "ops"]);
+
+        // A note retagged twice by one alias gets its original tags back
+        let update = UpdateNote {
+            tags: Some(vec!["k8s/nodes".into(), "k8s/pods".into()]),
+            ..Default::default()
+        };
+        service.update_note(1, update).await.unwrap();
+        service.add_tag_alias("k8s", "platform").await.unwrap();
+        assert_eq!(tags(1).await, ["platform/nodes", "platform/pods"]);
+        let undone = service.undo().await.unwrap().unwrap();
+        assert_eq!(undone.to_string(), "tag alias 'k8s'");
+        assert_eq!(tags(1).await, ["k8s/nodes", "k8s/pods"]);
+        assert!(service.list_tag_aliases().await.unwrap().is_empty());
+
+        service.undo().await.unwrap();
+        assert_eq!(tags(1).await, ["ops", "wrangler"]);
+        assert_eq!(
+            service.undo().await.unwrap(),
+            Some(Operation::Add { id: 2 })
+        );
+    }
 }
@@ -0,0 +1,192 @@
+//! Writing notes in an editor, for `veta add -e` and `veta edit`.
+//!
+//! The note is written to a temporary file as one Markdown document, with
+//! the title, tags and references as TOML frontmatter, like notes stored in
+//! the markdown format:
+//!
+//! ```text
+//! +++
+//! title = "Deploy"
+//! tags = ["ops"]
+//! references = ["wrangler.toml"]
+//! +++
+//!
+//! Uses wrangler.
+//! ```
+//!
+//! The editor is `$VISUAL`, else `$EDITOR`, else `vi`.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tempfile::TempPath;
+use veta_core::{Note, UpdateNote};
+use veta_files::{join_frontmatter, split_frontmatter};
+
+/// A note as written in the editor.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Document {
+    pub title: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub references: Vec<String>,
+    #[serde(skip)]
+    pub body: String,
+}
+
+impl Document {
+    pub fn from_note(note: &Note) -> Self {
+        Document {
+            title: note.title.clone(),
+            tags: note.tags.clone(),
+            references: note.references.clone(),
+            body: note.body.clone(),
+        }
+    }
+
+    fn render(&self) -> Result<String> {
+        let header = toml::to_string(self).context("Failed to write the note for the editor")?;
+        Ok(join_frontmatter(&header, &self.body))
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let (header, body) = split_frontmatter(contents).map_err(anyhow::Error::msg)?;
+        let document: Document = toml::from_str(&header)?;
+        Ok(Document { body, ..document })
+    }
+
+    /// The fields that differ from `note`.
+    pub fn changes_from(self, note: &Note) -> UpdateNote {
+        UpdateNote {
+            title: (self.title != note.title).then_some(self.title),
+            body: (self.body != note.body).then_some(self.body),
+            tags: (self.tags != note.tags).then_some(self.tags),
+            references: (self.references != note.references).then_some(self.references),
+        }
+    }
+}
+
+/// A document saved in the editor. Its file is deleted once it's no longer
+/// needed, unless saving the note fails.
+pub struct Edited {
+    pub document: Document,
+    path: TempPath,
+}
+
+impl Edited {
+    /// Keep the file, so what was written isn't lost, and return its path.
+    pub fn keep(self) -> Result<PathBuf> {
+        self.path.keep().context("Failed to keep the edited note")
+    }
+
+    /// Keep the file if `result` is an error, saying where it is.
+    pub fn keep_on_error<T>(self, result: Result<T, veta_core::Error>) -> Result<T> {
+        match result {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                let path = self.keep()?;
+                Err(e)
+                    .with_context(|| format!("Failed to save the note; it's in {}", path.display()))
+            }
+        }
+    }
+}
+
+/// Open `document` in the editor, and read it back once the editor exits.
+pub fn edit(document: &Document) -> Result<Edited> {
+    let file = tempfile::Builder::new()
+        .prefix("veta-")
+        .suffix(".md")
+        .tempfile()
+        .context("Failed to create a file to edit")?;
+    std::fs::write(file.path(), document.render()?)
+        .context("Failed to write the note for the editor")?;
+    let path = file.into_temp_path();
+
+    run_editor(&path)?;
+
+    let contents = std::fs::read_to_string(&path).context("Failed to read the edited note")?;
+    match Document::parse(&contents) {
+        Ok(document) => Ok(Edited { document, path }),
+        Err(e) => {
+            let path = path.keep().context("Failed to keep the edited note")?;
+            Err(e).with_context(|| {
+                format!(
+                    "Failed to parse the edited note; it's in {}",
+                    path.display()
+                )
+            })
+        }
+    }
+}
+
+fn run_editor(path: &Path) -> Result<()> {
+    let editor = ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+
+    // Like git, run the editor with the shell, so it can have arguments
+    // such as `code --wait`
+    #[cfg(unix)]
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(path)
+        .status();
+    #[cfg(not(unix))]
+    let status = Command::new(&editor).arg(path).status();
+
+    let status = status.with_context(|| format!("Failed to run the editor '{}'", editor))?;
+    if !status.success() {
+        bail!("The editor '{}' failed, so the note wasn't saved", editor);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_round_trip() {
+        let document = Document {
+            title: "Deploy \"prod\"".to_string(),
+            tags: vec!["ops".to_string()],
+            references: vec![],
+            body: "Uses wrangler\n\n+++\n".to_string(),
+        };
+        let rendered = document.render().unwrap();
+        assert!(rendered.starts_with("+++\ntitle = "));
+        assert!(rendered.contains("references = []\n"));
+        assert_eq!(Document::parse(&rendered).unwrap(), document);
+
+        let parsed = Document::parse("+++\ntitle = \"Auth\"\n+++\nJWT").unwrap();
+        assert_eq!(parsed.body, "JWT");
+        assert!(parsed.tags.is_empty());
+        assert!(Document::parse("title = \"Auth\"").is_err());
+    }
+
+    #[test]
+    fn test_changes_from() {
+        let note = Note {
+            id: 1,
+            title: "Deploy".to_string(),
+            body: "Uses wrangler".to_string(),
+            tags: vec!["ops".to_string()],
+            references: vec![],
+            created_at: String::new(),
+            updated_at: String::new(),
+        };
+        let mut document = Document::from_note(&note);
+        document.body = "Uses wrangler and D1".to_string();
+        let update = document.changes_from(&note);
+        assert_eq!(update.body.as_deref(), Some("Uses wrangler and D1"));
+        assert!(update.title.is_none() && update.tags.is_none() && update.references.is_none());
+    }
+}
@@ -1,18 +1,58 @@
 //! Veta CLI - memory and knowledge base for agents.
 
+mod editor;
+mod output;
+mod refs;
+mod vault;
+
 use anyhow::{bail, Context, Result};
 use clap::{Parser, Subcommand};
-use std::io::{self, Read};
+use editor::Document;
+use output::{NewApiKey, Output, OutputFormat};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Read, Write};
 use std::path::{Path, PathBuf};
-use veta_core::{parse_human_date, Database, NoteQuery, UpdateNote, VetaService};
-use veta_files::FilesDatabase;
+use vault::ExportFormat;
+use veta_core::archive::{read_archive, write_archive};
+use veta_core::auth::hash_key;
+use veta_core::sync::{self, ConflictPolicy, SyncMode, SyncState};
+use veta_core::{
+    parse_human_date, parse_query, Cursor, Database, ImportMode, LinkKind, NotePage, NoteQuery,
+    Operation, ParsedQuery, Reference, Scope, SortOrder, TagCount, TagExpr, TimeField, UpdateNote,
+    VetaService,
+};
+use veta_files::{merge_note_files, FilesConfig, FilesDatabase, NoteFormat, KEY_ENV, KEY_FILE};
+use veta_http::HttpDatabase;
+use veta_sqlite::SqliteDatabase;
 
 const VETA_DIR: &str = ".veta";
 const LEGACY_DB_FILE: &str = "db.sqlite";
+const SYNC_STATE_FILE: &str = "sync.json";
 
 #[derive(Parser)]
 #[command(name = "veta", about = "Memory and knowledge base for agents", version)]
 struct Cli {
+    /// Use the veta API at this URL instead of the local .veta directory
+    #[arg(long, global = true, env = "VETA_REMOTE")]
+    remote: Option<String>,
+
+    /// API key for the `--remote` server
+    #[arg(long, global = true, env = "VETA_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Namespace to use in the `--remote` store, or the `veta sync --sqlite`
+    /// database. The .veta directory always maps to this one namespace.
+    #[arg(long, global = true, env = "VETA_SPACE")]
+    space: Option<String>,
+
+    /// Output format: `text` (default), `json`, `jsonl` or `tsv`
+    #[arg(long, global = true, default_value = "text")]
+    format: OutputFormat,
+
+    /// Shorthand for `--format json`
+    #[arg(long, global = true, conflicts_with = "format")]
+    json: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -24,35 +64,67 @@ enum Commands {
         /// Delete existing database and reinitialize
         #[arg(long)]
         reinitialize: bool,
+        /// Store notes as `json` (default) or `markdown` files with TOML frontmatter
+        #[arg(long, default_value = "json")]
+        note_format: NoteFormat,
+        /// Name note files after random keys, for a .veta directory committed to git
+        #[arg(long)]
+        git: bool,
+        /// Commit every change to the notes (needs `--git`)
+        #[arg(long, requires = "git")]
+        auto_commit: bool,
+        /// Encrypt notes and tag names, with the key in VETA_KEY, the file
+        /// named by VETA_KEY_FILE, or a new key in .veta/key
+        #[arg(long)]
+        encrypt: bool,
     },
     /// Add a new note
     Add {
         /// Note title
-        #[arg(long)]
-        title: String,
+        #[arg(long, required_unless_present = "editor")]
+        title: Option<String>,
         /// Comma-separated tags
-        #[arg(long)]
-        tags: String,
+        #[arg(long, required_unless_present = "editor")]
+        tags: Option<String>,
         /// Note body (reads from stdin if not provided)
         #[arg(long)]
         body: Option<String>,
         /// Comma-separated references (source code paths, URLs, documentation links, etc.)
         #[arg(long)]
         references: Option<String>,
+        /// Write the note in $EDITOR, starting from the other options
+        #[arg(short = 'e', long)]
+        editor: bool,
     },
     /// List notes
     Ls {
-        /// Filter by comma-separated tags (optional)
+        /// Filter by tags: `a,b` (any), `a+b` (all), `-c` (exclude), e.g. `arch+auth,-old`
+        #[arg(allow_hyphen_values = true)]
         tags: Option<String>,
-        /// Filter notes updated after this time (e.g., "2 days ago", "2024-01-01")
+        /// Structured query, e.g. `title:jwt tag:auth updated:>2026-01-01 ref:src/auth.rs "token expiry"`
+        #[arg(short = 'q', long, allow_hyphen_values = true, conflicts_with_all = ["from", "to"])]
+        query: Option<String>,
+        /// Filter notes updated (or created, with `--sort created`) after this time (e.g., "2 days ago", "2024-01-01")
         #[arg(long)]
         from: Option<String>,
-        /// Filter notes updated before this time
+        /// Filter notes updated (or created, with `--sort created`) before this time
         #[arg(long)]
         to: Option<String>,
+        /// Timestamp to sort and filter by: `updated` (default) or `created`
+        #[arg(long)]
+        sort: Option<TimeField>,
+        /// Show the oldest notes first
+        #[arg(long)]
+        asc: bool,
         /// Number of notes to show (0 for all)
         #[arg(short = 'n', long, default_value = "100")]
         head: i64,
+        /// Continue after the cursor printed at the end of the previous page
+        #[arg(long, conflicts_with = "page")]
+        after: Option<String>,
+        /// Show page N of `--head` notes each
+        #[arg(long)]
+        page: Option<i64>,
     },
     /// Show one or more notes
     Show {
@@ -78,24 +150,259 @@ enum Commands {
         /// New comma-separated references (source code paths, URLs, documentation links, etc.)
         #[arg(long)]
         references: Option<String>,
+        /// Edit the whole note in $EDITOR (the default without other options, from a terminal)
+        #[arg(short = 'e', long, conflicts_with_all = ["title", "tags", "body", "references"])]
+        editor: bool,
     },
-    /// Delete one or more notes
+    /// Move one or more notes to the trash
     Rm {
         /// Comma-separated note IDs
         ids: String,
     },
+    /// Link a note to another, e.g. `veta link 42 17 --kind supersedes`
+    Link {
+        /// Note ID
+        from: i64,
+        /// ID of the note it links to
+        to: i64,
+        /// `relates-to`, `supersedes`, `contradicts` or `child-of`
+        #[arg(short, long, default_value = "relates-to")]
+        kind: LinkKind,
+    },
+    /// Remove links from a note to another
+    Unlink {
+        /// Note ID
+        from: i64,
+        /// ID of the note it links to
+        to: i64,
+        /// Only remove the link of this kind
+        #[arg(short, long)]
+        kind: Option<LinkKind>,
+    },
+    /// Check that file and note references still point at something
+    CheckRefs {
+        /// Comma-separated note IDs (all notes by default)
+        ids: Option<String>,
+        /// Directory that file references are relative to (by default the
+        /// one holding .veta, else the current directory)
+        #[arg(long)]
+        root: Option<PathBuf>,
+    },
+    /// List previous revisions of a note
+    Log {
+        /// Note ID
+        id: i64,
+    },
+    /// Show changes to a note since a revision (the latest by default)
+    Diff {
+        /// Note ID
+        id: i64,
+        /// Revision number
+        rev: Option<i64>,
+    },
+    /// Restore notes from the trash, or a note to a previous revision
+    Restore {
+        /// Comma-separated note IDs (a single ID when restoring a revision)
+        ids: String,
+        /// Revision number
+        rev: Option<i64>,
+    },
+    /// List deleted notes
+    Trash {
+        /// Permanently delete all notes in the trash
+        #[arg(long)]
+        empty: bool,
+    },
+    /// Undo the most recent add, edit, delete or restore
+    Undo,
     /// List all tags
     Tags,
+    /// Rename, merge, remove or describe tags
+    Tag {
+        #[command(subcommand)]
+        command: TagCommand,
+    },
+    /// List namespaces in the `--remote` store
+    Spaces,
     /// Search notes with regular expressions
     Grep {
         /// Search pattern (regex)
         pattern: String,
-        /// Filter by comma-separated tags
-        #[arg(long)]
+        /// Filter by tags: `a,b` (any), `a+b` (all), `-c` (exclude)
+        #[arg(long, allow_hyphen_values = true)]
         tags: Option<String>,
+        /// Only search notes matching a structured query (see `veta ls --help`)
+        #[arg(short = 'q', long, allow_hyphen_values = true)]
+        query: Option<String>,
         /// Case-sensitive search
         #[arg(short = 'C', long)]
         case_sensitive: bool,
+        /// Number of notes to show (0 for all)
+        #[arg(short = 'n', long, default_value = "100")]
+        head: i64,
+        /// Continue after the cursor printed at the end of the previous page
+        #[arg(long, conflicts_with = "page")]
+        after: Option<String>,
+        /// Show page N of `--head` notes each
+        #[arg(long)]
+        page: Option<i64>,
+    },
+    /// Full-text search, ranked by relevance
+    Search {
+        /// Search terms (all terms must match)
+        query: String,
+        /// Filter by tags: `a,b` (any), `a+b` (all), `-c` (exclude)
+        #[arg(long, allow_hyphen_values = true)]
+        tags: Option<String>,
+        /// Number of results to show (0 for all)
+        #[arg(short = 'n', long, default_value = "20")]
+        head: i64,
+    },
+    /// Serve the HTTP API locally (the same routes as the Cloudflare worker)
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        /// Port to listen on
+        #[arg(short, long, default_value = "8787")]
+        port: u16,
+        /// Serve a SQLite database file instead of the .veta directory
+        #[arg(long)]
+        sqlite: Option<PathBuf>,
+        /// Accept this token as an admin API key
+        #[arg(long, env = "VETA_ADMIN_TOKEN", hide_env_values = true)]
+        admin_token: Option<String>,
+    },
+    /// Manage API keys for `veta serve` and the worker
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
+    /// Run a Model Context Protocol server over stdin and stdout
+    Mcp,
+    /// Sync notes between the .veta directory and the `--remote` store
+    Sync {
+        /// `push` local changes, `pull` remote changes, or `both` (default)
+        #[arg(default_value = "both")]
+        mode: SyncMode,
+        /// Notes changed on both sides: `keep-local`, `keep-remote` or `keep-both` (default)
+        #[arg(long, default_value = "keep-both")]
+        conflict: ConflictPolicy,
+        /// Sync with a SQLite database file instead of the `--remote` store
+        #[arg(long)]
+        sqlite: Option<PathBuf>,
+    },
+    /// Export all notes as a JSON Lines archive for `veta import`, or as an Obsidian vault
+    Export {
+        /// Archive file (stdout if not provided), or the vault directory for `--to obsidian`
+        path: Option<PathBuf>,
+        /// What to write: a `jsonl` archive (default) or an `obsidian` vault
+        #[arg(long, value_name = "FORMAT", default_value = "jsonl")]
+        to: ExportFormat,
+        /// Export a SQLite database file instead of the .veta directory
+        #[arg(long)]
+        sqlite: Option<PathBuf>,
+    },
+    /// Import notes from an archive written by `veta export`, or from an Obsidian vault
+    Import {
+        /// Archive file (reads from stdin if not provided), or the vault directory for `--from obsidian`
+        file: Option<PathBuf>,
+        /// What to read: a `jsonl` archive (default) or an `obsidian` vault
+        #[arg(long, value_name = "FORMAT", default_value = "jsonl")]
+        from: ExportFormat,
+        /// For archives: `keep` original IDs (default), `renumber` them, or `merge` with existing notes
+        #[arg(long, default_value = "keep")]
+        mode: ImportMode,
+        /// Import into a SQLite database file instead of the .veta directory
+        #[arg(long)]
+        sqlite: Option<PathBuf>,
+    },
+    /// Rewrite the notes in the .veta directory as `json` or `markdown` files
+    Convert {
+        /// Note format to convert to
+        #[arg(value_name = "FORMAT")]
+        note_format: NoteFormat,
+    },
+    /// Merge two versions of a note file; the git merge driver set up by `veta init --git`
+    Merge {
+        /// The common ancestor (%O)
+        base: PathBuf,
+        /// Our version (%A), replaced with the merged note
+        ours: PathBuf,
+        /// Their version (%B)
+        theirs: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum TagCommand {
+    /// Rename a tag on every note that has it
+    Rename {
+        /// Current name
+        tag: String,
+        /// New name, which mustn't be a tag yet (see `veta tag merge`)
+        new_name: String,
+    },
+    /// Merge tags into one, e.g. `veta tag merge debugging,bugs into debug`
+    Merge {
+        /// Comma-separated tags to merge
+        tags: String,
+        #[arg(value_name = "into", value_parser = ["into"])]
+        into: String,
+        /// Tag to merge them into, which needn't exist yet
+        target: String,
+    },
+    /// Remove a tag from every note that has it, without deleting the notes
+    Rm {
+        /// Tag to remove
+        tag: String,
+    },
+    /// Describe what a tag is for, shown by `veta tags`
+    Describe {
+        /// Tag to describe
+        tag: String,
+        /// Description (clears it if not provided)
+        description: Option<String>,
+    },
+    /// Make a name stand for a tag, e.g. `veta tag alias debug debugging`.
+    /// Notes tagged with it are retagged, and it's replaced from then on
+    Alias {
+        /// The other name
+        alias: String,
+        /// Tag it stands for
+        tag: String,
+    },
+    /// Remove a tag alias
+    Unalias {
+        /// Alias to remove
+        alias: String,
+    },
+    /// List tag aliases
+    Aliases,
+}
+
+#[derive(Subcommand)]
+enum KeysCommand {
+    /// Create an API key and print it
+    Create {
+        /// Name to recognize the key by
+        name: String,
+        /// `read`, `write` or `admin`
+        #[arg(long, default_value = "read")]
+        scope: Scope,
+        /// Comma-separated tags; the key can only use notes with one of them
+        #[arg(long)]
+        tags: Option<String>,
+        /// Comma-separated namespaces; the key can only be used in them
+        #[arg(long)]
+        spaces: Option<String>,
+    },
+    /// List API keys
+    Ls,
+    /// Revoke an API key
+    Revoke {
+        /// API key ID
+        id: i64,
     },
 }
 
@@ -121,6 +428,29 @@ fn get_veta_dir() -> Result<PathBuf> {
     }
 }
 
+/// The directory that file references are relative to: the one holding
+/// .veta, else the current directory.
+fn project_root() -> Result<PathBuf> {
+    match find_veta_dir().as_deref().and_then(Path::parent) {
+        Some(root) => Ok(root.to_path_buf()),
+        None => std::env::current_dir().context("Failed to get the current directory"),
+    }
+}
+
+/// Register `veta merge` as the driver that `.veta/.gitattributes` uses for
+/// note files. Git doesn't read drivers from the repository, so every clone
+/// needs this.
+fn set_up_merge_driver() {
+    let status = std::process::Command::new("git")
+        .args(["config", "merge.veta.driver", "veta merge %O %A %B"])
+        .stderr(std::process::Stdio::null())
+        .status();
+    if !matches!(status, Ok(status) if status.success()) {
+        eprintln!("Not in a git repository. In each clone, run:");
+        eprintln!("  git config merge.veta.driver 'veta merge %O %A %B'");
+    }
+}
+
 /// Check if there's a legacy SQLite database that needs migration
 fn has_legacy_sqlite(veta_dir: &Path) -> bool {
     veta_dir.join(LEGACY_DB_FILE).exists()
@@ -128,8 +458,6 @@ fn has_legacy_sqlite(veta_dir: &Path) -> bool {
 
 /// Migrate from SQLite to file-based storage
 async fn migrate_from_sqlite(veta_dir: &PathBuf) -> Result<()> {
-    use veta_sqlite::SqliteDatabase;
-
     let sqlite_path = veta_dir.join(LEGACY_DB_FILE);
     eprintln!("Migrating from SQLite database to file-based storage...");
 
@@ -138,70 +466,20 @@ async fn migrate_from_sqlite(veta_dir: &PathBuf) -> Result<()> {
         SqliteDatabase::open(&sqlite_path).context("Failed to open legacy SQLite database")?;
 
     // Create the new file-based database
-    let _files_db =
-        FilesDatabase::open(veta_dir).context("Failed to create file-based database")?;
-
-    // Get all notes from SQLite
-    let notes = sqlite_db
-        .list_notes(NoteQuery {
-            limit: None,
-            ..Default::default()
-        })
+    let files_db = FilesDatabase::open(veta_dir).context("Failed to create file-based database")?;
+
+    // Copy the notes with their IDs, the same way as `veta import`. Merge,
+    // so that a migration that was interrupted can run again
+    let notes = VetaService::new(sqlite_db)
+        .export()
         .await
         .context("Failed to list notes from SQLite")?;
 
     eprintln!("Migrating {} notes...", notes.len());
-
-    // We need to preserve the original IDs, but FilesDatabase.add_note generates new IDs.
-    // So we'll directly write the note files with the correct IDs.
-    // This is a bit of a hack, but it's only for migration.
-
-    for note in notes {
-        // Create the note file directly to preserve the ID
-        let note_path = veta_dir.join("notes").join(format!("{}.json", note.id));
-
-        let note_file = serde_json::json!({
-            "title": note.title,
-            "body": note.body,
-            "references": note.references,
-            "modified": note.updated_at,
-        });
-
-        let contents =
-            serde_json::to_string_pretty(&note_file).context("Failed to serialize note")?;
-
-        std::fs::write(&note_path, contents)
-            .context(format!("Failed to write note {}", note.id))?;
-
-        // Create tag symlinks
-        for tag in &note.tags {
-            let tag_dir = veta_dir.join("tags").join(tag);
-            std::fs::create_dir_all(&tag_dir)
-                .context(format!("Failed to create tag directory: {}", tag))?;
-
-            let symlink_path = tag_dir.join(format!("{}.json", note.id));
-            let relative_target = format!("../../notes/{}.json", note.id);
-
-            #[cfg(unix)]
-            {
-                std::os::unix::fs::symlink(&relative_target, &symlink_path).context(format!(
-                    "Failed to create symlink for note {} tag {}",
-                    note.id, tag
-                ))?;
-            }
-
-            #[cfg(windows)]
-            {
-                // Try real symlink first, fall back to text file
-                if std::os::windows::fs::symlink_file(&relative_target, &symlink_path).is_err() {
-                    std::fs::write(&symlink_path, &relative_target).context(format!(
-                        "Failed to create link file for note {} tag {}",
-                        note.id, tag
-                    ))?;
-                }
-            }
-        }
-    }
+    VetaService::new(files_db)
+        .import(notes, ImportMode::Merge)
+        .await
+        .context("Failed to write notes")?;
 
     // Remove the old SQLite database
     std::fs::remove_file(&sqlite_path).context("Failed to remove old SQLite database")?;
@@ -229,6 +507,450 @@ fn parse_tags(tags: &str) -> Vec<String> {
         .collect()
 }
 
+fn parse_tag_expr(tags: Option<String>) -> Result<Option<TagExpr>> {
+    match tags {
+        Some(tags) => Ok(TagExpr::parse(&tags)?),
+        None => Ok(None),
+    }
+}
+
+/// Parse an optional structured query, narrowed by a separately given tag filter.
+fn parse_note_query(query: Option<String>, tags: Option<TagExpr>) -> Result<ParsedQuery> {
+    let mut parsed = match query {
+        Some(query) => parse_query(&query)?,
+        None => ParsedQuery::default(),
+    };
+    if let Some(tags) = tags {
+        parsed.query.tags = Some(match parsed.query.tags.take() {
+            Some(query_tags) => query_tags.and(tags),
+            None => tags,
+        });
+    }
+    Ok(parsed)
+}
+
+/// Apply `--head`, `--after` and `--page` to a query.
+fn paginate_query(
+    query: NoteQuery,
+    head: i64,
+    after: Option<String>,
+    page: Option<i64>,
+) -> Result<NoteQuery> {
+    let offset = match page {
+        Some(page) if page < 1 => bail!("--page must be at least 1"),
+        Some(page) if page > 1 && head == 0 => bail!("--page can't be used with --head 0"),
+        Some(page) => Some((page - 1) * head),
+        None => None,
+    };
+    Ok(NoteQuery {
+        limit: Some(head),
+        after: after.map(|s| Cursor::parse(&s)).transpose()?,
+        offset,
+        ..query
+    })
+}
+
+/// Print the notes of a listing page.
+fn print_notes(page: &NotePage, time_field: TimeField, out: Output) {
+    if !out.is_text() {
+        out.page(page);
+        return;
+    }
+    for note in &page.notes {
+        let time = match time_field {
+            TimeField::Created => &note.created_at,
+            TimeField::Updated => &note.updated_at,
+        };
+        println!(
+            "{}: {} ({}) -- {}",
+            note.id, note.title, time, note.body_preview
+        );
+    }
+}
+
+/// Tell how to get the next page, if there is one.
+fn print_next_page(page: &NotePage, page_number: Option<i64>, out: Output) {
+    if !out.is_text() {
+        return;
+    }
+    if let Some(ref cursor) = page.next_cursor {
+        match page_number {
+            Some(n) => println!("[Next page: --page {}]", n + 1),
+            None => println!("[Next page: --after {}]", cursor),
+        }
+    }
+}
+
+/// Serve the HTTP API on `addr` until the process is killed.
+async fn serve<D: Database>(
+    service: VetaService<D>,
+    addr: &str,
+    admin_token: Option<&str>,
+    out: Output,
+) -> Result<()> {
+    let server = veta_api::server::Server::bind(addr)
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    let message = format!("Serving veta API on http://{}", addr);
+    out.outcome("serve", vec![], message);
+    server.run(&service, admin_token).await;
+    Ok(())
+}
+
+/// Connect to the API at `url`, with the `--token` API key if given.
+fn remote_database(url: &str, token: Option<&str>) -> HttpDatabase {
+    let db = HttpDatabase::new(url);
+    match token {
+        Some(token) => db.with_token(token),
+        None => db,
+    }
+}
+
+/// Switch `service` to the `--space` namespace, if given.
+fn in_space<D: Database>(service: VetaService<D>, space: Option<&str>) -> Result<VetaService<D>> {
+    match space {
+        Some(space) => Ok(service.in_namespace(space)?),
+        None => Ok(service),
+    }
+}
+
+/// Key for the sync state of `remote`, so each namespace of a remote is
+/// synced separately.
+fn sync_state_key(remote: &str, space: Option<&str>) -> String {
+    match space {
+        Some(space) => format!("{}/spaces/{}", remote.trim_end_matches('/'), space),
+        None => remote.to_string(),
+    }
+}
+
+/// Answer MCP messages, one JSON-RPC message per line, until stdin closes.
+async fn mcp<D: Database>(service: &VetaService<D>) -> Result<()> {
+    let mut stdout = io::stdout();
+    for line in io::stdin().lock().lines() {
+        let line = line.context("Failed to read from stdin")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = veta_api::mcp::handle_message(service, &line).await {
+            writeln!(stdout, "{}", response)?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Sync the .veta directory with `remote`, keeping the sync state for each
+/// remote in `.veta/sync.json`.
+async fn sync_with<R: Database>(
+    veta_dir: &Path,
+    local: &VetaService<FilesDatabase>,
+    remote: &VetaService<R>,
+    remote_name: &str,
+    mode: SyncMode,
+    conflict: ConflictPolicy,
+    out: Output,
+) -> Result<()> {
+    let state_path = veta_dir.join(SYNC_STATE_FILE);
+    let mut states: BTreeMap<String, SyncState> = match std::fs::read_to_string(&state_path) {
+        Ok(content) => serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", state_path.display()))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => return Err(e).context("Failed to read sync state"),
+    };
+    let state = states.entry(remote_name.to_string()).or_default();
+
+    // Save the state even if syncing fails, so that notes copied before the
+    // failure aren't copied again
+    let result = sync::sync(local, remote, state, mode, conflict).await;
+    std::fs::write(&state_path, serde_json::to_string_pretty(&states)?)
+        .context("Failed to write sync state")?;
+
+    let changes = result?;
+    let mut message: Vec<String> = changes.iter().map(|change| change.to_string()).collect();
+    if changes.is_empty() {
+        message.push("Already in sync".to_string());
+    }
+    out.outcome("sync", vec![], message.join("\n"));
+    Ok(())
+}
+
+async fn keys<D: Database>(
+    service: &VetaService<D>,
+    command: KeysCommand,
+    out: Output,
+) -> Result<()> {
+    match command {
+        KeysCommand::Create {
+            name,
+            scope,
+            tags,
+            spaces,
+        } => {
+            // Generate the key here so only its hash reaches the database
+            let key = veta_api::auth::generate_key()?;
+            let tags = tags.map(|t| parse_tags(&t)).unwrap_or_default();
+            let spaces = spaces
+                .map(|s| {
+                    s.split(',')
+                        .map(|s| s.trim().to_string())
+                        .filter(|s| !s.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default();
+            let api_key = service
+                .add_api_key(name, scope, tags, spaces, hash_key(&key))
+                .await?;
+            if !out.is_text() {
+                out.record(&NewApiKey { api_key, key });
+                return Ok(());
+            }
+            eprintln!(
+                "Created API key {} ({}). It won't be shown again:",
+                api_key.id, api_key.name
+            );
+            println!("{}", key);
+        }
+        KeysCommand::Ls => {
+            let keys = service.list_api_keys().await?;
+            if !out.is_text() {
+                out.records(&keys);
+                return Ok(());
+            }
+            for key in keys {
+                let tags = if key.tags.is_empty() {
+                    String::new()
+                } else {
+                    format!(", tags: {}", key.tags.join(","))
+                };
+                let spaces = if key.namespaces.is_empty() {
+                    String::new()
+                } else {
+                    format!(", spaces: {}", key.namespaces.join(","))
+                };
+                println!(
+                    "{}: {} ({}{}{}) created {}",
+                    key.id, key.name, key.scope, tags, spaces, key.created_at
+                );
+            }
+        }
+        KeysCommand::Revoke { id } => {
+            if service.revoke_api_key(id).await? {
+                out.outcome("keys revoke", vec![id], format!("Revoked API key {}", id));
+            } else {
+                out.fail("not_found", &format!("API key {} not found", id));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn tag<D: Database>(
+    service: &VetaService<D>,
+    command: TagCommand,
+    out: Output,
+) -> Result<()> {
+    let on_notes = |ids: &[i64]| {
+        let noun = if ids.len() == 1 { "note" } else { "notes" };
+        format!("{} {}", ids.len(), noun)
+    };
+    // Tags are stored lowercase, so say what they're called
+    let name = |tag: &str| tag.trim().to_lowercase();
+    match command {
+        TagCommand::Rename { tag, new_name } => {
+            let ids = service.rename_tag(&tag, &new_name).await?;
+            let message = format!(
+                "Renamed tag {} to {} on {}",
+                name(&tag),
+                name(&new_name),
+                on_notes(&ids)
+            );
+            out.outcome("tag rename", ids, message);
+        }
+        TagCommand::Merge { tags, target, .. } => {
+            let tags = parse_tags(&tags);
+            let ids = service.merge_tags(&tags, &target).await?;
+            let message = format!(
+                "Merged {} into {} on {}",
+                tags.iter().map(|t| name(t)).collect::<Vec<_>>().join(", "),
+                name(&target),
+                on_notes(&ids)
+            );
+            out.outcome("tag merge", ids, message);
+        }
+        TagCommand::Rm { tag } => {
+            let ids = service.remove_tag(&tag).await?;
+            let message = format!("Removed tag {} from {}", name(&tag), on_notes(&ids));
+            out.outcome("tag rm", ids, message);
+        }
+        TagCommand::Describe { tag, description } => {
+            let description = description.unwrap_or_default();
+            service.describe_tag(&tag, &description).await?;
+            let message = if description.trim().is_empty() {
+                format!("Cleared the description of tag {}", name(&tag))
+            } else {
+                format!("Described tag {}", name(&tag))
+            };
+            out.outcome("tag describe", vec![], message);
+        }
+        TagCommand::Alias { alias, tag } => {
+            let ids = service.add_tag_alias(&alias, &tag).await?;
+            let mut message = format!("Made {} an alias of {}", name(&alias), name(&tag));
+            if !ids.is_empty() {
+                message.push_str(&format!(", retagging {}", on_notes(&ids)));
+            }
+            out.outcome("tag alias", ids, message);
+        }
+        TagCommand::Unalias { alias } => {
+            service.remove_tag_alias(&alias).await?;
+            let message = format!("Removed tag alias {}", name(&alias));
+            out.outcome("tag unalias", vec![], message);
+        }
+        TagCommand::Aliases => {
+            let aliases = service.list_tag_aliases().await?;
+            if !out.is_text() {
+                out.records(&aliases);
+                return Ok(());
+            }
+            for alias in aliases {
+                println!("{} -> {}", alias.alias, alias.tag);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the links from and to a note for `veta show`, with the titles of
+/// the linked notes.
+async fn print_links<D: Database>(service: &VetaService<D>, id: i64) -> Result<()> {
+    let links = service.list_links(id).await?.unwrap_or_default();
+    if links.is_empty() {
+        return Ok(());
+    }
+    println!("Links:");
+    let (outgoing, incoming): (Vec<_>, Vec<_>) = links.into_iter().partition(|l| l.from == id);
+    for link in outgoing {
+        let title = note_title(service, link.to).await?;
+        println!("  -> {} {}: {}", link.kind, link.to, title);
+    }
+    for link in incoming {
+        let title = note_title(service, link.from).await?;
+        println!("  <- {} {}: {}", link.kind.incoming(), link.from, title);
+    }
+    Ok(())
+}
+
+async fn note_title<D: Database>(service: &VetaService<D>, id: i64) -> Result<String> {
+    Ok(service
+        .get_note(id)
+        .await?
+        .map(|note| note.title)
+        .unwrap_or_default())
+}
+
+/// Print tags with children like `lang/rust` indented under their parent,
+/// as `rust`. Siblings keep the order of `tags`, and a parent that isn't a
+/// tag itself is printed without a count.
+fn print_tag_tree(tags: &[TagCount], parent: Option<&str>) {
+    let mut children: Vec<&str> = Vec::new();
+    for tag in tags {
+        let rest = match parent {
+            Some(parent) => tag
+                .name
+                .strip_prefix(parent)
+                .and_then(|rest| rest.strip_prefix('/')),
+            None => Some(tag.name.as_str()),
+        };
+        if let Some(child) = rest.and_then(|rest| rest.split('/').next()) {
+            if !children.contains(&child) {
+                children.push(child);
+            }
+        }
+    }
+
+    let indent = "  ".repeat(parent.map_or(0, |p| p.split('/').count()));
+    for child in children {
+        let name = match parent {
+            Some(parent) => format!("{}/{}", parent, child),
+            None => child.to_string(),
+        };
+        match tags.iter().find(|tag| tag.name == name) {
+            Some(tag) => {
+                let noun = if tag.count == 1 { "note" } else { "notes" };
+                match &tag.description {
+                    Some(description) => println!(
+                        "{}{} ({} {}) -- {}",
+                        indent, child, tag.count, noun, description
+                    ),
+                    None => println!("{}{} ({} {})", indent, child, tag.count, noun),
+                }
+            }
+            None => println!("{}{}", indent, child),
+        }
+        print_tag_tree(tags, Some(&name));
+    }
+}
+
+/// Names of the fields an update changes.
+fn updated_fields(update: &UpdateNote) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    if update.title.is_some() {
+        fields.push("title");
+    }
+    if update.body.is_some() {
+        fields.push("body");
+    }
+    if update.tags.is_some() {
+        fields.push("tags");
+    }
+    if update.references.is_some() {
+        fields.push("references");
+    }
+    fields
+}
+
+/// Edit a note in the editor. If the note changed while the editor was
+/// open, the edit isn't saved, so it can't overwrite the other change.
+async fn edit_in_editor<D: Database>(service: &VetaService<D>, id: i64, out: Output) -> Result<()> {
+    let Some(before) = service.get_note(id).await? else {
+        out.fail("not_found", &format!("Note {} not found", id));
+    };
+    let edited = editor::edit(&Document::from_note(&before))?;
+
+    if service.get_note(id).await?.as_ref() != Some(&before) {
+        let path = edited.keep()?;
+        out.fail(
+            "conflict",
+            &format!(
+                "Note {} changed while it was being edited, so the edit wasn't saved. It's in {}",
+                id,
+                path.display()
+            ),
+        );
+    }
+
+    let update = edited.document.clone().changes_from(&before);
+    let updated_fields = updated_fields(&update);
+    if updated_fields.is_empty() {
+        drop(edited);
+        out.fail("validation", "Nothing to update");
+    }
+    let result = service.update_note(id, update).await;
+    if !edited.keep_on_error(result)? {
+        out.fail("not_found", &format!("Note {} not found", id));
+    }
+    let message = format!("Edited note {}: Updated {}", id, updated_fields.join(", "));
+    out.outcome("edit", vec![id], message);
+    Ok(())
+}
+
+/// One "Note N not found" line per ID, followed by `suffix`.
+fn not_found_message(ids: &[i64], suffix: &str) -> String {
+    let lines: Vec<String> = ids
+        .iter()
+        .map(|id| format!("Note {} not found{}", id, suffix))
+        .collect();
+    lines.join("\n")
+}
+
 fn parse_ids(ids: &str) -> Result<Vec<i64>> {
     ids.split(',')
         .map(|s| s.trim())
@@ -249,11 +971,65 @@ fn is_stdin_tty() -> bool {
     atty::is(atty::Stream::Stdin)
 }
 
+impl Cli {
+    /// The format from `--format` or `--json`.
+    fn output_format(&self) -> OutputFormat {
+        if self.json {
+            OutputFormat::Json
+        } else {
+            self.format
+        }
+    }
+}
+
+/// Parse the arguments, exiting with a usage error if they're invalid.
+fn parse_args() -> Cli {
+    let error = match Cli::try_parse() {
+        Ok(cli) => return cli,
+        Err(error) => error,
+    };
+    let format = OutputFormat::from_args(std::env::args());
+    if !error.use_stderr() || Output::new(format).is_text() {
+        error.exit();
+    }
+    // Only the first line: the rest is the usage, for people
+    let rendered = error.render().to_string();
+    let message = rendered.lines().next().unwrap_or_default();
+    output::print_error("usage", message.trim_start_matches("error: "));
+    std::process::exit(output::EXIT_USAGE);
+}
+
+/// How to print the results of `command`. Export and import print text,
+/// since they may write an archive to stdout.
+fn output_for(command: &Commands, format: OutputFormat) -> Output {
+    match command {
+        Commands::Export { .. } | Commands::Import { .. } => Output::new(OutputFormat::Text),
+        _ => Output::new(format),
+    }
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let cli = parse_args();
+    let out = output_for(&cli.command, cli.output_format());
+    match try_main(cli, out).await {
+        Err(e) if !out.is_text() => out.fail(output::error_kind(&e), &format!("{:#}", e)),
+        result => result,
+    }
+}
 
-    if let Commands::Init { reinitialize } = cli.command {
+async fn try_main(cli: Cli, out: Output) -> Result<()> {
+    if let Commands::Init {
+        reinitialize,
+        note_format,
+        git,
+        auto_commit,
+        encrypt,
+    } = cli.command
+    {
+        if cli.remote.is_some() {
+            bail!("--remote can't be used with init");
+        }
         let veta_dir = PathBuf::from(VETA_DIR);
 
         if veta_dir.exists() {
@@ -272,93 +1048,256 @@ async fn main() -> Result<()> {
         }
 
         // Create the file-based database structure
-        let _db = FilesDatabase::open(&veta_dir).context("Failed to create database")?;
+        let config = FilesConfig {
+            format: note_format,
+            git,
+            auto_commit,
+            encrypted: encrypt,
+        };
+        let _db = FilesDatabase::create(&veta_dir, config).context("Failed to create database")?;
+
+        let mut message = if reinitialize {
+            format!("Reinitialized veta database in {}", veta_dir.display())
+        } else {
+            format!("Initialized veta database in {}", veta_dir.display())
+        };
+        if git {
+            set_up_merge_driver();
+        }
+        let key_path = veta_dir.join(KEY_FILE);
+        if encrypt && std::env::var_os(KEY_ENV).is_none() && key_path.exists() {
+            message.push_str(&format!(
+                "\nThe key is in {}. Keep a copy somewhere safe: the notes can't be read without it.",
+                key_path.display()
+            ));
+        }
+        out.outcome("init", vec![], message);
+        return Ok(());
+    }
 
-        if reinitialize {
-            println!("Reinitialized veta database in {}", veta_dir.display());
+    if let Commands::Merge {
+        ref base,
+        ref ours,
+        ref theirs,
+    } = cli.command
+    {
+        let read = |path: &PathBuf| {
+            std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))
+        };
+        let (base, ours_contents, theirs) = (read(base)?, read(ours)?, read(theirs)?);
+        // Git runs the driver at the top of the work tree. If that's where the
+        // .veta directory is, merge with its key in case the notes are encrypted
+        let veta_dir = Path::new(VETA_DIR);
+        let (merged, conflict) = if veta_dir.join("config.json").exists() {
+            FilesDatabase::open(veta_dir)
+                .context("Failed to open database")?
+                .merge_note_files(&base, &ours_contents, &theirs)?
         } else {
-            println!("Initialized veta database in {}", veta_dir.display());
+            merge_note_files(&base, &ours_contents, &theirs)?
+        };
+        std::fs::write(ours, merged)
+            .with_context(|| format!("Failed to write {}", ours.display()))?;
+        if conflict {
+            // Git leaves the file as a conflict to resolve
+            out.fail(
+                "error",
+                "Both sides changed the note's body; fix it with `veta edit`",
+            );
+        }
+        return Ok(());
+    }
+
+    // Serving a SQLite database doesn't need a .veta directory
+    if let Commands::Serve {
+        ref host,
+        port,
+        sqlite: Some(ref path),
+        ref admin_token,
+    } = cli.command
+    {
+        let db = SqliteDatabase::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let addr = format!("{}:{}", host, port);
+        return serve(VetaService::new(db), &addr, admin_token.as_deref(), out).await;
+    }
+
+    // Export and import can use a SQLite database file instead, and sync
+    // can sync with one
+    if let Commands::Export {
+        sqlite: Some(ref path),
+        ..
+    }
+    | Commands::Import {
+        sqlite: Some(ref path),
+        ..
+    }
+    | Commands::Sync {
+        sqlite: Some(ref path),
+        ..
+    } = cli.command
+    {
+        let db = SqliteDatabase::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let service = in_space(VetaService::new(db), cli.space.as_deref())?;
+        return run(cli, service, out).await;
+    }
+
+    if let Commands::Convert { note_format } = cli.command {
+        if cli.remote.is_some() {
+            bail!("--remote can't be used with convert");
         }
+        let veta_dir = get_veta_dir()?;
+        let mut db = open_database(&veta_dir).await?;
+        let count = db.convert(note_format).context("Failed to convert notes")?;
+        let noun = if count == 1 { "note" } else { "notes" };
+        let message = format!("Converted {} {} to {}", count, noun, note_format);
+        out.outcome("convert", vec![], message);
         return Ok(());
     }
 
+    if let Some(ref url) = cli.remote {
+        let db = remote_database(url, cli.token.as_deref());
+        let service = in_space(VetaService::new(db), cli.space.as_deref())?;
+        return run(cli, service, out).await;
+    }
+    if cli.space.is_some() {
+        bail!("--space needs --remote, since the .veta directory holds a single namespace");
+    }
+
     // All other commands need the database
     let veta_dir = get_veta_dir()?;
     let db = open_database(&veta_dir).await?;
-    let service = VetaService::new(db);
+    run(cli, VetaService::new(db), out).await
+}
 
+/// Run `cli.command` against `service`, the store `--remote` or `--sqlite`
+/// picked, else the .veta directory.
+async fn run<D: Database>(cli: Cli, service: VetaService<D>, out: Output) -> Result<()> {
     match cli.command {
-        Commands::Init { .. } => unreachable!(),
+        Commands::Init { .. } | Commands::Convert { .. } | Commands::Merge { .. } => {
+            unreachable!()
+        }
 
         Commands::Add {
             title,
             tags,
             body,
             references,
+            editor: true,
+        } => {
+            let document = Document {
+                title: title.unwrap_or_default(),
+                tags: tags.map(|t| parse_tags(&t)).unwrap_or_default(),
+                references: references.map(|r| parse_tags(&r)).unwrap_or_default(),
+                body: body.unwrap_or_default(),
+            };
+            let edited = editor::edit(&document)?;
+            let Document {
+                title,
+                tags,
+                references,
+                body,
+            } = edited.document.clone();
+            if title.trim().is_empty() && body.trim().is_empty() {
+                drop(edited);
+                out.fail("validation", "The note is empty, so it wasn't added");
+            }
+            let result = service.add_note(title, body, tags, references).await;
+            let id = edited.keep_on_error(result)?;
+            out.outcome("add", vec![id], format!("Added note {}", id));
+        }
+
+        Commands::Add {
+            title,
+            tags,
+            body,
+            references,
+            editor: false,
         } => {
             let body = match body {
                 Some(b) => b,
                 None => read_stdin()?,
             };
-            let tags = parse_tags(&tags);
+            let title = title.unwrap_or_default();
+            let tags = parse_tags(&tags.unwrap_or_default());
             let references = references.map(|r| parse_tags(&r)).unwrap_or_default();
             let id = service.add_note(title, body, tags, references).await?;
-            println!("Added note {}", id);
+            out.outcome("add", vec![id], format!("Added note {}", id));
         }
 
         Commands::Ls {
             tags,
+            query,
             from,
             to,
+            sort,
+            asc,
             head,
+            after,
+            page,
         } => {
-            let from = from.map(|s| parse_human_date(&s)).transpose()?;
-            let to = to.map(|s| parse_human_date(&s)).transpose()?;
-            let tags = tags.map(|t| parse_tags(&t));
-
+            let tags = parse_tag_expr(tags)?;
+            let ParsedQuery { query, pattern } = parse_note_query(query, tags)?;
+            let query = match sort {
+                Some(field) => query.with_time_field(field)?,
+                None => query,
+            };
             let query = NoteQuery {
-                tags: tags.clone(),
-                from: from.clone(),
-                to: to.clone(),
-                limit: Some(head),
+                order: if asc { SortOrder::Asc } else { SortOrder::Desc },
+                from: from
+                    .map(|s| parse_human_date(&s))
+                    .transpose()?
+                    .or(query.from),
+                to: to.map(|s| parse_human_date(&s)).transpose()?.or(query.to),
+                ..query
             };
-            let notes = service.list_notes(query).await?;
-            let num_notes = notes.len() as i64;
 
-            for note in notes {
-                println!(
-                    "{}: {} ({}) -- {}",
-                    note.id, note.title, note.updated_at, note.body_preview
-                );
-            }
+            let first_page = after.is_none() && page.unwrap_or(1) == 1;
+            let paged = paginate_query(query.clone(), head, after, page)?;
+            let notes = match pattern {
+                Some(ref pattern) => service.grep(pattern, paged, false).await?,
+                None => service.list_notes(paged).await?,
+            };
+
+            print_notes(&notes, query.time_field, out);
 
-            // Show truncation message if there are more notes
-            if head > 0 && num_notes >= head {
-                let count_query = NoteQuery {
-                    tags,
-                    from,
-                    to,
-                    limit: None,
+            // Show truncation message on the first page if there are more notes
+            if out.is_text() && first_page && notes.next_cursor.is_some() {
+                let which = if asc { "oldest" } else { "latest" };
+                let total = match pattern {
+                    Some(ref pattern) => {
+                        let all = NoteQuery {
+                            limit: Some(0),
+                            ..query.clone()
+                        };
+                        service.grep(pattern, all, false).await?.notes.len() as i64
+                    }
+                    None => service.count_notes(query).await?,
                 };
-                let total = service.count_notes(count_query).await?;
-                if total > head {
-                    println!("[Showing the latest {}/{} notes]", head, total);
-                }
+                println!("[Showing the {} {}/{} notes]", which, head, total);
             }
+            print_next_page(&notes, page, out);
         }
 
         Commands::Show { ids, head } => {
             let ids = parse_ids(&ids)?;
             if ids.is_empty() {
-                eprintln!("No note IDs provided");
-                std::process::exit(1);
+                out.fail("validation", "No note IDs provided");
             }
 
             let mut not_found = Vec::new();
             let mut first = true;
+            let mut found = Vec::new();
 
             for id in &ids {
                 match service.get_note(*id).await? {
+                    Some(mut note) if !out.is_text() => {
+                        if let Some(n) = head {
+                            note.body = note.body.lines().take(n).collect::<Vec<_>>().join("\n");
+                        }
+                        found.push(note);
+                    }
                     Some(note) => {
                         if !first {
                             println!("\n{}\n", "=".repeat(40));
@@ -379,6 +1318,7 @@ async fn main() -> Result<()> {
                         }
 
                         println!("\n---\n");
+                        println!("Created: {}", note.created_at);
                         println!("Last modified: {}", note.updated_at);
                         println!("Tags: {}", note.tags.join(","));
                         if !note.references.is_empty() {
@@ -387,6 +1327,7 @@ async fn main() -> Result<()> {
                                 println!("  - {}", reference);
                             }
                         }
+                        print_links(&service, *id).await?;
                     }
                     None => {
                         not_found.push(*id);
@@ -394,33 +1335,169 @@ async fn main() -> Result<()> {
                 }
             }
 
+            if !out.is_text() {
+                out.records(&found);
+            }
             if !not_found.is_empty() {
                 if !first {
                     eprintln!(); // Add spacing after last note
                 }
-                for id in &not_found {
-                    eprintln!("Note {} not found", id);
-                }
-                std::process::exit(1);
+                out.fail("not_found", &not_found_message(&not_found, ""));
+            }
+        }
+
+        Commands::Log { id } => {
+            let note = match service.get_note(id).await? {
+                Some(note) => note,
+                None => out.fail("not_found", &format!("Note {} not found", id)),
+            };
+            if !out.is_text() {
+                out.records(&service.list_revisions(id).await?);
+                return Ok(());
+            }
+            let current = note.to_summary(140);
+            println!(
+                "current: {} ({}) -- {}",
+                current.title, current.updated_at, current.body_preview
+            );
+            for revision in service.list_revisions(id).await? {
+                let summary = revision.to_note().to_summary(140);
+                println!(
+                    "{}: {} ({}) -- {}",
+                    revision.revision, summary.title, summary.updated_at, summary.body_preview
+                );
             }
         }
 
+        Commands::Diff { id, rev } => {
+            let diff = service.diff_revision(id, rev).await?;
+            if out.is_text() {
+                print!("{}", diff);
+            } else {
+                out.outcome("diff", vec![id], diff);
+            }
+        }
+
+        Commands::Restore { ids, rev } => {
+            let ids = parse_ids(&ids)?;
+            if ids.is_empty() {
+                out.fail("validation", "No note IDs provided");
+            }
+
+            if let Some(rev) = rev {
+                let [id] = ids[..] else {
+                    bail!("Only one note can be restored to a revision at a time");
+                };
+                service.restore_revision(id, rev).await?;
+                let message = format!("Restored note {} to revision {}", id, rev);
+                out.outcome("restore", vec![id], message);
+                return Ok(());
+            }
+
+            let restored = service.restore_notes(&ids).await?;
+            let message: Vec<String> = restored
+                .iter()
+                .map(|id| format!("Restored note {}", id))
+                .collect();
+            out.outcome("restore", restored.clone(), message.join("\n"));
+
+            let not_found: Vec<i64> = ids
+                .into_iter()
+                .filter(|id| !restored.contains(id))
+                .collect();
+            if !not_found.is_empty() {
+                out.fail("not_found", &not_found_message(&not_found, " in trash"));
+            }
+        }
+
+        Commands::Trash { empty } => {
+            if empty {
+                let count = service.empty_trash().await?;
+                let noun = if count == 1 { "note" } else { "notes" };
+                let message = format!("Permanently deleted {} {}", count, noun);
+                out.outcome("trash", vec![], message);
+                return Ok(());
+            }
+
+            let trash = service.list_trash().await?;
+            if !out.is_text() {
+                out.records(&trash);
+                return Ok(());
+            }
+            for trashed in trash {
+                let note = trashed.note.to_summary(140);
+                println!(
+                    "{}: {} (deleted {}) -- {}",
+                    note.id, note.title, trashed.deleted_at, note.body_preview
+                );
+            }
+        }
+
+        Commands::Undo => match service.undo().await? {
+            Some(operation) => {
+                let ids = match &operation {
+                    Operation::Add { id } | Operation::Update { id, .. } => vec![*id],
+                    Operation::Delete { ids } | Operation::Restore { ids } => ids.clone(),
+                    Operation::Retag { notes } | Operation::Alias { notes, .. } => {
+                        notes.iter().map(|note| note.id).collect()
+                    }
+                };
+                out.outcome("undo", ids, format!("Undid {}", operation));
+            }
+            None => out.fail("error", "Nothing to undo"),
+        },
+
         Commands::Tags => {
             let tags = service.list_tags().await?;
-            for tag in tags {
-                let noun = if tag.count == 1 { "note" } else { "notes" };
-                println!("{} ({} {})", tag.name, tag.count, noun);
+            if !out.is_text() {
+                out.records(&tags);
+                return Ok(());
+            }
+            print_tag_tree(&tags, None);
+        }
+
+        Commands::Spaces => {
+            let spaces = service.list_namespaces().await?;
+            if !out.is_text() {
+                out.records(&spaces);
+                return Ok(());
+            }
+            for space in spaces {
+                let noun = if space.count == 1 { "note" } else { "notes" };
+                println!("{} ({} {})", space.name, space.count, noun);
             }
         }
 
         Commands::Grep {
             pattern,
             tags,
+            query,
             case_sensitive,
+            head,
+            after,
+            page,
         } => {
-            let tags = tags.map(|t| parse_tags(&t));
-            let notes = service.grep(&pattern, tags, case_sensitive).await?;
-            for note in notes {
+            let tags = parse_tag_expr(tags)?;
+            let parsed = parse_note_query(query, tags)?;
+            if parsed.pattern.is_some() {
+                bail!("re: terms can't be used with grep; pass the regex as PATTERN instead");
+            }
+            let time_field = parsed.query.time_field;
+            let query = paginate_query(parsed.query, head, after, page)?;
+            let notes = service.grep(&pattern, query, case_sensitive).await?;
+            print_notes(&notes, time_field, out);
+            print_next_page(&notes, page, out);
+        }
+
+        Commands::Search { query, tags, head } => {
+            let tags = parse_tag_expr(tags)?;
+            let results = service.search(&query, tags, Some(head)).await?;
+            if !out.is_text() {
+                out.records(&results);
+                return Ok(());
+            }
+            for result in results {
+                let note = result.note;
                 println!(
                     "{}: {} ({}) -- {}",
                     note.id, note.title, note.updated_at, note.body_preview
@@ -428,13 +1505,109 @@ async fn main() -> Result<()> {
             }
         }
 
+        Commands::Serve {
+            host,
+            port,
+            admin_token,
+            ..
+        } => {
+            let addr = format!("{}:{}", host, port);
+            serve(service, &addr, admin_token.as_deref(), out).await?;
+        }
+
+        Commands::Keys { command } => keys(&service, command, out).await?,
+
+        Commands::Tag { command } => tag(&service, command, out).await?,
+
+        // The store picked is the remote side; the local side is always
+        // the .veta directory
+        Commands::Sync {
+            mode,
+            conflict,
+            sqlite,
+        } => {
+            let name = match (sqlite, cli.remote) {
+                (Some(path), _) => std::fs::canonicalize(path)?.display().to_string(),
+                (None, Some(url)) => url,
+                (None, None) => bail!("veta sync needs --remote <URL> or --sqlite <PATH>"),
+            };
+            let veta_dir = get_veta_dir()?;
+            let local = VetaService::new(open_database(&veta_dir).await?);
+            let key = sync_state_key(&name, cli.space.as_deref());
+            sync_with(&veta_dir, &local, &service, &key, mode, conflict, out).await?;
+        }
+
+        Commands::Export { path, to, .. } => {
+            let notes = service.export().await?;
+            match (to, path) {
+                (ExportFormat::Jsonl, Some(path)) => {
+                    std::fs::write(&path, write_archive(&notes))
+                        .with_context(|| format!("Failed to write {}", path.display()))?
+                }
+                (ExportFormat::Jsonl, None) => {
+                    io::stdout().write_all(write_archive(&notes).as_bytes())?
+                }
+                (ExportFormat::Obsidian, Some(dir)) => {
+                    let count = vault::write_vault(&dir, &notes)?;
+                    let noun = if count == 1 { "note" } else { "notes" };
+                    println!("Exported {} {} to {}", count, noun, dir.display());
+                }
+                (ExportFormat::Obsidian, None) => {
+                    bail!("veta export --to obsidian needs a directory to write to")
+                }
+            }
+        }
+
+        Commands::Import {
+            file,
+            from: ExportFormat::Obsidian,
+            ..
+        } => {
+            let dir = file.context("veta import --from obsidian needs a vault directory")?;
+            let notes = vault::read_vault(&dir)?;
+            let count = notes.len();
+            // Add the notes one by one, so they're normalized like any other
+            for note in notes {
+                service
+                    .add_note(note.title, note.body, note.tags, note.references)
+                    .await?;
+            }
+            let noun = if count == 1 { "note" } else { "notes" };
+            println!("Imported {} {} from {}", count, noun, dir.display());
+        }
+
+        Commands::Import { file, mode, .. } => {
+            let archive = match file {
+                Some(path) => std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?,
+                None if is_stdin_tty() => {
+                    bail!("veta import needs an archive file, or an archive on stdin")
+                }
+                None => read_stdin()?,
+            };
+            let notes = read_archive(&archive)?;
+            let count = notes.len();
+            let summary = service.import(notes, mode).await?;
+            let noun = if count == 1 { "note" } else { "notes" };
+            println!("Imported {} {} ({})", count, noun, summary);
+        }
+
+        Commands::Mcp => mcp(&service).await?,
+
         Commands::Edit {
             id,
             title,
             tags,
             body,
             references,
+            editor,
         } => {
+            let no_options =
+                title.is_none() && tags.is_none() && body.is_none() && references.is_none();
+            if editor || (no_options && is_stdin_tty()) {
+                return edit_in_editor(&service, id, out).await;
+            }
+
             let body = if body.is_none() && !is_stdin_tty() {
                 Some(read_stdin()?)
             } else {
@@ -448,60 +1621,126 @@ async fn main() -> Result<()> {
                 references: references.map(|r| parse_tags(&r)),
             };
 
-            let mut updated_fields = Vec::new();
-            if update.title.is_some() {
-                updated_fields.push("title");
-            }
-            if update.body.is_some() {
-                updated_fields.push("body");
-            }
-            if update.tags.is_some() {
-                updated_fields.push("tags");
-            }
-            if update.references.is_some() {
-                updated_fields.push("references");
-            }
-
+            let updated_fields = updated_fields(&update);
             if updated_fields.is_empty() {
-                eprintln!("Nothing to update");
-                std::process::exit(1);
+                out.fail("validation", "Nothing to update");
             }
 
             if service.update_note(id, update).await? {
-                println!("Edited note {}: Updated {}", id, updated_fields.join(", "));
+                let message = format!("Edited note {}: Updated {}", id, updated_fields.join(", "));
+                out.outcome("edit", vec![id], message);
             } else {
-                eprintln!("Note {} not found", id);
-                std::process::exit(1);
+                out.fail("not_found", &format!("Note {} not found", id));
             }
         }
 
-        Commands::Rm { ids } => {
-            let ids = parse_ids(&ids)?;
-            if ids.is_empty() {
-                eprintln!("No note IDs provided");
-                std::process::exit(1);
+        Commands::Link { from, to, kind } => {
+            if service.link_notes(from, to, kind).await? {
+                let message = format!("Linked note {} to note {} as {}", from, to, kind);
+                out.outcome("link", vec![from], message);
+            } else {
+                let message = format!("Note {} already links to note {} as {}", from, to, kind);
+                out.outcome("link", vec![], message);
             }
+        }
 
-            let mut deleted = Vec::new();
-            let mut not_found = Vec::new();
+        Commands::Unlink { from, to, kind } => {
+            let kinds = service.unlink_notes(from, to, kind).await?;
+            let kinds: Vec<String> = kinds.iter().map(LinkKind::to_string).collect();
+            let message = format!(
+                "Removed {} {} from note {} to note {}",
+                kinds.join(", "),
+                if kinds.len() == 1 { "link" } else { "links" },
+                from,
+                to
+            );
+            out.outcome("unlink", vec![from], message);
+        }
 
-            for id in &ids {
-                if service.delete_note(*id).await? {
-                    deleted.push(*id);
-                } else {
-                    not_found.push(*id);
+        Commands::CheckRefs { ids, root } => {
+            let root = match root {
+                Some(root) => root,
+                None => project_root()?,
+            };
+            let notes = match ids {
+                Some(ids) => {
+                    let mut notes = Vec::new();
+                    let mut not_found = Vec::new();
+                    for id in parse_ids(&ids)? {
+                        match service.get_note(id).await? {
+                            Some(note) => notes.push(note),
+                            None => not_found.push(id),
+                        }
+                    }
+                    if !not_found.is_empty() {
+                        out.fail("not_found", &not_found_message(&not_found, ""));
+                    }
+                    notes
+                }
+                None => service.export().await?,
+            };
+
+            let mut checked = 0;
+            let mut broken = Vec::new();
+            for note in &notes {
+                for reference in &note.references {
+                    match Reference::parse(reference) {
+                        Reference::File { .. } => {
+                            checked += 1;
+                            broken.extend(refs::check_file(&root, note.id, reference));
+                        }
+                        Reference::Note { id } => {
+                            checked += 1;
+                            if service.get_note(id).await?.is_none() {
+                                broken.push(refs::BrokenRef::missing_note(note.id, reference, id));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if !out.is_text() {
+                out.records(&broken);
+            } else {
+                for b in &broken {
+                    println!("Note {}: {}: {}", b.id, b.reference, b.message);
+                    if let Some(ref suggestion) = b.suggestion {
+                        println!("  use {}", suggestion);
+                    }
                 }
             }
+            let noun = if checked == 1 {
+                "reference"
+            } else {
+                "references"
+            };
+            if !broken.is_empty() {
+                let verb = if broken.len() == 1 { "is" } else { "are" };
+                let message = format!("{} of {} {} {} broken", broken.len(), checked, noun, verb);
+                out.fail("broken_refs", &message);
+            }
+            if out.is_text() {
+                println!("Checked {} {}; none are broken", checked, noun);
+            }
+        }
 
-            for id in &deleted {
-                println!("Deleted note {}", id);
+        Commands::Rm { ids } => {
+            let ids = parse_ids(&ids)?;
+            if ids.is_empty() {
+                out.fail("validation", "No note IDs provided");
             }
 
+            let deleted = service.delete_notes(&ids).await?;
+            let message: Vec<String> = deleted
+                .iter()
+                .map(|id| format!("Deleted note {}", id))
+                .collect();
+            out.outcome("rm", deleted.clone(), message.join("\n"));
+
+            let not_found: Vec<i64> = ids.into_iter().filter(|id| !deleted.contains(id)).collect();
             if !not_found.is_empty() {
-                for id in &not_found {
-                    eprintln!("Note {} not found", id);
-                }
-                std::process::exit(1);
+                out.fail("not_found", &not_found_message(&not_found, ""));
             }
         }
     }
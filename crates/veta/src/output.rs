@@ -0,0 +1,468 @@
+//! Machine-readable output, selected with `--format` or `--json`.
+//!
+//! Commands print text by default. With `json`, `jsonl` or `tsv`, listings
+//! print the serde forms of the core types (the same as the HTTP API), and
+//! commands that change something print an [`Outcome`]. Errors are printed
+//! to stderr as `{"error": {"kind": ..., "message": ...}}`, on one line.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde::Serialize;
+use veta_core::{
+    ApiKey, NamespaceCount, Note, NotePage, NoteSummary, Revision, SearchResult, TagAlias,
+    TagCount, TrashedNote,
+};
+
+use crate::refs::BrokenRef;
+
+/// Exit code for errors, including notes that weren't found.
+pub const EXIT_ERROR: i32 = 1;
+
+/// Exit code for invalid arguments, as used by clap.
+pub const EXIT_USAGE: i32 = 2;
+
+/// The `--format` option.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    /// One JSON document.
+    Json,
+    /// One JSON object per line.
+    Jsonl,
+    /// Tab-separated values with a header row.
+    Tsv,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Jsonl => write!(f, "jsonl"),
+            OutputFormat::Tsv => write!(f, "tsv"),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "tsv" => Ok(OutputFormat::Tsv),
+            _ => Err(format!(
+                "invalid format '{}': expected text, json, jsonl or tsv",
+                s
+            )),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Guess the format from raw arguments, for errors from parsing them.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut format = OutputFormat::Text;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let value = match arg.as_str() {
+                "--" => break,
+                "--json" => Some("json".to_string()),
+                "--format" => args.next(),
+                _ => arg.strip_prefix("--format=").map(str::to_string),
+            };
+            if let Some(parsed) = value.and_then(|value| value.parse().ok()) {
+                format = parsed;
+            }
+        }
+        format
+    }
+}
+
+/// Prints the results of commands in the chosen format.
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    format: OutputFormat,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Self {
+        Output { format }
+    }
+
+    /// Whether to print the usual text.
+    pub fn is_text(&self) -> bool {
+        self.format == OutputFormat::Text
+    }
+
+    /// Print records: a JSON array, one JSON object per line, or a table.
+    pub fn records<T: Record>(&self, records: &[T]) {
+        match self.format {
+            OutputFormat::Json => println!("{}", to_json(&records, true)),
+            OutputFormat::Jsonl => {
+                for record in records {
+                    println!("{}", to_json(record, false));
+                }
+            }
+            OutputFormat::Tsv => {
+                println!("{}", T::COLUMNS.join("\t"));
+                for record in records {
+                    let fields: Vec<String> = record.fields().iter().map(|f| escape(f)).collect();
+                    println!("{}", fields.join("\t"));
+                }
+            }
+            OutputFormat::Text => {}
+        }
+    }
+
+    /// Print a single record: a JSON object, or a list of one otherwise.
+    pub fn record<T: Record>(&self, record: &T) {
+        match self.format {
+            OutputFormat::Json => println!("{}", to_json(record, true)),
+            _ => self.records(std::slice::from_ref(record)),
+        }
+    }
+
+    /// Print a page of a listing. In JSON it's the whole page, with the
+    /// cursor for the next one; otherwise just the notes.
+    pub fn page(&self, page: &NotePage) {
+        match self.format {
+            OutputFormat::Json => println!("{}", to_json(page, true)),
+            _ => self.records(&page.notes),
+        }
+    }
+
+    /// Print what a command did: the message in text, or an [`Outcome`].
+    pub fn outcome(&self, command: &str, ids: Vec<i64>, message: String) {
+        if self.is_text() {
+            if !message.is_empty() {
+                println!("{}", message);
+            }
+            return;
+        }
+        self.record(&Outcome {
+            command: command.to_string(),
+            ids,
+            message,
+        });
+    }
+
+    /// Print an error and exit. `message` may have several lines, one per
+    /// problem; in text each is printed as is.
+    pub fn fail(&self, kind: &str, message: &str) -> ! {
+        if self.is_text() {
+            eprintln!("{}", message);
+        } else {
+            print_error(kind, message);
+        }
+        std::process::exit(EXIT_ERROR);
+    }
+}
+
+/// What a command that changes something did.
+#[derive(Debug, Serialize)]
+pub struct Outcome {
+    /// The command, e.g. `add` or `keys revoke`.
+    pub command: String,
+    /// The notes (or API keys) it changed.
+    pub ids: Vec<i64>,
+    /// What the command prints in text.
+    pub message: String,
+}
+
+/// A new API key, with the key itself.
+#[derive(Debug, Serialize)]
+pub struct NewApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+/// Print the error object to stderr.
+pub fn print_error(kind: &str, message: &str) {
+    let error = serde_json::json!({ "error": { "kind": kind, "message": message } });
+    eprintln!("{}", error);
+}
+
+/// The `kind` of an error object: `validation`, `not_found`, `forbidden`,
+/// `database` or `internal` for errors from veta itself, or `error`.
+pub fn error_kind(error: &anyhow::Error) -> &'static str {
+    let core_error = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<veta_core::Error>());
+    match core_error {
+        Some(veta_core::Error::Validation(_)) => "validation",
+        Some(veta_core::Error::NotFound(_)) => "not_found",
+        Some(veta_core::Error::Forbidden(_)) => "forbidden",
+        Some(veta_core::Error::Database(_)) => "database",
+        Some(veta_core::Error::Internal(_)) => "internal",
+        None => "error",
+    }
+}
+
+fn to_json<T: Serialize + ?Sized>(value: &T, pretty: bool) -> String {
+    let json = if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    };
+    json.expect("output types serialize to JSON")
+}
+
+/// Escape a TSV field, so that every record is one line.
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    let values: Vec<String> = values.iter().map(T::to_string).collect();
+    values.join(",")
+}
+
+/// Something printed as a row in TSV, and as its serde form in JSON.
+pub trait Record: Serialize {
+    /// The header row.
+    const COLUMNS: &'static [&'static str];
+
+    /// The fields, in the order of `COLUMNS`. Lists are comma-separated.
+    fn fields(&self) -> Vec<String>;
+}
+
+impl Record for NoteSummary {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "title",
+        "tags",
+        "created_at",
+        "updated_at",
+        "body_preview",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.title.clone(),
+            join(&self.tags),
+            self.created_at.clone(),
+            self.updated_at.clone(),
+            self.body_preview.clone(),
+        ]
+    }
+}
+
+impl Record for Note {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "title",
+        "tags",
+        "references",
+        "created_at",
+        "updated_at",
+        "body",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.title.clone(),
+            join(&self.tags),
+            join(&self.references),
+            self.created_at.clone(),
+            self.updated_at.clone(),
+            self.body.clone(),
+        ]
+    }
+}
+
+impl Record for SearchResult {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "title",
+        "tags",
+        "created_at",
+        "updated_at",
+        "body_preview",
+        "score",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        let mut fields = self.note.fields();
+        fields.push(self.score.to_string());
+        fields
+    }
+}
+
+impl Record for TrashedNote {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "title",
+        "tags",
+        "references",
+        "created_at",
+        "updated_at",
+        "body",
+        "deleted_at",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        let mut fields = self.note.fields();
+        fields.push(self.deleted_at.clone());
+        fields
+    }
+}
+
+impl Record for Revision {
+    const COLUMNS: &'static [&'static str] = &[
+        "note_id",
+        "revision",
+        "title",
+        "tags",
+        "references",
+        "updated_at",
+        "body",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.note_id.to_string(),
+            self.revision.to_string(),
+            self.title.clone(),
+            join(&self.tags),
+            join(&self.references),
+            self.updated_at.clone(),
+            self.body.clone(),
+        ]
+    }
+}
+
+impl Record for TagCount {
+    const COLUMNS: &'static [&'static str] = &["name", "count", "description"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.count.to_string(),
+            self.description.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+impl Record for TagAlias {
+    const COLUMNS: &'static [&'static str] = &["alias", "tag"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![self.alias.clone(), self.tag.clone()]
+    }
+}
+
+impl Record for NamespaceCount {
+    const COLUMNS: &'static [&'static str] = &["name", "count"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![self.name.clone(), self.count.to_string()]
+    }
+}
+
+impl Record for ApiKey {
+    const COLUMNS: &'static [&'static str] =
+        &["id", "name", "scope", "tags", "namespaces", "created_at"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.name.clone(),
+            self.scope.to_string(),
+            join(&self.tags),
+            join(&self.namespaces),
+            self.created_at.clone(),
+        ]
+    }
+}
+
+impl Record for NewApiKey {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "name",
+        "scope",
+        "tags",
+        "namespaces",
+        "created_at",
+        "key",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        let mut fields = self.api_key.fields();
+        fields.push(self.key.clone());
+        fields
+    }
+}
+
+impl Record for BrokenRef {
+    const COLUMNS: &'static [&'static str] =
+        &["id", "reference", "problem", "message", "suggestion"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.reference.clone(),
+            self.problem.to_string(),
+            self.message.clone(),
+            self.suggestion.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+impl Record for Outcome {
+    const COLUMNS: &'static [&'static str] = &["command", "ids", "message"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![self.command.clone(), join(&self.ids), self.message.clone()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_args() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            OutputFormat::from_args(args(&["veta", "ls", "--json"])),
+            OutputFormat::Json
+        );
+        assert_eq!(
+            OutputFormat::from_args(args(&["veta", "--format", "tsv", "ls"])),
+            OutputFormat::Tsv
+        );
+        assert_eq!(
+            OutputFormat::from_args(args(&["veta", "ls", "--format=jsonl"])),
+            OutputFormat::Jsonl
+        );
+        assert_eq!(
+            OutputFormat::from_args(args(&["veta", "add", "--", "--json"])),
+            OutputFormat::Text
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\tb\nc\\d"), "a\\tb\\nc\\\\d");
+        assert_eq!(escape("plain"), "plain");
+    }
+}
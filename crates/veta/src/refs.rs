@@ -0,0 +1,206 @@
+//! Checking note references, for `veta check-refs`.
+//!
+//! File references are resolved against the project root, the directory
+//! that holds `.veta`. A file reference is broken if the file is missing or
+//! its lines are past the end. One with a snippet (see
+//! `veta_core::reference`) is also broken if the snippet isn't at its lines
+//! anymore; if the snippet is elsewhere in the file, the check suggests the
+//! reference to the lines it moved to.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::Serialize;
+use veta_core::Reference;
+
+/// Why a reference is broken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    MissingFile,
+    /// The file exists but couldn't be read, e.g. it's a directory.
+    Unreadable,
+    OutOfRange,
+    /// The snippet is in the file, but not at the referenced lines.
+    Moved,
+    SnippetNotFound,
+    MissingNote,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::MissingFile => write!(f, "missing_file"),
+            Problem::Unreadable => write!(f, "unreadable"),
+            Problem::OutOfRange => write!(f, "out_of_range"),
+            Problem::Moved => write!(f, "moved"),
+            Problem::SnippetNotFound => write!(f, "snippet_not_found"),
+            Problem::MissingNote => write!(f, "missing_note"),
+        }
+    }
+}
+
+/// A broken reference of a note.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BrokenRef {
+    /// The note with the reference.
+    pub id: i64,
+    /// The reference as written.
+    pub reference: String,
+    pub problem: Problem,
+    pub message: String,
+    /// A reference to use instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+impl BrokenRef {
+    fn new(id: i64, reference: &str, problem: Problem, message: String) -> Self {
+        BrokenRef {
+            id,
+            reference: reference.trim().to_string(),
+            problem,
+            message,
+            suggestion: None,
+        }
+    }
+
+    /// A reference to a note that doesn't exist.
+    pub fn missing_note(id: i64, reference: &str, note: i64) -> Self {
+        let message = format!("note {} doesn't exist", note);
+        BrokenRef::new(id, reference, Problem::MissingNote, message)
+    }
+}
+
+/// Check a reference of note `id` if it's to a file. Returns why it's
+/// broken, or `None` if it's fine or not a file reference.
+pub fn check_file(root: &Path, id: i64, reference: &str) -> Option<BrokenRef> {
+    let Reference::File {
+        path,
+        lines,
+        snippet,
+    } = Reference::parse(reference)
+    else {
+        return None;
+    };
+    let broken = |problem, message| Some(BrokenRef::new(id, reference, problem, message));
+
+    let full_path = root.join(&path);
+    if !full_path.exists() {
+        return broken(Problem::MissingFile, format!("{} doesn't exist", path));
+    }
+    let snippet = snippet.filter(|s| !s.trim().is_empty());
+    if lines.is_none() && snippet.is_none() {
+        return None;
+    }
+    let contents = match std::fs::read(&full_path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => {
+            return broken(
+                Problem::Unreadable,
+                format!("couldn't read {}: {}", path, e),
+            )
+        }
+    };
+    let file_lines: Vec<&str> = contents.lines().collect();
+    let out_of_range = lines.filter(|lines| lines.end > file_lines.len());
+    let out_of_range_message = || {
+        let noun = if file_lines.len() == 1 {
+            "line"
+        } else {
+            "lines"
+        };
+        format!("{} has {} {}", path, file_lines.len(), noun)
+    };
+
+    let Some(snippet) = snippet else {
+        return match out_of_range {
+            Some(_) => broken(Problem::OutOfRange, out_of_range_message()),
+            None => None,
+        };
+    };
+    let needle = snippet.trim();
+    let matches: Vec<usize> = (1..=file_lines.len())
+        .filter(|&line| file_lines[line - 1].contains(needle))
+        .collect();
+    let Some(range) = lines else {
+        if matches.is_empty() {
+            let message = format!("\"{}\" isn't in {}", snippet, path);
+            return broken(Problem::SnippetNotFound, message);
+        }
+        return None;
+    };
+    if matches
+        .iter()
+        .any(|line| (range.start..=range.end).contains(line))
+    {
+        return None;
+    }
+
+    // The snippet moved: suggest the match closest to where it was
+    match matches.iter().min_by_key(|line| line.abs_diff(range.start)) {
+        Some(&line) => {
+            let mut lines = range.moved_to(line);
+            lines.end = lines.end.min(file_lines.len());
+            let moved = Reference::File {
+                path: path.clone(),
+                lines: Some(lines),
+                snippet: Some(snippet.clone()),
+            };
+            let message = format!("\"{}\" moved to line {}", snippet, line);
+            let mut broken = BrokenRef::new(id, reference, Problem::Moved, message);
+            broken.suggestion = Some(moved.to_string());
+            Some(broken)
+        }
+        None if out_of_range.is_some() => broken(Problem::OutOfRange, out_of_range_message()),
+        None => broken(
+            Problem::SnippetNotFound,
+            format!("\"{}\" isn't in {} anymore", snippet, path),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_file() {
+        let root = tempfile::tempdir().unwrap();
+        let code = "use std::fs;\n\nfn main() {\n    run();\n}\n\nfn run() {}\n";
+        std::fs::create_dir(root.path().join("src")).unwrap();
+        std::fs::write(root.path().join("src/main.rs"), code).unwrap();
+        let check = |reference: &str| check_file(root.path(), 1, reference);
+
+        for fine in [
+            "src/main.rs",
+            "src/main.rs:7",
+            "src/main.rs:3-5 \"fn main\"",
+            "src/main.rs:4 \"run();\"",
+            "src",
+            "https://example.com/missing.rs",
+            "note:99",
+        ] {
+            assert_eq!(check(fine), None, "{}", fine);
+        }
+
+        let problem = |reference: &str| check(reference).unwrap().problem;
+        assert_eq!(problem("src/lib.rs:1"), Problem::MissingFile);
+        assert_eq!(problem("src/main.rs:6-8"), Problem::OutOfRange);
+        assert_eq!(problem("src/main.rs:20 \"fn gone\""), Problem::OutOfRange);
+        assert_eq!(
+            problem("src/main.rs:3 \"fn gone\""),
+            Problem::SnippetNotFound
+        );
+        assert_eq!(problem("src/main.rs \"fn gone\""), Problem::SnippetNotFound);
+        assert_eq!(problem("src:1"), Problem::Unreadable);
+
+        let moved = check("src/main.rs:1-2 \"fn run\"").unwrap();
+        assert_eq!(moved.problem, Problem::Moved);
+        assert_eq!(moved.message, "\"fn run\" moved to line 7");
+        assert_eq!(
+            moved.suggestion.as_deref(),
+            Some("src/main.rs:7 \"fn run\"")
+        );
+    }
+}
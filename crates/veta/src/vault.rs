@@ -0,0 +1,356 @@
+//! Obsidian vaults: a directory of Markdown files with YAML frontmatter.
+//!
+//! `veta export --to obsidian` writes one file per note, named after its
+//! title, with the tags, references and timestamps as frontmatter properties.
+//! `veta import --from obsidian` reads any vault: tags come from the
+//! frontmatter and from `#tags` in the text, and links in the text become
+//! references.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_yaml::Value;
+use veta_core::Note;
+
+/// What `veta export` writes and `veta import` reads.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A JSON Lines archive, see `veta_core::archive`.
+    #[default]
+    Jsonl,
+    /// A directory of Markdown files.
+    Obsidian,
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportFormat::Jsonl => write!(f, "jsonl"),
+            ExportFormat::Obsidian => write!(f, "obsidian"),
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            "obsidian" => Ok(ExportFormat::Obsidian),
+            _ => Err(format!(
+                "invalid format '{}': expected jsonl or obsidian",
+                s
+            )),
+        }
+    }
+}
+
+/// A note read from a vault, ready for `VetaService::add_note`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct VaultNote {
+    pub title: String,
+    pub body: String,
+    pub tags: Vec<String>,
+    pub references: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Frontmatter<'a> {
+    /// Only written if the file name couldn't hold the whole title.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+    tags: &'a [String],
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    references: &'a [String],
+    created: &'a str,
+    updated: &'a str,
+}
+
+/// Write notes to a vault directory, one file per note. Returns the number
+/// of files written.
+pub fn write_vault(dir: &Path, notes: &[Note]) -> Result<usize> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let mut taken = HashSet::new();
+    for note in notes {
+        let mut name = file_name(&note.title);
+        // Titles aren't unique, and some file systems ignore case
+        if !taken.insert(name.to_lowercase()) {
+            name = format!("{} ({})", name, note.id);
+            taken.insert(name.to_lowercase());
+        }
+        let path = dir.join(format!("{}.md", name));
+        std::fs::write(&path, render_note(note, &name))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(notes.len())
+}
+
+/// Read every Markdown file in a vault, in path order. Hidden directories
+/// such as `.obsidian` and `.trash` are skipped.
+pub fn read_vault(dir: &Path) -> Result<Vec<VaultNote>> {
+    let mut paths = Vec::new();
+    collect_markdown(dir, &mut paths)?;
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let stem = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            parse_note(&stem, &contents).with_context(|| format!("Invalid note {}", path.display()))
+        })
+        .collect()
+}
+
+fn collect_markdown(dir: &Path, paths: &mut Vec<std::path::PathBuf>) -> Result<()> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .map(|n| n.to_string_lossy().starts_with('.'))
+            .unwrap_or(false);
+        if hidden {
+            continue;
+        }
+        if path.is_dir() {
+            collect_markdown(&path, paths)?;
+        } else if path.extension().map(|e| e == "md").unwrap_or(false) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// A file name for a note, without characters that Obsidian doesn't allow
+/// in file names or links.
+fn file_name(title: &str) -> String {
+    let name: String = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '#' | '^' | '[' | ']' => '-',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect();
+    let name = name.trim().trim_end_matches('.').trim();
+    if name.is_empty() {
+        "Untitled".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+fn render_note(note: &Note, name: &str) -> String {
+    let frontmatter = Frontmatter {
+        title: (name != note.title).then_some(note.title.as_str()),
+        tags: &note.tags,
+        references: &note.references,
+        created: &note.created_at,
+        updated: &note.updated_at,
+    };
+    let yaml = serde_yaml::to_string(&frontmatter).expect("frontmatter serializes");
+    format!("---\n{}---\n\n{}\n", yaml, note.body.trim_end())
+}
+
+/// Parse a Markdown file. The title is the frontmatter's `title`, or else the
+/// file name.
+pub fn parse_note(stem: &str, contents: &str) -> Result<VaultNote> {
+    let contents = contents
+        .trim_start_matches('\u{feff}')
+        .replace("\r\n", "\n");
+    let (frontmatter, body) = split_frontmatter(&contents);
+
+    let frontmatter: Value = match frontmatter {
+        Some(yaml) => serde_yaml::from_str(yaml).context("Invalid YAML frontmatter")?,
+        None => Value::Null,
+    };
+    let title = frontmatter
+        .get("title")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .unwrap_or_else(|| stem.to_string());
+
+    let mut tags = strings(frontmatter.get("tags").or_else(|| frontmatter.get("tag")));
+    tags.extend(inline_tags(body));
+    let tags = tags
+        .iter()
+        .map(|tag| tag.trim_start_matches('#').to_string())
+        .collect();
+
+    let mut references = strings(frontmatter.get("references"));
+    references.extend(links(body));
+    let mut seen = HashSet::new();
+    references.retain(|r| seen.insert(r.clone()));
+
+    Ok(VaultNote {
+        title,
+        body: body.trim().to_string(),
+        tags,
+        references,
+    })
+}
+
+/// Split a file into its frontmatter, if any, and the rest.
+fn split_frontmatter(contents: &str) -> (Option<&str>, &str) {
+    let rest = match contents.strip_prefix("---\n") {
+        Some(rest) => rest,
+        None => return (None, contents),
+    };
+    if let Some(body) = rest.strip_prefix("---\n") {
+        return (Some(""), body);
+    }
+    match rest.find("\n---\n") {
+        Some(end) => (Some(&rest[..end + 1]), &rest[end + 5..]),
+        None => match rest.strip_suffix("\n---") {
+            Some(yaml) => (Some(yaml), ""),
+            None => (None, contents),
+        },
+    }
+}
+
+/// A frontmatter property as a list of strings. Obsidian allows either a
+/// list or a single comma- or space-separated string.
+fn strings(value: Option<&Value>) -> Vec<String> {
+    let scalar = |value: &Value| match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    };
+    match value {
+        Some(Value::Sequence(items)) => items.iter().filter_map(scalar).collect(),
+        Some(Value::String(s)) => s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Some(value) => scalar(value).into_iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Lines of text outside fenced code blocks.
+fn prose_lines(body: &str) -> impl Iterator<Item = &str> {
+    let mut in_code = false;
+    body.lines().filter(move |line| {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+            return false;
+        }
+        !in_code
+    })
+}
+
+/// `#tags` in the text. Like Obsidian, a tag needs a character that isn't a
+/// digit, so `#123` isn't one.
+fn inline_tags(body: &str) -> Vec<String> {
+    let is_tag_char = |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '/');
+    let mut tags = Vec::new();
+    for line in prose_lines(body) {
+        let mut previous = ' ';
+        for (i, c) in line.char_indices() {
+            if c == '#' && previous.is_whitespace() {
+                let tag: String = line[i + 1..]
+                    .chars()
+                    .take_while(|&c| is_tag_char(c))
+                    .collect();
+                if tag.chars().any(|c| !c.is_ascii_digit()) {
+                    tags.push(tag);
+                }
+            }
+            previous = c;
+        }
+    }
+    tags
+}
+
+/// Targets of `[[wikilinks]]` and `[text](links)` in the text, except
+/// embedded images and links within the note. Wikilinks are kept as
+/// `[[Target]]`, which Obsidian shows as a link in the properties of an
+/// exported note.
+fn links(body: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    for line in prose_lines(body) {
+        let mut rest = line;
+        while let Some(start) = rest.find('[') {
+            let embedded = rest[..start].ends_with('!');
+            let after = &rest[start..];
+            if let Some(inner) = after.strip_prefix("[[") {
+                let Some(end) = inner.find("]]") else { break };
+                let target = inner[..end].split(['|', '#']).next().unwrap_or("").trim();
+                if !embedded && !target.is_empty() {
+                    links.push(format!("[[{}]]", target));
+                }
+                rest = &inner[end + 2..];
+            } else if let Some(close) = after.find("](") {
+                let target = &after[close + 2..];
+                let Some(end) = target.find(')') else { break };
+                let target = target[..end]
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or("")
+                    .trim_matches(|c| c == '<' || c == '>');
+                if !embedded && !target.is_empty() && !target.starts_with('#') {
+                    links.push(target.to_string());
+                }
+                rest = &after[close + 2 + end..];
+            } else {
+                rest = &after[1..];
+            }
+        }
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_note() {
+        let contents = "---\ntags: [ops, lang/rust]\nreferences:\n  - wrangler.toml\n---\n\n\
+                        Deploy with #cloudflare, see [[Auth flow|auth]] and\n\
+                        [the docs](https://example.com/docs \"Docs\"). Issue #123.\n\
+                        ![diagram](deploy.png)\n\n```\n#not-a-tag [[nor a link]]\n```\n";
+        let note = parse_note("Deploy", contents).unwrap();
+        assert_eq!(note.title, "Deploy");
+        assert_eq!(note.tags, vec!["ops", "lang/rust", "cloudflare"]);
+        assert_eq!(
+            note.references,
+            vec!["wrangler.toml", "[[Auth flow]]", "https://example.com/docs"]
+        );
+        assert!(note.body.starts_with("Deploy with #cloudflare"));
+    }
+
+    #[test]
+    fn test_parse_note_without_frontmatter() {
+        let note = parse_note("Scratch", "# Heading\r\n\r\nJust text\r\n").unwrap();
+        assert_eq!(note.title, "Scratch");
+        assert_eq!(note.body, "# Heading\n\nJust text");
+        assert!(note.tags.is_empty() && note.references.is_empty());
+
+        let note = parse_note("Tagged", "---\ntitle: Real title\ntags: a, b\n---").unwrap();
+        assert_eq!(note.title, "Real title");
+        assert_eq!(note.tags, vec!["a", "b"]);
+        assert!(parse_note("Broken", "---\ntags: [a\n---\n").is_err());
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("Auth: JWT / sessions"), "Auth- JWT - sessions");
+        assert_eq!(file_name("  ...  "), "Untitled");
+    }
+}
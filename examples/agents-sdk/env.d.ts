@@ -2,6 +2,7 @@ interface Env {
   OPENAI_API_KEY: string;
   Chat: DurableObjectNamespace<import("./src/agent").Chat>;
   VETA: Fetcher;
+  VETA_URL?: string;
 }
 
 declare namespace NodeJS {
@@ -3,17 +3,25 @@ import { z } from "zod/v3";
 import { getCurrentAgent } from "agents";
 import type { Chat } from "./agent";
 
-function getVetaFetcher() {
+function getVetaFetcher(): Pick<Fetcher, "fetch"> {
   const ctx = getCurrentAgent<Chat>();
   if (!ctx?.agent) {
     throw new Error("No agent context available");
   }
   // @ts-expect-error - env is protected but accessible at runtime
-  const fetcher = ctx.agent.env.VETA as Fetcher;
-  if (!fetcher) {
+  const env = ctx.agent.env as Env;
+  // VETA_URL points at a `veta serve` instance instead of the worker
+  if (env.VETA_URL) {
+    const baseUrl = env.VETA_URL.replace(/\/$/, "");
+    return {
+      fetch: (input: RequestInfo | URL, init?: RequestInit) =>
+        fetch(String(input).replace("http://veta", baseUrl), init),
+    } as Pick<Fetcher, "fetch">;
+  }
+  if (!env.VETA) {
     throw new Error("VETA service binding not available");
   }
-  return fetcher;
+  return env.VETA;
 }
 
 const VetaInput = z.object({
@@ -69,12 +77,14 @@ async function executeVetaCommand(input: VetaInput): Promise<string> {
     case "ls": {
       const params = input.tags?.length ? `?tags=${input.tags.join(",")}` : "";
       const res = await veta.fetch(`http://veta/notes${params}`);
-      const notes = (await res.json()) as {
-        id: number;
-        title: string;
-        body_preview: string;
-        tags: string[];
-      }[];
+      const { notes } = (await res.json()) as {
+        notes: {
+          id: number;
+          title: string;
+          body_preview: string;
+          tags: string[];
+        }[];
+      };
       if (!notes.length) return "No notes found.";
       return notes
         .map((n) => `[${n.id}] ${n.title} -- ${n.body_preview}`)
@@ -106,12 +116,14 @@ async function executeVetaCommand(input: VetaInput): Promise<string> {
         const error = await res.text();
         return `Search error: ${error}`;
       }
-      const notes = (await res.json()) as {
-        id: number;
-        title: string;
-        body_preview: string;
-        tags: string[];
-      }[];
+      const { notes } = (await res.json()) as {
+        notes: {
+          id: number;
+          title: string;
+          body_preview: string;
+          tags: string[];
+        }[];
+      };
       if (!notes.length) return "No matching notes found.";
       return notes
         .map((n) => `[${n.id}] ${n.title} -- ${n.body_preview}`)
@@ -0,0 +1,13 @@
+-- Previous versions of notes, saved before each update
+-- Tags and references are stored as JSON arrays
+
+CREATE TABLE IF NOT EXISTS note_revisions (
+    note_id INTEGER NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+    revision INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    tags TEXT NOT NULL DEFAULT '[]',
+    "references" TEXT NOT NULL DEFAULT '[]',
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (note_id, revision)
+);
@@ -0,0 +1,13 @@
+-- Soft delete: deleted notes stay in the table until the trash is emptied
+
+ALTER TABLE notes ADD COLUMN deleted_at TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_notes_deleted_at ON notes(deleted_at);
+
+-- Recent mutating operations, stored as JSON and used by undo
+
+CREATE TABLE IF NOT EXISTS journal (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    operation TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
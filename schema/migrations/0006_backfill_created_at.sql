@@ -0,0 +1,7 @@
+-- created_at has existed since the initial schema, but was never surfaced.
+-- Make sure every note has a sensible value before it is used for sorting.
+
+UPDATE notes SET created_at = updated_at
+WHERE created_at IS NULL OR created_at = '' OR created_at > updated_at;
+
+CREATE INDEX IF NOT EXISTS idx_notes_created_at ON notes(created_at);
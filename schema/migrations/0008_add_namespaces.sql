@@ -0,0 +1,9 @@
+-- Namespaces partition the database into isolated knowledge bases.
+-- Existing notes and undo history end up in the default namespace. Tag names
+-- are shared, but tag counts only include the notes of one namespace.
+
+ALTER TABLE notes ADD COLUMN namespace TEXT NOT NULL DEFAULT 'default';
+
+CREATE INDEX IF NOT EXISTS idx_notes_namespace ON notes(namespace, deleted_at);
+
+ALTER TABLE journal ADD COLUMN namespace TEXT NOT NULL DEFAULT 'default';
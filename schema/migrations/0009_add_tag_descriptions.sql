@@ -0,0 +1,9 @@
+-- What tags are for. Tag names are shared by namespaces, so descriptions are
+-- per namespace like tag counts.
+
+CREATE TABLE IF NOT EXISTS tag_descriptions (
+    namespace TEXT NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    PRIMARY KEY (namespace, name)
+);
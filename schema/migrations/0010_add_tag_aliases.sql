@@ -0,0 +1,9 @@
+-- Other names for tags, replaced by the tag when notes are written or
+-- filtered. Per namespace like tag descriptions.
+
+CREATE TABLE IF NOT EXISTS tag_aliases (
+    namespace TEXT NOT NULL,
+    alias TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (namespace, alias)
+);
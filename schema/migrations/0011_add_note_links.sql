@@ -0,0 +1,11 @@
+-- Typed links between notes, e.g. one note superseding another. Links are
+-- deleted with either note when it's purged.
+
+CREATE TABLE IF NOT EXISTS note_links (
+    from_id INTEGER NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+    to_id INTEGER NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    PRIMARY KEY (from_id, to_id, kind)
+);
+
+CREATE INDEX IF NOT EXISTS idx_note_links_to_id ON note_links(to_id);
@@ -0,0 +1,130 @@
+-- Each namespace numbers its own notes: `id` is the note's ID in its
+-- namespace, and `row_id` the key other tables refer to. Existing notes keep
+-- their IDs. Tags belong to a namespace. The tables are rebuilt, and the old
+-- ones dropped children first so that dropping them doesn't cascade.
+
+CREATE TABLE IF NOT EXISTS notes_new (
+    row_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    id INTEGER NOT NULL,
+    namespace TEXT NOT NULL DEFAULT 'default',
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    "references" TEXT NOT NULL DEFAULT '[]',
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+    deleted_at TEXT,
+    UNIQUE (namespace, id)
+);
+
+INSERT INTO notes_new
+    (row_id, id, namespace, title, body, "references", created_at, updated_at, deleted_at)
+SELECT id, id, namespace, title, body, "references", created_at, updated_at, deleted_at
+FROM notes;
+
+CREATE TABLE IF NOT EXISTS tags_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    namespace TEXT NOT NULL,
+    name TEXT NOT NULL,
+    UNIQUE (namespace, name)
+);
+
+INSERT INTO tags_new (namespace, name)
+SELECT DISTINCT n.namespace, t.name FROM note_tags nt
+JOIN tags t ON t.id = nt.tag_id
+JOIN notes n ON n.id = nt.note_id;
+
+CREATE TABLE IF NOT EXISTS note_tags_new (
+    note_id INTEGER NOT NULL REFERENCES notes_new(row_id) ON DELETE CASCADE,
+    tag_id INTEGER NOT NULL REFERENCES tags_new(id) ON DELETE CASCADE,
+    PRIMARY KEY (note_id, tag_id)
+);
+
+INSERT INTO note_tags_new (note_id, tag_id)
+SELECT nt.note_id, tn.id FROM note_tags nt
+JOIN tags t ON t.id = nt.tag_id
+JOIN notes n ON n.id = nt.note_id
+JOIN tags_new tn ON tn.namespace = n.namespace AND tn.name = t.name;
+
+CREATE TABLE IF NOT EXISTS note_revisions_new (
+    note_id INTEGER NOT NULL REFERENCES notes_new(row_id) ON DELETE CASCADE,
+    revision INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    tags TEXT NOT NULL DEFAULT '[]',
+    "references" TEXT NOT NULL DEFAULT '[]',
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (note_id, revision)
+);
+
+INSERT INTO note_revisions_new
+    (note_id, revision, title, body, tags, "references", updated_at)
+SELECT note_id, revision, title, body, tags, "references", updated_at
+FROM note_revisions;
+
+CREATE TABLE IF NOT EXISTS note_links_new (
+    from_id INTEGER NOT NULL REFERENCES notes_new(row_id) ON DELETE CASCADE,
+    to_id INTEGER NOT NULL REFERENCES notes_new(row_id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    PRIMARY KEY (from_id, to_id, kind)
+);
+
+INSERT INTO note_links_new (from_id, to_id, kind)
+SELECT from_id, to_id, kind FROM note_links;
+
+DROP TRIGGER IF EXISTS notes_fts_insert;
+DROP TRIGGER IF EXISTS notes_fts_delete;
+DROP TRIGGER IF EXISTS notes_fts_update;
+DROP TABLE IF EXISTS notes_fts;
+
+DROP TABLE note_links;
+DROP TABLE note_revisions;
+DROP TABLE note_tags;
+DROP TABLE tags;
+DROP TABLE notes;
+
+ALTER TABLE notes_new RENAME TO notes;
+ALTER TABLE tags_new RENAME TO tags;
+ALTER TABLE note_tags_new RENAME TO note_tags;
+ALTER TABLE note_revisions_new RENAME TO note_revisions;
+ALTER TABLE note_links_new RENAME TO note_links;
+
+CREATE INDEX IF NOT EXISTS idx_notes_updated_at ON notes(updated_at);
+CREATE INDEX IF NOT EXISTS idx_notes_created_at ON notes(created_at);
+CREATE INDEX IF NOT EXISTS idx_notes_deleted_at ON notes(deleted_at);
+CREATE INDEX IF NOT EXISTS idx_notes_namespace ON notes(namespace, deleted_at);
+CREATE INDEX IF NOT EXISTS idx_note_tags_tag_id ON note_tags(tag_id);
+CREATE INDEX IF NOT EXISTS idx_note_links_to_id ON note_links(to_id);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS notes_fts USING fts5(
+    title,
+    body,
+    content='notes',
+    content_rowid='row_id'
+);
+
+CREATE TRIGGER IF NOT EXISTS notes_fts_insert AFTER INSERT ON notes BEGIN
+    INSERT INTO notes_fts (rowid, title, body) VALUES (new.row_id, new.title, new.body);
+END;
+
+CREATE TRIGGER IF NOT EXISTS notes_fts_delete AFTER DELETE ON notes BEGIN
+    INSERT INTO notes_fts (notes_fts, rowid, title, body)
+    VALUES ('delete', old.row_id, old.title, old.body);
+END;
+
+CREATE TRIGGER IF NOT EXISTS notes_fts_update AFTER UPDATE OF title, body ON notes BEGIN
+    INSERT INTO notes_fts (notes_fts, rowid, title, body)
+    VALUES ('delete', old.row_id, old.title, old.body);
+    INSERT INTO notes_fts (rowid, title, body) VALUES (new.row_id, new.title, new.body);
+END;
+
+INSERT INTO notes_fts (notes_fts) VALUES ('rebuild');
+
+-- The last ID given out in each namespace, so IDs of purged notes aren't
+-- reused
+CREATE TABLE IF NOT EXISTS note_ids (
+    namespace TEXT PRIMARY KEY,
+    last_id INTEGER NOT NULL
+);
+
+INSERT OR IGNORE INTO note_ids (namespace, last_id)
+SELECT namespace, MAX(id) FROM notes GROUP BY namespace;
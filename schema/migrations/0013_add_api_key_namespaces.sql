@@ -0,0 +1,4 @@
+-- API keys can be limited to some namespaces, stored as a JSON array. An
+-- empty array allows every namespace, so existing keys keep working.
+
+ALTER TABLE api_keys ADD COLUMN namespaces TEXT NOT NULL DEFAULT '[]';